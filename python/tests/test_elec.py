@@ -0,0 +1,52 @@
+"""Tests of the elec extension module, run from the repository root after `maturin develop`:
+
+    python -m unittest discover python/tests
+"""
+
+import pathlib
+import unittest
+
+import elec
+
+TARIFF = "data/test/tariff/consumption.csv"
+SUPPLY = "data/test/tariff/supply.csv"
+CONSUMPTION = "data/test/energy/consumption.csv"
+
+
+class Frame:
+    """Stands in for a pandas DataFrame, written as CSV without its index."""
+
+    def to_csv(self, index=True):
+        assert not index
+        with open(CONSUMPTION, encoding="utf-8") as f:
+            return f.read()
+
+
+class TestElec(unittest.TestCase):
+    def test_price(self):
+        # files as a path, a pathlib.Path and a DataFrame
+        bill = elec.price(CONSUMPTION, consumption_tariff=TARIFF, daily=pathlib.Path(SUPPLY), holiday_region="NSW")
+        self.assertEqual(bill["days"], 3)
+        self.assertEqual(len(bill["monthly"]), 1)
+        self.assertEqual(elec.price(Frame(), consumption_tariff=TARIFF, daily=SUPPLY, holiday_region="NSW")["total"], bill["total"])
+
+    def test_compare(self):
+        plans = {"TOU": {"consumption_tariff": TARIFF, "daily": SUPPLY}}
+        ranked = elec.compare(plans, consumption=CONSUMPTION)
+        self.assertEqual((ranked[0]["rank"], ranked[0]["plan"]), (1, "TOU"))
+
+    def test_load_tariff(self):
+        with open(TARIFF, "rb") as f:
+            rows = elec.load_tariff(f.read())
+        self.assertEqual(rows[0]["name"], "Off-peak")
+        self.assertAlmostEqual(elec.price_energy(TARIFF, CONSUMPTION)["cost"], 0.1518297)
+
+    def test_errors(self):
+        with self.assertRaisesRegex(elec.ElecError, "consumption_tariff or cdr_plan is required"):
+            elec.price(CONSUMPTION, daily=SUPPLY)
+        with self.assertRaisesRegex(elec.ElecError, "can't convert object to JSON"):
+            elec.price(CONSUMPTION, cdr_plan={"plan": object()})
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,178 @@
+//! Pricing requests and responses as JSON, shared by the HTTP [`crate::server`] and the Python extension module
+//! (with the `python` feature).
+//!
+//! A request is a JSON object of file contents as strings, as the files would be given to `elec price`:
+//!
+//! ```json
+//! {
+//!   "consumption_tariff": "Tariff,Day Start,...",  // or "cdr_plan": {...}, a CDR plan object
+//!   "feedin_tariff": "...",                        // optional
+//!   "daily": "Daily Supply Charge\n1.45398\n",     // supply charge file
+//!   "demand_tariff": "...",                        // optional
+//!   "consumption": "Date/Time,00:00,...",          // wide or long CSV, NEM12 or Green Button data
+//!   "feedin": "...",                               // optional
+//!   "public_holidays": "...",                      // optional, or "holiday_region": "NSW"
+//! }
+//! ```
+//!
+//! The files are read in memory, never written to disk.
+
+use crate::bill::Bill;
+use crate::cdr::cdr_plan;
+use crate::demand::load_demand_tariff_from;
+use crate::energy::{read_energy_bytes, DayEnergy, Direction};
+use crate::holidays::{region_holidays, Region, YEARS};
+use crate::json::Json;
+use crate::plan::{compare_plans, price_plan, Plan};
+use crate::pricing::{load_region_holidays_from, load_tariff_from, price_intervals, Tariff, TariffIndex};
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+use std::str::FromStr;
+
+// The contents of the file at `key`, None if there's none
+fn contents<'a>(json: &'a Json, key: &str) -> Result<Option<&'a [u8]>> {
+    match json.get(key) {
+        None | Some(Json::Null) => Ok(None),
+        Some(v) => v.as_str().map(|s| Some(s.as_bytes())).ok_or_else(|| anyhow!("contents: {} is not a string of the file's contents", key)),
+    }
+}
+
+fn required<'a>(json: &'a Json, key: &str, what: &str) -> Result<&'a [u8]> {
+    contents(json, key)?.ok_or_else(|| anyhow!("required: {} is required", what))
+}
+
+// The plan from its files (or CDR plan object), named `name`
+fn plan(json: &Json, name: &str) -> Result<Plan> {
+    let demand = contents(json, "demand_tariff")?;
+    match json.get("cdr_plan") {
+        Some(cdr) => match demand {
+            Some(demand) => Ok(Plan { demand_tariff: load_demand_tariff_from("demand_tariff", demand)?, ..cdr_plan(cdr)? }),
+            None => cdr_plan(cdr),
+        },
+        None => {
+            let consumption = required(json, "consumption_tariff", "consumption_tariff or cdr_plan")?;
+            let daily = required(json, "daily", "daily (the supply charge file)")?;
+            Plan::from_bytes(name, consumption, contents(json, "feedin_tariff")?, daily, demand)
+        },
+    }
+}
+
+fn holidays(json: &Json) -> Result<HashSet<String>> {
+    Ok(match (contents(json, "public_holidays")?, json.get("holiday_region").and_then(Json::as_str)) {
+        (Some(csv), _) => load_region_holidays_from("public_holidays", csv, None)?,
+        (None, Some(region)) => region_holidays(Region::from_str(region)?, YEARS),
+        (None, None) => HashSet::new(),
+    })
+}
+
+// The (consumption, feedin) data
+fn data(json: &Json) -> Result<(Vec<DayEnergy>, Option<Vec<DayEnergy>>)> {
+    let consumption = read_energy_bytes("consumption", required(json, "consumption", "consumption data")?.to_vec(), Direction::Import)?;
+    let feedin = contents(json, "feedin")?.map(|f| read_energy_bytes("feedin", f.to_vec(), Direction::Export)).transpose()?;
+    Ok((consumption, feedin))
+}
+
+/// The bill for a request (as `elec price --format json`) with its `monthly` totals.
+pub fn price(json: &Json) -> Result<Json> {
+    price_bill(json).map(|bill| bill_json(&bill))
+}
+
+/// The bill for a request.
+pub fn price_bill(json: &Json) -> Result<Bill> {
+    let plan = plan(json, "Uploaded")?;
+    let (consumption, feedin) = data(json)?;
+    price_plan(&plan, consumption.into_iter().map(Ok), feedin.map(|f| f.into_iter().map(Ok)), &holidays(json)?)
+}
+
+/// A bill as `elec price --format json` with its `monthly` totals.
+pub fn bill_json(bill: &Bill) -> Json {
+    let mut json = bill.to_json();
+    if let Json::Object(kvs) = &mut json {
+        kvs.push(("monthly".to_string(), bill.monthly_json()));
+    }
+    json
+}
+
+/// The bills for `plans`, an object of plan name to the plan's files, priced on the request's data and ranked
+/// cheapest first (as `elec compare`), each bill with its `rank` and `plan` name.
+pub fn compare(json: &Json) -> Result<Json> {
+    let plans = match json.get("plans") {
+        Some(Json::Object(kvs)) if !kvs.is_empty() => kvs.iter().map(|(name, files)| plan(files, name)).collect::<Result<Vec<Plan>>>()?,
+        _ => return Err(anyhow!("compare: plans is required, an object of plan name to the plan's files")),
+    };
+    let (consumption, feedin) = data(json)?;
+    let ranked = compare_plans(&plans, &consumption, feedin.as_deref(), &holidays(json)?, 1)?;
+    Ok(Json::Array(ranked.iter().enumerate().map(|(rank, (plan, bill))| {
+        let mut json = bill.to_json();
+        if let Json::Object(kvs) = &mut json {
+            kvs.insert(0, ("plan".to_string(), plan.name.as_str().into()));
+            kvs.insert(0, ("rank".to_string(), (rank + 1).into()));
+        }
+        json
+    }).collect()))
+}
+
+fn tariff_json(t: &Tariff) -> Json {
+    Json::object(vec![
+        ("name", t.name.as_str().into()),
+        ("day_start", (t.day_start as f64).into()),
+        ("day_end", (t.day_end as f64).into()),
+        ("time_start", (t.time_start as f64).into()),
+        ("time_end", (t.time_end as f64).into()),
+        ("tariff", t.tariff.into()),
+    ])
+}
+
+/// The rows of the tariff file at `tariff`: name, days (0 for Monday, end exclusive), times (minutes since
+/// midnight, end exclusive) and $/kWh.
+pub fn load_tariff(json: &Json) -> Result<Json> {
+    let tariff = load_tariff_from("tariff", required(json, "tariff", "tariff")?)?;
+    Ok(tariff.iter().map(tariff_json).collect::<Vec<Json>>().into())
+}
+
+/// The cost of the `energy` data under the `tariff` file alone, without supply charges (as
+/// [`crate::pricing::price_energy`]): `days`, `columns` and `cost`.
+pub fn price_energy(json: &Json) -> Result<Json> {
+    let tariff = load_tariff_from("tariff", required(json, "tariff", "tariff")?)?;
+    let energy = read_energy_bytes("energy", required(json, "energy", "energy data")?.to_vec(), Direction::Import)?;
+    let index = TariffIndex::new(&tariff);
+    let (days, columns, cost) = price_intervals(energy.into_iter().map(Ok), |i| index.cost(i), &holidays(json)?)?;
+    Ok(Json::object(vec![("days", days.into()), ("columns", columns.into()), ("cost", cost.into())]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(path: &str) -> Json {
+        std::fs::read_to_string(path).unwrap().into()
+    }
+
+    #[test]
+    fn test_compare() -> Result<()> {
+        let files = |tariff: &str| Json::object(vec![("consumption_tariff", read(tariff)), ("daily", read("data/test/tariff/supply.csv"))]);
+        let request = Json::object(vec![
+            ("plans", Json::object(vec![("TOU", files("data/test/tariff/consumption.csv")), ("Flat", files("data/test/tariff/consumptionFlat.csv"))])),
+            ("consumption", read("data/test/energy/consumption.csv")),
+        ]);
+        let ranked = compare(&request)?;
+        let ranked = ranked.as_array().unwrap();
+        assert_eq!(ranked.len(), 2);
+        assert_eq!(ranked[0].number("rank")?, 1.0);
+        assert!(ranked[0].number("total")? <= ranked[1].number("total")?);
+        assert!(compare(&Json::object(vec![("plans", Json::object(Vec::<(String, Json)>::new()))])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_tariff_price_energy() -> Result<()> {
+        let tariff = read("data/test/tariff/consumption.csv");
+        let rows = load_tariff(&Json::object(vec![("tariff", tariff.clone())]))?;
+        assert!(!rows.as_array().unwrap().is_empty());
+        assert!(rows.as_array().unwrap()[0].string("name").is_ok());
+        let priced = price_energy(&Json::object(vec![("tariff", tariff), ("energy", read("data/test/energy/consumption.csv"))]))?;
+        assert_eq!(priced.number("days")?, 3.0);
+        assert!(priced.number("cost")? > 0.0);
+        Ok(())
+    }
+}
@@ -0,0 +1,356 @@
+//! Simulating changes to a home's energy use, for pricing like the actual data.
+//!
+//! A battery charges from energy that would otherwise be fed in (excess solar) and discharges to
+//! cover consumption at peak times. Load shifting moves consumption from one tariff window to another.
+//! Electric vehicle charging adds consumption, either from plug-in time or in the cheapest intervals.
+//! Solar generation offsets consumption, with the excess fed in.
+
+use crate::energy::DayEnergy;
+use crate::pricing::{tariff_at, Tariff};
+use anyhow::{anyhow, Context, Result};
+use chrono::{Datelike, NaiveDate};
+use log::info;
+use std::collections::{BTreeMap, HashSet};
+
+/// A battery's capacity, power and efficiency.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Battery {
+    pub kwh: f64,        // usable capacity
+    pub kw: f64,         // maximum charge and discharge power
+    pub efficiency: f64, // round trip, applied when charging
+}
+
+/// Consumption and feedin with the battery, and how much it charged and discharged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Simulation {
+    pub consumption: Vec<DayEnergy>,
+    pub feedin: Vec<DayEnergy>,
+    pub charged: f64,    // kWh taken from feedin
+    pub discharged: f64, // kWh replacing consumption
+}
+
+/// Whether consumption at a date and time (minutes since midnight) is priced at `threshold` $/kWh or more
+/// under the first matching tariff row, with holidays priced as Sundays (or the weekend).
+pub fn peak_times<'a>(tariff: &'a [Tariff], holidays: &'a HashSet<String>, threshold: f64) -> impl Fn(NaiveDate, i32) -> Result<bool> + 'a {
+    move |date, min| {
+        let row = tariff_at(tariff, holidays, date, min)
+            .with_context(|| format!("peak_times: no tariff for {} and min_since_midnight {}", date, min))?;
+        Ok(row.tariff >= threshold)
+    }
+}
+
+/// Whether a date and time (minutes since midnight) is in the tariff window `name`, i.e. the first matching
+/// tariff row has that name, with holidays priced as Sundays (or the weekend).
+pub fn tariff_window<'a>(tariff: &'a [Tariff], holidays: &'a HashSet<String>, name: &'a str) -> impl Fn(NaiveDate, i32) -> Result<bool> + 'a {
+    move |date, min| Ok(tariff_at(tariff, holidays, date, min).is_some_and(|t| t.name == name))
+}
+
+/// Move up to `kwh` of consumption each day from the intervals in the `from` window to those in the `to` window,
+/// returning the new consumption and the kWh moved.
+///
+/// Consumption is taken from the `from` intervals in proportion to their consumption and spread evenly over
+/// the `to` intervals. Nothing is moved on days without both windows.
+pub fn shift_load<F, T>(consumption: &[DayEnergy], kwh: f64, from: F, to: T) -> Result<(Vec<DayEnergy>, f64)> where
+F: Fn(NaiveDate, i32) -> Result<bool>,
+T: Fn(NaiveDate, i32) -> Result<bool> {
+    let mut moved = 0.0;
+    let days = consumption.iter().map(|day| {
+        let select = |window: &dyn Fn(NaiveDate, i32) -> Result<bool>| -> Result<Vec<usize>> {
+            (0..day.energy.len())
+            .map(|i| Ok((i, window(day.date, day.minute(i))?)))
+            .filter_map(|x| x.map(|(i, w)| Some(i).filter(|_| w)).transpose())
+            .collect()
+        };
+        let (from_i, to_i) = (select(&from)?, select(&to)?);
+        let available = from_i.iter().fold(0.0, |sum, i| sum + day.energy[*i]);
+        let mut day = day.clone();
+        if !to_i.is_empty() && available > 0.0 {
+            let shift = kwh.min(available);
+            for i in &from_i {
+                day.energy[*i] -= day.energy[*i] * shift / available;
+            }
+            for i in &to_i {
+                day.energy[*i] += shift / to_i.len() as f64;
+            }
+            moved += shift;
+        }
+        Ok(day)
+    }).collect::<Result<Vec<DayEnergy>>>()?;
+    Ok((days, moved))
+}
+
+/// How much an electric vehicle needs charging and how fast it charges.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ev {
+    pub kwh: f64,     // needed each day, or each week
+    pub weekly: bool, // kwh is per week (Monday to Sunday) rather than per day
+    pub kw: f64,      // maximum charge rate
+    pub plug_in: i32, // time plugged in (minutes since midnight)
+}
+
+impl Ev {
+    // kWh needed over a number of days
+    fn needed(&self, days: usize) -> f64 {
+        if self.weekly { self.kwh * days as f64 / 7.0 } else { self.kwh * days as f64 }
+    }
+}
+
+// The intervals of a day from the plug-in time to the end of the day, then from midnight
+fn plug_in_order(day: &DayEnergy, plug_in: i32) -> Vec<usize> {
+    let n = day.energy.len();
+    let first = (0..n).find(|i| day.minute(*i) >= plug_in).unwrap_or(n);
+    (first..n).chain(0..first).collect()
+}
+
+// Charge up to `kwh` in the (day, interval) slots in turn at up to `kw`, returning the kWh charged
+fn charge(days: &mut [DayEnergy], slots: &[(usize, usize)], kwh: f64, kw: f64) -> f64 {
+    let mut charged = 0.0;
+    for (d, i) in slots {
+        if charged >= kwh {
+            break;
+        }
+        let energy = (kw * days[*d].interval as f64 / 60.0).min(kwh - charged);
+        days[*d].energy[*i] += energy;
+        charged += energy;
+    }
+    charged
+}
+
+/// Consumption with the EV charged at full power from its plug-in time each day, a seventh of the kWh
+/// each day if weekly, returning the new consumption and the kWh charged.
+///
+/// Charging not done by the end of the day is done from midnight of the same day, as if carried over
+/// from the day before.
+pub fn charge_at_plug_in(consumption: &[DayEnergy], ev: &Ev) -> (Vec<DayEnergy>, f64) {
+    let mut days = consumption.to_vec();
+    let mut charged = 0.0;
+    for d in 0..days.len() {
+        let slots = plug_in_order(&days[d], ev.plug_in).into_iter().map(|i| (d, i)).collect::<Vec<_>>();
+        charged += charge(&mut days, &slots, ev.needed(1), ev.kw);
+    }
+    (days, charged)
+}
+
+/// Consumption with the EV charged in the cheapest intervals of each day (or each week, Monday to Sunday)
+/// by `rate(date, minute)` ($/kWh), returning the new consumption and the kWh charged.
+///
+/// Equally cheap intervals are used in plug-in order (see [`charge_at_plug_in`]). A week only partly in the
+/// data needs its share of the weekly kWh. Days must be in date order.
+pub fn charge_cheapest<F>(consumption: &[DayEnergy], ev: &Ev, rate: F) -> Result<(Vec<DayEnergy>, f64)> where
+F: Fn(NaiveDate, i32) -> Result<f64> {
+    let mut days = consumption.to_vec();
+    let mut charged = 0.0;
+    let mut start = 0;
+    while start < days.len() {
+        let week = days[start].date.iso_week();
+        let end = match ev.weekly {
+            true => start + days[start..].iter().take_while(|d| d.date.iso_week() == week).count(),
+            false => start + 1,
+        };
+        let mut slots = Vec::new();
+        for (d, day) in days.iter().enumerate().take(end).skip(start) {
+            for i in plug_in_order(day, ev.plug_in) {
+                slots.push((rate(day.date, day.minute(i))?, d, i));
+            }
+        }
+        slots.sort_by(|a, b| a.0.total_cmp(&b.0)); // stable, so ties stay in plug-in order
+        let slots = slots.into_iter().map(|(_, d, i)| (d, i)).collect::<Vec<_>>();
+        charged += charge(&mut days, &slots, ev.needed(end - start), ev.kw);
+        start = end;
+    }
+    Ok((days, charged))
+}
+
+/// The consumption tariff $/kWh at a date and time (minutes since midnight) under the first matching
+/// tariff row, with holidays priced as Sundays (or the weekend).
+pub fn tariff_rate<'a>(tariff: &'a [Tariff], holidays: &'a HashSet<String>) -> impl Fn(NaiveDate, i32) -> Result<f64> + 'a {
+    move |date, min| {
+        let row = tariff_at(tariff, holidays, date, min)
+            .with_context(|| format!("tariff_rate: no tariff for {} and min_since_midnight {}", date, min))?;
+        Ok(row.tariff)
+    }
+}
+
+/// Consumption and feedin with solar generation, and where the generation went.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Solar {
+    pub consumption: Vec<DayEnergy>,
+    pub feedin: Vec<DayEnergy>,
+    pub generated: f64,     // kWh
+    pub self_consumed: f64, // kWh replacing consumption
+    pub exported: f64,      // kWh added to feedin
+    pub missing: usize,     // days of consumption without generation
+}
+
+// A day's energy in `n` intervals of `interval` minutes, spreading or summing the day's intervals
+// (taken as evenly spaced over the whole day)
+fn resample(day: &DayEnergy, interval: usize, n: usize) -> Vec<f64> {
+    let from = 1440 / day.energy.len().max(1);
+    (0..n).map(|i| {
+        let (start, end) = (i * interval, (i + 1) * interval);
+        (start / from..end.div_ceil(from).min(day.energy.len())).map(|j| {
+            let overlap = end.min((j + 1) * from) - start.max(j * from);
+            day.energy[j] * overlap as f64 / from as f64
+        }).sum()
+    }).collect()
+}
+
+/// Add solar generation to consumption and feedin: generation used in the house reduces consumption and the excess
+/// is fed in.
+///
+/// Generation is found by date, or otherwise by month and day so a profile of a typical year applies to any year
+/// (February 29th uses February 28th), and is spread or summed to the consumption's intervals.
+/// Days without generation are left as they are. Feedin days must have the same interval length as the consumption
+/// on the same date, and are added for dates without them.
+pub fn add_solar(consumption: &[DayEnergy], feedin: &[DayEnergy], generation: &[DayEnergy]) -> Result<Solar> {
+    let by_date = generation.iter().map(|d| (d.date, d)).collect::<BTreeMap<NaiveDate, &DayEnergy>>();
+    let by_day = generation.iter().map(|d| ((d.date.month(), d.date.day()), d)).collect::<BTreeMap<(u32, u32), &DayEnergy>>();
+    let mut feedin = feedin.iter().map(|d| (d.date, d.clone())).collect::<BTreeMap<NaiveDate, DayEnergy>>();
+    let mut solar = Solar { consumption: Vec::with_capacity(consumption.len()), feedin: Vec::new(), generated: 0.0, self_consumed: 0.0, exported: 0.0, missing: 0 };
+    for day in consumption {
+        let (m, d) = (day.date.month(), day.date.day());
+        let Some(gen) = by_date.get(&day.date).or_else(|| by_day.get(&(m, d))).or_else(|| by_day.get(&(2, 28)).filter(|_| (m, d) == (2, 29))) else {
+            solar.missing += 1;
+            solar.consumption.push(day.clone());
+            continue;
+        };
+        let out = feedin.entry(day.date).or_insert_with(|| DayEnergy { energy: vec![0.0; day.energy.len()], ..day.clone() });
+        if out.energy.len() != day.energy.len() {
+            return Err(anyhow!("add_solar: {} has {} consumption but {} feedin intervals", day.date, day.energy.len(), out.energy.len()));
+        }
+        let mut used = day.clone();
+        for (i, g) in resample(gen, day.interval, day.energy.len()).into_iter().enumerate() {
+            let self_consumed = g.min(used.energy[i]).max(0.0);
+            used.energy[i] -= self_consumed;
+            out.energy[i] += g - self_consumed;
+            solar.generated += g;
+            solar.self_consumed += self_consumed;
+            solar.exported += g - self_consumed;
+        }
+        solar.consumption.push(used);
+    }
+    solar.feedin = feedin.into_values().collect();
+    Ok(solar)
+}
+
+/// Replay consumption and feedin through the battery, starting empty.
+///
+/// In each interval the battery charges from the feedin and, when `discharge(date, minute)` is true,
+/// discharges to cover the consumption, both limited by its power and capacity.
+/// Feedin days must have the same interval length as the consumption on the same date.
+pub fn simulate<F>(battery: &Battery, consumption: &[DayEnergy], feedin: &[DayEnergy], discharge: F) -> Result<Simulation> where
+F: Fn(NaiveDate, i32) -> Result<bool> {
+    info!("simulate: {:?}", battery);
+    let mut feedin = feedin.iter().map(|d| (d.date, d.clone())).collect::<BTreeMap<NaiveDate, DayEnergy>>();
+    let mut sim = Simulation { consumption: Vec::with_capacity(consumption.len()), feedin: Vec::new(), charged: 0.0, discharged: 0.0 };
+    let mut stored = 0.0;
+    for day in consumption {
+        let mut out = feedin.get_mut(&day.date);
+        if let Some(f) = out.as_ref().filter(|f| f.energy.len() != day.energy.len()) {
+            return Err(anyhow!("simulate: {} has {} consumption but {} feedin intervals", day.date, day.energy.len(), f.energy.len()));
+        }
+        let max_energy = battery.kw * day.interval as f64 / 60.0;
+        let mut used = day.clone();
+        for (i, e) in used.energy.iter_mut().enumerate() {
+            if let Some(f) = out.as_mut() {
+                let charge = f.energy[i].min(max_energy).min((battery.kwh - stored) / battery.efficiency).max(0.0);
+                f.energy[i] -= charge;
+                stored += charge * battery.efficiency;
+                sim.charged += charge;
+            }
+            if discharge(day.date, day.minute(i))? {
+                let energy = e.min(max_energy).min(stored).max(0.0);
+                *e -= energy;
+                stored -= energy;
+                sim.discharged += energy;
+            }
+        }
+        sim.consumption.push(used);
+    }
+    sim.feedin = feedin.into_values().collect();
+    Ok(sim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::energy::read_csv_energy;
+    use assert_float_eq::*;
+
+    #[test]
+    fn test_simulate() -> Result<()> {
+        let consumption = read_csv_energy("data/test/energy/consumption.csv")?.collect::<Result<Vec<_>>>()?;
+        let feedin = read_csv_energy("data/test/energy/feedIn.csv")?.collect::<Result<Vec<_>>>()?;
+        let battery = Battery { kwh: 10.0, kw: 5.0, efficiency: 0.9 };
+        // discharge from 16:00, after charging from the 08:00 to 16:00 feedin
+        let sim = simulate(&battery, &consumption, &feedin, |_, min| Ok(min >= 16 * 60))?;
+        assert_float_absolute_eq!(sim.discharged, 3.0 * 0.063, 1e-12);
+        assert!(sim.consumption.iter().all(|d| d.energy[2] == 0.0));
+        let kwh = |days: &[DayEnergy]| days.iter().flat_map(|d| d.energy.iter()).fold(0.0, |sum, e| sum + e);
+        assert_float_absolute_eq!(kwh(&feedin) - kwh(&sim.feedin), sim.charged, 1e-12);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ev_charging() -> Result<()> {
+        let consumption = read_csv_energy("data/test/energy/consumption.csv")?.collect::<Result<Vec<_>>>()?;
+        let tariff = crate::pricing::load_tariff("data/test/tariff/consumption.csv")?;
+        let holidays = HashSet::new();
+        // 8 hour intervals, so 7 kW charges up to 56 kWh an interval
+        let ev = Ev { kwh: 10.0, weekly: false, kw: 7.0, plug_in: 16 * 60 };
+        let (plug_in, charged) = charge_at_plug_in(&consumption, &ev);
+        assert_float_absolute_eq!(charged, 30.0, 1e-12);
+        assert_float_absolute_eq!(plug_in[1].energy[2], 10.063, 1e-12);
+        // Monday 7th and Tuesday 8th charge off-peak from midnight, Sunday 6th is all off-peak so charges at plug-in
+        let (cheapest, _) = charge_cheapest(&consumption, &ev, tariff_rate(&tariff, &holidays))?;
+        assert_eq!(cheapest[0], plug_in[0]);
+        assert_float_absolute_eq!(cheapest[1].energy[0], 10.073, 1e-12);
+        assert_float_absolute_eq!(cheapest[2].energy[0], 10.073, 1e-12);
+
+        // 70 kWh a week at 1 kW, Sunday 6th ends a week (10 kWh) and Monday 7th starts the next (20 kWh for 2 days):
+        // the 2 off-peak intervals take 8 kWh each, then 4 kWh in the cheaper Shoulder on Monday 16:00
+        let ev = Ev { kwh: 70.0, weekly: true, kw: 1.0, ..ev };
+        let (cheapest, charged) = charge_cheapest(&consumption, &ev, tariff_rate(&tariff, &holidays))?;
+        assert_float_absolute_eq!(charged, 10.0 + 20.0, 1e-12);
+        assert_float_absolute_eq!(cheapest[1].energy[0], 8.073, 1e-12);
+        assert_float_absolute_eq!(cheapest[2].energy[0], 8.073, 1e-12);
+        assert_float_absolute_eq!(cheapest[1].energy[2], 4.063, 1e-12);
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_solar() -> Result<()> {
+        let consumption = read_csv_energy("data/test/energy/consumption.csv")?.collect::<Result<Vec<_>>>()?;
+        // 2 hours of 0.02 kWh then 6 of 0.01 kWh from 08:00, in 1 hour intervals
+        let mut energy = vec![0.0; 24];
+        energy[8..10].fill(0.02);
+        energy[10..16].fill(0.01);
+        let generation = vec![
+            DayEnergy::new(NaiveDate::from_ymd_opt(2023, 8, 6).unwrap(), energy.clone())?,
+            DayEnergy::new(NaiveDate::from_ymd_opt(2020, 8, 7).unwrap(), energy)?, // typical year
+        ];
+        let solar = add_solar(&consumption, &[], &generation)?;
+        assert_eq!(solar.missing, 1);
+        assert_float_absolute_eq!(solar.generated, 2.0 * 0.1, 1e-12);
+        assert_float_absolute_eq!(solar.self_consumed, 2.0 * 0.028, 1e-12);
+        assert_eq!(solar.consumption[0].energy[1], 0.0);
+        assert_float_absolute_eq!(solar.feedin[1].energy[1], 0.1 - 0.028, 1e-12);
+        assert_eq!(solar.consumption[2], consumption[2]);
+        assert_eq!(solar.feedin.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_shift_load() -> Result<()> {
+        let consumption = read_csv_energy("data/test/energy/consumption.csv")?.collect::<Result<Vec<_>>>()?;
+        let tariff = crate::pricing::load_tariff("data/test/tariff/consumption.csv")?;
+        let holidays = HashSet::new();
+        // 20230806 is a Sunday, without a Shoulder or Off-peak window, the other days are weekdays
+        let (shifted, moved) = shift_load(&consumption, 1.0, tariff_window(&tariff, &holidays, "Shoulder"), tariff_window(&tariff, &holidays, "Off-peak"))?;
+        assert_float_absolute_eq!(moved, 2.0 * 0.063, 1e-12);
+        assert_eq!(shifted[0], consumption[0]);
+        assert_eq!(shifted[1].energy[2], 0.0);
+        assert_float_absolute_eq!(shifted[1].energy[0], 0.073 + 0.063, 1e-12);
+        Ok(())
+    }
+}
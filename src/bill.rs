@@ -0,0 +1,1027 @@
+//! The result of pricing: the components of a bill and its output formats.
+
+use crate::cap::{cap_lines, BillCap, CapLine};
+use crate::carbon::Emissions;
+use crate::concession::{Concession, ConcessionLine, Rebate};
+use crate::fees::{fee_lines, FeeLine, RecurringFee};
+use crate::demand::DemandCharge;
+use crate::discount::{DiscountLine, DiscountOn};
+use crate::energy::Direction;
+use crate::html;
+use crate::json::Json;
+use crate::money::{self, round_cents, Rounding};
+use crate::pricing::{Billing, TouPeriod};
+use anyhow::Result;
+use chrono::{Datelike, Months, NaiveDate};
+use csv::Writer;
+use log::info;
+use std::collections::BTreeMap;
+use std::io;
+use std::str::FromStr;
+
+/// Energy and cost totals for a day, month or any other period.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Totals {
+    pub kwh_in: f64,      // kWh consumed
+    pub kwh_out: f64,     // kWh fed in
+    pub consumption: f64, // $
+    pub feedin: f64,      // $, negative for a credit
+    pub supply: f64,      // $
+}
+
+impl Totals {
+    pub fn add(&mut self, other: &Totals) {
+        self.kwh_in += other.kwh_in;
+        self.kwh_out += other.kwh_out;
+        self.consumption = money::add(self.consumption, other.consumption);
+        self.feedin = money::add(self.feedin, other.feedin);
+        self.supply = money::add(self.supply, other.supply);
+    }
+
+    pub fn total(&self) -> f64 {
+        money::sum([self.consumption, self.feedin, self.supply])
+    }
+}
+
+/// Energy and cost for one tariff name, e.g. Peak.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NameTotals {
+    pub name: String,
+    pub period: Option<TouPeriod>, // of the tariff row named, None for other names (e.g. an event or wholesale)
+    pub kwh: f64,
+    pub cost: f64,
+}
+
+/// Energy and cost per tariff name, in the order the names were first seen.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ByName(pub Vec<NameTotals>);
+
+impl ByName {
+    /// Add to the totals for a name that isn't in a time of use period.
+    pub fn add(&mut self, name: &str, kwh: f64, cost: f64) {
+        self.add_in(name, || None, kwh, cost);
+    }
+
+    /// Add to the totals for a name, `period` giving its time of use period when the name is first seen.
+    pub fn add_in(&mut self, name: &str, period: impl FnOnce() -> Option<TouPeriod>, kwh: f64, cost: f64) {
+        match self.0.iter_mut().find(|x| x.name == name) {
+            Some(x) => {
+                x.kwh += kwh;
+                x.cost = money::add(x.cost, cost);
+            },
+            None => self.0.push(NameTotals { name: name.to_string(), period: period(), kwh, cost }),
+        }
+    }
+
+    /// The totals per time of use period (see [`crate::pricing::Tariff::tou_period`]): Peak, Shoulder and Off-peak
+    /// first, then names in no period (e.g. a flat rate or an event) in the order they were first seen.
+    pub fn by_period(&self) -> ByName {
+        let mut periods = ByName::default();
+        for p in TouPeriod::ALL {
+            for x in self.0.iter().filter(|x| x.period == Some(p)) {
+                periods.add_in(p.name(), || Some(p), x.kwh, x.cost);
+            }
+        }
+        for x in self.0.iter().filter(|x| x.period.is_none()) {
+            periods.add(&x.name, x.kwh, x.cost);
+        }
+        periods
+    }
+
+    /// Add the totals of `other`, keeping the order names were first seen.
+    pub fn merge(&mut self, other: &ByName) {
+        for x in &other.0 {
+            self.add_in(&x.name, || x.period, x.kwh, x.cost);
+        }
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::Array(self.0.iter().map(|x| Json::object(vec![
+            ("name", x.name.as_str().into()),
+            ("period", x.period.map_or(Json::Null, |p| p.name().into())),
+            ("kwh", x.kwh.into()),
+            ("cost", x.cost.into()),
+        ])).collect())
+    }
+
+    /// The totals written by [`ByName::to_json`].
+    pub fn from_json(json: &Json) -> Result<ByName> {
+        json.as_array().unwrap_or_default().iter()
+            .map(|x| Ok(NameTotals {
+                name: x.string("name")?.to_string(),
+                period: x.get("period").and_then(Json::as_str).map(TouPeriod::from_str).transpose()?,
+                kwh: x.number("kwh")?,
+                cost: x.number("cost")?,
+            }))
+            .collect::<Result<Vec<_>>>().map(ByName)
+    }
+}
+
+/// Which bill components are priced with rates that include GST.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GstInclusive {
+    pub consumption: bool,
+    pub supply: bool,
+    pub demand: bool,
+}
+
+impl Default for GstInclusive {
+    fn default() -> GstInclusive {
+        GstInclusive { consumption: true, supply: true, demand: true }
+    }
+}
+
+/// GST on a bill. Feedin credits are GST-free (as for households not registered for GST).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Gst {
+    pub rate: f64,     // e.g. 0.1 for 10%
+    pub subtotal: f64, // $ ex GST
+    pub gst: f64,      // $
+    pub total: f64,    // $ inc GST
+}
+
+/// A priced portion of an interval's energy at one tariff rate, for tracing how a bill was calculated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceRow {
+    pub date: NaiveDate,
+    pub minute: i32,          // start of the interval (min since midnight)
+    pub repeated: bool,       // after clocks went back on the date, so the second time round its repeated hour
+    pub direction: Direction, // consumption or feedin
+    pub kwh: f64,
+    pub name: String,         // tariff name
+    pub rate: f64,            // $/kWh
+    pub cost: f64,            // $
+}
+
+/// Priced consumption and supply for an extra metered channel, e.g. controlled load for hot water.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChannelCharge {
+    pub name: String,
+    pub kwh: f64,         // kWh consumed
+    pub consumption: f64, // $
+    pub supply: f64,      // $ for the channel's own daily supply charge
+    pub days: usize,      // number of days of data
+}
+
+impl ChannelCharge {
+    pub fn total(&self) -> f64 {
+        money::add(self.consumption, self.supply)
+    }
+}
+
+/// Yearly price rises for projecting a bill into future years, as fractions (e.g. 0.03 for 3%, negative for falls).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Escalation {
+    pub tariff: f64, // consumption, feedin and demand rates
+    pub supply: f64, // daily supply charges
+}
+
+/// What would make one plan's bill equal another's on the same data, for [`break_even`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BreakEven {
+    pub feedin_rate: Option<f64>,  // flat $/kWh for all feedin (negative for a credit), None without feedin
+    pub supply_scale: Option<f64>, // factor on the supply charges, None without supply charges
+}
+
+/// Priced consumption, feedin and supply for a dataset.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Bill {
+    pub consumption: f64,       // $ for consumption
+    pub feedin: f64,            // $ for feedin, negative for a credit
+    pub supply: f64,            // $ for daily supply charges
+    pub demand: Vec<DemandCharge>, // monthly demand charges
+    pub days: usize,            // number of days of consumption data
+    pub interval: usize,        // interval length (minutes) of the consumption data
+    pub warnings: Vec<String>,  // anything the user should check before trusting the result
+    pub daily: BTreeMap<NaiveDate, Totals>, // totals per date
+    pub consumption_by_name: ByName, // consumption per tariff name
+    pub feedin_by_name: ByName,      // feedin per tariff name
+    pub discounts: Vec<DiscountLine>, // applied after the charges above
+    pub fees: Vec<FeeLine>,          // recurring fees in each billing period (not discounted)
+    pub concessions: Vec<ConcessionLine>, // government concessions and rebates in each billing period (not taxed)
+    pub caps: Vec<CapLine>,          // minimums and caps on each billing period's total, applied after everything else
+    pub channels: Vec<ChannelCharge>, // extra channels priced independently (not discounted)
+    pub trace: Vec<TraceRow>,        // priced portions of intervals in time order, if traced
+    pub gst: Option<Gst>,            // set by apply_gst
+    pub billing: Option<Billing>,    // billing periods the totals are also shown for
+    pub emissions: Option<Emissions>, // with a carbon intensity, not in the total
+}
+
+impl Bill {
+    pub fn demand_total(&self) -> f64 {
+        money::sum(self.demand.iter().map(|d| d.charge))
+    }
+
+    // The month of a demand charge: YYYY-MM, or the first date of the billing month with billing periods
+    fn demand_month(&self, d: &DemandCharge) -> String {
+        match self.billing {
+            Some(_) => d.month.to_string(),
+            None => d.month.format("%Y-%m").to_string(),
+        }
+    }
+
+    pub fn discount_total(&self) -> f64 {
+        money::sum(self.discounts.iter().map(|d| d.amount))
+    }
+
+    pub fn fee_total(&self) -> f64 {
+        money::sum(self.fees.iter().map(|f| f.amount))
+    }
+
+    // The fees in the billing period starting on a date
+    fn period_fees(&self, start: NaiveDate) -> f64 {
+        money::sum(self.fees.iter().filter(|f| f.start == start).map(|f| f.amount))
+    }
+
+    pub fn concession_total(&self) -> f64 {
+        money::sum(self.concessions.iter().map(|c| c.amount))
+    }
+
+    // The concessions and rebates in the billing period starting on a date
+    fn period_concessions(&self, start: NaiveDate) -> f64 {
+        money::sum(self.concessions.iter().filter(|c| c.start == start).map(|c| c.amount))
+    }
+
+    pub fn cap_total(&self) -> f64 {
+        money::sum(self.caps.iter().map(|c| c.amount))
+    }
+
+    pub fn channel_total(&self) -> f64 {
+        money::sum(self.channels.iter().map(|c| c.total()))
+    }
+
+    pub fn total(&self) -> f64 {
+        money::sum([self.consumption, self.feedin, self.supply, self.demand_total(), self.discount_total(), self.fee_total(), self.concession_total(), self.cap_total(), self.channel_total()])
+    }
+
+    /// The amount to pay: the total inc GST once [`Bill::apply_gst`] has set it, otherwise the total.
+    pub fn payable(&self) -> f64 {
+        self.gst.as_ref().map_or(self.total(), |g| g.total)
+    }
+
+    /// The flat consumption rate ($/kWh) that would give the same consumption and demand charges, and the flat
+    /// feedin rate (negative for a credit, as in a tariff file) that would give the same feedin, None without energy.
+    pub fn flat_rates(&self) -> (Option<f64>, Option<f64>) {
+        let (kwh_in, kwh_out) = self.daily.values().fold((0.0, 0.0), |(i, o), t| (i + t.kwh_in, o + t.kwh_out));
+        let rate = |cost: f64, kwh: f64| Some(cost / kwh).filter(|_| kwh > 0.0);
+        (rate(money::add(self.consumption, self.demand_total()), kwh_in), rate(self.feedin, kwh_out))
+    }
+
+    /// The part of the total from supply charges: the supply of the dataset and its channels, with supply discounts,
+    /// the share of whole bill discounts in proportion to supply among the discounted charges, and recurring fees.
+    pub fn supply_total(&self) -> f64 {
+        let charges = self.consumption + self.supply + self.demand_total();
+        let discounts = self.discounts.iter().fold(0.0, |sum, d| sum + match d.on {
+            DiscountOn::Supply => d.amount,
+            DiscountOn::Bill if charges != 0.0 => d.amount * self.supply / charges,
+            _ => 0.0,
+        });
+        self.supply + self.channels.iter().fold(0.0, |sum, c| sum + c.supply) + discounts + self.fee_total()
+    }
+
+    /// The total in each of `years` years, the first as priced and each later one with the rates of the year
+    /// before risen by `escalation`. Percentage discounts rise with the charges they're on. The data is taken to be
+    /// a year, otherwise each figure is for a period as long as the data.
+    pub fn projection(&self, years: usize, escalation: Escalation) -> Vec<f64> {
+        let supply = self.supply_total();
+        let tariff = self.total() - supply;
+        (0..years as i32)
+            .map(|y| tariff * (1.0 + escalation.tariff).powi(y) + supply * (1.0 + escalation.supply).powi(y))
+            .collect()
+    }
+
+    /// Calculate the GST on the bill, with `inclusive` saying which components' rates already include it.
+    /// Usage and whole bill discounts and bill caps are taken to be as inclusive as consumption, supply discounts and
+    /// recurring fees as supply,
+    /// and likewise for the consumption and supply of extra channels. Concessions and rebates aren't taxed.
+    pub fn apply_gst(&mut self, rate: f64, inclusive: GstInclusive) {
+        let ex = |x: f64, inc: bool| if inc { x / (1.0 + rate) } else { x };
+        let discounts = self.discounts.iter().fold(0.0, |sum, d| sum + match d.on {
+            DiscountOn::Supply => ex(d.amount, inclusive.supply),
+            DiscountOn::Usage | DiscountOn::Bill => ex(d.amount, inclusive.consumption),
+        });
+        let channels = self.channels.iter().fold(0.0, |sum, c| sum + ex(c.consumption, inclusive.consumption) + ex(c.supply, inclusive.supply));
+        let taxable = ex(self.consumption, inclusive.consumption) + ex(self.supply, inclusive.supply) + ex(self.demand_total(), inclusive.demand) + discounts
+            + ex(self.fee_total(), inclusive.supply) + ex(self.cap_total(), inclusive.consumption) + channels;
+        let subtotal = taxable + self.feedin + self.concession_total();
+        let gst = taxable * rate;
+        self.gst = Some(Gst { rate, subtotal, gst, total: subtotal + gst });
+    }
+
+    /// Round every amount to whole cents: the charges, discounts, GST, and the daily and per tariff name
+    /// totals (so monthly totals are sums of rounded daily totals). Trace costs are left as they are.
+    pub fn round(&mut self, rounding: Rounding) {
+        let r = |x: &mut f64| *x = round_cents(*x, rounding);
+        for x in [&mut self.consumption, &mut self.feedin, &mut self.supply] {
+            r(x);
+        }
+        self.demand.iter_mut().for_each(|d| r(&mut d.charge));
+        self.discounts.iter_mut().for_each(|d| r(&mut d.amount));
+        self.fees.iter_mut().for_each(|f| r(&mut f.amount));
+        self.concessions.iter_mut().for_each(|c| r(&mut c.amount));
+        self.caps.iter_mut().for_each(|c| r(&mut c.amount));
+        for c in &mut self.channels {
+            r(&mut c.consumption);
+            r(&mut c.supply);
+        }
+        for t in self.daily.values_mut() {
+            for x in [&mut t.consumption, &mut t.feedin, &mut t.supply] {
+                r(x);
+            }
+        }
+        for x in self.consumption_by_name.0.iter_mut().chain(self.feedin_by_name.0.iter_mut()) {
+            r(&mut x.cost);
+        }
+        if let Some(g) = &mut self.gst {
+            for x in [&mut g.subtotal, &mut g.gst, &mut g.total] {
+                r(x);
+            }
+        }
+    }
+
+    /// A table of the projected total in each year (see [`Bill::projection`]) and the running total.
+    pub fn projection_text(projection: &[f64]) -> String {
+        let mut s = format!("{:<6} {:>12} {:>12}\n", "Year", "Total", "Cumulative");
+        let mut cumulative = 0.0;
+        for (year, total) in projection.iter().enumerate() {
+            cumulative += total;
+            s += &format!("{:<6} {:>12.2} {:>12.2}\n", year + 1, total, cumulative);
+        }
+        s
+    }
+
+    /// Daily totals summed by calendar month, keyed by the first day of the month.
+    pub fn monthly(&self) -> BTreeMap<NaiveDate, Totals> {
+        let mut months: BTreeMap<NaiveDate, Totals> = BTreeMap::new();
+        for (date, totals) in &self.daily {
+            months.entry(date.with_day(1).unwrap_or(*date)).or_default().add(totals);
+        }
+        months
+    }
+
+    // Demand charges summed by month
+    fn monthly_demand(&self) -> BTreeMap<NaiveDate, f64> {
+        let mut months: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+        for d in &self.demand {
+            let m = months.entry(d.month.with_day(1).unwrap_or(d.month)).or_default(); // billing months from the 1st
+            *m = money::add(*m, d.charge);
+        }
+        months
+    }
+
+    /// A table of the monthly totals, with a row for the total of all months.
+    pub fn monthly_text(&self) -> String {
+        let demand = self.monthly_demand();
+        let mut s = format!(
+            "{:<8} {:>10} {:>10} {:>12} {:>10} {:>10} {:>10} {:>10}\n",
+            "Month", "kWh In", "kWh Out", "Consumption", "Feedin", "Supply", "Demand", "Total"
+        );
+        let mut all = Totals::default();
+        let row = |label: &str, t: &Totals, d: f64| format!(
+            "{:<8} {:>10.3} {:>10.3} {:>12.2} {:>10.2} {:>10.2} {:>10.2} {:>10.2}\n",
+            label, t.kwh_in, t.kwh_out, t.consumption, t.feedin, t.supply, d, money::add(t.total(), d)
+        );
+        for (month, t) in self.monthly() {
+            all.add(&t);
+            s += &row(&month.format("%Y-%m").to_string(), &t, demand.get(&month).copied().unwrap_or(0.0));
+        }
+        s += &row("Total", &all, self.demand_total());
+        s
+    }
+
+    /// The totals of each billing period (if any) in date order: its first date, its last date, the energy totals,
+    /// the demand charges of the billing months starting in it and its share of the discounts.
+    pub fn billing_periods(&self) -> Vec<(NaiveDate, NaiveDate, Totals, f64, f64)> {
+        match self.billing {
+            Some(billing) => self.periods(|date| billing.period(date)),
+            None => Vec::new(),
+        }
+    }
+
+    /// The total ($) of each billing period like [`Bill::billing_periods`], or of each calendar month without billing
+    /// periods, with its fees, concessions and rebates and before caps: its first date, last date and total.
+    pub fn cap_periods(&self) -> Vec<(NaiveDate, NaiveDate, f64)> {
+        self.periods(|date| self.period_of(date)).into_iter()
+            .map(|(start, end, t, demand, discount)| {
+                (start, end, money::sum([t.total(), demand, discount, self.period_fees(start), self.period_concessions(start)]))
+            })
+            .collect()
+    }
+
+    /// Apply concessions to the charges in each billing period (or calendar month) with data, those with a season to
+    /// the charges of its days (and demand months), and credit each rebate in the period containing its date.
+    pub fn apply_concessions(&mut self, concessions: &[Concession], rebates: &[Rebate]) {
+        let mut lines = Vec::new();
+        for (start, end, ..) in self.periods(|date| self.period_of(date)) {
+            for c in concessions {
+                let days = self.daily.range(start..=end).filter(|(date, _)| c.in_season(**date));
+                let (consumption, supply) = days.fold((0.0, 0.0), |(c, s), (_, t)| (money::add(c, t.consumption), money::add(s, t.supply)));
+                let demand = money::sum(self.demand.iter()
+                    .filter(|d| self.period_of(d.month).0 == start && c.in_season(d.month))
+                    .map(|d| d.charge));
+                let base = match c.on {
+                    DiscountOn::Usage => consumption,
+                    DiscountOn::Supply => supply,
+                    DiscountOn::Bill => money::sum([consumption, supply, demand]),
+                };
+                let amount = c.credit(base);
+                if amount != 0.0 {
+                    lines.push(ConcessionLine { name: c.name.clone(), start, end, amount });
+                }
+            }
+            lines.extend(rebates.iter().filter(|r| start <= r.date && r.date <= end)
+                .map(|r| ConcessionLine { name: r.name.clone(), start, end, amount: -r.amount }));
+        }
+        self.concessions = lines;
+    }
+
+    /// Charge recurring fees in each billing period (or calendar month) with data, see [`fee_lines`].
+    pub fn apply_fees(&mut self, fees: &[RecurringFee]) {
+        let periods = self.periods(|date| self.period_of(date)).into_iter().map(|(start, end, ..)| (start, end)).collect::<Vec<_>>();
+        self.fees = fee_lines(fees, &periods, self.billing.map_or(1, |b| b.months() as u32));
+    }
+
+    // The billing period of a date (its first date and the next period's first date), or its calendar month
+    fn period_of(&self, date: NaiveDate) -> (NaiveDate, NaiveDate) {
+        match self.billing {
+            Some(billing) => billing.period(date),
+            None => {
+                let first = date.with_day(1).unwrap_or(date);
+                (first, first.checked_add_months(Months::new(1)).unwrap_or(first))
+            },
+        }
+    }
+
+    /// Apply minimums and caps to the total of each billing period (or calendar month), see [`cap_lines`].
+    pub fn apply_caps(&mut self, caps: &[BillCap]) {
+        self.caps = cap_lines(caps, &self.cap_periods());
+    }
+
+    // The totals of each period given by a function of a date to the period's first date and the next period's
+    fn periods(&self, period: impl Fn(NaiveDate) -> (NaiveDate, NaiveDate)) -> Vec<(NaiveDate, NaiveDate, Totals, f64, f64)> {
+        let mut periods: BTreeMap<(NaiveDate, NaiveDate), (Totals, f64)> = BTreeMap::new();
+        for (date, totals) in &self.daily {
+            periods.entry(period(*date)).or_default().0.add(totals);
+        }
+        for d in &self.demand {
+            let p = &mut periods.entry(period(d.month)).or_default().1;
+            *p = money::add(*p, d.charge);
+        }
+        // discounts are percentages, so each period's share is in proportion to what they're on
+        let on = |discount: DiscountOn, t: &Totals, demand: f64| match discount {
+            DiscountOn::Usage => t.consumption,
+            DiscountOn::Supply => t.supply,
+            DiscountOn::Bill => money::sum([t.consumption, t.supply, demand]),
+        };
+        let all = Totals { consumption: self.consumption, supply: self.supply, ..Totals::default() };
+        periods.into_iter().map(|((start, next), (t, demand))| {
+            let discount = money::sum(self.discounts.iter().map(|d| match on(d.on, &all, self.demand_total()) {
+                0.0 => 0.0,
+                whole => d.amount * on(d.on, &t, demand) / whole,
+            }));
+            (start, next.pred_opt().unwrap_or(next), t, demand, discount)
+        }).collect()
+    }
+
+    /// A table of the totals of each billing period, empty without billing periods.
+    pub fn billing_text(&self) -> String {
+        let periods = self.billing_periods();
+        if periods.is_empty() {
+            return String::new();
+        }
+        let mut s = format!(
+            "{:<24} {:>10} {:>10} {:>12} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}\n",
+            "Billing Period", "kWh In", "kWh Out", "Consumption", "Feedin", "Supply", "Demand", "Discount", "Fees", "Concession", "Total"
+        );
+        for (start, end, t, demand, discount) in periods {
+            let (fees, concessions) = (self.period_fees(start), self.period_concessions(start));
+            s += &format!(
+                "{:<24} {:>10.3} {:>10.3} {:>12.2} {:>10.2} {:>10.2} {:>10.2} {:>10.2} {:>10.2} {:>10.2} {:>10.2}\n",
+                format!("{} to {}", start, end), t.kwh_in, t.kwh_out, t.consumption, t.feedin, t.supply, demand, discount, fees, concessions,
+                money::sum([t.total(), demand, discount, fees, concessions])
+            );
+        }
+        s
+    }
+
+    /// The monthly totals as a JSON array.
+    pub fn monthly_json(&self) -> Json {
+        let demand = self.monthly_demand();
+        Json::Array(self.monthly().iter().map(|(month, t)| {
+            let d = demand.get(month).copied().unwrap_or(0.0);
+            Json::object(vec![
+                ("month", month.format("%Y-%m").to_string().into()),
+                ("kwh_in", t.kwh_in.into()),
+                ("kwh_out", t.kwh_out.into()),
+                ("consumption", t.consumption.into()),
+                ("feedin", t.feedin.into()),
+                ("supply", t.supply.into()),
+                ("demand", d.into()),
+                ("total", money::add(t.total(), d).into()),
+            ])
+        }).collect())
+    }
+
+    /// Sparklines of the daily cost and kWh in and out, each character being the mean of up to `width` equal
+    /// runs of days, labelled with the first and last dates and the range of daily values.
+    pub fn daily_chart_text(&self, width: usize) -> String {
+        let (Some(first), Some(last)) = (self.daily.keys().next(), self.daily.keys().last()) else {
+            return String::new();
+        };
+        let series = [
+            ("Cost $", Totals::total as fn(&Totals) -> f64),
+            ("kWh In", |t| t.kwh_in),
+            ("kWh Out", |t| t.kwh_out),
+        ];
+        let mut s = format!("{:<8} {:<10} {}\n", "Daily", first.format("%Y-%m-%d"), last.format("%Y-%m-%d"));
+        for (label, value) in series {
+            let values = self.daily.values().map(value).collect::<Vec<f64>>();
+            let (min, max) = values.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| (min.min(*v), max.max(*v)));
+            s += &format!("{:<8} {} {:.2} to {:.2}\n", label, sparkline(&values, width), min, max);
+        }
+        s
+    }
+
+    /// Write the line items of the summary as CSV: Item, kWh (empty for charges not on energy) and $.
+    pub fn write_csv<W: io::Write>(&self, w: W) -> Result<()> {
+        let mut writer = Writer::from_writer(w);
+        writer.write_record(["Item", "kWh", "$"])?;
+        let kwh = |f: fn(&Totals) -> f64| self.daily.values().map(f).sum::<f64>().to_string();
+        let mut rows = vec![
+            ("Consumption".to_string(), kwh(|t| t.kwh_in), self.consumption),
+            ("Feedin".to_string(), kwh(|t| t.kwh_out), self.feedin),
+            ("Supply".to_string(), String::new(), self.supply),
+            ("Demand".to_string(), String::new(), self.demand_total()),
+        ];
+        rows.extend(self.channels.iter().map(|c| (format!("Channel {}", c.name), c.kwh.to_string(), c.total())));
+        rows.extend(self.discounts.iter().map(|d| (format!("Discount {}", d.name), String::new(), d.amount)));
+        rows.extend(self.fees.iter().map(|f| (format!("Fee {} {} to {}", f.name, f.start, f.end), String::new(), f.amount)));
+        rows.extend(self.concessions.iter().map(|c| (format!("Concession {} {} to {}", c.name, c.start, c.end), String::new(), c.amount)));
+        rows.extend(self.caps.iter().map(|c| (format!("{} {} to {}", c.name, c.start, c.end), String::new(), c.amount)));
+        rows.push(("Total".to_string(), String::new(), self.total()));
+        if let Some(g) = &self.gst {
+            rows.push((format!("GST ({}%)", g.rate * 100.0), String::new(), g.gst));
+            rows.push(("Total inc GST".to_string(), String::new(), g.total));
+        }
+        for (label, by_name) in [("Consumption", &self.consumption_by_name), ("Feedin", &self.feedin_by_name)] {
+            rows.extend(by_name.0.iter().map(|x| (format!("{} {}", label, x.name), x.kwh.to_string(), x.cost)));
+        }
+        for (item, kwh, cost) in rows {
+            writer.write_record([item, kwh, cost.to_string()])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write the daily totals as CSV, one line per date.
+    pub fn write_daily_csv<W: io::Write>(&self, w: W) -> Result<()> {
+        let mut writer = Writer::from_writer(w);
+        writer.write_record(["Date", "kWh In", "kWh Out", "Consumption $", "Feedin $", "Supply $", "Total $"])?;
+        for (date, t) in &self.daily {
+            writer.write_record([
+                date.format("%Y%m%d").to_string(),
+                t.kwh_in.to_string(),
+                t.kwh_out.to_string(),
+                t.consumption.to_string(),
+                t.feedin.to_string(),
+                t.supply.to_string(),
+                t.total().to_string(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write the daily totals to a CSV file, see [`Bill::write_daily_csv`].
+    pub fn save_daily_csv(&self, path: &str) -> Result<()> {
+        info!("save_daily_csv: writing CSV file {}", path);
+        self.write_daily_csv(std::fs::File::create(path)?)
+    }
+
+    /// Write the trace as CSV, one line per priced portion of an interval (an interval crossing a block
+    /// threshold has a line for each block), with the running total of consumption and feedin costs.
+    pub fn write_trace_csv<W: io::Write>(&self, w: W) -> Result<()> {
+        let mut writer = Writer::from_writer(w);
+        writer.write_record(["Timestamp", "Direction", "kWh", "Tariff", "$/kWh", "Cost $", "Running Total $"])?;
+        let mut running = 0.0;
+        for t in &self.trace {
+            running = money::add(running, t.cost);
+            writer.write_record([
+                format!("{} {:02}:{:02}", t.date.format("%Y-%m-%d"), t.minute / 60, t.minute % 60),
+                match t.direction {
+                    Direction::Import => "consumption".to_string(),
+                    Direction::Export => "feedin".to_string(),
+                },
+                t.kwh.to_string(),
+                t.name.clone(),
+                t.rate.to_string(),
+                t.cost.to_string(),
+                running.to_string(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write the trace to a CSV file, see [`Bill::write_trace_csv`].
+    pub fn save_trace_csv(&self, path: &str) -> Result<()> {
+        info!("save_trace_csv: writing CSV file {}", path);
+        self.write_trace_csv(std::fs::File::create(path)?)
+    }
+
+    /// A self-contained HTML page with charts of the monthly cost, daily kWh and consumption cost per tariff name,
+    /// and tables of the totals and monthly totals.
+    pub fn to_html(&self, title: &str) -> String {
+        let demand = self.monthly_demand();
+        let months = self.monthly().into_iter()
+            .map(|(month, t)| (month.format("%Y-%m").to_string(), t, demand.get(&month).copied().unwrap_or(0.0)))
+            .collect::<Vec<_>>();
+        let dollars = |x: f64| format!("{:.2}", x);
+        let mut totals = vec![
+            vec!["Consumption".to_string(), dollars(self.consumption)],
+            vec!["Feedin".to_string(), dollars(self.feedin)],
+            vec!["Supply".to_string(), dollars(self.supply)],
+            vec!["Demand".to_string(), dollars(self.demand_total())],
+        ];
+        totals.extend(self.channels.iter().map(|c| vec![format!("Channel {}", c.name), dollars(c.total())]));
+        totals.extend(self.discounts.iter().map(|d| vec![format!("Discount {}", d.name), dollars(d.amount)]));
+        totals.extend(self.fees.iter().map(|f| vec![format!("Fee {} {} to {}", f.name, f.start, f.end), dollars(f.amount)]));
+        totals.extend(self.concessions.iter().map(|c| vec![format!("Concession {} {} to {}", c.name, c.start, c.end), dollars(c.amount)]));
+        totals.extend(self.caps.iter().map(|c| vec![format!("{} {} to {}", c.name, c.start, c.end), dollars(c.amount)]));
+        totals.push(vec!["Total".to_string(), dollars(self.total())]);
+        if let Some(g) = &self.gst {
+            totals.push(vec![format!("GST ({}%)", g.rate * 100.0), dollars(g.gst)]);
+            totals.push(vec!["Total inc GST".to_string(), dollars(g.total)]);
+        }
+        if let Some(cost) = self.emissions.and_then(|e| e.cost()) {
+            totals.push(vec!["Carbon (shadow price, not in the total)".to_string(), dollars(cost)]);
+        }
+        let monthly = months.iter().map(|(month, t, d)| vec![
+            month.clone(), format!("{:.3}", t.kwh_in), format!("{:.3}", t.kwh_out),
+            dollars(t.consumption), dollars(t.feedin), dollars(t.supply), dollars(*d), dollars(money::add(t.total(), *d)),
+        ]).collect::<Vec<_>>();
+        let mut body = html::table(&["", "$"], &totals);
+        if let Some(e) = &self.emissions {
+            body += &format!("<p>Emissions {:.3} kgCO2e, avoided by feedin {:.3} kgCO2e, net {:.3} kgCO2e</p>\n", e.consumption, e.avoided, e.net());
+        }
+        body += &html::bar_chart("Cost per month ($)", "$", &months.iter().map(|(m, t, d)| (m.clone(), money::add(t.total(), *d))).collect::<Vec<_>>());
+        body += &html::line_chart(
+            "kWh per day",
+            &self.daily.keys().map(|d| d.format("%Y-%m-%d").to_string()).collect::<Vec<_>>(),
+            &[("kWh In", self.daily.values().map(|t| t.kwh_in).collect()), ("kWh Out", self.daily.values().map(|t| t.kwh_out).collect())],
+        );
+        body += &html::pie_chart("Consumption cost per tariff", &self.consumption_by_name.0.iter().map(|x| (x.name.clone(), x.cost)).collect::<Vec<_>>());
+        body += &html::table(&["Month", "kWh In", "kWh Out", "Consumption", "Feedin", "Supply", "Demand", "Total"], &monthly);
+        for w in &self.warnings {
+            body += &format!("<p>Warning: {}</p>\n", html::escape(w));
+        }
+        html::page(title, &body)
+    }
+
+    /// Write the HTML page to a file, see [`Bill::to_html`].
+    pub fn save_html(&self, title: &str, path: &str) -> Result<()> {
+        info!("save_html: writing HTML file {}", path);
+        std::fs::write(path, self.to_html(title))?;
+        Ok(())
+    }
+
+    /// The free-text summary.
+    pub fn to_text(&self) -> String {
+        let mut s = format!("Consumption ${}, Feedin ${}, Supply ${}\n", self.consumption, self.feedin, self.supply);
+        if !self.demand.is_empty() {
+            s += &format!("Demand ${}\n", self.demand_total());
+            for d in &self.demand {
+                let over = match d.window > 1 {
+                    true => format!(" (maximum over {} of {} months)", d.months, d.window),
+                    false => String::new(),
+                };
+                s += &format!(
+                    "  {} {} {} {} at {} {:02}:{:02}{} ${}\n",
+                    self.demand_month(d), d.name, d.kw, d.unit.label(), d.at.0, d.at.1 / 60, d.at.1 % 60, over, d.charge
+                );
+            }
+        }
+        for c in &self.channels {
+            s += &format!("Channel {}: {:.3} kWh, Consumption ${}, Supply ${}\n", c.name, c.kwh, c.consumption, c.supply);
+        }
+        for d in &self.discounts {
+            s += &format!("Discount {} ${}\n", d.name, d.amount);
+        }
+        for f in &self.fees {
+            s += &format!("Fee {} {} to {} ${}\n", f.name, f.start, f.end, f.amount);
+        }
+        for c in &self.concessions {
+            s += &format!("Concession {} {} to {} ${}\n", c.name, c.start, c.end, c.amount);
+        }
+        for c in &self.caps {
+            s += &format!("{} {} to {} ${}\n", c.name, c.start, c.end, c.amount);
+        }
+        s += &format!("Total ${}\n", self.total());
+        if let Some(g) = &self.gst {
+            s += &format!("Subtotal ex GST ${}, GST ({}%) ${}\nTotal inc GST ${}\n", g.subtotal, g.rate * 100.0, g.gst, g.total);
+        }
+        if let Some(e) = &self.emissions {
+            s += &format!("Emissions {:.3} kgCO2e, avoided by feedin {:.3} kgCO2e, net {:.3} kgCO2e\n", e.consumption, e.avoided, e.net());
+            if let (Some(price), Some(cost)) = (e.price, e.cost()) {
+                s += &format!("Carbon at ${}/t (shadow price, not in the total) ${:.2}, Total with carbon ${:.2}\n", price, cost, self.total() + cost);
+            }
+        }
+        let rates = match self.flat_rates() {
+            (Some(c), Some(f)) => format!("consumption {:.3} c/kWh, feedin {:.3} c/kWh", c * 100.0, f * 100.0),
+            (Some(c), None) => format!("consumption {:.3} c/kWh", c * 100.0),
+            (None, Some(f)) => format!("feedin {:.3} c/kWh", f * 100.0),
+            (None, None) => String::new(),
+        };
+        if !rates.is_empty() {
+            s += &format!("Flat rate equivalent: {}\n", rates);
+        }
+        for (label, by_name) in [("Consumption", &self.consumption_by_name), ("Feedin", &self.feedin_by_name)] {
+            for x in &by_name.0 {
+                s += &format!("  {} {}: {:.3} kWh ${}\n", label, x.name, x.kwh, x.cost);
+            }
+        }
+        s += &self.by_period_text();
+        s += &self.billing_text();
+        for w in &self.warnings {
+            s += &format!("Warning: {}\n", w);
+        }
+        s
+    }
+
+    /// A table of the consumption and feedin per time of use period, with each period's share of the cost,
+    /// leaving out those with fewer than two periods (e.g. a flat rate).
+    pub fn by_period_text(&self) -> String {
+        let mut s = String::new();
+        for (label, by_name) in [("Consumption", &self.consumption_by_name), ("Feedin", &self.feedin_by_name)] {
+            let periods = by_name.by_period();
+            if periods.0.len() < 2 {
+                continue;
+            }
+            let total = money::sum(periods.0.iter().map(|x| x.cost));
+            s += &format!("{:<28} {:>10} {:>12} {:>8}\n", format!("{} by time of use", label), "kWh", "$", "% of $");
+            for x in &periods.0 {
+                let share = if total != 0.0 { format!("{:.1}", 100.0 * x.cost / total) } else { String::new() };
+                s += &format!("  {:<26} {:>10.3} {:>12.2} {:>8}\n", x.name, x.kwh, x.cost, share);
+            }
+        }
+        s
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("consumption", self.consumption.into()),
+            ("feedin", self.feedin.into()),
+            ("supply", self.supply.into()),
+            ("demand", self.demand_total().into()),
+            ("total", self.total().into()),
+            ("days", self.days.into()),
+            ("interval", self.interval.into()),
+            ("discount", self.discount_total().into()),
+            ("fee", self.fee_total().into()),
+            ("concession", self.concession_total().into()),
+            ("cap", self.cap_total().into()),
+            ("flat_rate", self.flat_rates().0.map_or(Json::Null, Json::from)),
+            ("flat_feedin_rate", self.flat_rates().1.map_or(Json::Null, Json::from)),
+            ("warnings", self.warnings.clone().into()),
+            ("gst", self.gst.map_or(Json::Null, |g| Json::object(vec![
+                ("rate", g.rate.into()),
+                ("subtotal", g.subtotal.into()),
+                ("gst", g.gst.into()),
+                ("total", g.total.into()),
+            ]))),
+            ("emissions", self.emissions.map_or(Json::Null, |e| e.to_json())),
+            ("consumption_by_name", self.consumption_by_name.to_json()),
+            ("feedin_by_name", self.feedin_by_name.to_json()),
+            ("billing_periods", Json::Array(self.billing_periods().into_iter().map(|(start, end, t, demand, discount)| Json::object(vec![
+                ("start", start.to_string().into()),
+                ("end", end.to_string().into()),
+                ("kwh_in", t.kwh_in.into()),
+                ("kwh_out", t.kwh_out.into()),
+                ("consumption", t.consumption.into()),
+                ("feedin", t.feedin.into()),
+                ("supply", t.supply.into()),
+                ("demand", demand.into()),
+                ("discount", discount.into()),
+                ("fees", self.period_fees(start).into()),
+                ("concession", self.period_concessions(start).into()),
+                ("total", money::sum([t.total(), demand, discount, self.period_fees(start), self.period_concessions(start)]).into()),
+            ])).collect())),
+            ("consumption_by_period", self.consumption_by_name.by_period().to_json()),
+            ("feedin_by_period", self.feedin_by_name.by_period().to_json()),
+            ("discounts", Json::Array(self.discounts.iter().map(|d| Json::object(vec![
+                ("name", d.name.as_str().into()),
+                ("amount", d.amount.into()),
+            ])).collect())),
+            ("fees", Json::Array(self.fees.iter().map(|f| Json::object(vec![
+                ("name", f.name.as_str().into()),
+                ("start", f.start.to_string().into()),
+                ("end", f.end.to_string().into()),
+                ("amount", f.amount.into()),
+            ])).collect())),
+            ("concessions", Json::Array(self.concessions.iter().map(|c| Json::object(vec![
+                ("name", c.name.as_str().into()),
+                ("start", c.start.to_string().into()),
+                ("end", c.end.to_string().into()),
+                ("amount", c.amount.into()),
+            ])).collect())),
+            ("caps", Json::Array(self.caps.iter().map(|c| Json::object(vec![
+                ("name", c.name.as_str().into()),
+                ("start", c.start.to_string().into()),
+                ("end", c.end.to_string().into()),
+                ("amount", c.amount.into()),
+            ])).collect())),
+            ("channels", Json::Array(self.channels.iter().map(|c| Json::object(vec![
+                ("name", c.name.as_str().into()),
+                ("kwh", c.kwh.into()),
+                ("consumption", c.consumption.into()),
+                ("supply", c.supply.into()),
+                ("days", c.days.into()),
+                ("total", c.total().into()),
+            ])).collect())),
+            ("demand_charges", Json::Array(self.demand.iter().map(|d| Json::object(vec![
+                ("month", self.demand_month(d).into()),
+                ("name", d.name.as_str().into()),
+                ("kw", d.kw.into()),
+                ("unit", d.unit.label().into()),
+                ("at", format!("{} {:02}:{:02}", d.at.0, d.at.1 / 60, d.at.1 % 60).into()),
+                ("days", d.days.into()),
+                ("months", d.months.into()),
+                ("charge", d.charge.into()),
+            ])).collect())),
+        ])
+    }
+}
+
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+// One block character per value from lowest to highest, averaging runs of values down to at most `width` characters
+fn sparkline(values: &[f64], width: usize) -> String {
+    let run = values.len().div_ceil(width.max(1)).max(1);
+    let means = values.chunks(run).map(|c| c.iter().fold(0.0, |sum, v| sum + v) / c.len() as f64).collect::<Vec<f64>>();
+    let (min, max) = means.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| (min.min(*v), max.max(*v)));
+    means.iter().map(|v| match max > min {
+        true => SPARKS[(((v - min) / (max - min)) * (SPARKS.len() - 1) as f64).round() as usize],
+        false => SPARKS[0],
+    }).collect()
+}
+
+/// The flat feedin rate, or the factor on its supply charges, at which bill `b` would equal bill `a`, each with
+/// the rest of `b` as it is. Below the feedin rate (a larger credit) or the supply factor `b` is cheaper.
+/// Supply discounts scale with the supply charges, see [`Bill::supply_total`].
+pub fn break_even(a: &Bill, b: &Bill) -> BreakEven {
+    let kwh_out = b.daily.values().fold(0.0, |sum, t| sum + t.kwh_out);
+    let supply = b.supply_total();
+    BreakEven {
+        feedin_rate: Some((a.total() - (b.total() - b.feedin)) / kwh_out).filter(|_| kwh_out > 0.0),
+        supply_scale: Some((a.total() - (b.total() - supply)) / supply).filter(|_| supply != 0.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::energy::{read_csv_energy, DayEnergy};
+    use crate::plan::{price_plan, Plan};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_apply_gst() {
+        let mut bill = Bill { consumption: 110.0, feedin: -20.0, supply: 50.0, ..Bill::default() };
+        assert_eq!(bill.payable(), 140.0);
+        bill.apply_gst(0.1, GstInclusive { supply: false, ..GstInclusive::default() });
+        assert!((bill.payable() - 145.0).abs() < 1e-9);
+        let g = bill.gst.unwrap();
+        assert!((g.subtotal - 130.0).abs() < 1e-9);
+        assert!((g.gst - 15.0).abs() < 1e-9);
+        assert!((g.total - 145.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_flat_rates() {
+        let d = NaiveDate::from_ymd_opt(2023, 8, 6).unwrap();
+        let mut bill = Bill {
+            consumption: 1.5, supply: 1.0,
+            daily: BTreeMap::from([(d, Totals { kwh_in: 6.0, ..Totals::default() })]),
+            ..Bill::default()
+        };
+        assert_eq!(bill.flat_rates(), (Some(0.25), None));
+        assert!(bill.to_text().contains("Flat rate equivalent: consumption 25.000 c/kWh\n"));
+        bill.feedin = -0.35;
+        bill.daily.get_mut(&d).unwrap().kwh_out = 5.0;
+        assert!((bill.flat_rates().1.unwrap() + 0.07).abs() < 1e-12);
+        assert!(bill.to_text().contains("Flat rate equivalent: consumption 25.000 c/kWh, feedin -7.000 c/kWh\n"));
+    }
+
+    #[test]
+    fn test_break_even() {
+        let d = NaiveDate::from_ymd_opt(2023, 8, 6).unwrap();
+        let a = Bill { consumption: 10.0, feedin: -1.0, supply: 2.0, ..Bill::default() };
+        let b = Bill {
+            consumption: 9.0, feedin: -0.5, supply: 4.0,
+            daily: BTreeMap::from([(d, Totals { kwh_out: 10.0, ..Totals::default() })]),
+            ..Bill::default()
+        };
+        // b is $1.50 dearer: a feedin credit of 20c/kWh rather than 5c, or 62.5% of its supply charge
+        let even = break_even(&a, &b);
+        assert!((even.feedin_rate.unwrap() + 0.2).abs() < 1e-12);
+        assert!((even.supply_scale.unwrap() - 0.625).abs() < 1e-12);
+        assert_eq!(break_even(&b, &a), BreakEven { feedin_rate: None, supply_scale: Some(1.75) });
+    }
+
+    #[test]
+    fn test_projection() {
+        let bill = Bill {
+            consumption: 100.0, feedin: -20.0, supply: 50.0,
+            discounts: vec![DiscountLine { name: "Pay on time".to_string(), on: DiscountOn::Bill, amount: -15.0 }],
+            ..Bill::default()
+        };
+        assert!((bill.supply_total() - 45.0).abs() < 1e-9);
+        let projection = bill.projection(3, Escalation { tariff: 0.1, supply: 0.0 });
+        let expected = [115.0, 115.0 + 7.0, 115.0 + 7.0 + 7.7];
+        assert!(projection.iter().zip(expected).all(|(p, e)| (p - e).abs() < 1e-9), "{:?}", projection);
+        assert_eq!(Bill::projection_text(&projection[..2]).lines().nth(2), Some("2            122.00       237.00"));
+    }
+
+    #[test]
+    fn test_write_csv() -> Result<()> {
+        let d = NaiveDate::from_ymd_opt(2023, 8, 6).unwrap();
+        let mut bill = Bill {
+            consumption: 1.5, feedin: -0.5, supply: 1.0,
+            daily: BTreeMap::from([(d, Totals { kwh_in: 6.0, kwh_out: 5.0, ..Totals::default() })]),
+            ..Bill::default()
+        };
+        bill.consumption_by_name.add("Peak", 6.0, 1.5);
+        let mut csv = Vec::new();
+        bill.write_csv(&mut csv)?;
+        assert_eq!(String::from_utf8(csv)?, "Item,kWh,$\nConsumption,6,1.5\nFeedin,5,-0.5\nSupply,,1\nDemand,,0\nTotal,,2\nConsumption Peak,6,1.5\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_by_period() {
+        let mut bill = Bill::default();
+        for (name, kwh, cost) in [("Off-peak (all weekend and public holidays)", 4.0, 1.0), ("Peak", 2.0, 1.5), ("Off-peak", 2.0, 0.5), ("Anytime", 1.0, 0.3), ("Shoulder", 1.0, 0.2)] {
+            bill.consumption_by_name.add_in(name, || TouPeriod::from_name(name), kwh, cost);
+        }
+        bill.consumption_by_name.add("Critical peak", 0.5, 0.75); // an event, in no period
+        let periods = bill.consumption_by_name.by_period();
+        assert_eq!(periods.0.iter().map(|x| (x.name.as_str(), x.kwh, x.cost)).collect::<Vec<_>>(),
+            vec![("Peak", 2.0, 1.5), ("Shoulder", 1.0, 0.2), ("Off-peak", 6.0, 1.5), ("Anytime", 1.0, 0.3), ("Critical peak", 0.5, 0.75)]);
+        assert_eq!(ByName::from_json(&bill.consumption_by_name.to_json()).unwrap(), bill.consumption_by_name);
+        assert_eq!(bill.by_period_text().lines().nth(1), Some("  Peak                            2.000         1.50     35.3"));
+        assert!(!bill.by_period_text().contains("Feedin"));
+    }
+
+    #[test]
+    fn test_round() {
+        let mut bill = Bill { consumption: 110.125, feedin: -20.015, supply: 50.0049, ..Bill::default() };
+        bill.round(Rounding::HalfEven);
+        assert_eq!((bill.consumption, bill.feedin, bill.supply, bill.total()), (110.12, -20.02, 50.0, 140.1));
+    }
+
+    #[test]
+    // the test data priced (0.1518297 of consumption and 3 days at 1.45398 of supply) with GST, then rounded to cents:
+    // the 0.03931488 of off-peak rounds up, or down when truncated, and the total is the sum of the rounded charges
+    fn test_round_priced() -> Result<()> {
+        let plan = Plan::load("Flat", "data/test/tariff/consumption.csv", None, "data/test/tariff/supply.csv", None)?;
+        for (rounding, off_peak) in [(Rounding::HalfUp, 0.04), (Rounding::HalfEven, 0.04), (Rounding::Truncate, 0.03)] {
+            let mut bill = price_plan(&plan, read_csv_energy("data/test/energy/consumption.csv")?, None::<Vec<Result<DayEnergy>>>, &HashSet::new())?;
+            assert_eq!((bill.consumption, bill.supply, bill.total()), (0.1518297, 4.36194, 4.5137697));
+            bill.apply_gst(0.1, GstInclusive { consumption: true, supply: true, demand: true });
+            bill.round(rounding);
+            assert_eq!((bill.consumption, bill.supply, bill.total()), (0.15, 4.36, 4.51));
+            assert_eq!(bill.consumption_by_name.0.iter().map(|x| x.cost).collect::<Vec<_>>(), vec![0.04, off_peak, 0.02, 0.04]);
+            let gst = bill.gst.unwrap();
+            assert_eq!((gst.subtotal, gst.gst, gst.total), (4.1, 0.41, 4.51));
+            assert_eq!(bill.daily.values().map(|t| t.supply).collect::<Vec<_>>(), vec![1.45; 3]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_monthly_and_daily() {
+        let d = |s| NaiveDate::parse_from_str(s, "%Y%m%d").unwrap();
+        let t = |kwh_in, consumption| Totals { kwh_in, consumption, supply: 1.0, ..Totals::default() };
+        let bill = Bill {
+            daily: BTreeMap::from([
+                (d("20230830"), t(1.0, 0.5)),
+                (d("20230831"), t(2.0, 1.0)),
+                (d("20230901"), t(4.0, 2.0)),
+            ]),
+            ..Bill::default()
+        };
+        let monthly = bill.monthly();
+        assert_eq!(monthly.len(), 2);
+        assert_eq!(monthly[&d("20230801")], Totals { kwh_in: 3.0, consumption: 1.5, supply: 2.0, ..Totals::default() });
+        assert_eq!(monthly[&d("20230901")], t(4.0, 2.0));
+        let mut csv = Vec::new();
+        bill.write_daily_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().nth(1), Some("20230830,1,0,0.5,0,1,1.5"));
+        assert_eq!(csv.lines().count(), 4);
+        assert_eq!(sparkline(&[0.0, 1.0, 2.0, 3.0, 7.0], 5), "▁▂▃▄█");
+        assert_eq!(sparkline(&[1.0, 3.0, 2.0, 2.0, 6.0], 2), "▁█"); // means of 2 and 4, the last run is shorter
+        assert_eq!(bill.daily_chart_text(72).lines().nth(1), Some("Cost $   ▁▃█ 1.50 to 3.00"));
+        assert!(bill.monthly_text().ends_with("Total         7.000      0.000         3.50       0.00       3.00       0.00       6.50\n"));
+    }
+}
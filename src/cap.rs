@@ -0,0 +1,91 @@
+//! Bill caps and minimum charges: limits on the total of each billing period (e.g. "capped at $150 a month"), applied
+//! after all other charges, discounts and credits.
+
+use crate::energy::{open_input, CsvLine};
+use crate::money::{self, Money};
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use csv::ReaderBuilder;
+use log::{debug, info};
+
+/// A minimum charge, a maximum (cap), or both, on the total of each billing period.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BillCap {
+    pub name: String,
+    pub minimum: Option<f64>, // $ per period, None for no minimum
+    pub maximum: Option<f64>, // $ per period, None for no cap
+}
+
+/// The adjustment of a billing period's total by a bill cap.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapLine {
+    pub name: String,
+    pub start: NaiveDate, // first date of the billing period
+    pub end: NaiveDate,   // last date of the billing period
+    pub amount: f64,      // $, positive to raise the total to a minimum, negative to lower it to a cap
+}
+
+/// Load bill caps from a CSV file with columns: Name, Minimum $ and Maximum $ (either may be empty).
+pub fn load_bill_caps(csv: &str) -> Result<Vec<BillCap>> {
+    info!("load_bill_caps: loading CSV file {}", csv);
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(open_input(csv)?);
+
+    let headers = reader.headers()?.clone();
+    reader.records().enumerate()
+    .map(|(i, record)| -> Result<BillCap> {
+        let r = record?;
+        debug!("load_bill_caps: record: {:?}", r);
+        let line = CsvLine::new("load_bill_caps", csv, i, &headers, &r);
+        let cap = BillCap {
+            name:    line.get(0).to_string(),
+            minimum: line.optional(1).map(|_| line.parse::<f64>(1)).transpose()?,
+            maximum: line.optional(2).map(|_| line.parse::<f64>(2)).transpose()?,
+        };
+        match (cap.minimum, cap.maximum) {
+            (None, None) => Err(anyhow!("{}: neither a Minimum nor a Maximum", line.at_line())),
+            (Some(min), Some(max)) if min > max => Err(anyhow!("{}: the Minimum is more than the Maximum", line.at_line())),
+            _ => Ok(cap),
+        }
+    })
+    .collect()
+}
+
+/// The cap lines for billing periods, each its (first date, last date, total $): for each period the caps are applied
+/// in order, each raising the total so far to its minimum or lowering it to its maximum. Periods a cap doesn't change
+/// have no line for it.
+pub fn cap_lines(caps: &[BillCap], periods: &[(NaiveDate, NaiveDate, f64)]) -> Vec<CapLine> {
+    let mut lines = Vec::new();
+    for (start, end, total) in periods {
+        let mut total = *total;
+        for cap in caps {
+            let capped = cap.maximum.map_or(total, |max| total.min(max));
+            let capped = cap.minimum.map_or(capped, |min| capped.max(min));
+            let amount = (Money::from_f64(capped) - Money::from_f64(total)).to_f64();
+            if amount != 0.0 {
+                lines.push(CapLine { name: cap.name.clone(), start: *start, end: *end, amount });
+                total = money::add(total, amount);
+            }
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cap_lines() -> Result<()> {
+        let caps = load_bill_caps("data/test/tariff/billCapMonthly.csv")?;
+        assert_eq!(caps[0], BillCap { name: "Minimum charge".to_string(), minimum: Some(2.0), maximum: None });
+        let d = |m, d| NaiveDate::from_ymd_opt(2023, m, d).unwrap();
+        let lines = cap_lines(&caps, &[(d(7, 1), d(7, 31), 1.5), (d(8, 1), d(8, 31), 50.0), (d(9, 1), d(9, 30), 150.25)]);
+        assert_eq!(lines.iter().map(|l| (l.name.as_str(), l.start, l.amount)).collect::<Vec<_>>(), vec![
+            ("Minimum charge", d(7, 1), 0.5),
+            ("Bill cap", d(9, 1), -30.25),
+        ]);
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+//! Carbon emissions: the kgCO2e of consumption and the emissions avoided by feedin, from the grid's carbon intensity.
+//!
+//! The intensity (kgCO2e/kWh) is a constant, one for each month, or one for each interval (e.g. 30 minute marginal
+//! or average emissions factors), see [`CarbonIntensity::load`]. Emissions can also be costed at a shadow carbon
+//! price ($/tonne), shown alongside the bill rather than in its total.
+
+use crate::energy::{open_input, parse_timestamp};
+use crate::json::Json;
+use crate::pricing::Interval;
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use csv::ReaderBuilder;
+use log::{debug, info};
+use std::collections::BTreeMap;
+
+/// The carbon intensity (kgCO2e/kWh) of grid electricity.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CarbonIntensity {
+    Constant(f64),
+    Monthly(BTreeMap<NaiveDate, f64>), // by the first day of the month
+    Intervals { intensity: BTreeMap<NaiveDateTime, f64>, length: i64 }, // by the start of each interval, minutes per interval
+}
+
+impl CarbonIntensity {
+    /// A constant intensity (e.g. `0.68`), or a CSV file with the columns: the month (`YYYY-MM`) or timestamp of the
+    /// start of the interval (`YYYY-MM-DD HH:MM` as for wholesale prices, in the time of the energy data), and
+    /// kgCO2e/kWh. Intervals are as long as the shortest time between consecutive starts.
+    pub fn load(intensity: &str) -> Result<CarbonIntensity> {
+        if let Ok(x) = intensity.trim().parse::<f64>() {
+            return Ok(CarbonIntensity::Constant(x));
+        }
+        info!("CarbonIntensity::load: loading CSV file {}", intensity);
+        let mut reader = ReaderBuilder::new()
+            .has_headers(true)
+            .from_reader(open_input(intensity)?);
+        let (mut monthly, mut intervals) = (BTreeMap::new(), BTreeMap::new());
+        for record in reader.records() {
+            let r = record?;
+            debug!("CarbonIntensity::load: record: {:?}", r);
+            let kg = r.get(1).unwrap_or_default().trim().parse::<f64>()
+                .map_err(|e| anyhow!("CarbonIntensity::load: {}: kgCO2e/kWh '{}': {}", intensity, r.get(1).unwrap_or_default(), e))?;
+            match NaiveDate::parse_from_str(&format!("{}-01", r[0].trim()), "%Y-%m-%d") {
+                Ok(month) => monthly.insert(month, kg),
+                Err(_) => intervals.insert(parse_timestamp(&r[0])?, kg),
+            };
+        }
+        match (monthly.is_empty(), intervals.is_empty()) {
+            (false, true) => Ok(CarbonIntensity::Monthly(monthly)),
+            (true, false) => Ok(CarbonIntensity::Intervals {
+                length: intervals.keys().zip(intervals.keys().skip(1)).map(|(a, b)| (*b - *a).num_minutes()).min().unwrap_or(30),
+                intensity: intervals,
+            }),
+            (true, true) => Err(anyhow!("CarbonIntensity::load: {}: no intensities", intensity)),
+            (false, false) => Err(anyhow!("CarbonIntensity::load: {}: both months and intervals", intensity)),
+        }
+    }
+
+    /// The intensity at the start of an interval of energy, None if not given.
+    pub fn at(&self, i: &Interval) -> Option<f64> {
+        match self {
+            CarbonIntensity::Constant(x) => Some(*x),
+            CarbonIntensity::Monthly(months) => months.get(&i.date.with_day(1)?).copied(),
+            CarbonIntensity::Intervals { intensity, length } => {
+                let start = i.date.and_hms_opt(0, 0, 0)? + Duration::minutes(i.min_since_midnight as i64);
+                intensity.range(..=start).next_back()
+                    .filter(|(t, _)| start < **t + Duration::minutes(*length))
+                    .map(|(_, x)| *x)
+            },
+        }
+    }
+}
+
+/// The grid's carbon intensity with an optional shadow carbon price.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Carbon {
+    pub intensity: CarbonIntensity,
+    pub price: Option<f64>, // $/tonne CO2e
+}
+
+/// The emissions of the energy priced.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Emissions {
+    pub consumption: f64,   // kgCO2e from consumption
+    pub avoided: f64,       // kgCO2e avoided by feedin
+    pub missing: usize,     // intervals without an intensity, not counted
+    pub price: Option<f64>, // shadow carbon price $/tonne CO2e
+}
+
+impl Emissions {
+    /// Add the emissions of an interval of consumption or (`feedin`) the emissions it avoids.
+    pub fn observe(&mut self, carbon: &Carbon, i: &Interval, feedin: bool) {
+        match (carbon.intensity.at(i), feedin) {
+            (Some(x), false) => self.consumption += i.energy * x,
+            (Some(x), true) => self.avoided += i.energy * x,
+            (None, _) => self.missing += 1,
+        }
+    }
+
+    /// Add the emissions of other days (e.g. priced on another thread).
+    pub fn merge(&mut self, other: &Emissions) {
+        self.consumption += other.consumption;
+        self.avoided += other.avoided;
+        self.missing += other.missing;
+    }
+
+    /// kgCO2e of consumption less those avoided by feedin.
+    pub fn net(&self) -> f64 {
+        self.consumption - self.avoided
+    }
+
+    /// The $ of the net emissions at the shadow carbon price, None without one.
+    pub fn cost(&self) -> Option<f64> {
+        self.price.map(|p| self.net() / 1000.0 * p)
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("consumption_kg", self.consumption.into()),
+            ("avoided_kg", self.avoided.into()),
+            ("net_kg", self.net().into()),
+            ("missing", self.missing.into()),
+            ("carbon_price", self.price.map_or(Json::Null, Json::from)),
+            ("carbon_cost", self.cost().map_or(Json::Null, Json::from)),
+        ])
+    }
+
+    /// The emissions accumulated by an earlier run (see [`Emissions::to_json`]), for a later run to continue from.
+    pub fn from_json(json: &Json, price: Option<f64>) -> Result<Emissions> {
+        Ok(Emissions {
+            consumption: json.number("consumption_kg")?,
+            avoided: json.number("avoided_kg")?,
+            missing: json.number("missing")? as usize,
+            price,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_float_eq::*;
+
+    #[test]
+    fn test_carbon_intensity() -> Result<()> {
+        let interval = |d, min_since_midnight| Interval {
+            date: NaiveDate::from_ymd_opt(2023, 8, d).unwrap(), dow: 0, holiday: false, min_since_midnight, length: 480,
+            energy: 2.0, usage_day: 0.0, usage_period: 0.0,
+        };
+        assert_eq!(CarbonIntensity::load("0.68")?.at(&interval(6, 0)), Some(0.68));
+        let monthly = CarbonIntensity::load("data/test/carbonMonthly.csv")?;
+        assert_eq!((monthly.at(&interval(6, 0)), monthly.at(&interval(31, 960))), (Some(0.7), Some(0.7)));
+        let intervals = CarbonIntensity::load("data/test/carbon.csv")?;
+        assert!(matches!(intervals, CarbonIntensity::Intervals { length: 480, .. }));
+        assert_eq!((intervals.at(&interval(6, 480)), intervals.at(&interval(9, 0))), (Some(0.2), None));
+
+        let carbon = Carbon { intensity: intervals, price: Some(50.0) };
+        let mut emissions = Emissions { price: carbon.price, ..Emissions::default() };
+        emissions.observe(&carbon, &interval(6, 0), false);
+        emissions.observe(&carbon, &interval(6, 480), true);
+        emissions.observe(&carbon, &interval(9, 0), false);
+        assert_f64_near!(emissions.consumption, 2.0 * 0.8);
+        assert_f64_near!(emissions.avoided, 2.0 * 0.2);
+        assert_eq!(emissions.missing, 1);
+        assert_f64_near!(emissions.cost().unwrap(), 1.2 / 1000.0 * 50.0);
+        assert_eq!(Emissions::from_json(&emissions.to_json(), carbon.price)?, emissions);
+        Ok(())
+    }
+}
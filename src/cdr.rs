@@ -0,0 +1,333 @@
+//! Loader for Consumer Data Right (CDR) energy plans, the JSON published by Australian retailers
+//! (`GET /energy/plans/{planId}`, as behind Energy Made Easy), so published plans can be priced
+//! without building tariff CSV files by hand.
+//!
+//! From the plan's `electricityContract`:
+//! - `tariffPeriod`: the `singleRate` or `timeOfUseRates` usage rates, the `dailySupplyCharges` and any
+//!   `demandCharges` (in kW or, with `measureUnit` `KVA`, kVA). Tariff periods with `startDate` and `endDate` (`MM-DD`, inclusive) become seasonal rows.
+//!   Stepped `rates` with a `volume` (the kWh in the step) become usage blocks, per day if the `period` is
+//!   `P1D` and per billing period otherwise
+//! - `solarFeedInTariff`: the retailer's (scheme `OTHER`) `singleTariff` or `timeVaryingTariffs`, or the first
+//!   tariff if there is none
+//! - `discounts`: `percentOfBill` and `percentOfUse` discounts, conditional on their category (e.g. pay on time)
+//!   if their type is `CONDITIONAL`
+//!
+//! CDR prices exclude GST, so usage, supply and demand rates have [`GST`] added to be like the CSV files;
+//! feed-in credits don't attract GST. Days of the week given as `PUBLIC_HOLIDAYS` are ignored since public
+//! holidays are priced as Sundays. Fees, incentives, controlled load and other discount methods are ignored.
+
+use crate::demand::{DemandPer, DemandTariff, DemandUnit};
+use crate::discount::{Discount, DiscountOn};
+use crate::energy::open_input;
+use crate::json::{parse_json, Json};
+use crate::plan::Plan;
+use crate::pricing::{check_tariff_coverage, Block, BlockPer, DateBound, DateRange, Tariff, TouPeriod};
+use anyhow::{anyhow, Context, Result};
+use chrono::{Datelike, NaiveDate};
+use log::{info, warn};
+use std::io::Read;
+
+/// The GST rate added to CDR usage, supply and demand prices.
+pub const GST: f64 = 0.1;
+
+fn inc_gst(ex: f64) -> f64 {
+    (ex * (1.0 + GST) * 1e9).round() / 1e9 // to the nano-dollar, without the float error of the product
+}
+
+fn number(json: &Json, key: &str) -> Result<f64> {
+    json.get(key).and_then(Json::as_f64).ok_or_else(|| anyhow!("no number {}", key))
+}
+
+fn array<'a>(json: &'a Json, key: &str) -> &'a [Json] {
+    json.get(key).and_then(Json::as_array).unwrap_or_default()
+}
+
+// Title case of an enum value such as OFF_PEAK, for a rate without a display name
+fn title(s: &str) -> String {
+    let s = s.to_lowercase().replace('_', "-");
+    s[..1.min(s.len())].to_uppercase() + &s[1.min(s.len())..]
+}
+
+// Minutes since midnight of HH:MM, HHMM or HH:MM:SS
+fn minutes(time: &str) -> Result<i32> {
+    let digits = time.replace(':', "");
+    match (digits.len(), digits.get(0..2).and_then(|h| h.parse::<i32>().ok()), digits.get(2..4).and_then(|m| m.parse::<i32>().ok())) {
+        (4 | 6, Some(h), Some(m)) if h <= 24 && m < 60 => Ok(h * 60 + m),
+        _ => Err(anyhow!("minutes: '{}' is not a time HH:MM", time)),
+    }
+}
+
+// Time ranges (min since midnight, end exclusive) from a start and end time, split at midnight if it wraps.
+// An end of 00:00 or 23:59 is the end of the day.
+fn time_ranges(json: &Json) -> Result<Vec<(i32, i32)>> {
+    let start = json.get("startTime").and_then(Json::as_str).map(minutes).transpose()?.unwrap_or(0);
+    let end = match json.get("endTime").and_then(Json::as_str).map(minutes).transpose()? {
+        None | Some(0) | Some(1439) => 1440,
+        Some(end) => end,
+    };
+    Ok(match end > start {
+        true => vec![(start, end)],
+        false => vec![(start, 1440), (0, end)].into_iter().filter(|(s, e)| s < e).collect(),
+    })
+}
+
+// Runs of consecutive days of the week (0 for Monday, end exclusive) from day names, all days if there are none
+fn day_ranges(days: &[Json]) -> Result<Vec<(i16, i16)>> {
+    let mut on = [days.is_empty(); 7];
+    for day in days {
+        let day = day.as_str().context("day_ranges: day is not a string")?;
+        let range = match day {
+            "MON" => 0..1,
+            "TUE" => 1..2,
+            "WED" => 2..3,
+            "THU" => 3..4,
+            "FRI" => 4..5,
+            "SAT" => 5..6,
+            "SUN" => 6..7,
+            "BUSINESS_DAYS" => 0..5,
+            "WEEKENDS" => 5..7,
+            "PUBLIC_HOLIDAYS" => 0..0, // priced as Sundays
+            d => return Err(anyhow!("day_ranges: unknown day '{}'", d)),
+        };
+        range.for_each(|d| on[d] = true);
+    }
+    let mut ranges: Vec<(i16, i16)> = Vec::new();
+    for d in (0..7).filter(|d| on[*d as usize]) {
+        match ranges.last_mut() {
+            Some((_, end)) if *end == d => *end = d + 1,
+            _ => ranges.push((d, d + 1)),
+        }
+    }
+    Ok(ranges)
+}
+
+// The days and times of a time of use entry (days, startTime, endTime) as (day_start, day_end, time_start, time_end)
+fn windows(json: &Json) -> Result<Vec<(i16, i16, i32, i32)>> {
+    let days = day_ranges(array(json, "days"))?;
+    let times = time_ranges(json)?;
+    Ok(days.iter().flat_map(|(d0, d1)| times.iter().map(move |(t0, t1)| (*d0, *d1, *t0, *t1))).collect())
+}
+
+// The rates of a rate block as ($/kWh, usage block) with `sign` (-1 for feed-in credits), a step per rate with a volume
+fn rate_steps(json: &Json, sign: f64, gst: bool) -> Result<Vec<(f64, Option<Block>)>> {
+    let price = |rate: &Json, key: &str| number(rate, key).map(|p| sign * if gst { inc_gst(p) } else { p });
+    let rates = array(json, "rates");
+    if rates.is_empty() { // CDR v1 feed-in tariffs have an amount
+        return Ok(vec![(price(json, "amount")?, None)]);
+    }
+    let per = match json.get("period").and_then(Json::as_str) {
+        Some("P1D") => BlockPer::Day,
+        _ => BlockPer::Period,
+    };
+    let mut start = 0.0;
+    rates.iter().enumerate().map(|(i, rate)| {
+        let volume = rate.get("volume").and_then(Json::as_f64).filter(|_| i + 1 < rates.len());
+        let block = match (rates.len(), volume) {
+            (1, _) => None,
+            (_, Some(v)) => Some(Block { start, end: start + v, per }),
+            (_, None) => Some(Block { start, end: f64::INFINITY, per }),
+        };
+        start += volume.unwrap_or(0.0);
+        Ok((price(rate, "unitPrice")?, block))
+    }).collect()
+}
+
+// Tariff rows for each window and rate step, named with the step if there are several
+fn tariff_rows(name: &str, period: Option<TouPeriod>, windows: &[(i16, i16, i32, i32)], steps: &[(f64, Option<Block>)], dates: Option<DateRange>) -> Vec<Tariff> {
+    windows.iter().flat_map(|(d0, d1, t0, t1)| steps.iter().enumerate().map(move |(i, (rate, block))| Tariff {
+        day_start: *d0,
+        day_end: *d1,
+        day_class: None,
+        time_start: *t0,
+        time_end: *t1,
+        tariff: *rate,
+        name: if steps.len() > 1 { format!("{} step {}", name, i + 1) } else { name.to_string() },
+        block: *block,
+        dates,
+        effective: None,
+        condition: None,
+        period,
+    })).collect()
+}
+
+// The dates of a tariff period from its startDate and endDate (MM-DD, inclusive), None for the whole year
+fn period_dates(period: &Json) -> Result<Option<DateRange>> {
+    let month_day = |key: &str| period.get(key).and_then(Json::as_str).map(|d| {
+        NaiveDate::parse_from_str(&format!("2024-{}", d), "%Y-%m-%d").map_err(|e| anyhow!("period_dates: {} '{}': {}", key, d, e))
+    }).transpose();
+    match (month_day("startDate")?, month_day("endDate")?.map(|d| d.succ_opt().unwrap_or(d))) {
+        (None, None) => Ok(None),
+        (start, end) if start.map(|s| (s.month(), s.day())) == end.map(|e| (e.month(), e.day())) => Ok(None), // the whole year
+        (start, end) => Ok(Some(DateRange {
+            start: start.map(|s| DateBound::MonthDay(s.month(), s.day())),
+            end: end.map(|e| DateBound::MonthDay(e.month(), e.day())),
+        })),
+    }
+}
+
+fn check_coverage(tariff: &[Tariff], what: &str) -> Result<()> {
+    match check_tariff_coverage(tariff).into_iter().filter(|p| p.starts_with("no tariff")).collect::<Vec<_>>()[..] {
+        [] => Ok(()),
+        ref gaps => Err(anyhow!("{} tariff has gaps: {}", what, gaps.join("; "))),
+    }
+}
+
+/// Convert a CDR energy plan (the response with its `data` or just the plan) into a [`Plan`],
+/// see the module documentation.
+pub fn cdr_plan(json: &Json) -> Result<Plan> {
+    let plan = json.get("data").unwrap_or(json);
+    let name = plan.get("displayName").or(plan.get("planId")).and_then(Json::as_str).unwrap_or("CDR plan").to_string();
+    let contract = plan.get("electricityContract").ok_or_else(|| anyhow!("cdr_plan: {} has no electricityContract", name))?;
+
+    let mut consumption_tariff = Vec::new();
+    let mut demand_tariff = Vec::new();
+    let mut supply = Vec::new();
+    for period in array(contract, "tariffPeriod") {
+        let dates = period_dates(period)?;
+        if let Some(daily) = period.get("dailySupplyCharges").or(period.get("dailySupplyCharge")).and_then(Json::as_f64) {
+            supply.push(inc_gst(daily));
+        }
+        if let Some(single) = period.get("singleRate") {
+            let name = single.get("displayName").and_then(Json::as_str).unwrap_or("Usage");
+            consumption_tariff.extend(tariff_rows(name, None, &[(0, 7, 0, 1440)], &rate_steps(single, 1.0, true)?, dates));
+        }
+        for tou in array(period, "timeOfUseRates") {
+            let name = tou.get("displayName").and_then(Json::as_str).map(str::to_string)
+                .or(tou.get("type").and_then(Json::as_str).map(title)).unwrap_or("Usage".to_string());
+            let period = tou.get("type").and_then(Json::as_str).and_then(TouPeriod::from_name); // e.g. OFF_PEAK, SHOULDER1
+            let windows = array(tou, "timeOfUse").iter().map(windows).collect::<Result<Vec<_>>>()?.concat();
+            consumption_tariff.extend(tariff_rows(&name, period, &windows, &rate_steps(tou, 1.0, true)?, dates));
+        }
+        for demand in array(period, "demandCharges") {
+            let name = demand.get("displayName").and_then(Json::as_str).unwrap_or("Demand");
+            let rate = inc_gst(number(demand, "amount")?);
+            let per = match demand.get("chargePeriod").and_then(Json::as_str) {
+                Some("DAY") => DemandPer::Day,
+                _ => DemandPer::Month,
+            };
+            let unit = match demand.get("measureUnit").and_then(Json::as_str) {
+                Some("KVA") => DemandUnit::Kva,
+                _ => DemandUnit::Kw,
+            };
+            demand_tariff.extend(windows(demand)?.into_iter().map(|(d0, d1, t0, t1)| DemandTariff {
+                day_start: d0, day_end: d1, time_start: t0, time_end: t1, rate, name: name.to_string(), per, months: 1, unit,
+            }));
+        }
+    }
+    if consumption_tariff.is_empty() {
+        return Err(anyhow!("cdr_plan: {} has no singleRate or timeOfUseRates usage rates", name));
+    }
+    check_coverage(&consumption_tariff, "cdr_plan: consumption")?;
+    let daily_supply = *supply.first().ok_or_else(|| anyhow!("cdr_plan: {} has no dailySupplyCharges", name))?;
+    if supply.iter().any(|s| *s != daily_supply) {
+        warn!("cdr_plan: {} has different daily supply charges in its tariff periods {:?}, using the first", name, supply);
+    }
+
+    let feedins = array(contract, "solarFeedInTariff");
+    let feedin_tariff = match feedins.iter().find(|f| f.get("scheme").and_then(Json::as_str) == Some("OTHER")).or(feedins.first()) {
+        None => None,
+        Some(feedin) => {
+            let name = feedin.get("displayName").and_then(Json::as_str).unwrap_or("Feed-in");
+            let mut tariff = Vec::new();
+            if let Some(single) = feedin.get("singleTariff") {
+                tariff.extend(tariff_rows(name, None, &[(0, 7, 0, 1440)], &rate_steps(single, -1.0, false)?, None));
+            }
+            let varying = match feedin.get("timeVaryingTariffs") {
+                Some(Json::Array(xs)) => xs.clone(),
+                Some(x) => vec![x.clone()], // CDR v1 has a single object
+                None => Vec::new(),
+            };
+            for tariff_json in &varying {
+                let name = tariff_json.get("displayName").and_then(Json::as_str).map(str::to_string)
+                    .or(tariff_json.get("type").and_then(Json::as_str).map(|t| format!("{} {}", name, title(t)))).unwrap_or(name.to_string());
+                let period = tariff_json.get("type").and_then(Json::as_str).and_then(TouPeriod::from_name);
+                let windows = array(tariff_json, "timeVariations").iter().map(windows).collect::<Result<Vec<_>>>()?.concat();
+                tariff.extend(tariff_rows(&name, period, &windows, &rate_steps(tariff_json, -1.0, false)?, None));
+            }
+            check_coverage(&tariff, "cdr_plan: feed-in")?;
+            Some(tariff)
+        },
+    };
+
+    let mut discounts = Vec::new();
+    for discount in array(contract, "discounts") {
+        let name = discount.get("displayName").and_then(Json::as_str).unwrap_or("Discount").to_string();
+        let method = discount.get("methodUType").and_then(Json::as_str).unwrap_or("");
+        let on = match method {
+            "percentOfBill" => DiscountOn::Bill,
+            "percentOfUse" => DiscountOn::Usage,
+            m => {
+                warn!("cdr_plan: ignoring discount {} with method {}", name, m);
+                continue;
+            },
+        };
+        let percent = 100.0 * discount.get(method).map(|m| number(m, "rate")).transpose()?.context("cdr_plan: discount has no rate")?;
+        let condition = match discount.get("type").and_then(Json::as_str) {
+            Some("CONDITIONAL") => Some(discount.get("category").and_then(Json::as_str).map(|c| c.to_lowercase().replace('_', " ")).unwrap_or(name.to_lowercase())),
+            _ => None,
+        };
+        discounts.push(Discount { name, percent, on, condition });
+    }
+
+    info!("cdr_plan: {} with {} consumption, {} feed-in and {} demand rows, {} discounts", name, consumption_tariff.len(),
+        feedin_tariff.as_ref().map_or(0, Vec::len), demand_tariff.len(), discounts.len());
+    Ok(Plan { name, consumption_tariff, feedin_tariff, daily_supply, supply_changes: Vec::new(), demand_tariff, discounts, feedin_cap: None, wholesale: None, power_factor: None, billing: None, policies: Vec::new(), events: Vec::new(), fees: Vec::new(), concessions: Vec::new(), rebates: Vec::new(), caps: Vec::new(), carbon: None })
+}
+
+/// Load a CDR energy plan JSON file (or standard input for [`crate::energy::STDIN`]), see [`cdr_plan`].
+pub fn load_cdr_plan(path: &str) -> Result<Plan> {
+    info!("load_cdr_plan: loading JSON file {}", path);
+    let mut s = String::new();
+    open_input(path)?.read_to_string(&mut s)?;
+    parse_json(&s).and_then(|json| cdr_plan(&json)).with_context(|| format!("load_cdr_plan: {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::energy::read_csv_energy;
+    use crate::plan::price_plan;
+    use crate::pricing::load_public_holidays;
+
+    #[test]
+    fn test_cdr_plan() -> Result<()> {
+        // the rates of data/test/tariff/consumption.csv ex GST, as a CDR plan
+        let cdr = load_cdr_plan("data/test/tariff/cdrPlan.json")?;
+        assert_eq!(cdr.name, "Test TOU");
+        assert_eq!(cdr.daily_supply, 1.45398);
+        assert_eq!(cdr.consumption_tariff.iter().map(|t| (t.day_start, t.day_end, t.time_start, t.time_end, t.tariff)).collect::<Vec<_>>(), vec![
+            (0, 5, 7 * 60, 9 * 60, 0.41745), (0, 5, 17 * 60, 20 * 60, 0.41745),
+            (0, 5, 9 * 60, 17 * 60, 0.35695), (0, 5, 20 * 60, 22 * 60, 0.35695),
+            (0, 5, 22 * 60, 1440, 0.26928), (0, 5, 0, 7 * 60, 0.26928), (5, 7, 0, 1440, 0.26928),
+        ]);
+        assert_eq!(cdr.consumption_tariff.iter().map(|t| t.period).collect::<Vec<_>>(),
+            [TouPeriod::Peak, TouPeriod::Peak, TouPeriod::Shoulder, TouPeriod::Shoulder, TouPeriod::OffPeak, TouPeriod::OffPeak, TouPeriod::OffPeak].map(Some));
+        assert_eq!(cdr.discounts, vec![Discount { name: "Pay on time".into(), percent: 5.0, on: DiscountOn::Bill, condition: Some("pay on time".into()) }]);
+        assert_eq!(cdr.demand_tariff.len(), 1);
+
+        let holidays = load_public_holidays("data/test/publicHolidaysTest.csv")?;
+        let csv = Plan::load("csv", "data/test/tariff/consumption.csv", Some("data/test/tariff/feedIn.csv"), "data/test/tariff/supply.csv", None)?;
+        let cdr = Plan { demand_tariff: Vec::new(), discounts: Vec::new(), ..cdr };
+        let price = |plan: &Plan| -> Result<f64> {
+            let bill = price_plan(plan, read_csv_energy("data/test/energy/consumption.csv")?, Some(read_csv_energy("data/test/energy/feedIn.csv")?), &holidays)?;
+            Ok(bill.total())
+        };
+        assert_eq!(price(&cdr)?, price(&csv)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cdr_parts() -> Result<()> {
+        assert_eq!(time_ranges(&parse_json(r#"{"startTime": "22:00", "endTime": "07:00"}"#)?)?, vec![(22 * 60, 1440), (0, 7 * 60)]);
+        assert_eq!(day_ranges(parse_json(r#"["SUN", "MON", "TUE", "PUBLIC_HOLIDAYS"]"#)?.as_array().unwrap_or_default())?, vec![(0, 2), (6, 7)]);
+        let summer = period_dates(&parse_json(r#"{"startDate": "12-01", "endDate": "02-29"}"#)?)?;
+        assert_eq!(summer, Some(DateRange { start: Some(DateBound::MonthDay(12, 1)), end: Some(DateBound::MonthDay(3, 1)) }));
+        assert_eq!(period_dates(&parse_json(r#"{"startDate": "01-01", "endDate": "12-31"}"#)?)?, None);
+        let steps = rate_steps(&parse_json(r#"{"period": "P1D", "rates": [{"unitPrice": "0.2", "volume": 10}, {"unitPrice": "0.3"}]}"#)?, 1.0, true)?;
+        assert_eq!(steps, vec![
+            (0.22, Some(Block { start: 0.0, end: 10.0, per: BlockPer::Day })),
+            (0.33, Some(Block { start: 10.0, end: f64::INFINITY, per: BlockPer::Day })),
+        ]);
+        Ok(())
+    }
+}
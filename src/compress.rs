@@ -0,0 +1,139 @@
+//! Reading gzip and zip compressed files.
+//!
+//! Retailer exports often arrive as `.zip` or `.csv.gz`, read with the flate2 and zip crates. [`snappy`] decodes the
+//! raw Snappy blocks used inside Parquet files.
+
+use anyhow::{anyhow, Context, Result};
+use flate2::read::MultiGzDecoder;
+use std::io::{Cursor, Read};
+use zip::ZipArchive;
+
+/// The kind of compression, detected from the first bytes of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zip,
+}
+
+impl Compression {
+    /// Detect gzip or zip from their magic bytes.
+    pub fn detect(bytes: &[u8]) -> Compression {
+        match bytes {
+            [0x1f, 0x8b, ..] => Compression::Gzip,
+            [b'P', b'K', 3, 4, ..] => Compression::Zip,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// Decompress gzip or zip data (the first file in the archive), or return other data unchanged.
+pub fn decompress(data: Vec<u8>) -> Result<Vec<u8>> {
+    match Compression::detect(&data) {
+        Compression::None => Ok(data),
+        Compression::Gzip => gunzip(&data),
+        Compression::Zip => {
+            let first = zip_names(&data)?.into_iter().find(|n| !n.ends_with('/')).ok_or_else(|| anyhow!("decompress: zip file has no files"))?;
+            zip_file(&data, &first)?.ok_or_else(|| anyhow!("decompress: zip file has no {}", first))
+        },
+    }
+}
+
+/// Decompress gzip data (every member of it), checking its CRC.
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    MultiGzDecoder::new(data).read_to_end(&mut out).context("gunzip")?;
+    Ok(out)
+}
+
+// Little endian integers at an offset, failing if the data is too short
+fn le(data: &[u8], at: usize, len: usize) -> Result<usize> {
+    let bytes = data.get(at..at + len).ok_or_else(|| anyhow!("le: truncated data reading {} bytes at {}", len, at))?;
+    Ok(bytes.iter().rev().fold(0, |n, b| (n << 8) | *b as usize))
+}
+
+/// Decompress a raw Snappy block (no framing), as used for Parquet pages.
+pub fn snappy(data: &[u8]) -> Result<Vec<u8>> {
+    let (mut len, mut at) = (0, 0);
+    for shift in (0..35).step_by(7) {
+        let b = le(data, at, 1)?;
+        (len, at) = (len | (b & 0x7f) << shift, at + 1);
+        if b & 0x80 == 0 {
+            break;
+        }
+    }
+    // a copy of up to 64 bytes takes 3, so don't trust the header for more than that expansion
+    let mut out = Vec::with_capacity(len.min(data.len().saturating_mul(22)));
+    while at < data.len() {
+        let tag = data[at] as usize;
+        let (length, offset, size) = match tag & 3 {
+            0 => { // literal, lengths over 60 follow in 1 to 4 bytes
+                let (length, size) = if tag >> 2 < 60 { (tag >> 2, 1) } else { (le(data, at + 1, (tag >> 2) - 59)?, (tag >> 2) - 58) };
+                let literal = data.get(at + size..at + size + length + 1).ok_or_else(|| anyhow!("snappy: truncated literal at {}", at))?;
+                out.extend_from_slice(literal);
+                at += size + length + 1;
+                continue;
+            },
+            1 => (((tag >> 2) & 7) + 4, (tag >> 5) << 8 | le(data, at + 1, 1)?, 2),
+            2 => ((tag >> 2) + 1, le(data, at + 1, 2)?, 3),
+            _ => ((tag >> 2) + 1, le(data, at + 1, 4)?, 5),
+        };
+        if offset == 0 || offset > out.len() {
+            return Err(anyhow!("snappy: copy offset {} at {} is outside the {} bytes output", offset, at, out.len()));
+        }
+        for _ in 0..length { // may overlap
+            out.push(out[out.len() - offset]);
+        }
+        at += size;
+    }
+    if out.len() != len {
+        return Err(anyhow!("snappy: {} bytes output but the header says {}", out.len(), len));
+    }
+    Ok(out)
+}
+
+/// The names of the files in a zip archive, in the order of its central directory.
+pub fn zip_names(data: &[u8]) -> Result<Vec<String>> {
+    let archive = ZipArchive::new(Cursor::new(data)).context("zip_names")?;
+    Ok(archive.file_names().map(str::to_string).collect())
+}
+
+/// Decompress a file in a zip archive, checking its CRC, or None if there's no such file.
+pub fn zip_file(data: &[u8], name: &str) -> Result<Option<Vec<u8>>> {
+    let mut archive = ZipArchive::new(Cursor::new(data)).context("zip_file")?;
+    let mut file = match archive.by_name(name) {
+        Ok(file) => file,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(anyhow!("zip_file: {}: {}", name, e)),
+    };
+    let mut out = Vec::new();
+    file.read_to_end(&mut out).with_context(|| format!("zip_file: {}", name))?;
+    Ok(Some(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decompress() -> Result<()> {
+        for (compressed, plain) in [("data/test/publicHolidaysNSW.csv.gz", "data/NB/publicHolidaysNSW.csv"), ("data/test/energy/consumption.zip", "data/test/energy/consumption.csv")] {
+            assert_eq!(decompress(std::fs::read(compressed)?)?, std::fs::read(plain)?, "{}", compressed);
+        }
+        assert_eq!(decompress(b"Date".to_vec())?, b"Date");
+        let zip = std::fs::read("data/test/energy/consumption.zip")?;
+        assert_eq!(zip_file(&zip, "missing.csv")?, None);
+        // truncated, and with a corrupted byte
+        let gz = std::fs::read("data/test/publicHolidaysNSW.csv.gz")?;
+        assert!(gunzip(&gz[..gz.len() / 2]).is_err());
+        let mut corrupt = gz.clone();
+        let at = corrupt.len() - 12;
+        corrupt[at] ^= 0xff;
+        assert!(gunzip(&corrupt).is_err());
+        assert!(decompress(zip[..zip.len() - 30].to_vec()).is_err());
+        // a literal then an overlapping 1 byte offset copy and a 2 byte offset copy
+        assert_eq!(snappy(&[0x0a, 0x08, b'h', b'i', b'a', 0x01, 0x01, 0x0a, 0x07, 0x00])?, b"hiaaaaahia");
+        assert!(snappy(&[0xff, 0xff, 0xff, 0xff, 0x7f]).is_err()); // a 32 GiB length, not allocated up front
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+//! Government concessions and rebates: percentage concessions on usage, supply or the whole bill (optionally for some
+//! months of the year and capped per billing period, as for pensioner and low income concessions) and one-off
+//! rebates credited in the billing period of their date.
+
+use crate::discount::DiscountOn;
+use crate::energy::{open_input, parse_date, CsvLine};
+use crate::money::Money;
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, NaiveDate};
+use csv::ReaderBuilder;
+use log::{debug, info};
+use std::str::FromStr;
+
+/// A percentage concession.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Concession {
+    pub name: String,
+    pub percent: f64,
+    pub on: DiscountOn,
+    pub cap: Option<f64>,           // $ per billing period, None for no cap
+    pub months: Option<(u32, u32)>, // first and last months (1 to 12) it applies in, None for all year
+}
+
+impl Concession {
+    /// Whether the concession applies to a date: its month is in the season, which may wrap around the new year.
+    pub fn in_season(&self, date: NaiveDate) -> bool {
+        match self.months {
+            Some((first, last)) if first <= last => (first..=last).contains(&date.month()),
+            Some((first, last)) => date.month() >= first || date.month() <= last,
+            None => true,
+        }
+    }
+
+    /// The concession ($, negative) on charges of `base` $ in a billing period, at most the cap.
+    pub fn credit(&self, base: f64) -> f64 {
+        let credit = base * self.percent / 100.0;
+        -Money::from_f64(self.cap.map_or(credit, |cap| credit.min(cap))).to_f64()
+    }
+}
+
+/// A one-off rebate, credited in the billing period containing its date.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rebate {
+    pub name: String,
+    pub date: NaiveDate,
+    pub amount: f64, // $ credited
+}
+
+/// A concession or rebate applied to a billing period.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConcessionLine {
+    pub name: String,
+    pub start: NaiveDate, // first date of the billing period
+    pub end: NaiveDate,   // last date of the billing period
+    pub amount: f64,      // $, negative
+}
+
+/// Load concessions from a CSV file with columns: Name, Percent, Applies To (`usage`, `supply` or `bill`), and
+/// optionally Cap $ (per billing period), Month Start and Month End (1 to 12, inclusive, empty for all year).
+pub fn load_concessions(csv: &str) -> Result<Vec<Concession>> {
+    info!("load_concessions: loading CSV file {}", csv);
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(open_input(csv)?);
+
+    let headers = reader.headers()?.clone();
+    reader.records().enumerate()
+    .map(|(i, record)| -> Result<Concession> {
+        let r = record?;
+        debug!("load_concessions: record: {:?}", r);
+        let line = CsvLine::new("load_concessions", csv, i, &headers, &r);
+        let month = |column| line.optional(column).map(|_| line.parse::<u32>(column)).transpose();
+        let months = match (month(4)?, month(5)?) {
+            (None, None) => None,
+            (Some(first), Some(last)) if (1..=12).contains(&first) && (1..=12).contains(&last) => Some((first, last)),
+            _ => return Err(anyhow!("{}: Month Start and Month End must both be 1 to 12 or both empty", line.at_line())),
+        };
+        Ok(Concession {
+            name:    line.get(0).to_string(),
+            percent: line.parse::<f64>(1)?,
+            on:      line.parse_with(2, DiscountOn::from_str)?,
+            cap:     line.optional(3).map(|_| line.parse::<f64>(3)).transpose()?,
+            months,
+        })
+    })
+    .collect()
+}
+
+/// Load rebates from a CSV file with columns: Name, Date (`YYYYMMDD` or `YYYY-MM-DD`) and $ (the amount credited).
+pub fn load_rebates(csv: &str) -> Result<Vec<Rebate>> {
+    info!("load_rebates: loading CSV file {}", csv);
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(open_input(csv)?);
+
+    let headers = reader.headers()?.clone();
+    reader.records().enumerate()
+    .map(|(i, record)| -> Result<Rebate> {
+        let r = record?;
+        debug!("load_rebates: record: {:?}", r);
+        let line = CsvLine::new("load_rebates", csv, i, &headers, &r);
+        Ok(Rebate {
+            name:   line.get(0).to_string(),
+            date:   line.parse_with(1, parse_date)?,
+            amount: line.parse::<f64>(2)?,
+        })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concessions() -> Result<()> {
+        let concessions = load_concessions("data/test/tariff/pensionerConcessions.csv")?;
+        let d = |m| NaiveDate::from_ymd_opt(2023, m, 15).unwrap();
+        let winter = &concessions[1];
+        assert_eq!((winter.on, winter.months), (DiscountOn::Bill, Some((5, 10))));
+        assert_eq!((winter.in_season(d(4)), winter.in_season(d(5)), winter.in_season(d(11))), (false, true, false));
+        let summer = Concession { months: Some((11, 2)), ..winter.clone() };
+        assert_eq!((summer.in_season(d(1)), summer.in_season(d(6)), summer.in_season(d(12))), (true, false, true));
+        assert_eq!((concessions[0].credit(100.0), concessions[0].credit(300.0)), (-17.5, -50.0));
+
+        let rebates = load_rebates("data/test/tariff/billRelief.csv")?;
+        assert_eq!(rebates, vec![Rebate { name: "Energy bill relief".to_string(), date: NaiveDate::from_ymd_opt(2023, 8, 7).unwrap(), amount: 75.0 }]);
+        Ok(())
+    }
+}
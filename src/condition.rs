@@ -0,0 +1,440 @@
+//! Conditions on tariff rows that don't fit their day, time and date columns: a small expression language evaluated
+//! for each interval, e.g. `usage_period > 500` for a rate once the billing period's usage exceeds 500 kWh, or
+//! `weekend && day <= 8` for the first weekend of the month.
+//!
+//! Variables, of the interval being priced:
+//! - `year`, `month` (1 to 12), `day` (of the month), `weekday` (0 for Monday, of the date even on a public holiday)
+//!   and `week` (of the month, 1 for days 1 to 7)
+//! - `time` (minutes since midnight at its start, compared with times like `17:30`) and `hour` (0 to 23)
+//! - `season`: `"summer"` (December to February), `"autumn"`, `"winter"` or `"spring"`
+//! - `holiday` (a public holiday) and `weekend` (Saturday, Sunday or a public holiday)
+//! - `kwh` (the interval's energy), `usage_day` and `usage_period` (kWh before the interval in the day and in the
+//!   billing period, which is the month with monthly billing)
+//!
+//! Operators, loosest first: `||` (or `or`), `&&` (`and`), `!` (`not`), comparisons `==`, `!=`, `<`, `<=`, `>`, `>=`,
+//! then `+`, `-`, `*`, `/`, `%` and unary `-`, with parentheses to group. Literals are numbers, times `HH:MM`, strings
+//! in double quotes, `true` and `false`. A condition is type checked when it's parsed and must be true or false.
+
+use crate::pricing::Interval;
+use anyhow::{anyhow, Result};
+use chrono::Datelike;
+use std::fmt;
+use std::str::FromStr;
+
+const SEASONS: [&str; 4] = ["summer", "autumn", "winter", "spring"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Number(f64),
+    Bool(bool),
+    Str(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Number,
+    Bool,
+    Str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Var {
+    Year,
+    Month,
+    Day,
+    Weekday,
+    Week,
+    Time,
+    Hour,
+    Season,
+    Holiday,
+    Weekend,
+    Kwh,
+    UsageDay,
+    UsagePeriod,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Value(Value),
+    Var(Var),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+impl Var {
+    fn from_name(name: &str) -> Option<Var> {
+        Some(match name {
+            "year" => Var::Year,
+            "month" => Var::Month,
+            "day" => Var::Day,
+            "weekday" => Var::Weekday,
+            "week" => Var::Week,
+            "time" => Var::Time,
+            "hour" => Var::Hour,
+            "season" => Var::Season,
+            "holiday" => Var::Holiday,
+            "weekend" => Var::Weekend,
+            "kwh" => Var::Kwh,
+            "usage_day" => Var::UsageDay,
+            "usage_period" => Var::UsagePeriod,
+            _ => return None,
+        })
+    }
+
+    fn value(self, i: &Interval) -> Value {
+        let weekday = i.date.weekday().num_days_from_monday();
+        match self {
+            Var::Year => Value::Number(i.date.year() as f64),
+            Var::Month => Value::Number(i.date.month() as f64),
+            Var::Day => Value::Number(i.date.day() as f64),
+            Var::Weekday => Value::Number(weekday as f64),
+            Var::Week => Value::Number(((i.date.day() - 1) / 7 + 1) as f64),
+            Var::Time => Value::Number(i.min_since_midnight as f64),
+            Var::Hour => Value::Number((i.min_since_midnight / 60) as f64),
+            Var::Season => Value::Str(SEASONS[(i.date.month() as usize % 12) / 3].to_string()),
+            Var::Holiday => Value::Bool(i.holiday),
+            Var::Weekend => Value::Bool(i.holiday || weekday >= 5),
+            Var::Kwh => Value::Number(i.energy),
+            Var::UsageDay => Value::Number(i.usage_day),
+            Var::UsagePeriod => Value::Number(i.usage_period),
+        }
+    }
+
+    fn value_type(self) -> Type {
+        match self {
+            Var::Season => Type::Str,
+            Var::Holiday | Var::Weekend => Type::Bool,
+            _ => Type::Number,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Str(String),
+    Name(String),
+    Sym(&'static str),
+}
+
+const SYMBOLS: [&str; 16] = ["||", "&&", "==", "!=", "<=", ">=", "<", ">", "!", "+", "-", "*", "/", "%", "(", ")"];
+
+// A number, or a time HH:MM as minutes since midnight
+fn number(s: &str) -> Result<f64> {
+    match s.split_once(':') {
+        Some((h, m)) => match (h.parse::<u32>(), m.parse::<u32>()) {
+            (Ok(hh), Ok(mm)) if m.len() == 2 && mm < 60 && hh * 60 + mm <= 1440 => Ok((hh * 60 + mm) as f64),
+            _ => Err(anyhow!("number: '{}' is not a time HH:MM", s)),
+        },
+        None => s.parse::<f64>().map_err(|_| anyhow!("number: '{}' is not a number", s)),
+    }
+}
+
+fn tokens(s: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_digit() || c == '.' {
+            let len = rest.find(|c: char| !(c.is_ascii_digit() || c == '.' || c == ':')).unwrap_or(rest.len());
+            tokens.push(Token::Number(number(&rest[..len])?));
+            len
+        } else if c == '"' {
+            let end = rest[1..].find('"').ok_or_else(|| anyhow!("tokens: unterminated string {}", rest))?;
+            tokens.push(Token::Str(rest[1..end + 1].to_string()));
+            end + 2
+        } else if c.is_alphabetic() || c == '_' {
+            let len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            tokens.push(match &rest[..len] {
+                "or" => Token::Sym("||"),
+                "and" => Token::Sym("&&"),
+                "not" => Token::Sym("!"),
+                name => Token::Name(name.to_string()),
+            });
+            len
+        } else {
+            let sym = SYMBOLS.iter().find(|sym| rest.starts_with(**sym)).ok_or_else(|| anyhow!("tokens: unexpected '{}'", c))?;
+            tokens.push(Token::Sym(sym));
+            sym.len()
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Deepest nesting of operators and parentheses in a condition, so a hostile one can't overflow the stack.
+pub const MAX_DEPTH: usize = 128;
+
+// Recursive descent over the tokens, one method for each level of precedence
+struct Parser {
+    tokens: Vec<Token>,
+    at: usize,
+    depth: usize, // parentheses and prefix operators the parser is in
+}
+
+impl Parser {
+    fn eat(&mut self, sym: &str) -> bool {
+        match self.tokens.get(self.at) {
+            Some(Token::Sym(s)) if *s == sym => {
+                self.at += 1;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    // Parse a nested expression, at most MAX_DEPTH deep
+    fn nested(&mut self, parse: fn(&mut Parser) -> Result<Expr>) -> Result<Expr> {
+        if self.depth == MAX_DEPTH {
+            return Err(anyhow!("nested: nested more than {} deep", MAX_DEPTH));
+        }
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    // An operator's expression, at most MAX_DEPTH deep as a long chain of binary operators is deep too
+    fn node(expr: Expr) -> Result<Expr> {
+        match expr.depth() > MAX_DEPTH {
+            true => Err(anyhow!("node: nested more than {} deep", MAX_DEPTH)),
+            false => Ok(expr),
+        }
+    }
+
+    // Binary operators of one level, left associative
+    fn binary(&mut self, ops: &[(&str, Op)], next: fn(&mut Parser) -> Result<Expr>) -> Result<Expr> {
+        let mut left = next(self)?;
+        'more: loop {
+            for (sym, op) in ops {
+                if self.eat(sym) {
+                    left = Parser::node(Expr::Binary(*op, Box::new(left), Box::new(next(self)?)))?;
+                    continue 'more;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        self.binary(&[("||", Op::Or)], Parser::and)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        self.binary(&[("&&", Op::And)], Parser::not)
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        match self.eat("!") {
+            true => Parser::node(Expr::Not(Box::new(self.nested(Parser::not)?))),
+            false => self.comparison(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let left = self.sum()?;
+        for (sym, op) in [("==", Op::Eq), ("!=", Op::Ne), ("<=", Op::Le), (">=", Op::Ge), ("<", Op::Lt), (">", Op::Gt)] {
+            if self.eat(sym) {
+                return Parser::node(Expr::Binary(op, Box::new(left), Box::new(self.sum()?)));
+            }
+        }
+        Ok(left)
+    }
+
+    fn sum(&mut self) -> Result<Expr> {
+        self.binary(&[("+", Op::Add), ("-", Op::Sub)], Parser::product)
+    }
+
+    fn product(&mut self) -> Result<Expr> {
+        self.binary(&[("*", Op::Mul), ("/", Op::Div), ("%", Op::Rem)], Parser::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        match self.eat("-") {
+            true => Parser::node(Expr::Neg(Box::new(self.nested(Parser::unary)?))),
+            false => self.atom(),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr> {
+        if self.eat("(") {
+            let expr = self.nested(Parser::or)?;
+            return match self.eat(")") {
+                true => Ok(expr),
+                false => Err(anyhow!("atom: expected ')'")),
+            };
+        }
+        let token = self.tokens.get(self.at).cloned().ok_or_else(|| anyhow!("atom: expected a value at the end"))?;
+        self.at += 1;
+        match token {
+            Token::Number(x) => Ok(Expr::Value(Value::Number(x))),
+            Token::Str(s) => Ok(Expr::Value(Value::Str(s))),
+            Token::Name(n) if n == "true" || n == "false" => Ok(Expr::Value(Value::Bool(n == "true"))),
+            Token::Name(n) => Var::from_name(&n).map(Expr::Var).ok_or_else(|| anyhow!("atom: unknown variable '{}'", n)),
+            Token::Sym(s) => Err(anyhow!("atom: expected a value before '{}'", s)),
+        }
+    }
+}
+
+impl Expr {
+    // Operators from the root to the deepest value
+    fn depth(&self) -> usize {
+        match self {
+            Expr::Value(_) | Expr::Var(_) => 0,
+            Expr::Not(e) | Expr::Neg(e) => 1 + e.depth(),
+            Expr::Binary(_, a, b) => 1 + a.depth().max(b.depth()),
+        }
+    }
+
+    fn value_type(&self) -> Result<Type> {
+        let expect = |e: &Expr, t: Type, what: &str| match e.value_type()? == t {
+            true => Ok(t),
+            false => Err(anyhow!("value_type: {} needs {:?} values", what, t)),
+        };
+        match self {
+            Expr::Value(Value::Number(_)) => Ok(Type::Number),
+            Expr::Value(Value::Bool(_)) => Ok(Type::Bool),
+            Expr::Value(Value::Str(_)) => Ok(Type::Str),
+            Expr::Var(v) => Ok(v.value_type()),
+            Expr::Not(e) => expect(e, Type::Bool, "!"),
+            Expr::Neg(e) => expect(e, Type::Number, "-"),
+            Expr::Binary(op, a, b) => match op {
+                Op::Or | Op::And => expect(a, Type::Bool, "|| and &&").and(expect(b, Type::Bool, "|| and &&")),
+                Op::Eq | Op::Ne => {
+                    if let (Expr::Var(Var::Season), Expr::Value(Value::Str(s))) | (Expr::Value(Value::Str(s)), Expr::Var(Var::Season)) = (a.as_ref(), b.as_ref()) {
+                        if !SEASONS.contains(&s.as_str()) {
+                            return Err(anyhow!("value_type: season '{}' is not one of {}", s, SEASONS.join(", ")));
+                        }
+                    }
+                    match a.value_type()? == b.value_type()? {
+                        true => Ok(Type::Bool),
+                        false => Err(anyhow!("value_type: == and != need values of the same type")),
+                    }
+                },
+                Op::Lt | Op::Le | Op::Gt | Op::Ge => expect(a, Type::Number, "comparisons").and(expect(b, Type::Number, "comparisons")).map(|_| Type::Bool),
+                _ => expect(a, Type::Number, "arithmetic").and(expect(b, Type::Number, "arithmetic")),
+            },
+        }
+    }
+
+    // The value for an interval, of the type checked by value_type
+    fn eval(&self, i: &Interval) -> Value {
+        let number = |e: &Expr| match e.eval(i) {
+            Value::Number(x) => x,
+            _ => f64::NAN,
+        };
+        let bool = |e: &Expr| e.eval(i) == Value::Bool(true);
+        match self {
+            Expr::Value(v) => v.clone(),
+            Expr::Var(v) => v.value(i),
+            Expr::Not(e) => Value::Bool(!bool(e)),
+            Expr::Neg(e) => Value::Number(-number(e)),
+            Expr::Binary(op, a, b) => match op {
+                Op::Or => Value::Bool(bool(a) || bool(b)),
+                Op::And => Value::Bool(bool(a) && bool(b)),
+                Op::Eq => Value::Bool(a.eval(i) == b.eval(i)),
+                Op::Ne => Value::Bool(a.eval(i) != b.eval(i)),
+                Op::Lt => Value::Bool(number(a) < number(b)),
+                Op::Le => Value::Bool(number(a) <= number(b)),
+                Op::Gt => Value::Bool(number(a) > number(b)),
+                Op::Ge => Value::Bool(number(a) >= number(b)),
+                Op::Add => Value::Number(number(a) + number(b)),
+                Op::Sub => Value::Number(number(a) - number(b)),
+                Op::Mul => Value::Number(number(a) * number(b)),
+                Op::Div => Value::Number(number(a) / number(b)),
+                Op::Rem => Value::Number(number(a) % number(b)),
+            },
+        }
+    }
+}
+
+/// A condition on a tariff row, true or false for each interval.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+impl Condition {
+    /// Whether the condition holds for an interval.
+    pub fn holds(&self, interval: &Interval) -> bool {
+        self.expr.eval(interval) == Value::Bool(true)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Condition> {
+        let mut parser = Parser { tokens: tokens(s)?, at: 0, depth: 0 };
+        let expr = parser.or()?;
+        if let Some(token) = parser.tokens.get(parser.at) {
+            return Err(anyhow!("Condition::from_str: '{}': unexpected {:?}", s, token));
+        }
+        match expr.value_type() {
+            Ok(Type::Bool) => Ok(Condition { source: s.trim().to_string(), expr }),
+            Ok(t) => Err(anyhow!("Condition::from_str: '{}' is a {:?}, not true or false", s, t)),
+            Err(e) => Err(e.context(format!("Condition::from_str: '{}'", s))),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_condition() -> Result<()> {
+        let interval = |y, m, d, min, usage_period| Interval {
+            date: NaiveDate::from_ymd_opt(y, m, d).unwrap(), dow: 0, holiday: false, min_since_midnight: min, length: 30,
+            energy: 0.5, usage_day: 2.0, usage_period,
+        };
+        let holds = |c: &str, i: &Interval| Condition::from_str(c).map(|c| c.holds(i));
+        let saturday = interval(2024, 6, 1, 17 * 60, 600.0);
+        assert!(holds("usage_period > 500", &saturday)?);
+        assert!(holds("weekend and day <= 8 && week == 1", &saturday)?);
+        assert!(holds("season == \"winter\" && time >= 17:00 && hour < 20", &saturday)?);
+        assert!(holds("not holiday || (kwh + usage_day) * 2 % 3 == -(-2)", &saturday)?);
+        assert!(!holds("usage_period - 100 > 500 or weekday < 5 || year == 2023 || month != 6", &saturday)?);
+        assert!(holds("season == \"summer\"", &interval(2024, 12, 1, 0, 0.0))?);
+        assert_eq!(Condition::from_str(" usage_day >= 10 ")?.to_string(), "usage_day >= 10");
+
+        for bad in ["usage > 1", "month + 1", "weekend > 1", "season == \"wet\"", "day == \"1\"", "(day == 1", "day == 1 2", "time > 25:00", "day = 1", ""] {
+            assert!(Condition::from_str(bad).is_err(), "{}", bad);
+        }
+
+        // deep nesting is an error, not a stack overflow
+        let nested = |depth: usize| format!("{}weekend{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(Condition::from_str(&nested(MAX_DEPTH)).is_ok());
+        for deep in [nested(100_000), "!".repeat(100_000) + "weekend", format!("day == {}1", "-".repeat(100_000)), format!("day == 1{}", " + 1".repeat(100_000))] {
+            let e = Condition::from_str(&deep).unwrap_err();
+            assert!(format!("{:#}", e).contains("nested more than 128 deep"), "{:#}", e);
+        }
+        Ok(())
+    }
+}
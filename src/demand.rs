@@ -0,0 +1,642 @@
+//! Demand (kW) charges: a charge on the maximum demand in each calendar month within a time of use window.
+//!
+//! Some tariffs charge each month on the maximum demand over a number of months (e.g. the last 12) rather than
+//! the month alone. With less history than that (e.g. in the first months of data) the maximum is over the months
+//! there are, so the charge may be understated, and [`DemandCharge::months`] says how many months it's over.
+//!
+//! With billing periods (see [`DemandMeter::with_billing`]) a month runs from the billing anchor's day of the month
+//! rather than the 1st.
+//!
+//! Demand is measured over 30 minute periods, finer intervals are summed into 30 minute periods
+//! (intervals longer than 30 minutes are used as they are).
+//!
+//! Demand is in kW, or in kVA for tariffs that charge on apparent power: each interval's kWh over its
+//! [`PowerFactor`] (kVAh) is summed into the period instead, taking a power factor of 1 where there is none.
+//!
+//! [`peak_demand`] reports the peak demand of each day or month, whatever the tariff, and [`demand_stats`]
+//! the distribution of interval demand (percentiles and the load duration curve).
+
+use crate::energy::{open_input, read_csv_energy, CsvLine, DayEnergy, Direction};
+use crate::json::Json;
+use crate::policy::RatePolicy;
+use crate::pricing::{minutes_since_midnight, Billing, Interval};
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Months, NaiveDate};
+use csv::{ReaderBuilder, Writer};
+use log::{debug, info};
+use std::collections::BTreeMap;
+use std::io;
+use std::str::FromStr;
+
+/// Minutes over which demand is measured.
+pub const DEMAND_PERIOD: usize = 30;
+
+/// What a demand rate is charged per.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DemandPer {
+    Month, // $/kW per month
+    Day,   // $/kW per day, for the days of data in the month
+}
+
+/// The unit of demand a rate is charged on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DemandUnit {
+    Kw,  // real power
+    Kva, // apparent power, kW over the power factor
+}
+
+impl FromStr for DemandPer {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<DemandPer> {
+        match s {
+            "month" => Ok(DemandPer::Month),
+            "day" => Ok(DemandPer::Day),
+            _ => Err(anyhow!("DemandPer::from_str: Per '{}' is not 'month' or 'day'", s)),
+        }
+    }
+}
+
+impl FromStr for DemandUnit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<DemandUnit> {
+        match s.to_lowercase().as_str() {
+            "kw" => Ok(DemandUnit::Kw),
+            "kva" => Ok(DemandUnit::Kva),
+            _ => Err(anyhow!("DemandUnit::from_str: Unit '{}' is not 'kW' or 'kVA'", s)),
+        }
+    }
+}
+
+impl DemandUnit {
+    pub fn label(self) -> &'static str {
+        match self {
+            DemandUnit::Kw => "kW",
+            DemandUnit::Kva => "kVA",
+        }
+    }
+}
+
+/// The power factor (real over apparent power) of the energy data, for kVA demand.
+#[derive(Debug, Clone)]
+pub enum PowerFactor {
+    Constant(f64),
+    Intervals(BTreeMap<NaiveDate, DayEnergy>), // the power factor of each interval, as energy data is laid out
+}
+
+impl PowerFactor {
+    /// A constant power factor (e.g. `0.9`), or a CSV file of the power factor of each interval laid out like
+    /// energy data (a date and a column per interval).
+    pub fn load(power_factor: &str) -> Result<PowerFactor> {
+        let check = |pf: f64| match pf > 0.0 && pf <= 1.0 {
+            true => Ok(pf),
+            false => Err(anyhow!("PowerFactor::load: {}: power factor {} is not over 0 and at most 1", power_factor, pf)),
+        };
+        if let Ok(pf) = power_factor.trim().parse::<f64>() {
+            return Ok(PowerFactor::Constant(check(pf)?));
+        }
+        let mut days = BTreeMap::new();
+        for day in read_csv_energy(power_factor)? {
+            let day = day?;
+            day.energy.iter().try_for_each(|pf| check(*pf).map(|_| ()))?;
+            days.insert(day.date, day);
+        }
+        Ok(PowerFactor::Intervals(days))
+    }
+
+    /// The power factor of the interval starting at `min` (since midnight) on a date, in the hour repeated when clocks
+    /// go back if `repeated` (see [`DayEnergy::index`]), None if not given.
+    pub fn at(&self, date: NaiveDate, min: i32, repeated: bool) -> Option<f64> {
+        match self {
+            PowerFactor::Constant(pf) => Some(*pf),
+            PowerFactor::Intervals(days) => days.get(&date).and_then(|d| d.energy.get(d.index(min, repeated)?).copied()),
+        }
+    }
+}
+
+/// One row of a demand tariff table: the demand window and its rate.
+#[derive(Debug)]
+pub struct DemandTariff {
+    pub day_start: i16,  // Day Start (0 for Monday)
+    pub day_end: i16,    // Day End (Exclusive)
+    pub time_start: i32, // Time Start (min since midnight)
+    pub time_end: i32,   // Time End (Exclusive)
+    pub rate: f64,       // $/kW
+    pub name: String,    // Tariff Name
+    pub per: DemandPer,
+    pub months: usize,   // months the maximum demand is over, ending with the month charged (1 for the month alone)
+    pub unit: DemandUnit,
+}
+
+/// Load a demand tariff table from a CSV file with columns:
+/// Day Start (0 for Monday), Day End (Exclusive), Time Start, Time End (Exclusive), $/kW, Name, Per (`month` or `day`)
+/// and optionally Months (the months the maximum demand is over, 1 if empty, e.g. 12 for a rolling 12 months)
+/// and Unit (`kW`, the default, or `kVA`).
+pub fn load_demand_tariff(csv_tariff: &str) -> Result<Vec<DemandTariff>> {
+    info!("load_demand_tariff: loading CSV file {}", csv_tariff);
+    load_demand_tariff_from(csv_tariff, open_input(csv_tariff)?)
+}
+
+/// Load a demand tariff like [`load_demand_tariff`] from any reader (e.g. a byte slice), `csv_tariff` naming it in errors.
+pub fn load_demand_tariff_from<R: io::Read>(csv_tariff: &str, input: R) -> Result<Vec<DemandTariff>> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(input);
+
+    let headers = reader.headers()?.clone();
+    reader.records().enumerate()
+    .map(|(i, record)| -> Result<DemandTariff> {
+        let r = record?;
+        debug!("load_demand_tariff: record: {:?}", r);
+        let line = CsvLine::new("load_demand_tariff", csv_tariff, i, &headers, &r);
+        Ok(DemandTariff {
+            day_start:  line.parse::<i16>(0)?,
+            day_end:    line.parse::<i16>(1)?,
+            time_start: line.parse_with(2, minutes_since_midnight)?,
+            time_end:   line.parse_with(3, minutes_since_midnight)?,
+            rate:       line.parse::<f64>(4)?,
+            name:       line.get(5).to_string(),
+            per: line.parse_with(6, |per| match per {
+                "" => Ok(DemandPer::Month),
+                _ => DemandPer::from_str(per),
+            })?,
+            months: line.parse_with(7, |months| match months {
+                "" => Ok(1),
+                _ => months.parse::<usize>().ok().filter(|m| *m > 0).ok_or_else(|| anyhow!("Months is not a positive whole number")),
+            })?,
+            unit: line.parse_with(8, |unit| match unit {
+                "" => Ok(DemandUnit::Kw),
+                _ => DemandUnit::from_str(unit),
+            })?,
+        })
+    })
+    .collect()
+}
+
+/// The demand charge for one demand tariff row in one month.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DemandCharge {
+    pub month: NaiveDate, // first day of the month (billing month with billing periods)
+    pub name: String,     // demand tariff name
+    pub kw: f64,          // maximum demand (kVA for a kVA tariff)
+    pub unit: DemandUnit,
+    pub at: (NaiveDate, i32), // date and start (min since midnight) of the maximum demand period
+    pub days: usize,      // days of data in the month
+    pub charge: f64,      // $
+    pub months: usize,    // months of data the maximum is over, fewer than `window` with a partial history
+    pub window: usize,    // months the tariff takes the maximum over
+}
+
+/// Accumulates the maximum demand per month and demand tariff row from the intervals being priced.
+pub struct DemandMeter<'a> {
+    tariff: &'a [DemandTariff],
+    power_factor: Option<&'a PowerFactor>,
+    period: Option<(NaiveDate, i32, i16)>, // current 30 minute period: date, start, day of week
+    period_kwh: f64,
+    period_kvah: f64,
+    period_minutes: usize,
+    peaks: BTreeMap<(NaiveDate, usize), (f64, (NaiveDate, i32))>, // (month, tariff row) -> (kW, at)
+    days: BTreeMap<NaiveDate, usize>, // month -> days of data
+    last_date: Option<NaiveDate>,
+    last_min: i32,               // start of the last interval of the last date
+    repeated: bool,              // whether the last date's intervals have gone back (clocks going back)
+    missing_power_factor: usize, // intervals without a power factor, taken as 1
+    months: Option<Billing>,     // monthly billing periods months start on, None for calendar months
+}
+
+fn first_of_month(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap_or(date)
+}
+
+impl<'a> DemandMeter<'a> {
+    /// A meter for the demand tariff rows, with the power factor for any kVA rows.
+    pub fn new(tariff: &'a [DemandTariff], power_factor: Option<&'a PowerFactor>) -> DemandMeter<'a> {
+        DemandMeter {
+            tariff, power_factor, period: None, period_kwh: 0.0, period_kvah: 0.0, period_minutes: 0,
+            peaks: BTreeMap::new(), days: BTreeMap::new(), last_date: None, last_min: 0, repeated: false, missing_power_factor: 0,
+            months: None,
+        }
+    }
+
+    /// The meter with months starting on the day of the month of the billing periods' anchor, so demand is charged
+    /// per billing month (each month of a quarterly bill).
+    pub fn with_billing(self, billing: Option<Billing>) -> DemandMeter<'a> {
+        DemandMeter { months: billing.map(|b| Billing::monthly(b.anchor)), ..self }
+    }
+
+    // The first day of the month (calendar or billing) containing a date
+    fn month(&self, date: NaiveDate) -> NaiveDate {
+        self.months.map_or_else(|| first_of_month(date), |b| b.period_start(date))
+    }
+
+    /// The number of intervals without a power factor, for kVA rows only.
+    pub fn missing_power_factor(&self) -> usize {
+        self.missing_power_factor
+    }
+
+    /// Add an interval, typically from the cost function given to [`crate::pricing::price_intervals`].
+    pub fn observe(&mut self, interval: &Interval) {
+        if self.last_date != Some(interval.date) {
+            let month = self.month(interval.date);
+            *self.days.entry(month).or_insert(0) += 1;
+            self.last_date = Some(interval.date);
+            self.repeated = false;
+        } else if interval.min_since_midnight <= self.last_min {
+            self.repeated = true;
+        }
+        self.last_min = interval.min_since_midnight;
+        let period_len = DEMAND_PERIOD.max(interval.length) as i32;
+        let start = interval.min_since_midnight - interval.min_since_midnight % period_len;
+        if self.period.map(|(d, s, _)| (d, s)) != Some((interval.date, start)) {
+            self.end_period();
+            self.period = Some((interval.date, start, interval.dow));
+        }
+        self.period_kwh += interval.energy;
+        self.period_minutes += interval.length;
+        if self.tariff.iter().any(|t| t.unit == DemandUnit::Kva) {
+            let pf = self.power_factor.and_then(|pf| pf.at(interval.date, interval.min_since_midnight, self.repeated));
+            if pf.is_none() {
+                self.missing_power_factor += 1;
+            }
+            self.period_kvah += interval.energy / pf.unwrap_or(1.0);
+        }
+    }
+
+    fn end_period(&mut self) {
+        if let Some((date, start, dow)) = self.period.take() {
+            let kw = self.period_kwh * 60.0 / self.period_minutes as f64;
+            let kva = self.period_kvah * 60.0 / self.period_minutes as f64;
+            let interval = Interval {
+                date, dow, holiday: false, min_since_midnight: start, length: self.period_minutes,
+                energy: self.period_kwh, usage_day: 0.0, usage_period: 0.0,
+            };
+            for (i, t) in self.tariff.iter().enumerate() {
+                if matches!(t.rates(&interval, Direction::Import), Ok(Some(rates)) if !rates.is_empty()) {
+                    let kw = if t.unit == DemandUnit::Kva { kva } else { kw };
+                    let month = self.month(date);
+                    let peak = self.peaks.entry((month, i)).or_insert((kw, (date, start)));
+                    if kw > peak.0 {
+                        *peak = (kw, (date, start));
+                    }
+                }
+            }
+        }
+        self.period_kwh = 0.0;
+        self.period_kvah = 0.0;
+        self.period_minutes = 0;
+    }
+
+    /// Add the observations of a meter for later days (e.g. priced on another thread).
+    pub fn merge(&mut self, mut other: DemandMeter<'a>) {
+        self.end_period();
+        other.end_period();
+        for (key, (kw, at)) in other.peaks {
+            let peak = self.peaks.entry(key).or_insert((kw, at));
+            if kw > peak.0 {
+                *peak = (kw, at);
+            }
+        }
+        for (month, days) in other.days {
+            *self.days.entry(month).or_insert(0) += days;
+        }
+        self.last_date = other.last_date.or(self.last_date);
+        self.missing_power_factor += other.missing_power_factor;
+    }
+
+    /// What the meter has observed (the peaks and days of data per month), to continue from with [`DemandMeter::with_state`].
+    pub fn state(&mut self) -> Json {
+        self.end_period(); // periods don't span days, and a later run prices later days
+        Json::object(vec![
+            ("peaks", Json::Array(self.peaks.iter().map(|((month, row), (kw, (date, start)))| Json::object(vec![
+                ("month", month.to_string().into()),
+                ("row", (*row).into()),
+                ("kw", (*kw).into()),
+                ("date", date.to_string().into()),
+                ("start", (*start as f64).into()),
+            ])).collect())),
+            ("days", Json::Array(self.days.iter().map(|(month, days)| Json::object(vec![
+                ("month", month.to_string().into()),
+                ("days", (*days).into()),
+            ])).collect())),
+            ("missing_power_factor", self.missing_power_factor.into()),
+        ])
+    }
+
+    /// The meter continuing from the observations saved by [`DemandMeter::state`] under the same tariff.
+    pub fn with_state(mut self, json: &Json) -> Result<DemandMeter<'a>> {
+        let date = |json: &Json, key| json.string(key)?.parse::<NaiveDate>().map_err(|e| anyhow!("DemandMeter::with_state: {}: {}", key, e));
+        for peak in json.get("peaks").and_then(Json::as_array).unwrap_or_default() {
+            let row = peak.number("row")? as usize;
+            if row >= self.tariff.len() {
+                return Err(anyhow!("DemandMeter::with_state: demand tariff row {} but the tariff has {} rows", row + 1, self.tariff.len()));
+            }
+            self.peaks.insert((date(peak, "month")?, row), (peak.number("kw")?, (date(peak, "date")?, peak.number("start")? as i32)));
+        }
+        for days in json.get("days").and_then(Json::as_array).unwrap_or_default() {
+            self.days.insert(date(days, "month")?, days.number("days")? as usize);
+        }
+        self.missing_power_factor = json.number("missing_power_factor")? as usize;
+        Ok(self)
+    }
+
+    /// The demand charges per month (in order) and demand tariff row (in file order).
+    pub fn charges(mut self) -> Vec<DemandCharge> {
+        self.end_period();
+        let mut charges = Vec::new();
+        for month in self.days.keys() {
+            for (i, t) in self.tariff.iter().enumerate() {
+                // the months of data in the window ending with this month
+                let from = month.checked_sub_months(Months::new(t.months as u32 - 1)).unwrap_or(NaiveDate::MIN);
+                let history = self.days.range(from..=*month).map(|(m, _)| *m).collect::<Vec<_>>();
+                let peak = history.iter().filter_map(|m| self.peaks.get(&(*m, i)))
+                    .fold(None, |max: Option<&(f64, (NaiveDate, i32))>, p| match max {
+                        Some(max) if max.0 >= p.0 => Some(max),
+                        _ => Some(p),
+                    });
+                if let Some((kw, at)) = peak {
+                    let days = self.days[month];
+                    let charge = match t.per {
+                        DemandPer::Month => kw * t.rate,
+                        DemandPer::Day => kw * t.rate * days as f64,
+                    };
+                    charges.push(DemandCharge { month: *month, name: t.name.clone(), kw: *kw, unit: t.unit, at: *at, days, charge, months: history.len(), window: t.months });
+                }
+            }
+        }
+        charges
+    }
+}
+
+/// The peak demand of a day or month: the highest kW of any interval of energy data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Peak {
+    pub period: NaiveDate,    // the day, or the first day of the month
+    pub kw: f64,              // maximum demand, an interval's kWh over its length
+    pub at: (NaiveDate, i32), // date and start (min since midnight) of the interval, the first if there are ties
+    pub kwh: f64,             // energy in the day or month
+}
+
+/// The peak demand of each day, or of each month if `monthly`, in date order.
+///
+/// Unlike demand charges, each interval's demand is its own (not summed into 30 minute periods),
+/// so 5 minute data shows shorter peaks than 30 minute data.
+pub fn peak_demand<I>(days: I, monthly: bool) -> Result<Vec<Peak>> where
+I: IntoIterator<Item = Result<DayEnergy>> {
+    let mut peaks: BTreeMap<NaiveDate, Peak> = BTreeMap::new();
+    for day in days {
+        let day = day?;
+        let period = if monthly { first_of_month(day.date) } else { day.date };
+        for (i, kwh) in day.energy.iter().enumerate() {
+            let kw = kwh * 60.0 / day.interval as f64;
+            let at = (day.date, day.minute(i));
+            let peak = peaks.entry(period).or_insert(Peak { period, kw, at, kwh: 0.0 });
+            if kw > peak.kw || (kw == peak.kw && at < peak.at) {
+                peak.kw = kw;
+                peak.at = at;
+            }
+            peak.kwh += kwh;
+        }
+    }
+    Ok(peaks.into_values().collect())
+}
+
+/// A table of the peaks, as made by [`peak_demand`].
+pub fn peaks_text(peaks: &[Peak], monthly: bool) -> String {
+    let mut s = format!("{:<10} {:>10} {:>10}  {}\n", if monthly { "Month" } else { "Date" }, "kWh", "Max kW", "At");
+    for p in peaks {
+        let at = format!("{:02}:{:02}", p.at.1 / 60, p.at.1 % 60);
+        let (period, at) = match monthly {
+            true => (p.period.format("%Y-%m").to_string(), format!("{} {}", p.at.0, at)),
+            false => (p.period.to_string(), at),
+        };
+        s += &format!("{:<10} {:>10.3} {:>10.3}  {}\n", period, p.kwh, p.kw, at);
+    }
+    s
+}
+
+/// The peaks as a JSON array.
+pub fn peaks_json(peaks: &[Peak]) -> Json {
+    Json::Array(peaks.iter().map(|p| Json::object(vec![
+        ("period", p.period.to_string().into()),
+        ("kwh", p.kwh.into()),
+        ("kw", p.kw.into()),
+        ("at", format!("{} {:02}:{:02}", p.at.0, p.at.1 / 60, p.at.1 % 60).into()),
+    ])).collect())
+}
+
+/// The demand (kW) of every interval of energy data, its kWh over its length, in date order.
+pub fn interval_demand<I>(days: I) -> Result<Vec<f64>> where
+I: IntoIterator<Item = Result<DayEnergy>> {
+    let mut kw = Vec::new();
+    for day in days {
+        let day = day?;
+        kw.extend(day.energy.iter().map(|kwh| kwh * 60.0 / day.interval as f64));
+    }
+    Ok(kw)
+}
+
+/// Percentile statistics of interval demand (kW).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DemandStats {
+    pub intervals: usize,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+// The p-th percentile of ascending values, interpolating linearly between the closest ranks
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p / 100.0 * (sorted.len() - 1) as f64;
+    let (below, above) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[below] + (sorted[above] - sorted[below]) * (rank - below as f64)
+}
+
+/// Percentile statistics of interval demand (e.g. from [`interval_demand`]), None if there are no intervals.
+///
+/// Each interval counts once whatever its length, so data should have the same interval length throughout.
+pub fn demand_stats(kw: &[f64]) -> Option<DemandStats> {
+    if kw.is_empty() {
+        return None;
+    }
+    let mut sorted = kw.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    Some(DemandStats {
+        intervals: kw.len(),
+        mean: kw.iter().sum::<f64>() / kw.len() as f64,
+        p50: percentile(&sorted, 50.0),
+        p90: percentile(&sorted, 90.0),
+        p99: percentile(&sorted, 99.0),
+        max: sorted[sorted.len() - 1],
+    })
+}
+
+impl DemandStats {
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("intervals", self.intervals.into()),
+            ("mean", self.mean.into()),
+            ("p50", self.p50.into()),
+            ("p90", self.p90.into()),
+            ("p99", self.p99.into()),
+            ("max", self.max.into()),
+        ])
+    }
+}
+
+/// A table of the statistics of each (label, statistics).
+pub fn stats_text(stats: &[(&str, DemandStats)]) -> String {
+    let mut s = format!("{:<12} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}\n", "kW", "Intervals", "Mean", "p50", "p90", "p99", "Max");
+    for (label, x) in stats {
+        s += &format!("{:<12} {:>10} {:>10.3} {:>10.3} {:>10.3} {:>10.3} {:>10.3}\n", label, x.intervals, x.mean, x.p50, x.p90, x.p99, x.max);
+    }
+    s
+}
+
+/// Write the load duration curve of each (label, interval demand) as CSV: a line per interval with the label,
+/// the percent of intervals with at least its demand and its kW, from the highest demand to the lowest.
+pub fn write_duration_curve<W: io::Write>(curves: &[(&str, &[f64])], w: W) -> Result<()> {
+    let mut writer = Writer::from_writer(w);
+    writer.write_record(["Direction", "Percent of Time", "kW"])?;
+    for (label, kw) in curves {
+        let mut sorted = kw.to_vec();
+        sorted.sort_by(|a, b| b.total_cmp(a));
+        for (i, x) in sorted.iter().enumerate() {
+            writer.write_record([label.to_string(), (100.0 * (i + 1) as f64 / sorted.len() as f64).to_string(), x.to_string()])?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write the load duration curves to a CSV file, see [`write_duration_curve`].
+pub fn save_duration_curve(curves: &[(&str, &[f64])], path: &str) -> Result<()> {
+    info!("save_duration_curve: writing CSV file {}", path);
+    write_duration_curve(curves, std::fs::File::create(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pricing::price_intervals;
+    use assert_float_eq::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_demand_charges() -> Result<()> {
+        let tariff = load_demand_tariff("data/test/tariff/peakDemand.csv")?;
+        let mut meter = DemandMeter::new(&tariff, None);
+        price_intervals(
+            read_csv_energy("data/test/energy/consumption.csv")?,
+            |i| { meter.observe(i); Ok(0.0) },
+            &HashSet::new()
+        )?;
+        let charges = meter.charges();
+        assert_eq!(charges.len(), 1);
+        // 8 hour intervals: 0.063 kWh over 16:00 to 24:00 on week days (20230807 and 20230808)
+        let c = &charges[0];
+        assert_f64_near!(c.kw, 0.063 / 8.0);
+        assert_eq!(c.at, (NaiveDate::from_ymd_opt(2023, 8, 7).unwrap(), 16 * 60));
+        assert_eq!(c.days, 3);
+        assert_f64_near!(c.charge, 0.063 / 8.0 * 0.5 * 3.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rolling_demand_charges() -> Result<()> {
+        let tariff = load_demand_tariff("data/test/tariff/peakDemandRolling.csv")?;
+        assert_eq!(tariff[0].months, 12);
+        // 8 hour intervals on Mondays, the 16:00 interval in the window
+        let monday = |y, m, d, kwh| DayEnergy::new(NaiveDate::from_ymd_opt(y, m, d).unwrap(), vec![0.0, 0.0, kwh]);
+        let days = vec![monday(2023, 6, 5, 0.08), monday(2023, 7, 3, 0.04), monday(2023, 8, 7, 0.06), monday(2024, 7, 1, 0.02)];
+        let mut meter = DemandMeter::new(&tariff, None);
+        price_intervals(days, |i| { meter.observe(i); Ok(0.0) }, &HashSet::new())?;
+        let charges = meter.charges();
+        let month = |y, m| NaiveDate::from_ymd_opt(y, m, 1).unwrap();
+        assert_eq!(charges.iter().map(|c| (c.month, c.at.0, c.months, c.window)).collect::<Vec<_>>(), vec![
+            (month(2023, 6), NaiveDate::from_ymd_opt(2023, 6, 5).unwrap(), 1, 12),
+            (month(2023, 7), NaiveDate::from_ymd_opt(2023, 6, 5).unwrap(), 2, 12),
+            (month(2023, 8), NaiveDate::from_ymd_opt(2023, 6, 5).unwrap(), 3, 12),
+            // June 2023 is out of the 12 months to July 2024
+            (month(2024, 7), NaiveDate::from_ymd_opt(2023, 8, 7).unwrap(), 2, 12),
+        ]);
+        assert_f64_near!(charges[1].charge, 0.08 / 8.0 * 10.0);
+        assert_f64_near!(charges[3].kw, 0.06 / 8.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_kva_demand_charges() -> Result<()> {
+        let tariff = load_demand_tariff("data/test/tariff/peakDemandKva.csv")?;
+        assert_eq!((tariff[0].unit, tariff[0].months), (DemandUnit::Kva, 1));
+        let charges = |power_factor: Option<&PowerFactor>| -> Result<(Vec<DemandCharge>, usize)> {
+            let mut meter = DemandMeter::new(&tariff, power_factor);
+            price_intervals(read_csv_energy("data/test/energy/consumption.csv")?, |i| { meter.observe(i); Ok(0.0) }, &HashSet::new())?;
+            let missing = meter.missing_power_factor();
+            Ok((meter.charges(), missing))
+        };
+        let (c, missing) = charges(Some(&PowerFactor::load("0.9")?))?;
+        assert_eq!((c[0].unit, missing), (DemandUnit::Kva, 0));
+        assert_f64_near!(c[0].kw, 0.063 / 8.0 / 0.9);
+
+        // 0.7 at 16:00 on 20230807, no power factors for 20230808 (taken as 1)
+        let (c, missing) = charges(Some(&PowerFactor::load("data/test/energy/powerFactor.csv")?))?;
+        assert_eq!((c[0].at.0, missing), (NaiveDate::from_ymd_opt(2023, 8, 7).unwrap(), 3));
+        assert_f64_near!(c[0].kw, 0.063 / 8.0 / 0.7);
+        assert_f64_near!(c[0].charge, 0.063 / 8.0 / 0.7 * 0.5 * 3.0);
+
+        let (c, missing) = charges(None)?;
+        assert_eq!(missing, 9);
+        assert_f64_near!(c[0].kw, 0.063 / 8.0);
+        assert!(PowerFactor::load("1.2").is_err());
+
+        // on daylight saving days by the interval's position in the day, not its start time
+        let days = read_csv_energy("data/test/energy/consumptionDst.csv")?
+            .map(|d| d.map(|d| (d.date, DayEnergy { energy: (0..d.energy.len()).map(|i| 1.0 / (i + 1) as f64).collect(), ..d })))
+            .collect::<Result<BTreeMap<_, _>>>()?;
+        let pf = PowerFactor::Intervals(days);
+        let (forward, back) = (NaiveDate::from_ymd_opt(2024, 10, 6).unwrap(), NaiveDate::from_ymd_opt(2025, 4, 6).unwrap());
+        assert_eq!((pf.at(forward, 60, false), pf.at(forward, 180, false)), (Some(1.0 / 2.0), Some(1.0 / 3.0)));
+        assert_eq!((pf.at(back, 120, false), pf.at(back, 120, true), pf.at(back, 180, true)), (Some(1.0 / 3.0), Some(1.0 / 4.0), Some(1.0 / 5.0)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_peak_demand() -> Result<()> {
+        // 0.073 kWh over 00:00 to 08:00 each day
+        let date = |d| NaiveDate::from_ymd_opt(2023, 8, d).unwrap();
+        let peaks = peak_demand(read_csv_energy("data/test/energy/consumption.csv")?, false)?;
+        assert_eq!(peaks.iter().map(|p| (p.period, p.at)).collect::<Vec<_>>(), vec![(date(6), (date(6), 0)), (date(7), (date(7), 0)), (date(8), (date(8), 0))]);
+        assert_f64_near!(peaks[1].kw, 0.073 / 8.0);
+        assert_f64_near!(peaks[1].kwh, 0.164);
+
+        let peaks = peak_demand(read_csv_energy("data/test/energy/feedIn.csv")?, true)?;
+        assert_eq!(peaks.len(), 1);
+        assert_eq!((peaks[0].period, peaks[0].at), (date(1), (date(6), 0)));
+        assert_eq!(peaks_text(&peaks, true), "Month             kWh     Max kW  At\n2023-08         2.226      0.041  2023-08-06 00:00\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_demand_stats() -> Result<()> {
+        let kw = interval_demand(read_csv_energy("data/test/energy/consumption.csv")?)?;
+        assert_eq!(kw.len(), 9);
+        let stats = demand_stats(&kw).unwrap();
+        // kW each day 0.073 / 8, 0.028 / 8, 0.063 / 8: the median is 0.063 / 8, p90 between the 8th and 9th of 9
+        assert_f64_near!(stats.p50, 0.063 / 8.0);
+        assert_f64_near!(stats.p90, 0.073 / 8.0);
+        assert_f64_near!(stats.max, 0.073 / 8.0);
+        assert_f64_near!(stats.mean, 0.164 / 24.0);
+        assert_eq!(demand_stats(&[]), None);
+        assert_f64_near!(percentile(&[1.0, 2.0, 3.0, 4.0, 5.0], 90.0), 4.6);
+
+        let mut csv = Vec::new();
+        write_duration_curve(&[("consumption", &kw)], &mut csv)?;
+        let csv = String::from_utf8(csv)?;
+        assert_eq!(csv.lines().nth(1), Some("consumption,11.11111111111111,0.009125"));
+        assert_eq!(csv.lines().last(), Some("consumption,100,0.0035"));
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+//! Percentage discounts on usage, supply or the whole bill, optionally conditional (e.g. pay on time, direct debit).
+
+use crate::energy::open_input;
+use crate::money::{self, Money};
+use anyhow::{anyhow, Result};
+use csv::ReaderBuilder;
+use log::{debug, info};
+use std::str::FromStr;
+
+/// What a discount is a percentage of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscountOn {
+    Usage,  // consumption charges
+    Supply, // supply charges
+    Bill,   // consumption, supply and demand charges (feedin credits are not discounted)
+}
+
+impl FromStr for DiscountOn {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<DiscountOn> {
+        match s {
+            "usage" => Ok(DiscountOn::Usage),
+            "supply" => Ok(DiscountOn::Supply),
+            "bill" => Ok(DiscountOn::Bill),
+            _ => Err(anyhow!("DiscountOn::from_str: Applies To '{}' is not 'usage', 'supply' or 'bill'", s)),
+        }
+    }
+}
+
+/// A percentage discount.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discount {
+    pub name: String,
+    pub percent: f64,
+    pub on: DiscountOn,
+    pub condition: Option<String>, // e.g. "pay on time", None for unconditional
+}
+
+/// A discount applied to a bill.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscountLine {
+    pub name: String,
+    pub on: DiscountOn,
+    pub amount: f64, // $, negative
+}
+
+/// Load discounts from a CSV file with columns: Name, Percent, Applies To (`usage`, `supply` or `bill`),
+/// Condition (empty for unconditional).
+pub fn load_discounts(csv: &str) -> Result<Vec<Discount>> {
+    info!("load_discounts: loading CSV file {}", csv);
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(open_input(csv)?);
+
+    reader.records()
+    .map(|record| -> Result<Discount> {
+        let r = record?;
+        debug!("load_discounts: record: {:?}", r);
+        Ok(Discount {
+            name: r[0].trim().to_string(),
+            percent: r[1].trim().parse::<f64>()?,
+            on: DiscountOn::from_str(r[2].trim())?,
+            condition: r.get(3).map(str::trim).filter(|x| !x.is_empty()).map(str::to_string),
+        })
+    })
+    .collect()
+}
+
+/// The discount lines for charges of `consumption`, `supply` and `demand` ($).
+/// Each discount is a percentage of the undiscounted charges, discounts don't compound.
+pub fn discount_lines(discounts: &[Discount], consumption: f64, supply: f64, demand: f64) -> Vec<DiscountLine> {
+    discounts.iter().map(|d| {
+        let base = match d.on {
+            DiscountOn::Usage => consumption,
+            DiscountOn::Supply => supply,
+            DiscountOn::Bill => money::sum([consumption, supply, demand]),
+        };
+        DiscountLine { name: d.name.clone(), on: d.on, amount: -Money::from_f64(base * d.percent / 100.0).to_f64() }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discounts() -> Result<()> {
+        let discounts = load_discounts("data/test/tariff/payOnTimeDiscounts.csv")?;
+        assert_eq!(discounts[1].condition.as_deref(), Some("pay on time"));
+        let lines = discount_lines(&discounts, 100.0, 50.0, 10.0);
+        assert_eq!(lines.iter().map(|x| x.amount).collect::<Vec<_>>(), vec![-10.0, -8.0]);
+        Ok(())
+    }
+}
@@ -0,0 +1,1333 @@
+//! The internal interval representation of energy data and the reader for the wide CSV format.
+//!
+//! Every input format is mapped into a sequence of [`DayEnergy`], one per date, which is what the
+//! pricing functions consume.
+
+use crate::compress::{decompress, Compression};
+use crate::progress::{BytesRead, Counting};
+use crate::xlsx::{is_xlsx, xlsx_csv};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, Timelike, Weekday};
+use csv::{ByteRecord, Reader, ReaderBuilder, StringRecord};
+use log::{debug, info, warn};
+use std::cell::RefCell;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// Direction of energy flow, selecting which channels or readings to read from formats holding both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Direction {
+    Import, // energy consumed from the grid
+    Export, // energy fed into the grid
+}
+
+/// Quality of an interval reading (from the NEM12 QualityMethod flag), ordered from the most to the least reliable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Quality {
+    Actual,
+    Estimated,
+    Substituted,
+    FinalSubstituted,
+    Null,
+}
+
+impl Quality {
+    /// Map the first character of a NEM12 QualityMethod (e.g. `A`, `E52`, `S53`) to a `Quality`.
+    pub fn from_flag(flag: &str) -> Result<Quality> {
+        match flag.trim().chars().next() {
+            Some('A') => Ok(Quality::Actual),
+            Some('E') => Ok(Quality::Estimated),
+            Some('S') => Ok(Quality::Substituted),
+            Some('F') => Ok(Quality::FinalSubstituted),
+            Some('N') => Ok(Quality::Null),
+            _ => Err(anyhow!("Quality::from_flag: unknown quality flag '{}'", flag)),
+        }
+    }
+
+    /// The NEM12 quality flag letter, the inverse of [`Quality::from_flag`].
+    pub fn flag(self) -> &'static str {
+        match self {
+            Quality::Actual => "A",
+            Quality::Estimated => "E",
+            Quality::Substituted => "S",
+            Quality::FinalSubstituted => "F",
+            Quality::Null => "N",
+        }
+    }
+}
+
+/// What to do with a blank interval in an energy file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Missing {
+    Error,       // abort reading
+    Zero,        // count as zero kWh
+    Interpolate, // linearly interpolate between the readings either side on the same day
+    SkipDay,     // drop the whole day with a warning
+}
+
+impl FromStr for Missing {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Missing> {
+        match s.trim().to_lowercase().as_str() {
+            "error" => Ok(Missing::Error),
+            "zero" => Ok(Missing::Zero),
+            "interpolate" => Ok(Missing::Interpolate),
+            "skip-day" => Ok(Missing::SkipDay),
+            _ => Err(anyhow!("Missing::from_str: '{}' is not one of error, zero, interpolate, skip-day", s)),
+        }
+    }
+}
+
+/// How the meter measures solar: what the consumption and feedin data are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metering {
+    Net,   // consumption is what's imported and feedin only the surplus exported
+    Gross, // consumption is the whole household load and feedin the whole generation, see [`net_metering`]
+}
+
+impl FromStr for Metering {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Metering> {
+        match s.trim().to_lowercase().as_str() {
+            "net" => Ok(Metering::Net),
+            "gross" => Ok(Metering::Gross),
+            _ => Err(anyhow!("Metering::from_str: '{}' is not one of net, gross", s)),
+        }
+    }
+}
+
+/// What to do with a date repeated in an energy file, which would otherwise be priced twice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Duplicates {
+    Error,  // abort reading
+    Dedupe, // keep the first, with a warning
+    Warn,   // keep every copy, with a warning
+}
+
+impl FromStr for Duplicates {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Duplicates> {
+        match s.trim().to_lowercase().as_str() {
+            "error" => Ok(Duplicates::Error),
+            "dedupe" => Ok(Duplicates::Dedupe),
+            "warn" => Ok(Duplicates::Warn),
+            _ => Err(anyhow!("Duplicates::from_str: '{}' is not one of error, dedupe, warn", s)),
+        }
+    }
+}
+
+/// Warnings about days of energy data, shared between the code reading the days and the code consuming them, as some
+/// are only known once the days have streamed through (e.g. from [`check_dates`]).
+#[derive(Debug, Clone, Default)]
+pub struct Warnings(Rc<RefCell<Vec<String>>>);
+
+impl Warnings {
+    pub fn new(warnings: Vec<String>) -> Warnings {
+        Warnings(Rc::new(RefCell::new(warnings)))
+    }
+
+    pub fn push(&self, warning: String) {
+        self.0.borrow_mut().push(warning);
+    }
+
+    pub fn extend(&self, warnings: impl IntoIterator<Item = String>) {
+        self.0.borrow_mut().extend(warnings);
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+
+    /// The warning added `i`th, if there are that many.
+    pub fn get(&self, i: usize) -> Option<String> {
+        self.0.borrow().get(i).cloned()
+    }
+
+    /// The warnings so far, all of them once the days have been read, leaving none.
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut self.0.borrow_mut())
+    }
+}
+
+/// Check the dates of the days of an energy file as they stream through in the order they were read, handling
+/// repeated dates as `duplicates` says, adding warnings for repeated dates and dates out of order (earlier than the
+/// date before) to `warnings` once the days have all been read.
+///
+/// Only the dates seen are kept, not the days.
+pub fn check_dates<I>(name: &str, days: I, duplicates: Duplicates, warnings: &Warnings) -> impl Iterator<Item = Result<DayEnergy>> where
+I: IntoIterator<Item = Result<DayEnergy>> {
+    let (name, warnings) = (name.to_string(), warnings.clone());
+    let mut days = days.into_iter();
+    let mut seen = HashSet::new();
+    let mut last: Option<NaiveDate> = None;
+    let (mut repeated, mut out_of_order) = ((0, None), (0, None)); // count and the first
+    let mut done = false;
+    std::iter::from_fn(move || loop {
+        if done {
+            return None;
+        }
+        let Some(day) = days.next() else {
+            done = true;
+            if let (n, Some(first)) = repeated {
+                let action = if duplicates == Duplicates::Dedupe { "only the first copy is priced" } else { "every copy is priced" };
+                warnings.push(format!("{}: {} dates are repeated (the first {}), {}", name, n, first, action));
+            }
+            if let (n, Some((date, before))) = out_of_order {
+                warnings.push(format!("{}: {} dates are out of order (the first {} after {})", name, n, date, before));
+            }
+            return None;
+        };
+        let Ok(d) = &day else {
+            return Some(day);
+        };
+        if let Some(before) = last.filter(|l| d.date < *l) {
+            out_of_order = (out_of_order.0 + 1, out_of_order.1.or(Some((d.date, before))));
+        }
+        last = Some(d.date);
+        if !seen.insert(d.date) {
+            repeated = (repeated.0 + 1, repeated.1.or(Some(d.date)));
+            match duplicates {
+                Duplicates::Error => {
+                    done = true;
+                    return Some(Err(anyhow!("check_dates: {}: {} is repeated", name, d.date)));
+                },
+                Duplicates::Dedupe => continue,
+                Duplicates::Warn => (),
+            }
+        }
+        return Some(day);
+    })
+}
+
+/// Local time (minutes since midnight) of daylight saving changeovers: clocks go forward from 02:00 to 03:00
+/// (an hour of intervals missing) or back from 03:00 to 02:00 (an hour of intervals repeated).
+pub const DST_CHANGEOVER: usize = 2 * 60;
+
+/// A daylight saving changeover: clocks go forward an hour (a day an hour short) or back (an hour long).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Changeover {
+    Forward,
+    Back,
+}
+
+// The first Sunday in a month, when clocks change
+fn first_sunday(year: i32, month: u32) -> Option<NaiveDate> {
+    NaiveDate::from_weekday_of_month_opt(year, month, Weekday::Sun, 1)
+}
+
+/// The daylight saving changeover on a date, if any: as in south eastern Australia, clocks go forward at
+/// [`DST_CHANGEOVER`] on the first Sunday in October and back on the first Sunday in April.
+pub fn changeover(date: NaiveDate) -> Option<Changeover> {
+    match Some(date) {
+        d if d == first_sunday(date.year(), 10) => Some(Changeover::Forward),
+        d if d == first_sunday(date.year(), 4) => Some(Changeover::Back),
+        _ => None,
+    }
+}
+
+/// Whether the whole of a date is in daylight saving time (see [`changeover`]), after the day clocks go forward and
+/// before the day they go back.
+pub fn daylight_saving(date: NaiveDate) -> bool {
+    first_sunday(date.year(), 10).is_some_and(|d| date > d) || first_sunday(date.year(), 4).is_some_and(|d| date < d)
+}
+
+/// One day of interval energy data (kWh), consumption or feedin.
+///
+/// On daylight saving changeover days (see [`changeover`]) there may be an hour less or more of intervals than usual.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DayEnergy {
+    pub date: NaiveDate,
+    pub interval: usize,        // interval length (minutes), the first interval starts at midnight
+    pub energy: Vec<f64>,       // kWh per interval
+    pub quality: Vec<Quality>,  // quality per interval
+}
+
+impl DayEnergy {
+    /// Day of all actual readings, with the interval length inferred from the number of readings.
+    pub fn new(date: NaiveDate, energy: Vec<f64>) -> Result<DayEnergy> {
+        if energy.is_empty() {
+            return Err(anyhow!("DayEnergy::new: zero data items for {}", date));
+        }
+        let interval = (24 * 60) / energy.len(); // 288 data points => 5 minute intervals
+        let quality = vec![Quality::Actual; energy.len()];
+        Ok(DayEnergy { date, interval, energy, quality })
+    }
+
+    /// Day of all actual readings with the given interval length, allowing an hour less or more
+    /// of readings on daylight saving changeover days (see [`changeover`]).
+    pub fn with_interval(date: NaiveDate, interval: usize, energy: Vec<f64>) -> Result<DayEnergy> {
+        if interval == 0 {
+            return Err(anyhow!("DayEnergy::with_interval: zero interval length for {}", date));
+        }
+        let usual = (24 * 60) / interval;
+        let changed = changed_len(date, interval);
+        if energy.len() != usual && Some(energy.len()) != changed {
+            return Err(anyhow!(
+                "DayEnergy::with_interval: {} data items for {} is not {}{} for {} minute intervals",
+                energy.len(), date, usual, changed.map_or(String::new(), |n| format!(" (or {} on a daylight saving changeover)", n)), interval
+            ));
+        }
+        let quality = vec![Quality::Actual; energy.len()];
+        Ok(DayEnergy { date, interval, energy, quality })
+    }
+
+    /// Local start time (minutes since midnight) of interval `i`, allowing for daylight saving changeovers.
+    pub fn minute(&self, i: usize) -> i32 {
+        self.clock()(i)
+    }
+
+    /// Local start times (minutes since midnight) of every interval in order, as [`DayEnergy::minute`] but dividing
+    /// once per day rather than once per interval.
+    pub fn minutes(&self) -> impl Iterator<Item = i32> {
+        (0..self.energy.len()).map(self.clock())
+    }
+
+    /// The interval containing local time `minute` (since midnight), the later of the two in the hour repeated when
+    /// clocks go back if `repeated`, as [`DayEnergy::minute`] in reverse. None for a time not in the day.
+    pub fn index(&self, minute: i32, repeated: bool) -> Option<usize> {
+        let (len, interval) = (self.energy.len(), self.interval);
+        let hour = 60 / interval;
+        let changeover = DST_CHANGEOVER / interval;
+        let local = usize::try_from(minute).ok()? / interval;
+        let i = match self.changeover() {
+            Some(Changeover::Forward) if local >= changeover + hour => local - hour,
+            Some(Changeover::Forward) if local >= changeover => return None, // the hour skipped
+            Some(Changeover::Back) if local >= changeover + hour || repeated && local >= changeover => local + hour,
+            _ => local,
+        };
+        (i < len).then_some(i)
+    }
+
+    // The changeover on the day if its intervals are in local time, an hour short or long
+    fn changeover(&self) -> Option<Changeover> {
+        changeover(self.date).filter(|_| Some(self.energy.len()) == changed_len(self.date, self.interval))
+    }
+
+    // Map an interval's index to its local start time
+    fn clock(&self) -> impl Fn(usize) -> i32 {
+        let interval = self.interval;
+        let hour = 60 / interval;
+        let changeover = DST_CHANGEOVER / interval;
+        let shift = self.changeover();
+        move |i| {
+            let local = match shift {
+                Some(Changeover::Forward) if i >= changeover => i + hour,
+                Some(Changeover::Back) if i >= changeover + hour => i - hour, // the second time round the repeated hour
+                _ => i,
+            };
+            (local * interval) as i32
+        }
+    }
+}
+
+// The number of intervals on a daylight saving changeover date in local time, None on other dates (or for intervals
+// longer than an hour)
+fn changed_len(date: NaiveDate, interval: usize) -> Option<usize> {
+    let (usual, hour) = ((24 * 60) / interval, 60 / interval);
+    match changeover(date) {
+        _ if hour == 0 => None,
+        Some(Changeover::Forward) => Some(usual - hour),
+        Some(Changeover::Back) => Some(usual + hour),
+        None => None,
+    }
+}
+
+/// Map interval readings (local start time, kWh or None if missing, quality), in any order, into days.
+///
+/// The interval length is the smallest gap between readings. Intervals of a day without a reading (or with a
+/// None kWh) are zero with [`Quality::Null`].
+pub fn readings_days(mut readings: Vec<(NaiveDateTime, Option<f64>, Quality)>) -> Result<Vec<DayEnergy>> {
+    readings.sort_by_key(|r| r.0);
+    let interval = readings.windows(2).map(|w| (w[1].0 - w[0].0).num_minutes()).filter(|m| *m > 0).min()
+        .context("readings_days: need at least two readings to find the interval length")? as usize;
+    if (24 * 60) % interval != 0 {
+        return Err(anyhow!("readings_days: interval length {} minutes doesn't divide a day", interval));
+    }
+    let mut days: BTreeMap<NaiveDate, DayEnergy> = BTreeMap::new();
+    for (time, kwh, quality) in readings {
+        let minute = (time.num_seconds_from_midnight() / 60) as usize;
+        if time.second() != 0 || !minute.is_multiple_of(interval) {
+            return Err(anyhow!("readings_days: reading at {} doesn't start a {} minute interval", time, interval));
+        }
+        let num_intervals = (24 * 60) / interval;
+        let day = days.entry(time.date()).or_insert_with(|| DayEnergy {
+            date: time.date(),
+            interval,
+            energy: vec![0.0; num_intervals],
+            quality: vec![Quality::Null; num_intervals],
+        });
+        let i = minute / interval;
+        if day.quality[i] != Quality::Null {
+            return Err(anyhow!("readings_days: more than one reading at {}", time));
+        }
+        if let Some(kwh) = kwh {
+            day.energy[i] = kwh;
+            day.quality[i] = quality;
+        }
+    }
+    info!("readings_days: {} days of {} minute intervals", days.len(), interval);
+    Ok(days.into_values().collect())
+}
+
+/// Parse a timestamp `YYYY-MM-DD HH:MM[:SS]`, with `T` rather than a space, `DD/MM/YYYY HH:MM[:SS]`,
+/// or RFC 3339 (in its own time zone).
+pub fn parse_timestamp(s: &str) -> Result<NaiveDateTime> {
+    let s = s.trim();
+    ["%Y-%m-%d %H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S", "%d/%m/%Y %H:%M", "%d/%m/%Y %H:%M:%S"].iter()
+    .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())
+    .or_else(|| DateTime::parse_from_rfc3339(s).ok().map(|t| t.naive_local()))
+    .ok_or_else(|| anyhow!("parse_timestamp: '{}' is not a timestamp YYYY-MM-DD HH:MM", s))
+}
+
+/// Keep only the days from `from` to `to` (both inclusive, None for no limit).
+pub fn filter_dates<I>(days: I, from: Option<NaiveDate>, to: Option<NaiveDate>) -> impl Iterator<Item = Result<DayEnergy>> where
+I: IntoIterator<Item = Result<DayEnergy>> {
+    days.into_iter().filter(move |day| match day {
+        Ok(d) => from.is_none_or(|f| f <= d.date) && to.is_none_or(|t| d.date <= t),
+        Err(_) => true, // keep errors so they are reported
+    })
+}
+
+/// Merge named sources of days (e.g. one file per month) into a single dataset in date order.
+///
+/// All days must have the same interval length. Where a source has dates already read from an
+/// earlier source the earlier data is kept, and a warning is returned naming the source and dates.
+pub fn merge_days<I>(sources: Vec<(String, I)>) -> Result<(Vec<DayEnergy>, Vec<String>)> where
+I: IntoIterator<Item = Result<DayEnergy>> {
+    merge_sources(sources, false)
+}
+
+/// Merge overlapping exports of the same meter (e.g. downloaded every few weeks) like [`merge_days`], except that
+/// a date already read from an earlier source is dropped if it has the same kWh (whatever the quality) and is an
+/// error if it doesn't. The warnings say how many dates of each source were duplicates.
+pub fn merge_exports<I>(sources: Vec<(String, I)>) -> Result<(Vec<DayEnergy>, Vec<String>)> where
+I: IntoIterator<Item = Result<DayEnergy>> {
+    merge_sources(sources, true)
+}
+
+// Merge sources, keeping the first copy of a date and, if `conflicts`, erroring when a later copy's kWh differ
+fn merge_sources<I>(sources: Vec<(String, I)>, conflicts: bool) -> Result<(Vec<DayEnergy>, Vec<String>)> where
+I: IntoIterator<Item = Result<DayEnergy>> {
+    let mut days: BTreeMap<NaiveDate, DayEnergy> = BTreeMap::new();
+    let mut warnings = Vec::new();
+    for (name, source) in sources {
+        let mut overlaps = Vec::new();
+        for day in source {
+            let day = day?;
+            if let Some(first) = days.values().next().filter(|first| first.interval != day.interval) {
+                return Err(anyhow!(
+                    "merge_days: {} has {} minute intervals on {} but {} has {} minute intervals",
+                    name, day.interval, day.date, first.date, first.interval
+                ));
+            }
+            match days.entry(day.date) {
+                Entry::Occupied(e) if conflicts && e.get().energy != day.energy => return Err(conflict(&name, e.get(), &day)),
+                Entry::Occupied(_) => overlaps.push(day.date),
+                Entry::Vacant(e) => { e.insert(day); },
+            }
+        }
+        if let (Some(from), Some(to)) = (overlaps.first(), overlaps.last()) {
+            let action = if conflicts { "are the same as in an earlier file and are deduplicated" } else { "were already read from an earlier file and are ignored" };
+            warnings.push(format!("{}: {} dates from {} to {} {}", name, overlaps.len(), from, to, action));
+        }
+    }
+    info!("merge_days: {} days, {} warnings", days.len(), warnings.len());
+    Ok((days.into_values().collect(), warnings))
+}
+
+// The error for a date with different kWh in source `name` than in an earlier source, at the first interval differing
+fn conflict(name: &str, earlier: &DayEnergy, day: &DayEnergy) -> anyhow::Error {
+    match earlier.energy.iter().zip(&day.energy).position(|(a, b)| a != b) {
+        Some(i) if earlier.energy.len() == day.energy.len() => {
+            let minute = day.minute(i);
+            anyhow!("merge_exports: {} has {} kWh at {} {:02}:{:02} but an earlier file has {} kWh",
+                name, day.energy[i], day.date, minute / 60, minute % 60, earlier.energy[i])
+        },
+        _ => anyhow!("merge_exports: {} has {} intervals on {} but an earlier file has {}", name, day.energy.len(), day.date, earlier.energy.len()),
+    }
+}
+
+/// The net (import, export) from gross metered consumption (the household load) and generation, with warnings:
+/// in each interval generation is used in the house first and only the surplus exported.
+///
+/// Each import interval has the quality of the consumption and each export interval that of the generation.
+/// Days of consumption without generation are imported as they are, and days of generation without consumption
+/// are exported as they are, each with a warning. Generation must have the same intervals as the consumption
+/// on the same date.
+pub fn net_metering(consumption: &[DayEnergy], generation: &[DayEnergy]) -> Result<(Vec<DayEnergy>, Vec<DayEnergy>, Vec<String>)> {
+    let mut generation = generation.iter().map(|d| (d.date, d)).collect::<BTreeMap<NaiveDate, &DayEnergy>>();
+    let (mut import, mut export, mut no_generation) = (Vec::with_capacity(consumption.len()), Vec::new(), 0);
+    for day in consumption {
+        let Some(gen) = generation.remove(&day.date) else {
+            no_generation += 1;
+            import.push(day.clone());
+            continue;
+        };
+        if gen.energy.len() != day.energy.len() {
+            return Err(anyhow!("net_metering: {} has {} consumption but {} generation intervals", day.date, day.energy.len(), gen.energy.len()));
+        }
+        let mut used = day.clone();
+        let mut surplus = gen.clone();
+        for (u, s) in used.energy.iter_mut().zip(surplus.energy.iter_mut()) {
+            let self_consumed = u.min(*s).max(0.0);
+            *u -= self_consumed;
+            *s -= self_consumed;
+        }
+        import.push(used);
+        export.push(surplus);
+    }
+    let mut warnings = Vec::new();
+    if no_generation > 0 {
+        warnings.push(format!("{} days of consumption have no generation and are imported as they are", no_generation));
+    }
+    if !generation.is_empty() {
+        warnings.push(format!("{} days of generation have no consumption and are exported as they are", generation.len()));
+        export.extend(generation.into_values().cloned());
+        export.sort_by_key(|d| d.date);
+    }
+    Ok((import, export, warnings))
+}
+
+/// A day's intervals summed into longer `interval` minute intervals (e.g. 5 minute data into 30 minute intervals,
+/// for tariffs defined on 30 minute boundaries), each with the worst quality of the intervals summed.
+///
+/// `interval` must be a multiple of the day's interval length and divide the day, and on daylight saving changeover
+/// days also divide the hour. A day already at `interval` is returned as it is.
+pub fn resample(day: &DayEnergy, interval: usize) -> Result<DayEnergy> {
+    if interval == 0 || !interval.is_multiple_of(day.interval) || !(24 * 60usize).is_multiple_of(interval) {
+        return Err(anyhow!("resample: can't sum {} minute intervals of {} into {} minute intervals, which must be a multiple of {} dividing the day",
+            day.interval, day.date, interval, day.interval));
+    }
+    if day.energy.len() != (24 * 60) / day.interval && !60usize.is_multiple_of(interval) {
+        return Err(anyhow!("resample: can't sum the daylight saving changeover day {} into {} minute intervals, which don't divide the hour", day.date, interval));
+    }
+    let n = interval / day.interval;
+    Ok(DayEnergy {
+        date: day.date,
+        interval,
+        energy: day.energy.chunks(n).map(|c| c.iter().sum()).collect(),
+        quality: day.quality.chunks(n).map(|c| c.iter().copied().max().unwrap_or(Quality::Null)).collect(),
+    })
+}
+
+/// The file name for standard input, so the tool can read energy data from a pipeline.
+pub const STDIN: &str = "-";
+
+/// Open a file for reading, or standard input for [`STDIN`], decompressing it if it's gzip or zip
+/// (detected from its first bytes, see [`crate::compress`]) or converting the first worksheet to CSV
+/// if it's an Excel workbook (see [`crate::xlsx`]).
+pub fn open_input(path: &str) -> Result<Box<dyn io::Read>> {
+    open_input_counted(path, &BytesRead::default())
+}
+
+/// Open a file like [`open_input`], counting the bytes read from it (before any decompression) in `read`, e.g. for
+/// a [`crate::progress::Progress`] bar.
+pub fn open_input_counted(path: &str, read: &BytesRead) -> Result<Box<dyn io::Read>> {
+    let input: Box<dyn io::Read> = match path {
+        STDIN => Box::new(io::stdin()),
+        _ => Box::new(std::fs::File::open(Path::new(path)).map_err(|e| anyhow!("open_input: {}: {}", path, e))?),
+    };
+    let mut input = io::BufReader::new(Counting::new(input, read));
+    match Compression::detect(input.fill_buf()?) {
+        Compression::None => Ok(Box::new(input)),
+        compression => {
+            info!("open_input: decompressing {:?} file {}", compression, path);
+            let mut data = Vec::new();
+            input.read_to_end(&mut data)?;
+            Ok(Box::new(io::Cursor::new(input_bytes(path, data)?)))
+        },
+    }
+}
+
+/// The contents of a file already in memory (e.g. uploaded in a browser) as [`open_input`] would read it:
+/// decompressed if it's gzip or zip, or the first worksheet as CSV if it's an Excel workbook.
+pub fn input_bytes(name: &str, data: Vec<u8>) -> Result<Vec<u8>> {
+    let data = match Compression::detect(&data) {
+        Compression::None => return Ok(data),
+        Compression::Zip if is_xlsx(&data) => xlsx_csv(&data),
+        _ => decompress(data),
+    };
+    data.map_err(|e| anyhow!("input_bytes: {}: {}", name, e))
+}
+
+/// A data line of a CSV file with a header, to read its fields with the function reading it, the file, line and
+/// column (with its header) and the value in any error, e.g.
+/// `load_tariff: tariff.csv: line 3: column 5 ($/kWh) '0.2x'`.
+pub struct CsvLine<'a> {
+    pub func: &'static str,
+    pub file: &'a str,
+    pub line: usize, // in the file, from 1 for the header
+    pub headers: &'a StringRecord,
+    pub record: &'a StringRecord,
+}
+
+impl<'a> CsvLine<'a> {
+    /// The `index`th data line (from 0) of a file.
+    pub fn new(func: &'static str, file: &'a str, index: usize, headers: &'a StringRecord, record: &'a StringRecord) -> CsvLine<'a> {
+        CsvLine { func, file, line: index + 2, headers, record }
+    }
+
+    /// The trimmed field in a column (from 0), empty if the line is too short.
+    pub fn get(&self, column: usize) -> &'a str {
+        self.record.get(column).map(str::trim).unwrap_or_default()
+    }
+
+    /// The trimmed field in a column (from 0), None if it's empty or the line is too short.
+    pub fn optional(&self, column: usize) -> Option<&'a str> {
+        Some(self.get(column)).filter(|x| !x.is_empty())
+    }
+
+    /// Where a field is and its value, for the context of an error.
+    pub fn at(&self, column: usize) -> String {
+        format!("{}: {}: line {}: {} '{}'", self.func, self.file, self.line, column_text(self.headers, column), self.get(column))
+    }
+
+    /// Where the line is, for the context of an error in several fields.
+    pub fn at_line(&self) -> String {
+        format!("{}: {}: line {}", self.func, self.file, self.line)
+    }
+
+    /// Parse the field in a column (from 0).
+    pub fn parse<T>(&self, column: usize) -> Result<T> where T: FromStr, T::Err: Into<anyhow::Error> {
+        self.get(column).parse::<T>().map_err(Into::into).with_context(|| self.at(column))
+    }
+
+    /// Convert the field in a column (from 0).
+    pub fn parse_with<T>(&self, column: usize, f: impl FnOnce(&'a str) -> Result<T>) -> Result<T> {
+        f(self.get(column)).with_context(|| self.at(column))
+    }
+}
+
+// A column (from 0) as its number from 1 and its header if it has one, e.g. "column 5 ($/kWh)"
+fn column_text(headers: &StringRecord, column: usize) -> String {
+    match headers.get(column).map(str::trim).filter(|h| !h.is_empty()) {
+        Some(h) => format!("column {} ({})", column + 1, h),
+        None => format!("column {}", column + 1),
+    }
+}
+
+/// Energy data file formats, as told apart by [`detect_format`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataFormat {
+    WideCsv,     // a line per day, see read_csv_energy
+    LongCsv,     // a line per interval, see crate::longcsv
+    Nem12,
+    GreenButton,
+    Parquet,
+    Sqlite,
+}
+
+/// Detect the format of an energy data file from its extension and its first few kilobytes (after decompressing
+/// it or converting a workbook to CSV, see [`open_input`]). Standard input can't be read twice, so it's taken to be
+/// wide CSV.
+///
+/// A CSV file is long if the first field of its second line is a timestamp (see [`parse_timestamp`]) rather than a date.
+pub fn detect_format(path: &str) -> Result<DataFormat> {
+    if path == STDIN {
+        return Ok(DataFormat::WideCsv);
+    }
+    let mut head = Vec::new();
+    open_input(path)?.take(64 * 1024).read_to_end(&mut head)?;
+    let extension = Path::new(path).extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+    let format = detect_format_bytes(&head, extension.as_deref());
+    info!("detect_format: {} is {:?}", path, format);
+    Ok(format)
+}
+
+/// Detect the format of energy data like [`detect_format`] from its first few kilobytes (decompressed) and the
+/// extension of its file name (lower case) if there is one.
+pub fn detect_format_bytes(head: &[u8], extension: Option<&str>) -> DataFormat {
+    let text = String::from_utf8_lossy(head);
+    if head.starts_with(crate::parquet::MAGIC) {
+        DataFormat::Parquet
+    } else if head.starts_with(crate::sqlite::MAGIC) {
+        DataFormat::Sqlite
+    } else if text.trim_start_matches('\u{feff}').trim_start().starts_with('<') || extension == Some("xml") {
+        DataFormat::GreenButton
+    } else if text.trim_start_matches('\u{feff}').starts_with("100,") {
+        DataFormat::Nem12
+    } else {
+        let first = text.lines().nth(1).and_then(|line| line.split(',').next()).unwrap_or_default();
+        match parse_timestamp(first.trim_matches('"')) {
+            Ok(_) => DataFormat::LongCsv,
+            Err(_) => DataFormat::WideCsv,
+        }
+    }
+}
+
+/// Read energy data in memory (e.g. an uploaded file, see [`input_bytes`]) in any text format: wide or long CSV,
+/// NEM12 or Green Button, detected from its contents. `name` is used in errors.
+pub fn read_energy_bytes(name: &str, data: Vec<u8>, direction: Direction) -> Result<Vec<DayEnergy>> {
+    let data = input_bytes(name, data)?;
+    let extension = Path::new(name).extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+    match detect_format_bytes(&data[..data.len().min(64 * 1024)], extension.as_deref()) {
+        DataFormat::WideCsv => read_csv_energy_from(name, data.as_slice(), &CsvLayout::default())?.collect(),
+        DataFormat::LongCsv => crate::longcsv::read_long_csv_from(name, data.as_slice(), direction),
+        DataFormat::Nem12 => crate::nem12::read_nem12_from(name, data.as_slice(), direction),
+        DataFormat::GreenButton => crate::greenbutton::parse_green_button(&String::from_utf8_lossy(&data), direction),
+        format => Err(anyhow!("read_energy_bytes: {}: {:?} data can only be read from a file", name, format)),
+    }
+}
+
+/// Parse a date given as `YYYYMMDD` or `YYYY-MM-DD`.
+pub fn parse_date(s: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(s.trim(), "%Y%m%d")
+    .or_else(|_| NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d"))
+    .map_err(|e| anyhow!("parse_date: '{}' is not YYYYMMDD or YYYY-MM-DD: {}", s, e))
+}
+
+/// Date formats (chrono `strftime` syntax) recognised in energy data when no format is given, tried in order.
+/// Dates with slashes are taken to be day first, give `--date-format %m/%d/%Y` for month first.
+pub const DATE_FORMATS: [&str; 6] = ["%Y%m%d", "%Y-%m-%d", "%d/%m/%Y", "%Y/%m/%d", "%d-%m-%Y", "%d.%m.%Y"];
+
+/// The first of [`DATE_FORMATS`] that `date` is in.
+pub fn detect_date_format(date: &str) -> Result<&'static str> {
+    DATE_FORMATS.iter().find(|f| NaiveDate::parse_from_str(date, f).is_ok()).copied()
+    .ok_or_else(|| anyhow!("detect_date_format: '{}' is not in a known date format ({}), give the format", date, DATE_FORMATS.join(", ")))
+}
+
+/// Read a wide CSV energy file (or standard input for [`STDIN`]): 1 header line then one line per date with
+/// the date (`YYYYMMDD`, or another of [`DATE_FORMATS`]) followed by the kWh for each interval of the day.
+///
+/// The interval length is set by the header line: by its time labels (the start or end of each interval, e.g.
+/// `00:00` or `00:30` first) if every column has one, otherwise by the number of columns. Every line must have the same
+/// number of columns, except on daylight saving changeover days which have an hour less or more of intervals.
+///
+/// Lines are read one at a time into a reused buffer, so memory use doesn't grow with the size of the file, and plain
+/// decimal kWh are parsed straight from its bytes.
+pub fn read_csv_energy(csv_energy: &str) -> Result<impl Iterator<Item = Result<DayEnergy>>> {
+    read_csv_energy_reader(csv_energy, open_input(csv_energy)?, &CsvLayout::default())
+}
+
+/// How to read the lines of a wide CSV energy file: the date format and which columns (from 0) hold the date
+/// and the interval kWh, so exports with extra columns can be read as they are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvLayout {
+    pub date_format: Option<String>, // chrono strftime syntax (e.g. %d/%m/%Y), detected from the first date if None
+    pub date_column: usize,
+    pub first_column: usize,         // the first interval
+    pub last_column: Option<usize>,  // the last interval (inclusive), None for the end of each line
+}
+
+impl Default for CsvLayout {
+    fn default() -> CsvLayout {
+        CsvLayout { date_format: None, date_column: 0, first_column: 1, last_column: None }
+    }
+}
+
+/// Parse a 1-based inclusive range of columns, `N-M`, `N-` (to the end of the line) or `N`, into the first
+/// and last columns from 0.
+pub fn parse_columns(spec: &str) -> Result<(usize, Option<usize>)> {
+    let err = || anyhow!("parse_columns: '{}' is not a column range N-M, N- or N (columns from 1)", spec);
+    let column = |x: &str| x.trim().parse::<usize>().ok().and_then(|c| c.checked_sub(1)).ok_or_else(err);
+    match spec.split_once('-') {
+        None => column(spec).map(|c| (c, Some(c))),
+        Some((first, "")) => Ok((column(first)?, None)),
+        Some((first, last)) => match (column(first)?, column(last)?) {
+            (first, last) if first <= last => Ok((first, Some(last))),
+            _ => Err(err()),
+        },
+    }
+}
+
+/// Read a wide CSV energy file like [`read_csv_energy`] with the lines laid out as `layout` says.
+///
+/// With a last interval column every line must have it, so daylight saving changeover days need the intervals
+/// to run to the end of the line.
+pub fn read_csv_energy_layout(csv_energy: &str, layout: &CsvLayout) -> Result<impl Iterator<Item = Result<DayEnergy>>> {
+    read_csv_energy_reader(csv_energy, open_input(csv_energy)?, layout)
+}
+
+/// Read wide CSV energy data like [`read_csv_energy_layout`] from any reader (e.g. a byte slice), `name` being used in
+/// errors in place of the file name.
+pub fn read_csv_energy_from<R: io::Read>(name: &str, input: R, layout: &CsvLayout) -> Result<impl Iterator<Item = Result<DayEnergy>>> {
+    read_csv_energy_reader(name, input, layout)
+}
+
+fn read_csv_energy_reader<R: io::Read>(csv_energy: &str, input: R, layout: &CsvLayout) -> Result<CsvEnergy<R>> {
+    info!("read_csv_energy: loading CSV file {}", csv_energy);
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true) // daylight saving changeover days have more or fewer columns
+        .from_reader(input);
+
+    let headers = reader.headers()?.clone();
+    let interval = header_interval(&headers, layout)?;
+    Ok(CsvEnergy {
+        path: csv_energy.to_string(),
+        headers,
+        reader,
+        record: ByteRecord::new(),
+        interval,
+        line_no: 0,
+        blank: false,
+        layout: layout.clone(),
+    })
+}
+
+// A header time label (H:MM or HH:MM, optionally with seconds) as minutes since midnight
+fn header_minute(label: &str) -> Option<usize> {
+    let mut parts = label.trim().split(':').map(|x| x.parse::<usize>().ok());
+    match (parts.next()??, parts.next()??, parts.next().map(|ss| ss.filter(|ss| *ss < 60)), parts.next()) {
+        (hh, mm, None | Some(Some(_)), None) if hh <= 24 && mm < 60 => Some(hh * 60 + mm),
+        _ => None,
+    }
+}
+
+// The interval length from the interval columns of the header line. When every interval column is labelled with
+// a time, the labels must be evenly spaced over the whole day starting at 00:00 (the start of each interval) or one
+// interval later (the end of each interval, e.g. 00:30 to 24:00), so a misaligned file fails rather than being priced
+// at the wrong times. Otherwise the interval length is set by the number of columns.
+fn header_interval(headers: &StringRecord, layout: &CsvLayout) -> Result<usize> {
+    let last = layout.last_column.unwrap_or(headers.len().saturating_sub(1));
+    if layout.first_column > last || last >= headers.len() {
+        return Err(anyhow!("read_csv_energy: zero data items in header line"));
+    }
+    let columns = layout.first_column..=last;
+    let label = |i: usize| headers[layout.first_column + i].trim();
+    let Some(labels) = headers.iter().skip(layout.first_column).take(columns.clone().count()).map(header_minute).collect::<Option<Vec<usize>>>() else {
+        return Ok((24 * 60) / columns.count());
+    };
+    let interval = match labels.as_slice() {
+        [_] => 24 * 60,
+        [first, second, ..] => second.checked_sub(*first).filter(|i| *i > 0)
+            .ok_or_else(|| anyhow!("read_csv_energy: header time labels {} and {} are not in order", label(0), label(1)))?,
+        [] => unreachable!("read_csv_energy: header has data items"),
+    };
+    let offset = labels[0];
+    if offset != 0 && offset != interval {
+        return Err(anyhow!(
+            "read_csv_energy: header time labels start at {}, not 00:00 (interval starts) or {:02}:{:02} (interval ends) for {} minute intervals, the data is misaligned",
+            label(0), interval / 60, interval % 60, interval
+        ));
+    }
+    for (i, minute) in labels.iter().enumerate() {
+        let expected = offset + i * interval;
+        if *minute != expected && *minute != expected % (24 * 60) {
+            return Err(anyhow!(
+                "read_csv_energy: header column {} is {}, expected {:02}:{:02} for {} minute intervals",
+                layout.first_column + i + 1, label(i), expected / 60 % 24, expected % 60, interval
+            ));
+        }
+    }
+    if labels.len() * interval != 24 * 60 {
+        return Err(anyhow!("read_csv_energy: header has {} intervals of {} minutes, not a whole day", labels.len(), interval));
+    }
+    Ok(interval)
+}
+
+/// Read a wide CSV energy file like [`read_csv_energy_layout`], handling blank intervals as set by `missing`,
+/// and return the days with warnings for any days skipped.
+///
+/// Intervals set to zero or interpolated have [`Quality::Substituted`]. A day with no readings at all can't be
+/// interpolated, it is an error.
+pub fn read_csv_energy_missing(csv_energy: &str, missing: Missing, layout: &CsvLayout) -> Result<(Vec<DayEnergy>, Vec<String>)> {
+    read_csv_energy_days(csv_energy, missing, layout, false)
+}
+
+/// Read a wide CSV energy file like [`read_csv_energy_missing`], but skip the lines that can't be read
+/// (e.g. a malformed value, or a blank day that can't be interpolated) rather than failing, logging each
+/// and adding a warning with how many were skipped and the first error.
+pub fn read_csv_energy_lenient(csv_energy: &str, missing: Missing, layout: &CsvLayout) -> Result<(Vec<DayEnergy>, Vec<String>)> {
+    read_csv_energy_days(csv_energy, missing, layout, true)
+}
+
+fn read_csv_energy_days(csv_energy: &str, missing: Missing, layout: &CsvLayout, lenient: bool) -> Result<(Vec<DayEnergy>, Vec<String>)> {
+    let mut days = Vec::new();
+    let mut skipped = Vec::new();
+    let mut malformed = Vec::new();
+    let reader = CsvEnergy { blank: missing != Missing::Error, ..read_csv_energy_reader(csv_energy, open_input(csv_energy)?, layout)? };
+    let mut skip = |e: anyhow::Error| match lenient {
+        true => {
+            warn!("read_csv_energy_lenient: skipping {}", e);
+            malformed.push(e);
+            Ok(())
+        },
+        false => Err(e),
+    };
+    for day in reader {
+        let mut day = match day {
+            Ok(day) => day,
+            Err(e) => {
+                skip(e)?;
+                continue;
+            },
+        };
+        let blanks = day.quality.iter().filter(|q| **q == Quality::Null).count();
+        if blanks == 0 {
+            days.push(day);
+            continue;
+        }
+        match missing {
+            Missing::Error => unreachable!("read_csv_energy_missing: blank intervals are errors"),
+            Missing::Zero => {
+                day.energy.iter_mut().filter(|e| e.is_nan()).for_each(|e| *e = 0.0);
+            },
+            Missing::Interpolate => if let Err(e) = interpolate(&mut day) {
+                skip(anyhow!("read_csv_energy: {}: {}", csv_energy, e))?;
+                continue;
+            },
+            Missing::SkipDay => {
+                warn!("read_csv_energy_missing: skipping {} with {} blank intervals", day.date, blanks);
+                skipped.push(day.date);
+                continue;
+            },
+        }
+        day.quality.iter_mut().filter(|q| **q == Quality::Null).for_each(|q| *q = Quality::Substituted);
+        days.push(day);
+    }
+    let mut warnings = match (skipped.first(), skipped.last()) {
+        (Some(from), Some(to)) => vec![format!("{}: {} dates from {} to {} with blank intervals are skipped", csv_energy, skipped.len(), from, to)],
+        _ => Vec::new(),
+    };
+    if let Some(first) = malformed.first() {
+        warnings.push(format!("{}: {} malformed lines are skipped, the first: {}", csv_energy, malformed.len(), first));
+    }
+    info!("read_csv_energy_missing: {} days, {} skipped", days.len(), skipped.len());
+    Ok((days, warnings))
+}
+
+/// Write days of energy as a wide CSV file that [`read_csv_energy`] reads back: a header line labelled with the
+/// start time of each interval of the first day, then a line per day with the date (`YYYYMMDD`) and the kWh of
+/// each interval, empty for [`Quality::Null`].
+pub fn write_csv_energy<W: io::Write>(days: &[DayEnergy], w: W) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(w);
+    let interval = days.first().map_or(30, |d| d.interval);
+    let labels = (0..24 * 60).step_by(interval).map(|m| format!("{:02}:{:02}", m / 60, m % 60));
+    writer.write_record(["Date/Time".to_string()].into_iter().chain(labels))?;
+    for day in days {
+        let kwh = day.energy.iter().zip(&day.quality).map(|(e, q)| match q {
+            Quality::Null => String::new(),
+            _ => e.to_string(),
+        });
+        writer.write_record([day.date.format("%Y%m%d").to_string()].into_iter().chain(kwh))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// Replace blank (NaN) intervals with the straight line between the readings either side,
+// or the nearest reading at the start or end of the day
+fn interpolate(day: &mut DayEnergy) -> Result<()> {
+    let readings = (0..day.energy.len()).filter(|i| !day.energy[*i].is_nan()).collect::<Vec<usize>>();
+    if readings.is_empty() {
+        return Err(anyhow!("interpolate: {} has no readings to interpolate from", day.date));
+    }
+    for i in 0..day.energy.len() {
+        if day.energy[i].is_nan() {
+            let after = readings.partition_point(|r| *r < i);
+            day.energy[i] = match (after.checked_sub(1).map(|b| readings[b]), readings.get(after)) {
+                (Some(b), Some(a)) => day.energy[b] + (day.energy[*a] - day.energy[b]) * (i - b) as f64 / (a - b) as f64,
+                (Some(b), None) => day.energy[b],
+                (None, Some(a)) => day.energy[*a],
+                (None, None) => unreachable!("interpolate: there are readings"),
+            };
+        }
+    }
+    Ok(())
+}
+
+// Powers of ten exactly representable as f64
+const POWERS_OF_TEN: [f64; 23] = [
+    1e0, 1e1, 1e2, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8, 1e9, 1e10, 1e11, 1e12, 1e13, 1e14, 1e15, 1e16, 1e17, 1e18, 1e19, 1e20,
+    1e21, 1e22,
+];
+
+// Parse a plain decimal such as `0.125` or `-3` straight from bytes: at most 15 digits as an integer (below 2^53)
+// divided by an exact power of ten is correctly rounded, so the result is the same as str::parse::<f64>. None for
+// anything else (exponents, long numbers, blanks, errors), left to str::parse.
+fn parse_decimal(field: &[u8]) -> Option<f64> {
+    let (negative, digits) = match field {
+        [b'-', rest @ ..] => (true, rest),
+        [b'+', rest @ ..] => (false, rest),
+        _ => (false, field),
+    };
+    if digits.is_empty() || digits.len() > 15 || digits == b"." {
+        return None;
+    }
+    let (mut mantissa, mut scale) = (0u64, 0);
+    for (i, &b) in digits.iter().enumerate() {
+        match b {
+            b'0'..=b'9' => mantissa = mantissa * 10 + (b - b'0') as u64,
+            b'.' if scale == 0 => scale = digits.len() - 1 - i,
+            _ => return None,
+        }
+    }
+    let kwh = mantissa as f64 / POWERS_OF_TEN[scale];
+    Some(if negative { -kwh } else { kwh })
+}
+
+// Iterator over the days of a wide CSV energy file, reusing one record buffer for every line
+struct CsvEnergy<R> {
+    path: String,
+    headers: StringRecord,
+    reader: Reader<R>,
+    record: ByteRecord,
+    interval: usize,
+    line_no: usize, // of the data, from 0
+    blank: bool,    // read blank intervals as NaN with Quality::Null rather than failing
+    layout: CsvLayout, // the date format is detected from the first line if not given
+}
+
+impl<R: io::Read> CsvEnergy<R> {
+    fn day(&mut self) -> Result<DayEnergy> {
+        let r = &self.record;
+        debug!("read_csv_energy: record: {:?}", r);
+        let field = |i: usize| r.get(i).ok_or_else(|| anyhow!("no {}", column_text(&self.headers, i))).and_then(|f| Ok(std::str::from_utf8(f)?.trim()));
+        let date_column = self.layout.date_column;
+        let date_field = field(date_column)?;
+        let err = |i: usize, f: &str, e: &dyn std::fmt::Display| anyhow!("{} '{}': {}", column_text(&self.headers, i), f, e);
+        let format = match &self.layout.date_format {
+            Some(f) => f,
+            None => self.layout.date_format.insert(detect_date_format(date_field).map_err(|e| err(date_column, date_field, &e))?.to_string()),
+        };
+        let date = NaiveDate::parse_from_str(date_field, format).map_err(|e| err(date_column, date_field, &format!("not in format {}: {}", format, e)))?;
+        let columns = self.layout.first_column..=self.layout.last_column.unwrap_or(r.len().saturating_sub(1));
+        let mut energy = Vec::with_capacity(columns.clone().count());
+        for i in columns {
+            // plain decimals are parsed straight from the record's bytes, anything else as a str
+            energy.push(match r.get(i).and_then(|f| parse_decimal(f.trim_ascii())) {
+                Some(kwh) => kwh,
+                None => match field(i)? {
+                    "" if self.blank => f64::NAN,
+                    f => f.parse::<f64>().map_err(|e| err(i, f, &e))?,
+                },
+            });
+        }
+        let mut day = DayEnergy::with_interval(date, self.interval, energy)?;
+        for (q, e) in day.quality.iter_mut().zip(&day.energy) {
+            if e.is_nan() {
+                *q = Quality::Null;
+            }
+        }
+        Ok(day)
+    }
+}
+
+impl<R: io::Read> Iterator for CsvEnergy<R> {
+    type Item = Result<DayEnergy>;
+
+    fn next(&mut self) -> Option<Result<DayEnergy>> {
+        let day = match self.reader.read_byte_record(&mut self.record) {
+            Ok(false) => return None,
+            Ok(true) => self.day(),
+            Err(e) => Err(e.into()),
+        };
+        self.line_no += 1;
+        Some(day.map_err(|e| anyhow!("read_csv_energy: {}: line {}: {}", self.path, self.line_no + 1, e)))
+    }
+}
+
+/// Assert that a reader of another format reads the same energy as `data/test/energy/consumption.csv` and
+/// `feedIn.csv` (summed into their 8 hour intervals), so it prices the same.
+#[cfg(test)]
+pub(crate) fn assert_test_energy(read: impl Fn(Direction) -> Result<Vec<DayEnergy>>) -> Result<()> {
+    for (direction, csv) in [(Direction::Import, "consumption.csv"), (Direction::Export, "feedIn.csv")] {
+        let expected = read_csv_energy(&format!("data/test/energy/{}", csv))?.collect::<Result<Vec<_>>>()?;
+        let days = read(direction)?.iter().map(|d| resample(d, expected[0].interval)).collect::<Result<Vec<_>>>()?;
+        assert_eq!(days.iter().map(|d| d.date).collect::<Vec<_>>(), expected.iter().map(|d| d.date).collect::<Vec<_>>(), "{:?}", direction);
+        for (day, expected) in days.iter().zip(&expected) {
+            assert_eq!(day.energy.len(), expected.energy.len(), "{:?} {}", direction, day.date);
+            assert!(day.energy.iter().zip(&expected.energy).all(|(a, b)| (a - b).abs() < 1e-9), "{:?} {:?}", day, expected);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_dates() -> Result<()> {
+        let days = read_csv_energy("data/test/energy/consumption.csv")?.collect::<Vec<_>>();
+        let from = parse_date("2023-08-07")?;
+        let kept = filter_dates(days, Some(from), Some(from)).collect::<Result<Vec<_>>>()?;
+        assert_eq!(kept.iter().map(|d| d.date).collect::<Vec<_>>(), vec![parse_date("20230807")?]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_days() -> Result<()> {
+        let file = "data/test/energy/consumption.csv";
+        let (days, warnings) = merge_days(vec![
+            (file.to_string(), read_csv_energy(file)?),
+            (file.to_string(), read_csv_energy(file)?),
+        ])?;
+        assert_eq!(days.len(), 3);
+        assert_eq!(warnings, vec![format!("{}: 3 dates from 2023-08-06 to 2023-08-08 were already read from an earlier file and are ignored", file)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_exports() -> Result<()> {
+        let date = |d| NaiveDate::from_ymd_opt(2023, 8, d).unwrap();
+        let earlier = vec![DayEnergy::new(date(6), vec![0.5, 1.0, 2.0])?, DayEnergy::new(date(7), vec![1.0, 1.0, 1.0])?];
+        let mut later = vec![DayEnergy::new(date(7), vec![1.0, 1.0, 1.0])?, DayEnergy::new(date(8), vec![0.0, 2.0, 0.0])?];
+        later[0].quality[0] = Quality::Estimated;
+        let source = |name: &str, days: &[DayEnergy]| (name.to_string(), days.iter().cloned().map(Ok).collect::<Vec<_>>());
+        let (days, warnings) = merge_exports(vec![source("a.csv", &earlier), source("b.csv", &later)])?;
+        assert_eq!(days.iter().map(|d| d.date).collect::<Vec<_>>(), vec![date(6), date(7), date(8)]);
+        assert_eq!(days[1].quality[0], Quality::Actual);
+        assert_eq!(warnings, vec!["b.csv: 1 dates from 2023-08-07 to 2023-08-07 are the same as in an earlier file and are deduplicated"]);
+
+        later[0].energy[1] = 1.5;
+        let error = merge_exports(vec![source("a.csv", &earlier), source("b.csv", &later)]).unwrap_err();
+        assert_eq!(error.to_string(), "merge_exports: b.csv has 1.5 kWh at 2023-08-07 08:00 but an earlier file has 1 kWh");
+        assert!(merge_days(vec![source("a.csv", &earlier), source("b.csv", &later)]).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_net_metering() -> Result<()> {
+        let date = |d| NaiveDate::from_ymd_opt(2023, 8, d).unwrap();
+        let consumption = vec![DayEnergy::new(date(6), vec![0.5, 1.0, 2.0])?, DayEnergy::new(date(7), vec![1.0, 1.0, 1.0])?];
+        let mut generation = vec![DayEnergy::new(date(6), vec![0.0, 3.0, 1.5])?, DayEnergy::new(date(8), vec![0.0, 2.0, 0.0])?];
+        generation[0].quality[1] = Quality::Estimated;
+        let (import, export, warnings) = net_metering(&consumption, &generation)?;
+        assert_eq!(import.iter().map(|d| d.energy.clone()).collect::<Vec<_>>(), vec![vec![0.5, 0.0, 0.5], vec![1.0, 1.0, 1.0]]);
+        assert_eq!(export.iter().map(|d| (d.date, d.energy.clone())).collect::<Vec<_>>(), vec![(date(6), vec![0.0, 2.0, 0.0]), (date(8), vec![0.0, 2.0, 0.0])]);
+        assert_eq!(export[0].quality[1], Quality::Estimated);
+        assert_eq!(warnings.len(), 2);
+        assert!(net_metering(&consumption, &[DayEnergy::new(date(6), vec![1.0, 1.0])?]).is_err());
+        assert_eq!(Metering::from_str("Gross")?, Metering::Gross);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_energy_bytes() -> Result<()> {
+        let file = "data/test/energy/consumption.csv";
+        let days = read_csv_energy(file)?.collect::<Result<Vec<_>>>()?;
+        for path in [file, "data/test/energy/consumption.zip"] {
+            assert_eq!(read_energy_bytes(path, std::fs::read(path)?, Direction::Import)?, days, "{}", path);
+        }
+        let (nem12, long, xml) = ("data/test/energy/nem12.csv", "data/test/energy/long.csv", "data/test/energy/greenbutton.xml");
+        assert_eq!(read_energy_bytes(nem12, std::fs::read(nem12)?, Direction::Export)?, crate::nem12::read_nem12(nem12, Direction::Export)?);
+        assert_eq!(read_energy_bytes(long, std::fs::read(long)?, Direction::Import)?, crate::longcsv::read_long_csv(long, Direction::Import)?);
+        assert_eq!(read_energy_bytes(xml, std::fs::read(xml)?, Direction::Import)?, crate::greenbutton::read_green_button(xml, Direction::Import)?);
+        assert!(read_energy_bytes("energy.parquet", std::fs::read("data/test/energy/energy.parquet")?, Direction::Import).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resample() -> Result<()> {
+        let date = NaiveDate::from_ymd_opt(2023, 8, 6).unwrap();
+        let mut day = DayEnergy::with_interval(date, 5, (0..288).map(|i| (i % 6) as f64).collect())?;
+        day.quality[7] = Quality::Estimated;
+        let half_hourly = resample(&day, 30)?;
+        assert_eq!((half_hourly.interval, half_hourly.energy.len(), half_hourly.energy[0]), (30, 48, 15.0));
+        assert_eq!(&half_hourly.quality[0..3], &[Quality::Actual, Quality::Estimated, Quality::Actual]);
+        assert_eq!(resample(&half_hourly, 1440)?.energy, vec![15.0 * 48.0]);
+        assert_eq!(resample(&half_hourly, 30)?, half_hourly);
+        assert!(resample(&half_hourly, 5).is_err());
+        assert!(resample(&day, 7).is_err());
+
+        let forward = DayEnergy::with_interval(NaiveDate::from_ymd_opt(2023, 10, 1).unwrap(), 5, vec![1.0; 276])?; // clocks forward, an hour short
+        let resampled = resample(&forward, 30)?;
+        assert_eq!((resampled.energy.len(), resampled.minute(3), resampled.minute(4)), (46, 90, 180));
+        assert!(resample(&forward, 120).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_csv_energy() -> Result<()> {
+        let days = read_csv_energy("data/test/energy/consumption.csv")?.collect::<Result<Vec<_>>>()?;
+        let mut csv = Vec::new();
+        write_csv_energy(&days, &mut csv)?;
+        assert_eq!(String::from_utf8(csv)?, std::fs::read_to_string("data/test/energy/consumption.csv")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing() -> Result<()> {
+        let file = "data/test/energy/consumptionBlank.csv";
+        assert!(read_csv_energy(file)?.collect::<Result<Vec<_>>>().is_err());
+        assert!(read_csv_energy_missing(file, Missing::Interpolate, &CsvLayout::default()).is_err()); // 20230807 is all blank
+        let (days, warnings) = read_csv_energy_missing(file, Missing::Zero, &CsvLayout::default())?;
+        assert_eq!(days.iter().map(|d| d.energy.clone()).collect::<Vec<_>>(), vec![vec![0.073, 0.0, 0.063], vec![0.0; 3], vec![0.0, 0.028, 0.063]]);
+        assert_eq!(days[0].quality, vec![Quality::Actual, Quality::Substituted, Quality::Actual]);
+        assert!(warnings.is_empty());
+        let (days, warnings) = read_csv_energy_missing(file, Missing::SkipDay, &CsvLayout::default())?;
+        assert!(days.is_empty());
+        assert_eq!(warnings, vec![format!("{}: 3 dates from 2023-08-06 to 2023-08-08 with blank intervals are skipped", file)]);
+        let mut day = DayEnergy::new(parse_date("20230806")?, vec![f64::NAN, 1.0, f64::NAN, f64::NAN, 4.0, f64::NAN])?;
+        interpolate(&mut day)?;
+        assert_eq!(day.energy, vec![1.0, 1.0, 2.0, 3.0, 4.0, 4.0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed() -> Result<()> {
+        let days = |file| read_csv_energy(file)?.collect::<Result<Vec<_>>>();
+        assert_eq!(days("data/test/energy/consumption.zip")?, days("data/test/energy/consumption.csv")?);
+        let (xlsx, _) = read_csv_energy_missing("data/test/energy/consumption.xlsx", Missing::Zero, &CsvLayout::default())?;
+        assert_eq!(xlsx.iter().map(|d| d.energy.clone()).collect::<Vec<_>>(), vec![vec![0.073, 0.028, 0.063], vec![0.073, 0.028, 0.063], vec![0.073, 0.0, 0.063]]);
+        let holidays = crate::pricing::load_public_holidays("data/test/publicHolidaysNSW.csv.gz")?;
+        assert_eq!(holidays, crate::pricing::load_public_holidays("data/NB/publicHolidaysNSW.csv")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_date_formats() -> Result<()> {
+        let days = |file, format: Option<&str>| {
+            read_csv_energy_layout(file, &CsvLayout { date_format: format.map(str::to_string), ..CsvLayout::default() })?.collect::<Result<Vec<_>>>()
+        };
+        let expected = days("data/test/energy/consumption.csv", None)?;
+        assert_eq!(days("data/test/energy/consumptionSlashDates.csv", None)?, expected);
+        assert_eq!(days("data/test/energy/consumptionSlashDates.csv", Some("%d/%m/%Y"))?, expected);
+        assert!(days("data/test/energy/consumptionSlashDates.csv", Some("%Y%m%d")).is_err());
+        assert_eq!(detect_date_format("2023-08-06")?, "%Y-%m-%d");
+        assert_eq!(detect_date_format("31.12.2023")?, "%d.%m.%Y");
+        assert!(detect_date_format("Aug 6 2023").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_layout() -> Result<()> {
+        // NMI, date and unit columns before the intervals and a total after them
+        let layout = CsvLayout { date_column: 1, first_column: 3, last_column: Some(5), ..CsvLayout::default() };
+        let days = read_csv_energy_layout("data/test/energy/consumptionColumns.csv", &layout)?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(days, read_csv_energy("data/test/energy/consumption.csv")?.collect::<Result<Vec<_>>>()?);
+        assert!(read_csv_energy("data/test/energy/consumptionColumns.csv")?.collect::<Result<Vec<_>>>().is_err());
+        assert_eq!(parse_columns("4-6")?, (3, Some(5)));
+        assert_eq!(parse_columns("2-")?, (1, None));
+        assert_eq!(parse_columns("2")?, (1, Some(1)));
+        assert!(parse_columns("0-3").is_err());
+        assert!(parse_columns("6-4").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() -> Result<()> {
+        let err = read_csv_energy("data/test/energy/consumptionBad.csv")?.collect::<Result<Vec<_>>>().unwrap_err();
+        assert_eq!(err.to_string(), "read_csv_energy: data/test/energy/consumptionBad.csv: line 3: column 3 (08:00) '0.O28': invalid float literal");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_decimal() {
+        for s in ["0", "0.019", "-0.07", "+12.5", "5.", ".5", "123456789012345", "0.1234567890123", "-0", "0.3"] {
+            assert_eq!(parse_decimal(s.as_bytes()).map(f64::to_bits), s.parse::<f64>().ok().map(f64::to_bits), "{}", s);
+        }
+        for s in ["", "-", ".", "1.2.3", "1e3", "0.O28", "1234567890123456", "nan"] {
+            assert_eq!(parse_decimal(s.as_bytes()), None, "{}", s);
+        }
+        // every 3 decimal place kWh as str::parse gives it
+        assert!((0..100_000).all(|n| {
+            let s = format!("{}.{:03}", n / 1000, n % 1000);
+            parse_decimal(s.as_bytes()) == s.parse::<f64>().ok()
+        }));
+    }
+
+    #[test]
+    fn test_lenient() -> Result<()> {
+        let (days, warnings) = read_csv_energy_lenient("data/test/energy/consumptionBad.csv", Missing::Error, &CsvLayout::default())?;
+        assert_eq!(days.len(), 1);
+        assert_eq!(warnings, vec!["data/test/energy/consumptionBad.csv: 1 malformed lines are skipped, the first: \
+            read_csv_energy: data/test/energy/consumptionBad.csv: line 3: column 3 (08:00) '0.O28': invalid float literal"]);
+        let (days, warnings) = read_csv_energy_lenient("data/test/energy/consumptionBlank.csv", Missing::Interpolate, &CsvLayout::default())?;
+        assert_eq!(days.iter().map(|d| d.date).collect::<Vec<_>>(), vec![parse_date("20230806")?, parse_date("20230808")?]); // 7th is all blank
+        assert_eq!(warnings.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_dates() -> Result<()> {
+        let file = "data/test/energy/consumptionRepeated.csv"; // 7th, 6th, 7th again
+        let check = |duplicates| -> Result<(Vec<DayEnergy>, Vec<String>)> {
+            let warnings = Warnings::default();
+            let days = check_dates(file, read_csv_energy(file)?, duplicates, &warnings).collect::<Result<Vec<_>>>()?;
+            Ok((days, warnings.take()))
+        };
+        assert!(check(Duplicates::Error).is_err());
+        let (days, warnings) = check(Duplicates::Dedupe)?;
+        assert_eq!(days.iter().map(|d| d.energy[0]).collect::<Vec<_>>(), vec![0.073, 0.073]);
+        assert_eq!(warnings, vec![
+            "data/test/energy/consumptionRepeated.csv: 1 dates are repeated (the first 2023-08-07), only the first copy is priced",
+            "data/test/energy/consumptionRepeated.csv: 1 dates are out of order (the first 2023-08-06 after 2023-08-07)",
+        ]);
+        let (days, warnings) = check(Duplicates::Warn)?;
+        assert_eq!((days.len(), warnings.len()), (3, 2));
+
+        // the warnings are only known once the days have been read
+        let warnings = Warnings::default();
+        let mut days = check_dates(file, read_csv_energy(file)?, Duplicates::Warn, &warnings);
+        assert!(days.next().is_some() && warnings.is_empty());
+        assert_eq!((days.count(), warnings.len()), (2, 2));
+        let warnings = Warnings::default();
+        assert_eq!(check_dates(file, read_csv_energy("data/test/energy/consumption.csv")?, Duplicates::Error, &warnings).count(), 3);
+        assert!(warnings.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_header_interval() -> Result<()> {
+        let header = |labels: &[&str]| header_interval(&StringRecord::from([&["Date/Time"], labels].concat()), &CsvLayout::default());
+        assert_eq!(header(&["00:00", "08:00", "16:00"])?, 480);
+        assert_eq!(header(&["08:00", "16:00", "24:00"])?, 480);
+        assert_eq!(header(&["8:00:00", "16:00:00", "0:00:00"])?, 480);
+        assert_eq!(header(&["a", "b", "c", "d"])?, 360);
+        assert!(header(&["04:00", "12:00", "20:00"]).unwrap_err().to_string().contains("misaligned"));
+        assert!(header(&["00:00", "08:00", "12:00"]).unwrap_err().to_string().contains("column 4 is 12:00, expected 16:00"));
+        assert!(header(&["00:00", "08:00"]).unwrap_err().to_string().contains("not a whole day"));
+        assert_eq!(header_minute("24:00"), Some(24 * 60));
+        assert_eq!(header_minute("12:60"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_format() -> Result<()> {
+        let format = |file| detect_format(&format!("data/test/energy/{}", file));
+        assert_eq!(format("consumption.csv")?, DataFormat::WideCsv);
+        assert_eq!(format("consumption.zip")?, DataFormat::WideCsv);
+        assert_eq!(format("consumption.xlsx")?, DataFormat::WideCsv);
+        assert_eq!(format("long.csv")?, DataFormat::LongCsv);
+        assert_eq!(format("nem12.csv")?, DataFormat::Nem12);
+        assert_eq!(format("greenbutton.xml")?, DataFormat::GreenButton);
+        assert_eq!(format("energy.parquet")?, DataFormat::Parquet);
+        assert_eq!(format("energy.sqlite")?, DataFormat::Sqlite);
+        assert_eq!(detect_format(STDIN)?, DataFormat::WideCsv);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dst_days() -> Result<()> {
+        let days = read_csv_energy("data/test/energy/consumptionDst.csv")?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(days.iter().map(|d| d.energy.len()).collect::<Vec<_>>(), vec![24, 23, 25]);
+        assert_eq!((0..24).map(|i| days[0].minute(i)).collect::<Vec<_>>(), (0..24).map(|h| h * 60).collect::<Vec<_>>());
+        assert_eq!((1..4).map(|i| days[1].minute(i)).collect::<Vec<_>>(), vec![60, 180, 240]);
+        assert_eq!((2..5).map(|i| days[2].minute(i)).collect::<Vec<_>>(), vec![120, 120, 180]);
+        assert_eq!(days[2].minute(24), 23 * 60);
+        for day in &days {
+            let mut repeated = false;
+            for i in 0..day.energy.len() {
+                repeated |= i > 0 && day.minute(i) <= day.minute(i - 1);
+                assert_eq!(day.index(day.minute(i), repeated), Some(i), "{} {}", day.date, i);
+            }
+        }
+        assert_eq!((days[1].index(150, false), days[0].index(24 * 60, false)), (None, None));
+
+        // only a changeover date may be an hour short or long, and a changeover day in standard time is as usual
+        let wednesday = NaiveDate::from_ymd_opt(2023, 8, 2).unwrap();
+        let e = DayEnergy::with_interval(wednesday, 30, vec![0.1; 46]).unwrap_err().to_string();
+        assert!(e.contains("46 data items for 2023-08-02 is not 48 for 30 minute"), "{}", e);
+        assert!(DayEnergy::with_interval(wednesday, 30, vec![0.1; 50]).is_err());
+        assert!(DayEnergy::with_interval(days[1].date, 60, vec![0.1; 25]).is_err());
+        let standard = DayEnergy::with_interval(days[2].date, 60, vec![0.1; 24])?;
+        assert_eq!((standard.minute(3), standard.index(120, true)), (180, Some(2)));
+        assert_eq!((changeover(days[1].date), changeover(days[2].date), changeover(wednesday)), (Some(Changeover::Forward), Some(Changeover::Back), None));
+        assert!(daylight_saving(days[0].date) && !daylight_saving(wednesday));
+        Ok(())
+    }
+}
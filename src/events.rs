@@ -0,0 +1,117 @@
+//! Critical peak pricing and demand response events: special rates or credits for reductions on particular dates and
+//! times, layered on top of whatever else prices the interval (the tariff, wholesale prices or a rate policy).
+
+use crate::energy::{open_input, parse_date, CsvLine};
+use crate::policy::Rate;
+use crate::pricing::{minutes_since_midnight, Interval};
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use csv::ReaderBuilder;
+use log::{debug, info};
+use std::io;
+
+/// An event: a window of a date with a special rate, a credit for consumption below a baseline, or both.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub date: NaiveDate,
+    pub time_start: i32,      // min since midnight
+    pub time_end: i32,        // min since midnight, exclusive
+    pub rate: Option<f64>,    // $/kWh for all consumption in the window, None to keep the usual rates
+    pub name: String,
+    pub credit: Option<f64>,  // $/kWh credited for each kWh below the baseline
+    pub baseline: f64,        // kW the reduction is measured from
+}
+
+impl Event {
+    /// Whether the event covers the start of an interval.
+    pub fn covers(&self, i: &Interval) -> bool {
+        self.date == i.date && self.time_start <= i.min_since_midnight && i.min_since_midnight < self.time_end
+    }
+
+    /// The rates of an interval of consumption with the event layered on top: all of its energy at the event's rate
+    /// (replacing the other energy rates) and a credit for the kWh below the baseline.
+    pub fn layer(&self, i: &Interval, mut rates: Vec<Rate>) -> Vec<Rate> {
+        if let Some(rate) = self.rate {
+            rates.retain(|r| !matches!(r, Rate::Energy { .. }));
+            rates.insert(0, Rate::Energy { name: self.name.clone(), kwh: i.energy, rate });
+        }
+        if let Some(credit) = self.credit {
+            let reduction = (self.baseline * i.length as f64 / 60.0 - i.energy).max(0.0);
+            if reduction > 0.0 {
+                rates.push(Rate::Charge { name: format!("{} credit", self.name), amount: -credit * reduction });
+            }
+        }
+        rates
+    }
+}
+
+/// Load events from a CSV file with columns: Date (`YYYYMMDD` or `YYYY-MM-DD`), Time Start, Time End (Exclusive),
+/// $/kWh (empty to keep the usual rates), Name and optionally Credit $/kWh and Baseline kW, for a credit on each kWh
+/// of consumption in the window below the baseline.
+pub fn load_events(csv: &str) -> Result<Vec<Event>> {
+    info!("load_events: loading CSV file {}", csv);
+    load_events_from(csv, open_input(csv)?)
+}
+
+/// Load events like [`load_events`] from any reader (e.g. a byte slice), `csv` naming it in errors.
+pub fn load_events_from<R: io::Read>(csv: &str, input: R) -> Result<Vec<Event>> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(input);
+
+    let headers = reader.headers()?.clone();
+    reader.records().enumerate()
+    .map(|(i, record)| -> Result<Event> {
+        let r = record?;
+        debug!("load_events: record: {:?}", r);
+        let line = CsvLine::new("load_events", csv, i, &headers, &r);
+        let event = Event {
+            date:       line.parse_with(0, parse_date)?,
+            time_start: line.parse_with(1, minutes_since_midnight)?,
+            time_end:   line.parse_with(2, minutes_since_midnight)?,
+            rate:       line.optional(3).map(|_| line.parse::<f64>(3)).transpose()?,
+            name:       line.get(4).to_string(),
+            credit:     line.optional(5).map(|_| line.parse::<f64>(5)).transpose()?,
+            baseline:   line.optional(6).map(|_| line.parse::<f64>(6)).transpose()?.unwrap_or(0.0),
+        };
+        match event {
+            Event { time_start, time_end, .. } if time_end <= time_start => Err(anyhow!("{}: the Time End is not after the Time Start", line.at_line())),
+            Event { rate: None, credit: None, .. } => Err(anyhow!("{}: neither a $/kWh nor a credit", line.at_line())),
+            Event { credit: Some(_), .. } if line.optional(6).is_none() => Err(anyhow!("{}: a credit without a Baseline kW", line.at_line())),
+            _ => Ok(event),
+        }
+    })
+    .collect()
+}
+
+/// The rates of an interval of consumption with the first event covering it, if any, layered on top.
+pub fn layer_events(events: &[Event], i: &Interval, rates: Vec<Rate>) -> Vec<Rate> {
+    match events.iter().find(|e| e.covers(i)) {
+        Some(e) => e.layer(i, rates),
+        None => rates,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events() -> Result<()> {
+        let events = load_events("data/test/tariff/criticalPeak.csv")?;
+        assert_eq!(events.len(), 2);
+        let interval = |d, min_since_midnight| Interval {
+            date: NaiveDate::from_ymd_opt(2023, 8, d).unwrap(), dow: 0, holiday: false, min_since_midnight, length: 480,
+            energy: 0.063, usage_day: 0.0, usage_period: 0.0,
+        };
+        let rates = || vec![Rate::Energy { name: "Flat".to_string(), kwh: 0.063, rate: 0.4 }];
+        assert_eq!(layer_events(&events, &interval(7, 480), rates()), rates());
+        assert_eq!(layer_events(&events, &interval(7, 960), rates()), vec![Rate::Energy { name: "Critical peak".to_string(), kwh: 0.063, rate: 1.5 }]);
+        let credited = layer_events(&events, &interval(8, 960), rates());
+        assert!(matches!(&credited[..], [_, Rate::Charge { amount, .. }] if (amount + 0.5 * (0.08 - 0.063)).abs() < 1e-12));
+
+        let err = load_events_from("bad.csv", "Date,Time Start,Time End,$/kWh,Name\n20230807,16:00:00,24:00:00,,Nothing\n".as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "load_events: bad.csv: line 2: neither a $/kWh nor a credit");
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+//! Explain how a plan prices a moment: whether the day is a public holiday, the tariff rows and usage blocks that
+//! match, the rates charged (after any rate policy, wholesale price or event) and the price of 1 kWh, for debugging
+//! tariff files.
+
+use crate::energy::Direction;
+use crate::json::Json;
+use crate::plan::Plan;
+use crate::policy::{Rate, RatePolicy};
+use crate::pricing::{day_of_week, is_holiday, BlockPer, Interval, Tariff, TariffIndex};
+use anyhow::Result;
+use chrono::{NaiveDateTime, Timelike};
+use std::collections::HashSet;
+
+/// How consumption or feedin is priced at a moment.
+#[derive(Debug)]
+pub struct Priced<'a> {
+    pub rows: Vec<(&'a Tariff, f64)>, // the tariff rows matching and the kWh of each, empty if none match
+    pub rates: Vec<Rate>,             // the rates charged
+    pub price: f64,                   // $ for the kWh
+}
+
+/// How a plan prices 1 kWh at a moment.
+#[derive(Debug)]
+pub struct Explanation<'a> {
+    pub at: NaiveDateTime,
+    pub dow: i16,      // day of week (0 for Monday), 6 for public holidays
+    pub holiday: bool, // a public holiday
+    pub interval: Interval,
+    pub consumption: Priced<'a>,
+    pub feedin: Option<Priced<'a>>, // None without a feedin tariff or wholesale prices
+    pub demand: Vec<(String, f64)>, // the demand tariff rows the time is in and their $/kW (or $/kVA)
+    pub supply: f64,                // $/day
+}
+
+/// Explain how a plan prices 1 kWh over an interval of `length` minutes starting at a time, with `usage_day` and
+/// `usage_period` kWh used before it in the day and billing period (for usage blocks).
+pub fn explain<'a>(plan: &'a Plan, holidays: &HashSet<String>, at: NaiveDateTime, length: usize, usage_day: f64, usage_period: f64) -> Result<Explanation<'a>> {
+    let date = at.date();
+    let interval = Interval {
+        date,
+        dow: day_of_week(date, holidays),
+        holiday: is_holiday(date, holidays),
+        min_since_midnight: (at.hour() * 60 + at.minute()) as i32,
+        length,
+        energy: 1.0,
+        usage_day,
+        usage_period,
+    };
+    let priced = |tariff: &'a [Tariff], direction| -> Result<Priced<'a>> {
+        let index = TariffIndex::new(tariff);
+        let rates = plan.rates(&interval, &index, direction)?;
+        let price = rates.iter().map(|r| match r {
+            Rate::Energy { kwh, rate, .. } => kwh * rate,
+            Rate::Charge { amount, .. } => *amount,
+            Rate::Demand { .. } => 0.0,
+        }).sum();
+        Ok(Priced { rows: index.portions(&interval).unwrap_or_default(), rates, price })
+    };
+    let feedin = match (&plan.feedin_tariff, &plan.wholesale) {
+        (Some(f), _) => Some(priced(f, Direction::Export)?),
+        (None, Some(_)) => Some(priced(&[], Direction::Export)?).filter(|p| !p.rates.is_empty()),
+        (None, None) => None,
+    };
+    let mut demand = Vec::new();
+    for d in &plan.demand_tariff {
+        for rate in d.rates(&interval, Direction::Import)?.unwrap_or_default() {
+            if let Rate::Demand { name, rate } = rate {
+                demand.push((name, rate));
+            }
+        }
+    }
+    Ok(Explanation {
+        at,
+        dow: interval.dow,
+        holiday: interval.holiday,
+        consumption: priced(&plan.consumption_tariff, Direction::Import)?,
+        feedin,
+        demand,
+        supply: plan.supply_on(date),
+        interval,
+    })
+}
+
+// A tariff row's days, times, rate, usage block and condition
+fn row_text(t: &Tariff, kwh: f64) -> String {
+    let mut s = format!("{} {} {} ${}/kWh", t.name, t.days_text(), t.times_text(), t.tariff);
+    if let Some(b) = t.block {
+        let per = match b.per {
+            BlockPer::Day => "day",
+            BlockPer::Period => "billing period",
+        };
+        s += &match b.end.is_finite() {
+            true => format!(", block {} to {} kWh a {}", b.start, b.end, per),
+            false => format!(", block from {} kWh a {}", b.start, per),
+        };
+    }
+    if let Some(c) = &t.condition {
+        s += &format!(", when {}", c);
+    }
+    s + &format!(": {} kWh", kwh)
+}
+
+fn rate_text(r: &Rate) -> String {
+    match r {
+        Rate::Energy { name, kwh, rate } => format!("{} {} kWh at ${}/kWh", name, kwh, rate),
+        Rate::Charge { name, amount } => format!("{} ${}", name, amount),
+        Rate::Demand { name, rate } => format!("{} demand at ${}/kW", name, rate),
+    }
+}
+
+fn priced_json(p: &Priced) -> Json {
+    Json::object(vec![
+        ("rows", Json::Array(p.rows.iter().map(|(t, kwh)| Json::object(vec![
+            ("name", t.name.as_str().into()),
+            ("days", t.days_text().into()),
+            ("times", t.times_text().into()),
+            ("rate", t.tariff.into()),
+            ("block", t.block.map_or(Json::Null, |b| Json::object(vec![
+                ("start", b.start.into()),
+                ("end", if b.end.is_finite() { b.end.into() } else { Json::Null }),
+                ("per", match b.per { BlockPer::Day => "day", BlockPer::Period => "period" }.into()),
+            ]))),
+            ("condition", t.condition.as_ref().map_or(Json::Null, |c| c.to_string().into())),
+            ("kwh", (*kwh).into()),
+        ])).collect())),
+        ("rates", Json::Array(p.rates.iter().map(|r| rate_text(r).into()).collect())),
+        ("price", p.price.into()),
+    ])
+}
+
+impl Explanation<'_> {
+    pub fn to_text(&self) -> String {
+        let mut s = format!(
+            "{} {}{}, {} kWh used before it in the day and {} in the billing period\n",
+            self.at.format("%Y-%m-%d %H:%M"), self.at.format("%A"), if self.holiday { " (public holiday, priced as Sunday)" } else { "" },
+            self.interval.usage_day, self.interval.usage_period,
+        );
+        for (label, priced) in [("Consumption", Some(&self.consumption)), ("Feedin", self.feedin.as_ref())] {
+            let Some(p) = priced else {
+                continue;
+            };
+            s += &format!("{}:\n", label);
+            if p.rows.is_empty() {
+                s += "  no tariff row matches\n";
+            }
+            for (t, kwh) in &p.rows {
+                s += &format!("  row: {}\n", row_text(t, *kwh));
+            }
+            for r in &p.rates {
+                s += &format!("  rate: {}\n", rate_text(r));
+            }
+            s += &format!("  1 kWh: ${}\n", p.price);
+        }
+        for (name, rate) in &self.demand {
+            s += &format!("Demand: {} ${}/kW on the maximum demand\n", name, rate);
+        }
+        s += &format!("Supply: ${}/day\n", self.supply);
+        s
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("at", self.at.format("%Y-%m-%d %H:%M").to_string().into()),
+            ("day_of_week", (self.dow as f64).into()),
+            ("holiday", self.holiday.into()),
+            ("usage_day", self.interval.usage_day.into()),
+            ("usage_period", self.interval.usage_period.into()),
+            ("consumption", priced_json(&self.consumption)),
+            ("feedin", self.feedin.as_ref().map_or(Json::Null, priced_json)),
+            ("demand", Json::Array(self.demand.iter().map(|(name, rate)| Json::object(vec![("name", name.as_str().into()), ("rate", (*rate).into())])).collect())),
+            ("supply", self.supply.into()),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pricing::load_public_holidays;
+    use assert_float_eq::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_explain() -> Result<()> {
+        let plan = Plan::load("Block", "data/test/tariff/consumptionBlock.csv", Some("data/test/tariff/feedIn.csv"), "data/test/tariff/supply.csv", Some("data/test/tariff/peakDemand.csv"))?;
+        let holidays = load_public_holidays("data/test/publicHolidaysTest.csv")?;
+        let at = |d, h, m| NaiveDate::from_ymd_opt(2023, 8, d).unwrap().and_hms_opt(h, m, 0).unwrap();
+        // 2023-08-08 is a public holiday in the test holidays, priced as Sunday without the weekday peak demand
+        let e = explain(&plan, &holidays, at(8, 17, 35), 30, 1.0, 0.0)?;
+        assert_eq!((e.dow, e.holiday, e.demand.len()), (6, true, 0));
+        assert_eq!(e.consumption.rows.iter().map(|(t, _)| t.name.as_str()).collect::<Vec<_>>(), vec!["Block 2"]);
+        assert_f64_near!(e.consumption.price, 0.30);
+        assert!(e.feedin.is_some());
+
+        // crossing a block threshold splits the kWh between the blocks
+        let e = explain(&plan, &holidays, at(9, 17, 35), 30, 0.0, 0.0)?;
+        assert_eq!((e.dow, e.holiday), (2, false));
+        assert_eq!(e.demand, vec![("Peak demand".to_string(), 0.5)]);
+        assert_eq!(e.consumption.rows.len(), 2);
+        assert_f64_near!(e.consumption.price, 0.1 * 0.20 + 0.9 * 0.30);
+        assert!(e.to_text().contains("Block 1 Mon-Sun 00:00-24:00 $0.2/kWh, block 0 to 0.1 kWh a day"), "{}", e.to_text());
+        Ok(())
+    }
+}
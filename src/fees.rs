@@ -0,0 +1,113 @@
+//! Recurring fees: fixed charges beyond the daily supply charge (e.g. a monthly membership fee, a smart meter charge
+//! or an annual fee), charged in each billing period in proportion to its length.
+
+use crate::energy::{open_input, CsvLine};
+use crate::money::Money;
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use csv::ReaderBuilder;
+use log::{debug, info};
+use std::str::FromStr;
+
+/// How often a fee is charged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recurrence {
+    Monthly,
+    Quarterly,
+    Annual,
+}
+
+impl Recurrence {
+    pub fn months(&self) -> u32 {
+        match self {
+            Recurrence::Monthly => 1,
+            Recurrence::Quarterly => 3,
+            Recurrence::Annual => 12,
+        }
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Recurrence> {
+        match s.trim().to_lowercase().as_str() {
+            "monthly" => Ok(Recurrence::Monthly),
+            "quarterly" => Ok(Recurrence::Quarterly),
+            "annual" | "annually" | "yearly" => Ok(Recurrence::Annual),
+            _ => Err(anyhow!("Recurrence::from_str: '{}' is not one of monthly, quarterly, annual", s)),
+        }
+    }
+}
+
+/// A fixed fee charged every month, quarter or year.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecurringFee {
+    pub name: String,
+    pub amount: f64, // $ each recurrence
+    pub recurrence: Recurrence,
+}
+
+/// A fee charged in a billing period.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeLine {
+    pub name: String,
+    pub start: NaiveDate, // first date of the billing period
+    pub end: NaiveDate,   // last date of the billing period
+    pub amount: f64,      // $
+}
+
+/// Load recurring fees from a CSV file with columns: Name, $ and Recurrence (`monthly`, `quarterly` or `annual`).
+pub fn load_fees(csv: &str) -> Result<Vec<RecurringFee>> {
+    info!("load_fees: loading CSV file {}", csv);
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(open_input(csv)?);
+
+    let headers = reader.headers()?.clone();
+    reader.records().enumerate()
+    .map(|(i, record)| -> Result<RecurringFee> {
+        let r = record?;
+        debug!("load_fees: record: {:?}", r);
+        let line = CsvLine::new("load_fees", csv, i, &headers, &r);
+        Ok(RecurringFee {
+            name:       line.get(0).to_string(),
+            amount:     line.parse::<f64>(1)?,
+            recurrence: line.parse::<Recurrence>(2)?,
+        })
+    })
+    .collect()
+}
+
+/// The fees charged in billing periods, each its (first date, last date), of `months` months each: a fee recurring
+/// every n months is charged `months / n` of its amount in each period, e.g. a quarter of an annual fee each quarter.
+pub fn fee_lines(fees: &[RecurringFee], periods: &[(NaiveDate, NaiveDate)], months: u32) -> Vec<FeeLine> {
+    periods.iter().flat_map(|(start, end)| fees.iter().map(|f| FeeLine {
+        name: f.name.clone(),
+        start: *start,
+        end: *end,
+        amount: Money::from_f64(f.amount * months as f64 / f.recurrence.months() as f64).to_f64(),
+    }))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fee_lines() -> Result<()> {
+        let fees = load_fees("data/test/tariff/recurringFees.csv")?;
+        assert_eq!(fees[1], RecurringFee { name: "Annual fee".to_string(), amount: 60.0, recurrence: Recurrence::Annual });
+        let d = |m, d| NaiveDate::from_ymd_opt(2023, m, d).unwrap();
+        let lines = fee_lines(&fees, &[(d(7, 1), d(9, 30)), (d(10, 1), d(12, 31))], 3);
+        assert_eq!(lines.iter().map(|l| (l.name.as_str(), l.start, l.amount)).collect::<Vec<_>>(), vec![
+            ("Membership", d(7, 1), 45.0),
+            ("Annual fee", d(7, 1), 15.0),
+            ("Membership", d(10, 1), 45.0),
+            ("Annual fee", d(10, 1), 15.0),
+        ]);
+        assert_eq!("Yearly".parse::<Recurrence>()?, Recurrence::Annual);
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+//! Bill forecasts: the rest of the current billing period projected from the average of the same weekday in the data,
+//! and priced with the days so far, so the bill at the end of the period isn't a surprise.
+
+use crate::bill::Bill;
+use crate::energy::{DayEnergy, Quality};
+use crate::json::Json;
+use crate::plan::{price_plan, Plan};
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Days, Months, NaiveDate};
+use std::collections::HashSet;
+
+/// Days from `from` to `to` (exclusive), each interval the average of that interval on days of the same weekday in
+/// `history` (or of every day if none are that weekday), with [`Quality::Estimated`].
+///
+/// Only days with the interval length of the last day and no daylight saving changeover are averaged.
+pub fn project_days(history: &[DayEnergy], from: NaiveDate, to: NaiveDate) -> Result<Vec<DayEnergy>> {
+    let last = history.last().ok_or_else(|| anyhow!("project_days: no days of data to project from"))?;
+    let (interval, len) = (last.interval, (24 * 60) / last.interval);
+    let usual = history.iter().filter(|d| d.interval == interval && d.energy.len() == len).collect::<Vec<_>>();
+    let average = |days: &[&DayEnergy]| -> Vec<f64> {
+        let n = days.len().max(1) as f64;
+        (0..len).map(|i| days.iter().map(|d| d.energy[i]).sum::<f64>() / n).collect()
+    };
+    let all = average(&usual);
+    let by_weekday = (0..7).map(|w| {
+        let days = usual.iter().filter(|d| d.date.weekday().num_days_from_monday() == w).copied().collect::<Vec<_>>();
+        if days.is_empty() { all.clone() } else { average(&days) }
+    }).collect::<Vec<_>>();
+    from.iter_days().take_while(|d| *d < to).map(|date| {
+        let mut day = DayEnergy::with_interval(date, interval, by_weekday[date.weekday().num_days_from_monday() as usize].clone())?;
+        day.quality.fill(Quality::Estimated);
+        Ok(day)
+    })
+    .collect()
+}
+
+/// The bill so far in the billing period of the last date of data and the bill forecast for the whole period.
+#[derive(Debug)]
+pub struct Forecast {
+    pub start: NaiveDate, // the first date of the billing period
+    pub end: NaiveDate,   // the last date of the billing period
+    pub last: NaiveDate,  // the last date of data
+    pub days_so_far: usize,
+    pub days_remaining: usize,
+    pub so_far: Bill,
+    pub forecast: Bill,
+}
+
+/// Forecast the bill for the billing period of the last date of consumption data (its billing period, or calendar
+/// month without billing periods), from the data in the period so far and the remaining days projected from all the
+/// data by [`project_days`].
+pub fn forecast_bill(plan: &Plan, consumption: &[DayEnergy], feedin: &[DayEnergy], holidays: &HashSet<String>) -> Result<Forecast> {
+    let last = consumption.iter().map(|d| d.date).max().ok_or_else(|| anyhow!("forecast_bill: no consumption data"))?;
+    let (start, next) = match plan.billing {
+        Some(billing) => billing.period(last),
+        None => {
+            let start = last.with_day(1).unwrap_or(last);
+            (start, start.checked_add_months(Months::new(1)).unwrap_or(last))
+        },
+    };
+    let from = last.checked_add_days(Days::new(1)).unwrap_or(next);
+    let in_period = |days: &[DayEnergy]| days.iter().filter(|d| d.date >= start && d.date <= last).cloned().collect::<Vec<_>>();
+    let (consumption_so_far, feedin_so_far) = (in_period(consumption), in_period(feedin));
+    let price = |consumption: &[DayEnergy], feedin: &[DayEnergy]| {
+        let feedin = Some(feedin.iter().cloned().map(Ok)).filter(|_| !feedin.is_empty());
+        price_plan(plan, consumption.iter().cloned().map(Ok), feedin, holidays)
+    };
+    let so_far = price(&consumption_so_far, &feedin_so_far)?;
+    // the days so far and the rest of the period projected from all the data
+    let whole = |so_far: &[DayEnergy], history: &[DayEnergy]| -> Result<Vec<DayEnergy>> {
+        let mut days = so_far.to_vec();
+        if from < next && !history.is_empty() {
+            days.extend(project_days(history, from, next)?);
+        }
+        Ok(days)
+    };
+    let forecast = price(&whole(&consumption_so_far, consumption)?, &whole(&feedin_so_far, feedin)?)?;
+    Ok(Forecast {
+        start,
+        end: next.pred_opt().unwrap_or(next),
+        last,
+        days_so_far: consumption_so_far.len(),
+        days_remaining: (next - from).num_days().max(0) as usize,
+        so_far,
+        forecast,
+    })
+}
+
+impl Forecast {
+    pub fn to_text(&self) -> String {
+        format!(
+            "Billing period {} to {}: {} days of data to {}, {} days remaining\nBill so far ${:.2}\nForecast bill ${:.2}\n",
+            self.start, self.end, self.days_so_far, self.last, self.days_remaining, self.so_far.total(), self.forecast.total()
+        )
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("start", self.start.to_string().into()),
+            ("end", self.end.to_string().into()),
+            ("last", self.last.to_string().into()),
+            ("days_so_far", self.days_so_far.into()),
+            ("days_remaining", self.days_remaining.into()),
+            ("so_far", self.so_far.to_json()),
+            ("forecast", self.forecast.to_json()),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::energy::read_csv_energy;
+    use crate::pricing::Billing;
+    use assert_float_eq::*;
+
+    #[test]
+    fn test_forecast_bill() -> Result<()> {
+        let consumption = read_csv_energy("data/test/energy/consumption.csv")?.collect::<Result<Vec<_>>>()?;
+        let feedin = read_csv_energy("data/test/energy/feedIn.csv")?.collect::<Result<Vec<_>>>()?;
+        let d = |m, d| NaiveDate::from_ymd_opt(2023, m, d).unwrap();
+
+        // Monday 14th is projected from Monday 7th alone, Wednesday 9th from the average of the 3 days
+        let projected = project_days(&consumption, d(8, 9), d(8, 15))?;
+        assert_eq!(projected.len(), 6);
+        assert_eq!(projected[5].energy, consumption[1].energy);
+        assert_f64_near!(projected[0].energy.iter().sum::<f64>(), consumption.iter().map(|d| d.energy.iter().sum::<f64>()).sum::<f64>() / 3.0);
+        assert_eq!(projected[0].quality[0], Quality::Estimated);
+
+        // data from the 6th to the 8th of a billing period from the 7th to 6 September
+        let plan = Plan::load("TOU", "data/test/tariff/consumption.csv", Some("data/test/tariff/feedIn.csv"), "data/test/tariff/supply.csv", None)?
+            .with_billing(Some(Billing::monthly(d(7, 7))));
+        let forecast = forecast_bill(&plan, &consumption, &feedin, &HashSet::new())?;
+        assert_eq!((forecast.start, forecast.end, forecast.days_so_far, forecast.days_remaining), (d(8, 7), d(9, 6), 2, 29));
+        assert_eq!(forecast.forecast.days, 31);
+        assert!(forecast.forecast.total() > forecast.so_far.total() * 10.0);
+        assert!(forecast.to_text().contains("2 days of data to 2023-08-08, 29 days remaining"), "{}", forecast.to_text());
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+//! Synthetic interval energy data, for trying plans before real data is available and for benchmarking.
+//!
+//! Consumption follows the hourly shape of a household [`Profile`], higher in winter than summer, with random
+//! variation between days and intervals. Optional rooftop solar is generated over the daylight hours of the date
+//! (longer in summer), with random cloudy days, and offsets the consumption as a meter sees it: the solar used in
+//! the house reduces consumption and the excess is fed in. Dates are in the southern hemisphere (winter in July).
+//! The data is the same for the same seed.
+
+use crate::energy::DayEnergy;
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Days, NaiveDate, Weekday};
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+/// The daily shape of a household's consumption.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    FamilyEveningPeak, // a morning bump and a large evening peak, busier weekends
+    WorkingCouple,     // little during weekdays, mornings and evenings
+    HomeAllDay,        // steady through the day and evening
+    Flat,              // the same every hour
+}
+
+impl FromStr for Profile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Profile> {
+        match s.trim().to_lowercase().as_str() {
+            "family-evening-peak" => Ok(Profile::FamilyEveningPeak),
+            "working-couple" => Ok(Profile::WorkingCouple),
+            "home-all-day" => Ok(Profile::HomeAllDay),
+            "flat" => Ok(Profile::Flat),
+            _ => Err(anyhow!("Profile::from_str: '{}' is not one of family-evening-peak, working-couple, home-all-day, flat", s)),
+        }
+    }
+}
+
+impl Profile {
+    /// Typical kWh a day for the profile.
+    pub fn daily_kwh(self) -> f64 {
+        match self {
+            Profile::FamilyEveningPeak => 18.0,
+            Profile::WorkingCouple => 10.0,
+            Profile::HomeAllDay => 14.0,
+            Profile::Flat => 12.0,
+        }
+    }
+
+    // Relative consumption in each hour of the day, weekends busier during the day
+    fn hourly(self, weekend: bool) -> [f64; 24] {
+        let (night, morning, day, evening, late) = match self {
+            Profile::FamilyEveningPeak => (0.4, 1.2, if weekend { 1.0 } else { 0.6 }, 2.2, 1.2),
+            Profile::WorkingCouple => (0.35, 1.0, if weekend { 0.9 } else { 0.3 }, 1.8, 0.9),
+            Profile::HomeAllDay => (0.4, 1.0, 1.0, 1.5, 0.9),
+            Profile::Flat => (1.0, 1.0, 1.0, 1.0, 1.0),
+        };
+        let mut shape = [night; 24];
+        shape[6..9].fill(morning);
+        shape[9..17].fill(day);
+        shape[17..21].fill(evening);
+        shape[21..23].fill(late);
+        shape
+    }
+}
+
+/// What to generate.
+#[derive(Debug, Clone)]
+pub struct Synthetic {
+    pub profile: Profile,
+    pub start: NaiveDate, // first date
+    pub days: usize,
+    pub interval: usize,  // interval length (minutes), dividing the day
+    pub daily_kwh: f64,   // average consumption before solar
+    pub solar_kw: f64,    // solar system size, 0 for none
+    pub seed: u64,
+}
+
+// A small deterministic random number generator (SplitMix64), so data is reproducible without a dependency
+struct Random(u64);
+
+impl Random {
+    // uniform in [0, 1)
+    fn uniform(&mut self) -> f64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)) as f64 / (u64::MAX as f64 + 1.0)
+    }
+
+    // roughly normal with mean 0 and standard deviation 1, from the sum of uniforms
+    fn normal(&mut self) -> f64 {
+        ((0..12).map(|_| self.uniform()).sum::<f64>() - 6.0).clamp(-3.0, 3.0)
+    }
+}
+
+// kWh to the nearest Wh, as meters report
+fn round_kwh(kwh: f64) -> f64 {
+    (kwh * 1000.0).round() / 1000.0
+}
+
+// 1 in July, -1 in January
+fn winter(date: NaiveDate) -> f64 {
+    (2.0 * PI * (date.ordinal() as f64 - 196.0) / 365.25).cos()
+}
+
+// The sun on a date: longer days and stronger in summer, weaker on cloudy days
+struct Sun {
+    daylight: f64, // hours
+    sunrise: f64,  // hours after midnight
+    strength: f64, // fraction of the system size at solar noon
+}
+
+impl Sun {
+    fn new(date: NaiveDate, random: &mut Random) -> Sun {
+        let daylight = 12.0 - 2.5 * winter(date);
+        Sun {
+            daylight,
+            sunrise: 12.5 - daylight / 2.0, // solar noon about 12:30
+            strength: (0.7 - 0.2 * winter(date)) * (0.3 + 0.7 * random.uniform().sqrt()), // season and cloud
+        }
+    }
+
+    // kWh generated by a `kw` system from `start` to `end` (minutes since midnight)
+    fn kwh(&self, kw: f64, start: usize, end: usize) -> f64 {
+        let middle = (start + end) as f64 / 120.0 - self.sunrise; // hours after sunrise
+        match middle > 0.0 && middle < self.daylight {
+            true => kw * self.strength * (PI * middle / self.daylight).sin() * (end - start) as f64 / 60.0,
+            false => 0.0,
+        }
+    }
+}
+
+fn check_interval(interval: usize) -> Result<()> {
+    match interval == 0 || 1440 % interval != 0 {
+        true => Err(anyhow!("check_interval: interval {} minutes doesn't divide the day", interval)),
+        false => Ok(()),
+    }
+}
+
+/// Generation of a `kw` rooftop solar system on each date, as for [`generate`] but without the consumption.
+pub fn solar_generation(dates: &[NaiveDate], interval: usize, kw: f64, seed: u64) -> Result<Vec<DayEnergy>> {
+    check_interval(interval)?;
+    let mut random = Random(seed);
+    dates.iter().map(|date| {
+        let sun = Sun::new(*date, &mut random);
+        let kwh = (0..1440 / interval).map(|i| round_kwh(sun.kwh(kw, i * interval, (i + 1) * interval))).collect();
+        DayEnergy::with_interval(*date, interval, kwh)
+    }).collect()
+}
+
+/// Generate (consumption, feedin) days, feedin empty without solar.
+pub fn generate(synthetic: &Synthetic) -> Result<(Vec<DayEnergy>, Vec<DayEnergy>)> {
+    let interval = synthetic.interval;
+    check_interval(interval)?;
+    let mut random = Random(synthetic.seed);
+    let (mut consumption, mut feedin) = (Vec::new(), Vec::new());
+    for d in 0..synthetic.days {
+        let date = synthetic.start.checked_add_days(Days::new(d as u64))
+            .ok_or_else(|| anyhow!("generate: {} days from {} is out of range", d, synthetic.start))?;
+        let shape = synthetic.profile.hourly(matches!(date.weekday(), Weekday::Sat | Weekday::Sun));
+        let total = shape.iter().sum::<f64>();
+        let day_kwh = synthetic.daily_kwh * (1.0 + 0.2 * winter(date)) * (1.0 + 0.1 * random.normal()).max(0.5);
+        let sun = Sun::new(date, &mut random);
+
+        let (mut used, mut exported) = (Vec::new(), Vec::new());
+        for i in 0..1440 / interval {
+            let (start, end) = (i * interval, (i + 1) * interval);
+            let load = (start / 60..end.div_ceil(60)).map(|h| {
+                let overlap = end.min(h * 60 + 60) - start.max(h * 60);
+                day_kwh * shape[h] / total * overlap as f64 / 60.0
+            }).sum::<f64>() * (1.0 + 0.3 * random.normal()).max(0.05);
+            let solar = sun.kwh(synthetic.solar_kw, start, end);
+            used.push(round_kwh((load - solar).max(0.0)));
+            exported.push(round_kwh((solar - load).max(0.0)));
+        }
+        consumption.push(DayEnergy::with_interval(date, interval, used)?);
+        if synthetic.solar_kw > 0.0 {
+            feedin.push(DayEnergy::with_interval(date, interval, exported)?);
+        }
+    }
+    Ok((consumption, feedin))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn synthetic(profile: Profile, solar_kw: f64) -> Synthetic {
+        Synthetic {
+            profile,
+            start: NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(),
+            days: 365,
+            interval: 30,
+            daily_kwh: profile.daily_kwh(),
+            solar_kw,
+            seed: 1,
+        }
+    }
+
+    #[test]
+    fn test_generate() -> Result<()> {
+        let (consumption, feedin) = generate(&synthetic(Profile::FamilyEveningPeak, 0.0))?;
+        assert_eq!((consumption.len(), consumption[0].energy.len(), feedin.len()), (365, 48, 0));
+        assert_eq!(consumption.last().unwrap().date, NaiveDate::from_ymd_opt(2023, 12, 31).unwrap());
+        let daily = |days: &[DayEnergy]| days.iter().map(|d| d.energy.iter().sum::<f64>()).sum::<f64>() / days.len() as f64;
+        assert!((daily(&consumption) - 18.0).abs() < 1.0, "{}", daily(&consumption));
+        assert!(daily(&consumption[181..212]) > daily(&consumption[0..31]), "July is higher than January");
+        let at = |days: &[DayEnergy], i: usize| days.iter().map(|d| d.energy[i]).sum::<f64>();
+        assert!(at(&consumption, 37) > 3.0 * at(&consumption, 6), "18:30 is higher than 03:00");
+        assert_eq!(generate(&synthetic(Profile::FamilyEveningPeak, 0.0))?.0[100].energy, consumption[100].energy);
+
+        let (with_solar, feedin) = generate(&synthetic(Profile::WorkingCouple, 6.6))?;
+        assert_eq!(feedin.len(), 365);
+        assert_eq!(at(&feedin, 0), 0.0);
+        assert!(at(&feedin, 25) > at(&with_solar, 25), "more is fed in than used at 12:30");
+        assert!(generate(&Synthetic { interval: 7, ..synthetic(Profile::Flat, 0.0) }).is_err());
+        assert!(Profile::from_str("night-owl").is_err());
+
+        let dates = consumption.iter().map(|d| d.date).collect::<Vec<_>>();
+        let generation = solar_generation(&dates, 60, 5.0, 1)?;
+        let at = |days: &[DayEnergy], i: usize| days.iter().map(|d| d.energy[i]).sum::<f64>();
+        assert_eq!((generation.len(), at(&generation, 0), at(&generation, 23)), (365, 0.0, 0.0));
+        let daily = generation.iter().map(|d| d.energy.iter().sum::<f64>()).sum::<f64>() / 365.0;
+        assert!(daily > 3.0 * 5.0 && daily < 5.0 * 5.0, "{} kWh a day", daily);
+        Ok(())
+    }
+}
@@ -0,0 +1,198 @@
+//! Reader for Green Button (NAESB ESPI) XML energy usage files, as exported by US utilities.
+//!
+//! The relevant ESPI elements are:
+//! - LocalTimeParameters/tzOffset: offset of local standard time from UTC (seconds)
+//! - ReadingType: uom (72 = Wh), powerOfTenMultiplier and flowDirection (1 = forward/delivered, 19 = reverse/received)
+//! - IntervalBlock/IntervalReading: timePeriod (start in seconds since the Unix epoch, duration in seconds),
+//!   value and optional ReadingQuality/quality
+//!
+//! A ReadingType applies to the IntervalBlocks that follow it in the document, which is how utilities
+//! lay out their downloads. Readings are mapped to local dates and summed into [`DayEnergy`] in kWh.
+//! Intervals of a day without a reading are zero with [`Quality::Null`].
+
+use crate::energy::{open_input, DayEnergy, Direction, Quality};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, NaiveDate, Timelike};
+use log::{debug, info};
+use std::collections::BTreeMap;
+use std::io::Read;
+
+// Minimal XML pull parser events, element names have their namespace prefix removed
+#[derive(Debug, PartialEq)]
+enum Event<'a> {
+    Start(&'a str),
+    End(&'a str),
+    Text(&'a str),
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+// Split an XML document into events, ignoring declarations, comments and attributes
+fn xml_events(xml: &str) -> Result<Vec<Event<'_>>> {
+    let mut events = Vec::new();
+    let mut rest = xml;
+    while !rest.is_empty() {
+        match rest.find('<') {
+            None => break,
+            Some(lt) => {
+                let text = rest[.. lt].trim();
+                if !text.is_empty() {
+                    events.push(Event::Text(text));
+                }
+                rest = &rest[lt ..];
+                if let Some(after) = rest.strip_prefix("<!--") {
+                    let end = after.find("-->").context("xml_events: unterminated comment")?;
+                    rest = &after[end + 3 ..];
+                    continue;
+                }
+                let gt = rest.find('>').context("xml_events: unterminated tag")?;
+                let tag = &rest[1 .. gt];
+                rest = &rest[gt + 1 ..];
+                if tag.starts_with('?') || tag.starts_with('!') {
+                    continue;
+                }
+                if let Some(name) = tag.strip_prefix('/') {
+                    events.push(Event::End(local_name(name.trim())));
+                } else {
+                    let self_closing = tag.ends_with('/');
+                    let name = tag.trim_end_matches('/').split_whitespace().next().unwrap_or("");
+                    events.push(Event::Start(local_name(name)));
+                    if self_closing {
+                        events.push(Event::End(local_name(name)));
+                    }
+                }
+            },
+        }
+    }
+    Ok(events)
+}
+
+// Map ESPI QualityOfReading codes to a Quality
+fn quality_of_reading(code: i32) -> Quality {
+    match code {
+        8 | 9 | 12 => Quality::Estimated,
+        7 | 10 | 11 | 13 => Quality::Substituted,
+        _ => Quality::Actual,
+    }
+}
+
+#[derive(Default)]
+struct Reading {
+    start: i64,
+    duration: i64,
+    value: f64,
+    quality: Option<i32>,
+}
+
+/// Read the readings matching `direction` (Import: forward, Export: reverse) from a Green Button XML file
+/// (or standard input for [`crate::energy::STDIN`]).
+pub fn read_green_button(xml_file: &str, direction: Direction) -> Result<Vec<DayEnergy>> {
+    info!("read_green_button: loading XML file {}, {:?}", xml_file, direction);
+    let mut xml = String::new();
+    open_input(xml_file)?.read_to_string(&mut xml)?;
+    parse_green_button(&xml, direction)
+}
+
+/// Parse Green Button XML, see [`read_green_button`].
+pub fn parse_green_button(xml: &str, direction: Direction) -> Result<Vec<DayEnergy>> {
+    let wanted_flow = match direction {
+        Direction::Import => 1,
+        Direction::Export => 19,
+    };
+    let mut tz_offset: i64 = 0;
+    let mut uom = 72;
+    let mut power_of_ten: i32 = 0;
+    let mut flow = 1;
+    let mut reading = Reading::default();
+    let mut days: BTreeMap<NaiveDate, DayEnergy> = BTreeMap::new();
+    let mut stack: Vec<&str> = Vec::new();
+
+    for event in xml_events(xml)? {
+        match event {
+            Event::Start(name) => {
+                if name == "IntervalReading" {
+                    reading = Reading::default();
+                }
+                stack.push(name);
+            },
+            Event::End(name) => {
+                if stack.pop() != Some(name) {
+                    return Err(anyhow!("parse_green_button: unexpected closing tag {}", name));
+                }
+                if name == "IntervalReading" {
+                    if flow != wanted_flow || uom != 72 {
+                        continue;
+                    }
+                    let kwh = reading.value * 10f64.powi(power_of_ten) / 1000.0;
+                    add_reading(&mut days, &reading, tz_offset, kwh)?;
+                }
+            },
+            Event::Text(text) => {
+                let parent = stack.len().checked_sub(2).map(|i| stack[i]);
+                match (parent, stack.last().copied()) {
+                    (Some("LocalTimeParameters"), Some("tzOffset")) => tz_offset = text.parse()?,
+                    (Some("ReadingType"), Some("uom")) => uom = text.parse()?,
+                    (Some("ReadingType"), Some("powerOfTenMultiplier")) => power_of_ten = text.parse()?,
+                    (Some("ReadingType"), Some("flowDirection")) => flow = text.parse()?,
+                    (Some("timePeriod"), Some("start")) if stack.contains(&"IntervalReading") => reading.start = text.parse()?,
+                    (Some("timePeriod"), Some("duration")) if stack.contains(&"IntervalReading") => reading.duration = text.parse()?,
+                    (Some("IntervalReading"), Some("value")) => reading.value = text.parse()?,
+                    (Some("ReadingQuality"), Some("quality")) => reading.quality = Some(text.parse()?),
+                    _ => {},
+                }
+            },
+        }
+    }
+
+    info!("parse_green_button: {} days", days.len());
+    Ok(days.into_values().collect())
+}
+
+// Add a reading to the day containing its local start time
+fn add_reading(days: &mut BTreeMap<NaiveDate, DayEnergy>, reading: &Reading, tz_offset: i64, kwh: f64) -> Result<()> {
+    if reading.duration <= 0 || (24 * 60 * 60) % reading.duration != 0 || reading.duration % 60 != 0 {
+        return Err(anyhow!("parse_green_button: invalid interval duration {}", reading.duration));
+    }
+    let interval = (reading.duration / 60) as usize;
+    let local = DateTime::from_timestamp(reading.start + tz_offset, 0)
+        .with_context(|| format!("parse_green_button: invalid start {}", reading.start))?
+        .naive_utc();
+    let date = local.date();
+    let i = (local.num_seconds_from_midnight() / 60) as usize / interval;
+    debug!("parse_green_button: date {}, interval {}, i {}, kWh {}", date, interval, i, kwh);
+
+    let num_intervals = (24 * 60) / interval;
+    let day = days.entry(date).or_insert_with(|| DayEnergy {
+        date,
+        interval,
+        energy: vec![0.0; num_intervals],
+        quality: vec![Quality::Null; num_intervals],
+    });
+    if day.interval != interval {
+        return Err(anyhow!("parse_green_button: readings for {} have different interval lengths {} and {}", date, day.interval, interval));
+    }
+    let quality = reading.quality.map(quality_of_reading).unwrap_or(Quality::Actual);
+    if day.quality[i] == Quality::Null || quality != Quality::Actual {
+        day.quality[i] = quality;
+    }
+    day.energy[i] += kwh;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::energy::assert_test_energy;
+
+    #[test]
+    // same energy as data/test/energy/*.csv
+    fn test_read_green_button() -> Result<()> {
+        assert_test_energy(|direction| read_green_button("data/test/energy/greenbutton.xml", direction))?;
+        let consumption = read_green_button("data/test/energy/greenbutton.xml", Direction::Import)?;
+        assert_eq!(consumption[0].interval, 480);
+        assert_eq!(consumption[1].quality, vec![Quality::Actual, Quality::Estimated, Quality::Actual]);
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+//! Usage heatmaps: average kWh in each hour of the day by day of the week (or by month),
+//! to show when consumption happens.
+
+use crate::energy::DayEnergy;
+use crate::html;
+use anyhow::{anyhow, Result};
+use chrono::Datelike;
+use csv::Writer;
+use log::info;
+use std::io;
+use std::path::Path;
+
+const DAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Average kWh in each hour of the day for each day of the week (Monday first) or month (January first).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heatmap {
+    pub by_month: bool,
+    pub days: Vec<usize>,      // days of data in each row
+    pub kwh: Vec<[f64; 24]>,   // average kWh per hour in each row, 0 for rows without data
+}
+
+/// The heatmap of energy data, by day of the week or by month if `by_month`.
+///
+/// Intervals longer than an hour are spread evenly over their hours. On the day clocks go back
+/// the repeated hour counts twice.
+pub fn usage_heatmap<I>(days: I, by_month: bool) -> Result<Heatmap> where
+I: IntoIterator<Item = Result<DayEnergy>> {
+    let rows = if by_month { MONTHS.len() } else { DAYS.len() };
+    let mut map = Heatmap { by_month, days: vec![0; rows], kwh: vec![[0.0; 24]; rows] };
+    for day in days {
+        let day = day?;
+        let row = if by_month { day.date.month0() } else { day.date.weekday().num_days_from_monday() } as usize;
+        map.days[row] += 1;
+        for (i, kwh) in day.energy.iter().enumerate() {
+            let (start, end) = (day.minute(i) as usize, day.minute(i) as usize + day.interval);
+            for hour in start / 60..end.div_ceil(60) {
+                let overlap = end.min(hour * 60 + 60) - start.max(hour * 60);
+                map.kwh[row][hour % 24] += kwh * overlap as f64 / day.interval as f64;
+            }
+        }
+    }
+    for (kwh, days) in map.kwh.iter_mut().zip(&map.days).filter(|(_, days)| **days > 0) {
+        kwh.iter_mut().for_each(|x| *x /= *days as f64);
+    }
+    Ok(map)
+}
+
+impl Heatmap {
+    /// The row labels, e.g. Mon or Jan.
+    pub fn labels(&self) -> &'static [&'static str] {
+        if self.by_month { &MONTHS } else { &DAYS }
+    }
+
+    /// Write the heatmap as CSV: a line per row with its label, days of data and the average kWh for each hour.
+    pub fn write_csv<W: io::Write>(&self, w: W) -> Result<()> {
+        let mut writer = Writer::from_writer(w);
+        let header = [if self.by_month { "Month" } else { "Day" }.to_string(), "Days".to_string()].into_iter()
+            .chain((0..24).map(|h| format!("{:02}:00", h)));
+        writer.write_record(header)?;
+        for ((label, days), kwh) in self.labels().iter().zip(&self.days).zip(&self.kwh) {
+            writer.write_record([label.to_string(), days.to_string()].into_iter().chain(kwh.iter().map(f64::to_string)))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// The heatmap as a standalone SVG image.
+    pub fn to_svg(&self) -> String {
+        let hours = (0..24).map(|h| format!("{:02}:00", h)).collect::<Vec<_>>();
+        let labels = self.labels().iter().map(|l| l.to_string()).collect::<Vec<_>>();
+        let title = if self.by_month { "Average kWh per hour by month" } else { "Average kWh per hour by day of the week" };
+        html::heatmap_svg(title, "kWh", &labels, &hours, &self.kwh.iter().map(|k| k.to_vec()).collect::<Vec<_>>())
+    }
+
+    /// Write the heatmap to a `.csv` or `.svg` file, chosen by the extension.
+    pub fn save(&self, path: &str) -> Result<()> {
+        info!("Heatmap::save: writing {}", path);
+        match Path::new(path).extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+            Some("csv") => self.write_csv(std::fs::File::create(path)?),
+            Some("svg") => Ok(std::fs::write(path, self.to_svg())?),
+            _ => Err(anyhow!("Heatmap::save: {} is not a .csv or .svg file", path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::energy::read_csv_energy;
+    use assert_float_eq::*;
+
+    #[test]
+    fn test_usage_heatmap() -> Result<()> {
+        // Sun 6th, Mon 7th and Tue 8th of August: 0.073, 0.028, 0.063 kWh over 8 hour intervals
+        let map = usage_heatmap(read_csv_energy("data/test/energy/consumption.csv")?, false)?;
+        assert_eq!(map.days, vec![1, 1, 0, 0, 0, 0, 1]);
+        assert_f64_near!(map.kwh[0][0], 0.073 / 8.0);
+        assert_f64_near!(map.kwh[0][12], 0.028 / 8.0);
+        assert_f64_near!(map.kwh[6][23], 0.063 / 8.0);
+        assert_eq!(map.kwh[2], [0.0; 24]);
+
+        let map = usage_heatmap(read_csv_energy("data/test/energy/consumption.csv")?, true)?;
+        assert_eq!(map.days[7], 3);
+        assert_f64_near!(map.kwh[7].iter().sum::<f64>(), 0.164);
+
+        let mut csv = Vec::new();
+        map.write_csv(&mut csv)?;
+        let csv = String::from_utf8(csv)?;
+        assert!(csv.starts_with("Month,Days,00:00,01:00,"));
+        assert_eq!(csv.lines().count(), 13);
+        assert!(csv.lines().nth(8).unwrap().starts_with("Aug,3,0.009125,"));
+        assert!(map.to_svg().contains("<title>Aug 16:00 0.008 kWh</title>"));
+        assert!(map.save("out.txt").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+//! Computed public holiday calendars for the Australian states and territories.
+//!
+//! Only the statewide public holidays (with their weekend substitutes) are computed. Local, part-day and
+//! proclaimed-each-year holidays (e.g. the AFL Grand Final Friday or show days) are not, for those use a
+//! public holiday CSV file.
+
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// Years covered when a calendar is computed without knowing the dates of the energy data.
+pub const YEARS: RangeInclusive<i32> = 2000..=2099;
+
+/// An Australian state or territory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Nsw,
+    Vic,
+    Qld,
+    Sa,
+    Wa,
+    Tas,
+    Nt,
+    Act,
+}
+
+impl FromStr for Region {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Region> {
+        match s.trim().to_uppercase().as_str() {
+            "NSW" => Ok(Region::Nsw),
+            "VIC" => Ok(Region::Vic),
+            "QLD" => Ok(Region::Qld),
+            "SA" => Ok(Region::Sa),
+            "WA" => Ok(Region::Wa),
+            "TAS" => Ok(Region::Tas),
+            "NT" => Ok(Region::Nt),
+            "ACT" => Ok(Region::Act),
+            _ => Err(anyhow!("Region::from_str: '{}' is not one of NSW, VIC, QLD, SA, WA, TAS, NT, ACT", s)),
+        }
+    }
+}
+
+// Easter Sunday (anonymous Gregorian algorithm)
+fn easter_sunday(year: i32) -> NaiveDate {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+    date(year, month as u32, day as u32)
+}
+
+fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).expect("holidays: invalid date")
+}
+
+// The first `weekday` on or after `from`
+fn on_or_after(from: NaiveDate, weekday: Weekday) -> NaiveDate {
+    let days = (7 + weekday.num_days_from_monday() as i64 - from.weekday().num_days_from_monday() as i64) % 7;
+    from + Duration::days(days)
+}
+
+// The nth (from 1) `weekday` of the month
+fn nth(year: i32, month: u32, weekday: Weekday, n: i64) -> NaiveDate {
+    on_or_after(date(year, month, 1), weekday) + Duration::weeks(n - 1)
+}
+
+// The last `weekday` of the month
+fn last(year: i32, month: u32, weekday: Weekday) -> NaiveDate {
+    let d = nth(year, month, weekday, 5);
+    if d.month() == month { d } else { d - Duration::weeks(1) }
+}
+
+// The holiday and, if it falls on a weekend, the following Monday
+fn with_monday(d: NaiveDate) -> Vec<NaiveDate> {
+    match d.weekday() {
+        Weekday::Sat | Weekday::Sun => vec![d, on_or_after(d, Weekday::Mon)],
+        _ => vec![d],
+    }
+}
+
+/// The public holidays of a region in a year, in date order.
+pub fn region_year_holidays(region: Region, year: i32) -> Vec<NaiveDate> {
+    use Region::*;
+    let easter = easter_sunday(year);
+    let mut days = Vec::new();
+    days.extend(with_monday(date(year, 1, 1)));
+    days.extend(with_monday(date(year, 1, 26)));
+    days.push(easter - Duration::days(2)); // Good Friday
+    if !matches!(region, Wa | Tas) {
+        days.push(easter - Duration::days(1)); // Easter Saturday
+    }
+    if matches!(region, Nsw | Vic | Qld | Wa | Act) {
+        days.push(easter);
+    }
+    days.push(easter + Duration::days(1)); // Easter Monday
+    match region {
+        Wa | Act => days.extend(with_monday(date(year, 4, 25))),
+        _ => days.push(date(year, 4, 25)),
+    }
+    days.push(match region {
+        Qld => nth(year, 10, Weekday::Mon, 1),
+        Wa => last(year, 9, Weekday::Mon),
+        _ => nth(year, 6, Weekday::Mon, 2),
+    }); // King's (Queen's) Birthday
+    days.push(match region {
+        Nsw | Act | Sa => nth(year, 10, Weekday::Mon, 1),
+        Vic | Tas => nth(year, 3, Weekday::Mon, 2), // Eight Hours Day in TAS
+        Qld | Nt => nth(year, 5, Weekday::Mon, 1),  // May Day in NT
+        Wa => nth(year, 3, Weekday::Mon, 1),
+    }); // Labour Day
+    match region {
+        Nsw => days.push(nth(year, 8, Weekday::Mon, 1)), // Bank Holiday
+        Vic => days.push(nth(year, 11, Weekday::Mon, 1) + Duration::days(1)), // Melbourne Cup
+        Sa => days.push(nth(year, 3, Weekday::Mon, 2)), // Adelaide Cup
+        Wa => days.push(nth(year, 6, Weekday::Mon, 1)), // Western Australia Day
+        Nt => days.push(nth(year, 8, Weekday::Mon, 1)), // Picnic Day
+        Act => {
+            days.push(nth(year, 3, Weekday::Mon, 2)); // Canberra Day
+            days.push(on_or_after(date(year, 5, 27), Weekday::Mon)); // Reconciliation Day
+        },
+        Qld | Tas => {},
+    }
+    let christmas = date(year, 12, 25);
+    days.push(christmas);
+    days.push(christmas + Duration::days(1)); // Boxing Day (Proclamation Day in SA)
+    days.extend(match christmas.weekday() { // substitutes on the following weekdays
+        Weekday::Fri => vec![3],
+        Weekday::Sat => vec![2, 3],
+        Weekday::Sun => vec![2],
+        _ => vec![],
+    }.into_iter().map(|n| christmas + Duration::days(n)));
+    days.sort();
+    days.dedup();
+    days
+}
+
+/// The public holidays of a region over the years, as `YYYYMMDD` strings like [`crate::pricing::load_public_holidays`].
+pub fn region_holidays(region: Region, years: RangeInclusive<i32>) -> HashSet<String> {
+    years
+    .flat_map(|year| region_year_holidays(region, year))
+    .map(|d| d.format("%Y%m%d").to_string())
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pricing::load_public_holidays;
+
+    #[test]
+    fn test_region_holidays() -> Result<()> {
+        let expected = load_public_holidays("data/NB/publicHolidaysNSW.csv")?.into_iter().filter(|d| d.starts_with("2023")).collect::<HashSet<_>>();
+        assert_eq!(region_holidays("nsw".parse()?, 2023..=2023), expected);
+        assert_eq!(easter_sunday(2024), date(2024, 3, 31));
+        let christmas_2021 = region_year_holidays(Region::Vic, 2021).into_iter().filter(|d| d.month() == 12).collect::<Vec<_>>();
+        assert_eq!(christmas_2021, vec![date(2021, 12, 25), date(2021, 12, 26), date(2021, 12, 27), date(2021, 12, 28)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+//! Minimal HTML and inline SVG charts for self-contained reports (no scripts).
+
+use std::f64::consts::PI;
+
+const WIDTH: f64 = 720.0;
+const HEIGHT: f64 = 240.0;
+const MARGIN: f64 = 40.0; // for the axis labels
+const COLOURS: [&str; 8] = ["#4e79a7", "#f28e2b", "#e15759", "#76b7b2", "#59a14f", "#edc948", "#b07aa1", "#9c755f"];
+
+/// Escape text for HTML element content or attribute values.
+pub fn escape(s: &str) -> String {
+    s.chars().fold(String::with_capacity(s.len()), |mut out, c| {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+        out
+    })
+}
+
+// Scale from a value to the y coordinate, with room for zero and negative values (e.g. feedin credits)
+fn y_scale(values: impl Iterator<Item = f64>) -> impl Fn(f64) -> f64 {
+    let (min, max) = values.fold((0.0_f64, 0.0_f64), |(min, max), v| (min.min(v), max.max(v)));
+    let range = if max > min { max - min } else { 1.0 };
+    move |v| MARGIN / 2.0 + (max - v) / range * (HEIGHT - MARGIN)
+}
+
+fn svg_element(body: &str) -> String {
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {} {}\" width=\"{}\" height=\"{}\">\n{}</svg>\n",
+        WIDTH, HEIGHT, WIDTH, HEIGHT, body
+    )
+}
+
+fn svg(title: &str, body: &str) -> String {
+    format!("<figure>\n<figcaption>{}</figcaption>\n{}</figure>\n", escape(title), svg_element(body))
+}
+
+/// A bar chart of labelled values, e.g. cost per month.
+pub fn bar_chart(title: &str, unit: &str, bars: &[(String, f64)]) -> String {
+    let y = y_scale(bars.iter().map(|b| b.1));
+    let step = (WIDTH - MARGIN) / bars.len().max(1) as f64;
+    let mut body = format!("<line x1=\"{}\" y1=\"{:.1}\" x2=\"{}\" y2=\"{:.1}\" stroke=\"#888\"/>\n", MARGIN, y(0.0), WIDTH, y(0.0));
+    for (i, (label, v)) in bars.iter().enumerate() {
+        let x = MARGIN + i as f64 * step;
+        let (top, bottom) = (y(v.max(0.0)), y(v.min(0.0)));
+        body += &format!(
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\"><title>{} {:.2} {}</title></rect>\n",
+            x + step * 0.1, top, step * 0.8, bottom - top, COLOURS[0], escape(label), v, escape(unit)
+        );
+        body += &format!(
+            "<text x=\"{:.1}\" y=\"{}\" font-size=\"10\" text-anchor=\"middle\">{}</text>\n",
+            x + step / 2.0, HEIGHT - 4.0, escape(label)
+        );
+    }
+    svg(title, &body)
+}
+
+/// A line chart of one or more named series sharing the x labels, e.g. kWh in and out per day.
+/// Only the first and last x labels are shown.
+pub fn line_chart(title: &str, labels: &[String], series: &[(&str, Vec<f64>)]) -> String {
+    let y = y_scale(series.iter().flat_map(|s| s.1.iter().copied()));
+    let step = (WIDTH - MARGIN) / labels.len().saturating_sub(1).max(1) as f64;
+    let mut body = format!("<line x1=\"{}\" y1=\"{:.1}\" x2=\"{}\" y2=\"{:.1}\" stroke=\"#888\"/>\n", MARGIN, y(0.0), WIDTH, y(0.0));
+    for (i, (name, values)) in series.iter().enumerate() {
+        let points = values.iter().enumerate().map(|(j, v)| format!("{:.1},{:.1}", MARGIN + j as f64 * step, y(*v))).collect::<Vec<_>>();
+        body += &format!(
+            "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\"><title>{}</title></polyline>\n",
+            points.join(" "), COLOURS[i % COLOURS.len()], escape(name)
+        );
+        body += &format!(
+            "<text x=\"{}\" y=\"{}\" font-size=\"10\" fill=\"{}\">{}</text>\n",
+            MARGIN + 4.0 + i as f64 * 100.0, MARGIN / 4.0 + 4.0, COLOURS[i % COLOURS.len()], escape(name)
+        );
+    }
+    if let (Some(first), Some(last)) = (labels.first(), labels.last()) {
+        body += &format!("<text x=\"{}\" y=\"{}\" font-size=\"10\">{}</text>\n", MARGIN, HEIGHT - 4.0, escape(first));
+        body += &format!("<text x=\"{}\" y=\"{}\" font-size=\"10\" text-anchor=\"end\">{}</text>\n", WIDTH, HEIGHT - 4.0, escape(last));
+    }
+    svg(title, &body)
+}
+
+/// A pie chart of the shares of labelled values, e.g. cost per tariff name. Values that aren't positive are left out.
+pub fn pie_chart(title: &str, slices: &[(String, f64)]) -> String {
+    let slices = slices.iter().filter(|s| s.1 > 0.0).collect::<Vec<_>>();
+    let total = slices.iter().fold(0.0, |sum, s| sum + s.1);
+    let (cx, cy, r) = (HEIGHT / 2.0, HEIGHT / 2.0, HEIGHT / 2.0 - 10.0);
+    let point = |fraction: f64| (cx + r * (2.0 * PI * fraction).sin(), cy - r * (2.0 * PI * fraction).cos());
+    let mut body = String::new();
+    let mut start = 0.0;
+    for (i, (label, v)) in slices.iter().enumerate() {
+        let fraction = v / total;
+        let colour = COLOURS[i % COLOURS.len()];
+        let tip = format!("<title>{} {:.1}%</title>", escape(label), fraction * 100.0);
+        body += &match slices.len() {
+            1 => format!("<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\">{}</circle>\n", cx, cy, r, colour, tip),
+            _ => {
+                let ((x0, y0), (x1, y1)) = (point(start), point(start + fraction));
+                format!(
+                    "<path d=\"M{},{} L{:.1},{:.1} A{},{} 0 {} 1 {:.1},{:.1} Z\" fill=\"{}\">{}</path>\n",
+                    cx, cy, x0, y0, r, r, i32::from(fraction > 0.5), x1, y1, colour, tip
+                )
+            },
+        };
+        body += &format!(
+            "<rect x=\"{}\" y=\"{}\" width=\"10\" height=\"10\" fill=\"{}\"/><text x=\"{}\" y=\"{}\" font-size=\"12\">{} {:.1}%</text>\n",
+            HEIGHT + 20.0, 20.0 + i as f64 * 18.0, colour, HEIGHT + 36.0, 29.0 + i as f64 * 18.0, escape(label), fraction * 100.0
+        );
+        start += fraction;
+    }
+    svg(title, &body)
+}
+
+/// A standalone SVG image of a grid of values shaded by size, with row labels on the left and column labels
+/// along the top, e.g. average kWh by day of the week and hour.
+pub fn heatmap_svg(title: &str, unit: &str, rows: &[String], columns: &[String], values: &[Vec<f64>]) -> String {
+    let max = values.iter().flatten().fold(0.0_f64, |max, v| max.max(*v));
+    let (width, height) = ((WIDTH - MARGIN) / columns.len().max(1) as f64, (HEIGHT - MARGIN) / rows.len().max(1) as f64);
+    let mut body = format!("<title>{}</title>\n", escape(title));
+    body += &format!("<text x=\"{}\" y=\"12\" font-size=\"12\" font-weight=\"bold\">{}</text>\n", MARGIN, escape(title));
+    for (j, column) in columns.iter().enumerate() {
+        body += &format!(
+            "<text x=\"{:.1}\" y=\"{}\" font-size=\"8\" text-anchor=\"middle\">{}</text>\n",
+            MARGIN + (j as f64 + 0.5) * width, MARGIN - 6.0, escape(column)
+        );
+    }
+    for (i, (row, values)) in rows.iter().zip(values).enumerate() {
+        let y = MARGIN + i as f64 * height;
+        body += &format!("<text x=\"4\" y=\"{:.1}\" font-size=\"10\">{}</text>\n", y + height / 2.0 + 4.0, escape(row));
+        for (j, (column, v)) in columns.iter().zip(values).enumerate() {
+            body += &format!(
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\" fill-opacity=\"{:.3}\" stroke=\"#eee\"><title>{} {} {:.3} {}</title></rect>\n",
+                MARGIN + j as f64 * width, y, width, height, COLOURS[2], if max > 0.0 { v / max } else { 0.0 },
+                escape(row), escape(column), v, escape(unit)
+            );
+        }
+    }
+    svg_element(&body)
+}
+
+/// A table with a header row, text cells are escaped.
+pub fn table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let cells = |tag: &str, row: &mut dyn Iterator<Item = &str>| row.map(|c| format!("<{}>{}</{}>", tag, escape(c), tag)).collect::<String>();
+    let mut s = format!("<table>\n<tr>{}</tr>\n", cells("th", &mut header.iter().copied()));
+    for row in rows {
+        s += &format!("<tr>{}</tr>\n", cells("td", &mut row.iter().map(String::as_str)));
+    }
+    s + "</table>\n"
+}
+
+/// A complete HTML page with a title and body, styled inline so it is self-contained.
+pub fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}</style>\n</head>\n<body>\n<h1>{}</h1>\n{}</body>\n</html>\n",
+        escape(title),
+        "body { font-family: sans-serif; margin: 2em; }\nfigure { margin: 1em 0; }\nfigcaption { font-weight: bold; }\n\
+        table { border-collapse: collapse; }\nth, td { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: right; }\n\
+        th:first-child, td:first-child { text-align: left; }\n",
+        escape(title),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html() {
+        assert_eq!(escape("<a & 'b'>"), "&lt;a &amp; &#39;b&#39;&gt;");
+        let bars = bar_chart("Cost", "$", &[("2023-08".to_string(), 10.0), ("2023-09".to_string(), -5.0)]);
+        assert_eq!(bars.matches("<rect").count(), 2);
+        let pie = pie_chart("TOU", &[("Peak".to_string(), 3.0), ("Off-peak".to_string(), 1.0), ("None".to_string(), 0.0)]);
+        assert!(pie.contains("Peak 75.0%") && pie.contains("Off-peak 25.0%") && !pie.contains("None"));
+        assert!(pie.contains(" 0 1 1 ")); // the large arc flag for the peak slice
+        assert_eq!(table(&["a"], &[vec!["<1>".to_string()]]), "<table>\n<tr><th>a</th></tr>\n<tr><td>&lt;1&gt;</td></tr>\n</table>\n");
+    }
+}
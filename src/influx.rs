@@ -0,0 +1,209 @@
+//! A bill's intervals and days as InfluxDB line protocol, written to a file or posted to an InfluxDB write endpoint
+//! (by a minimal HTTP client), for graphing costs alongside meter data in Grafana.
+//!
+//! Each interval is a point of the `<measurement>_interval` measurement tagged with its `direction` (consumption or
+//! feedin) and `tariff`, with `kwh` and `cost` fields (summing the portions of an interval crossing a block threshold).
+//! Each day is a point of `<measurement>_daily` with `kwh_in`, `kwh_out`, `consumption`, `feedin`, `supply` and
+//! `total` fields. Timestamps are in seconds (precision `s`), the data's local times converted to UTC by
+//! [`LocalTime`]; on the day clocks go back the repeated hour's intervals are points of their own.
+
+use crate::bill::Bill;
+use crate::energy::{changeover, daylight_saving, Changeover, Direction, DST_CHANGEOVER};
+use anyhow::{anyhow, Context, Result};
+use chrono::{FixedOffset, NaiveDate, NaiveDateTime, TimeDelta};
+use log::warn;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+type Point<'a> = (NaiveDate, bool, i32, &'a str, &'a str); // date, repeated, minute, direction, tariff
+
+// Escape a measurement name (commas and spaces) or tag value (also equals signs)
+fn escape(s: &str, specials: &[char]) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if specials.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The data's local time: a standard offset from UTC, and an hour ahead in daylight saving time if it's observed,
+/// as in south eastern Australia from 02:00 on the first Sunday in October to 03:00 on the first Sunday in April
+/// (see [`changeover`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    pub offset: FixedOffset,
+    pub daylight_saving: bool,
+}
+
+impl LocalTime {
+    /// Seconds since the epoch of the interval starting at `minute` on a date, the second time round the hour
+    /// repeated when clocks go back if `repeated`.
+    pub fn timestamp(&self, date: NaiveDate, minute: i32, repeated: bool) -> i64 {
+        let at: NaiveDateTime = date.and_hms_opt(0, 0, 0).unwrap_or_default() + TimeDelta::minutes(minute as i64);
+        let offset = self.offset.local_minus_utc() as i64 + if self.in_daylight_saving(date, minute, repeated) { 3600 } else { 0 };
+        at.and_utc().timestamp() - offset
+    }
+
+    fn in_daylight_saving(&self, date: NaiveDate, minute: i32, repeated: bool) -> bool {
+        let changeover_minute = DST_CHANGEOVER as i32;
+        match (self.daylight_saving, changeover(date)) {
+            (false, _) => false,
+            (true, Some(Changeover::Forward)) => minute >= changeover_minute,
+            (true, Some(Changeover::Back)) => minute < changeover_minute || minute < changeover_minute + 60 && !repeated,
+            (true, None) => daylight_saving(date),
+        }
+    }
+}
+
+/// The bill's points in line protocol, one per line: its intervals (from the trace, so none unless it was priced with
+/// [`crate::plan::price_plan_traced`]) then its days, timestamped by `local`.
+pub fn influx_lines(bill: &Bill, measurement: &str, local: LocalTime) -> String {
+    let measurement = escape(measurement, &[',', ' ']);
+    let mut changeovers = Vec::new();
+    let mut intervals: BTreeMap<Point, (f64, f64)> = BTreeMap::new();
+    for t in &bill.trace {
+        if t.repeated && changeovers.last() != Some(&t.date) {
+            changeovers.push(t.date);
+        }
+        let direction = match t.direction {
+            Direction::Import => "consumption",
+            Direction::Export => "feedin",
+        };
+        let point = intervals.entry((t.date, t.repeated, t.minute, direction, t.name.as_str())).or_default();
+        point.0 += t.kwh;
+        point.1 += t.cost;
+    }
+    if !local.daylight_saving && !changeovers.is_empty() {
+        warn!("influx_lines: clocks go back on {:?} but the local time has no daylight saving, so the repeated hour's points have the timestamps of its first time round", changeovers);
+    }
+    let mut lines = String::new();
+    for ((date, repeated, minute, direction, name), (kwh, cost)) in intervals {
+        lines += &format!(
+            "{}_interval,direction={},tariff={} kwh={},cost={} {}\n",
+            measurement, direction, escape(name, &[',', ' ', '=']), kwh, cost, local.timestamp(date, minute, repeated)
+        );
+    }
+    for (date, t) in &bill.daily {
+        lines += &format!(
+            "{}_daily kwh_in={},kwh_out={},consumption={},feedin={},supply={},total={} {}\n",
+            measurement, t.kwh_in, t.kwh_out, t.consumption, t.feedin, t.supply, t.total(), local.timestamp(*date, 0, false)
+        );
+    }
+    lines
+}
+
+/// Post line protocol to an InfluxDB write endpoint, e.g. `http://localhost:8086/api/v2/write?org=home&bucket=elec`
+/// (InfluxDB 2, with an API `token`) or `http://localhost:8086/write?db=elec` (InfluxDB 1), with precision `s`.
+pub fn write_influx(url: &str, token: Option<&str>, lines: &str) -> Result<()> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| anyhow!("write_influx: {} is not an http:// URL", url))?;
+    let (host, target) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let target = match target.contains("precision=") {
+        true => target.to_string(),
+        false => format!("{}{}precision=s", target, if target.contains('?') { '&' } else { '?' }),
+    };
+    let address = if host.contains(':') { host.to_string() } else { format!("{}:80", host) };
+    let mut stream = TcpStream::connect(&address).with_context(|| format!("write_influx: {}", address))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut request = format!("POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n", target, host, lines.len());
+    if let Some(token) = token {
+        request += &format!("Authorization: Token {}\r\n", token);
+    }
+    request += "\r\n";
+    stream.write_all(request.as_bytes())?;
+    stream.write_all(lines.as_bytes())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).with_context(|| format!("write_influx: reading the response from {}", address))?;
+    let status = response.split_whitespace().nth(1).and_then(|s| s.parse::<u16>().ok()).unwrap_or_default();
+    match status {
+        200..=299 => Ok(()),
+        _ => {
+            let body = response.split_once("\r\n\r\n").map_or("", |(_, body)| body.trim());
+            Err(anyhow!("write_influx: {} replied {}: {}", url, response.lines().next().unwrap_or_default(), body))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::energy::read_csv_energy;
+    use crate::plan::{price_plan_traced, Plan};
+    use crate::server::{read_request, DEFAULT_MAX_BODY};
+    use std::collections::HashSet;
+    use std::io::BufReader;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_influx_lines() -> Result<()> {
+        let plan = Plan::load("TOU", "data/test/tariff/consumption.csv", Some("data/test/tariff/feedIn.csv"), "data/test/tariff/supply.csv", None)?;
+        let consumption = read_csv_energy("data/test/energy/consumption.csv")?;
+        let feedin = read_csv_energy("data/test/energy/feedIn.csv")?;
+        let bill = price_plan_traced(&plan, consumption, Some(feedin), &HashSet::new())?;
+        let aest = LocalTime { offset: "+10:00".parse::<FixedOffset>()?, daylight_saving: false };
+        let lines = influx_lines(&bill, "elec", aest);
+        let lines = lines.lines().collect::<Vec<_>>();
+        // 3 days of 3 intervals of consumption and feedin, then the 3 days
+        assert_eq!(lines.iter().filter(|l| l.starts_with("elec_interval,direction=consumption,")).count(), 9);
+        assert_eq!(lines.iter().filter(|l| l.starts_with("elec_daily ")).count(), 3);
+        // 2023-08-06 00:00 AEST is 2023-08-05 14:00 UTC
+        assert!(lines[0].ends_with(" 1691244000"), "{}", lines[0]);
+        assert!(lines.last().is_some_and(|l| l.ends_with(" 1691416800")), "{:?}", lines.last());
+        assert_eq!(escape("Off peak,x=1", &[',', ' ', '=']), r"Off\ peak\,x\=1");
+
+        // an endpoint accepting the points, then one rejecting them
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/api/v2/write?org=home&bucket=elec", listener.local_addr()?);
+        let server = std::thread::spawn(move || -> Result<Vec<String>> {
+            let mut requests = Vec::new();
+            for status in ["204 No Content", "400 Bad Request"] {
+                let (mut stream, _) = listener.accept()?;
+                let request = read_request(BufReader::new(&stream), DEFAULT_MAX_BODY)?;
+                requests.push(format!("{} {}", request.path, request.body.len()));
+                stream.write_all(format!("HTTP/1.1 {}\r\nContent-Length: 9\r\n\r\nbad point", status).as_bytes())?;
+            }
+            Ok(requests)
+        });
+        let body = influx_lines(&bill, "elec", aest);
+        write_influx(&url, Some("secret"), &body)?;
+        let e = write_influx(&url, None, &body).unwrap_err().to_string();
+        assert!(e.contains("400 Bad Request: bad point"), "{}", e);
+        let requests = server.join().map_err(|_| anyhow!("server panicked"))??;
+        assert_eq!(requests, vec![format!("/api/v2/write {}", body.len()); 2]);
+        assert!(write_influx("https://example.com/write", None, "").is_err());
+        Ok(())
+    }
+
+    #[test]
+    // an hour ahead in daylight saving time, with an hour's intervals missing when clocks go forward on 2024-10-06
+    // and repeated when they go back on 2025-04-06
+    fn test_influx_daylight_saving() -> Result<()> {
+        let plan = Plan::load("TOU", "data/test/tariff/consumption.csv", None, "data/test/tariff/supply.csv", None)?;
+        let bill = price_plan_traced(&plan, read_csv_energy("data/test/energy/consumptionDst.csv")?, None::<Vec<_>>, &HashSet::new())?;
+        let timestamps = |daylight_saving| -> Result<Vec<i64>> {
+            let local = LocalTime { offset: "+10:00".parse::<FixedOffset>()?, daylight_saving };
+            Ok(influx_lines(&bill, "elec", local).lines().filter(|l| l.starts_with("elec_interval,"))
+                .map(|l| l.rsplit(' ').next().unwrap_or_default().parse::<i64>()).collect::<Result<Vec<_>, _>>()?)
+        };
+        let hourly = |from: i64, hours: i64| (0..hours).map(move |h| from + h * 3600);
+        let expected = hourly(1711890000, 24) // 2024-04-01 00:00 AEDT is 2024-03-31 13:00 UTC
+            .chain(hourly(1728136800, 23))    // 2024-10-06 00:00 AEST is 2024-10-05 14:00 UTC
+            .chain(hourly(1743858000, 25))    // 2025-04-06 00:00 AEDT is 2025-04-05 13:00 UTC
+            .collect::<Vec<_>>();
+        assert_eq!(timestamps(true)?, expected);
+        // without daylight saving the repeated hour's intervals are still points of their own
+        assert_eq!(timestamps(false)?.len(), 72);
+        assert_eq!(LocalTime { offset: "+10:00".parse::<FixedOffset>()?, daylight_saving: true }.timestamp(NaiveDate::from_ymd_opt(2024, 7, 1).unwrap(), 0, false), 1719756000);
+        Ok(())
+    }
+}
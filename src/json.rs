@@ -0,0 +1,449 @@
+//! Minimal JSON values for machine-readable output and parsing input files.
+
+use anyhow::{anyhow, Result};
+use std::fmt;
+
+/// A JSON value, objects keep their keys in insertion order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Object from (key, value) pairs.
+    pub fn object<K: Into<String>>(pairs: Vec<(K, Json)>) -> Json {
+        Json::Object(pairs.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    /// The value of `key` in an object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(kvs) => kvs.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// A number, or a string holding a number (as used for amounts in many APIs).
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(x) => Some(*x),
+            Json::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// The number at `key` in an object, an error if there's none.
+    pub fn number(&self, key: &str) -> Result<f64> {
+        self.get(key).and_then(Json::as_f64).ok_or_else(|| anyhow!("Json::number: no number {}", key))
+    }
+
+    /// The string at `key` in an object, an error if there's none.
+    pub fn string(&self, key: &str) -> Result<&str> {
+        self.get(key).and_then(Json::as_str).ok_or_else(|| anyhow!("Json::string: no string {}", key))
+    }
+
+    /// The items of an array, none for null or a missing value.
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(xs) => Some(xs),
+            _ => None,
+        }
+    }
+}
+
+/// Deepest nesting of arrays and objects parsed, so a hostile document can't overflow the stack.
+pub const MAX_DEPTH: usize = 128;
+
+// Recursive descent over a JSON document
+struct Parser<'a> {
+    s: &'a str,
+    at: usize,
+    depth: usize, // arrays and objects the parser is in
+}
+
+impl Parser<'_> {
+    fn err(&self, msg: &str) -> anyhow::Error {
+        anyhow!("parse_json: {} at byte {}", msg, self.at)
+    }
+
+    fn skip(&mut self) {
+        self.at += self.s[self.at..].len() - self.s[self.at..].trim_start().len();
+    }
+
+    fn eat(&mut self, prefix: &str) -> bool {
+        self.skip();
+        match self.s[self.at..].starts_with(prefix) {
+            true => {
+                self.at += prefix.len();
+                true
+            },
+            false => false,
+        }
+    }
+
+    fn expect(&mut self, prefix: &str) -> Result<()> {
+        match self.eat(prefix) {
+            true => Ok(()),
+            false => Err(self.err(&format!("expected '{}'", prefix))),
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect("\"")?;
+        let mut s = String::new();
+        let mut chars = self.s[self.at..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.at += i + 1;
+                    return Ok(s);
+                },
+                '\\' => {
+                    let (_, e) = chars.next().ok_or_else(|| self.err("unterminated string"))?;
+                    s.push(match e {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'u' => {
+                            let hex = |chars: &mut std::str::CharIndices| -> Option<u32> {
+                                let h = (0..4).map(|_| chars.next().map(|(_, c)| c)).collect::<Option<String>>()?;
+                                u32::from_str_radix(&h, 16).ok()
+                            };
+                            let mut code = hex(&mut chars).ok_or_else(|| self.err("invalid \\u escape"))?;
+                            if (0xd800..0xdc00).contains(&code) { // a surrogate pair
+                                let low = chars.next().filter(|(_, c)| *c == '\\').and(chars.next()).filter(|(_, c)| *c == 'u').and_then(|_| hex(&mut chars))
+                                    .filter(|low| (0xdc00..0xe000).contains(low)).ok_or_else(|| self.err("invalid surrogate pair"))?;
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            char::from_u32(code).ok_or_else(|| self.err("lone low surrogate"))?
+                        },
+                        e => e, // " \\ /
+                    });
+                },
+                c => s.push(c),
+            }
+        }
+        Err(self.err("unterminated string"))
+    }
+
+    fn value(&mut self) -> Result<Json> {
+        self.skip();
+        match self.s[self.at..].chars().next() {
+            None => Err(self.err("expected a value")),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('[') => self.nested(Parser::array),
+            Some('{') => self.nested(Parser::object),
+            Some(_) if self.eat("null") => Ok(Json::Null),
+            Some(_) if self.eat("true") => Ok(Json::Bool(true)),
+            Some(_) if self.eat("false") => Ok(Json::Bool(false)),
+            Some(_) => {
+                let len = self.s[self.at..].find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c))).unwrap_or(self.s.len() - self.at);
+                let token = &self.s[self.at..self.at + len];
+                let x = token.parse::<f64>().map_err(|_| self.err(&format!("'{}' is not a value", token)))?;
+                self.at += len;
+                Ok(Json::Number(x))
+            },
+        }
+    }
+
+    // Parse an array or object, at most MAX_DEPTH deep
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Json>) -> Result<Json> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.err(&format!("nested more than {} deep", MAX_DEPTH)));
+        }
+        self.depth += 1;
+        let json = parse(self);
+        self.depth -= 1;
+        json
+    }
+
+    fn array(&mut self) -> Result<Json> {
+        self.at += 1;
+        let mut xs = Vec::new();
+        if self.eat("]") {
+            return Ok(Json::Array(xs));
+        }
+        loop {
+            xs.push(self.value()?);
+            if !self.eat(",") {
+                self.expect("]")?;
+                return Ok(Json::Array(xs));
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json> {
+        self.at += 1;
+        let mut kvs = Vec::new();
+        if self.eat("}") {
+            return Ok(Json::Object(kvs));
+        }
+        loop {
+            self.skip();
+            let key = self.string()?;
+            self.expect(":")?;
+            kvs.push((key, self.value()?));
+            if !self.eat(",") {
+                self.expect("}")?;
+                return Ok(Json::Object(kvs));
+            }
+        }
+    }
+}
+
+/// Parse a JSON document.
+pub fn parse_json(s: &str) -> Result<Json> {
+    let mut p = Parser { s, at: 0, depth: 0 };
+    let json = p.value()?;
+    p.skip();
+    match p.at == s.len() {
+        true => Ok(json),
+        false => Err(p.err("unexpected text after the value")),
+    }
+}
+
+/// Validate `json` against a JSON Schema, returning a message for each problem (none if it's valid).
+///
+/// Supports the keywords `type`, `enum`, `properties`, `required`, `additionalProperties`, `items`, `minItems`,
+/// `minimum`, `maximum`, `anyOf` and `$ref` to `#/$defs/...` in the same schema; other keywords are ignored.
+pub fn validate(json: &Json, schema: &Json) -> Vec<String> {
+    let mut problems = Vec::new();
+    validate_at(json, schema, schema, "", &mut problems);
+    problems
+}
+
+fn type_name(json: &Json) -> &'static str {
+    match json {
+        Json::Null => "null",
+        Json::Bool(_) => "boolean",
+        Json::Number(_) => "number",
+        Json::String(_) => "string",
+        Json::Array(_) => "array",
+        Json::Object(_) => "object",
+    }
+}
+
+fn has_type(json: &Json, t: &str) -> bool {
+    match (json, t) {
+        (Json::Number(x), "integer") => x.fract() == 0.0,
+        _ => type_name(json) == t,
+    }
+}
+
+fn validate_at(json: &Json, schema: &Json, root: &Json, path: &str, problems: &mut Vec<String>) {
+    let at = if path.is_empty() { "/" } else { path };
+    if let Some(r) = schema.get("$ref").and_then(Json::as_str) {
+        match r.strip_prefix("#/$defs/").and_then(|name| root.get("$defs")?.get(name)) {
+            Some(s) => validate_at(json, s, root, path, problems),
+            None => problems.push(format!("{}: unsupported $ref {}", at, r)),
+        }
+    }
+    let types = match schema.get("type") {
+        Some(Json::String(t)) => vec![t.as_str()],
+        Some(Json::Array(ts)) => ts.iter().filter_map(Json::as_str).collect(),
+        _ => Vec::new(),
+    };
+    if !types.is_empty() && !types.iter().any(|t| has_type(json, t)) {
+        problems.push(format!("{}: {} is not {}", at, type_name(json), types.join(" or ")));
+        return;
+    }
+    if let Some(values) = schema.get("enum").and_then(Json::as_array) {
+        if !values.contains(json) {
+            let values = values.iter().map(Json::to_string).collect::<Vec<_>>().join(", ");
+            problems.push(format!("{}: {} is not one of {}", at, json, values));
+        }
+    }
+    if let Some(any) = schema.get("anyOf").and_then(Json::as_array) {
+        let matches = |s: &Json| {
+            let mut p = Vec::new();
+            validate_at(json, s, root, path, &mut p);
+            p.is_empty()
+        };
+        if !any.iter().any(matches) {
+            problems.push(format!("{}: {} doesn't match any of the allowed schemas", at, json));
+        }
+    }
+    if let Json::Number(x) = json {
+        if let Some(min) = schema.get("minimum").and_then(Json::as_f64).filter(|min| x < min) {
+            problems.push(format!("{}: {} is less than {}", at, x, min));
+        }
+        if let Some(max) = schema.get("maximum").and_then(Json::as_f64).filter(|max| x > max) {
+            problems.push(format!("{}: {} is more than {}", at, x, max));
+        }
+    }
+    if let Json::Array(xs) = json {
+        if let Some(min) = schema.get("minItems").and_then(Json::as_f64).filter(|min| (xs.len() as f64) < *min) {
+            problems.push(format!("{}: {} items is fewer than {}", at, xs.len(), min));
+        }
+        if let Some(items) = schema.get("items") {
+            for (i, x) in xs.iter().enumerate() {
+                validate_at(x, items, root, &format!("{}/{}", path, i), problems);
+            }
+        }
+    }
+    if let Json::Object(kvs) = json {
+        for key in schema.get("required").and_then(Json::as_array).unwrap_or_default().iter().filter_map(Json::as_str) {
+            if json.get(key).is_none() {
+                problems.push(format!("{}: {} is required", at, key));
+            }
+        }
+        let properties = schema.get("properties");
+        for (k, v) in kvs {
+            match (properties.and_then(|p| p.get(k)), schema.get("additionalProperties")) {
+                (Some(s), _) => validate_at(v, s, root, &format!("{}/{}", path, k), problems),
+                (None, Some(Json::Bool(false))) => problems.push(format!("{}: {} is not allowed", at, k)),
+                (None, Some(s @ Json::Object(_))) => validate_at(v, s, root, &format!("{}/{}", path, k), problems),
+                (None, _) => (),
+            }
+        }
+    }
+}
+
+impl From<f64> for Json {
+    fn from(x: f64) -> Json { Json::Number(x) }
+}
+
+impl From<usize> for Json {
+    fn from(x: usize) -> Json { Json::Number(x as f64) }
+}
+
+impl From<bool> for Json {
+    fn from(x: bool) -> Json { Json::Bool(x) }
+}
+
+impl From<&str> for Json {
+    fn from(x: &str) -> Json { Json::String(x.to_string()) }
+}
+
+impl From<String> for Json {
+    fn from(x: String) -> Json { Json::String(x) }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(x: Vec<T>) -> Json { Json::Array(x.into_iter().map(Into::into).collect()) }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(x) if x.is_finite() => write!(f, "{}", x),
+            Json::Number(_) => write!(f, "null"), // JSON has no NaN or infinity
+            Json::String(s) => write_string(f, s),
+            Json::Array(xs) => {
+                write!(f, "[")?;
+                for (i, x) in xs.iter().enumerate() {
+                    if i > 0 { write!(f, ",")?; }
+                    write!(f, "{}", x)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(kvs) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in kvs.iter().enumerate() {
+                    if i > 0 { write!(f, ",")?; }
+                    write_string(f, k)?;
+                    write!(f, ":{}", v)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let j = Json::object(vec![
+            ("a", Json::from(1.5)),
+            ("b", Json::from(vec!["x\"y", "z\n"])),
+            ("c", Json::Null),
+            ("d", Json::from(f64::NAN)),
+        ]);
+        assert_eq!(j.to_string(), r#"{"a":1.5,"b":["x\"y","z\n"],"c":null,"d":null}"#);
+    }
+
+    #[test]
+    fn test_parse_json() -> Result<()> {
+        let j = Json::object(vec![
+            ("a", Json::from(-1.5e3)),
+            ("b", Json::from(vec!["x\"y", "z\n\u{e9}"])),
+            ("c", Json::Null),
+            ("d", Json::Array(vec![Json::Bool(true), Json::object::<&str>(vec![])])),
+        ]);
+        assert_eq!(parse_json(&j.to_string())?, j);
+        let j = parse_json(r#" { "rate" : "0.05", "s": "\u00e9\ud83d\ude00\/" } "#)?;
+        assert_eq!(j.get("rate").and_then(Json::as_f64), Some(0.05));
+        assert_eq!(j.get("s").and_then(Json::as_str), Some("\u{e9}\u{1f600}/"));
+        assert!(parse_json("[1, 2").is_err());
+        assert!(parse_json("{} x").is_err());
+
+        // invalid surrogates and deep nesting are errors, not panics or stack overflows
+        for s in [r#""\ud800\u0041""#, r#""\ud800""#, r#""\udc00""#] {
+            assert!(parse_json(s).is_err(), "{}", s);
+        }
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(parse_json(&nested(MAX_DEPTH)).is_ok());
+        let e = parse_json(&nested(MAX_DEPTH + 1)).unwrap_err().to_string();
+        assert!(e.contains("nested more than 128 deep"), "{}", e);
+        assert!(parse_json(&"[{\"a\":".repeat(1_000_000)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate() -> Result<()> {
+        let schema = parse_json(r##"{
+            "type": "object", "required": ["a"], "additionalProperties": false,
+            "properties": {
+                "a": {"type": "array", "minItems": 1, "items": {"$ref": "#/$defs/n"}},
+                "b": {"anyOf": [{"type": "integer"}, {"enum": ["x", "y"]}]}
+            },
+            "$defs": {"n": {"type": "number", "minimum": 0, "maximum": 7}}
+        }"##)?;
+        assert!(validate(&parse_json(r#"{"a": [0, 7], "b": "x"}"#)?, &schema).is_empty());
+        assert!(validate(&parse_json(r#"{"a": [1], "b": 2}"#)?, &schema).is_empty());
+        assert_eq!(validate(&parse_json(r#"{"a": [-1, "2"], "b": 1.5, "c": 1}"#)?, &schema), vec![
+            "/a/0: -1 is less than 0",
+            "/a/1: string is not number",
+            "/b: 1.5 doesn't match any of the allowed schemas",
+            "/: c is not allowed",
+        ]);
+        assert_eq!(validate(&parse_json(r#"{"a": []}"#)?, &schema), vec!["/a: 0 items is fewer than 1"]);
+        assert_eq!(validate(&parse_json("[]")?, &schema), vec!["/: array is not object"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+//! Electricity costs: apply a Time of Use (TOU) or fixed tariff to your actual electricity data.
+//!
+//! The pricing logic used by the `elec` command line tool is available in [`pricing`] so that
+//! other tools can embed it without shelling out to the CLI.
+//! Energy data in any supported input format is read into the [`energy::DayEnergy`] representation.
+//!
+//! Nothing in pricing needs a filesystem: plans and energy data can be read from memory (see [`plan::Plan::from_bytes`],
+//! [`energy::read_energy_bytes`] and the `_from` versions of the loaders taking any reader), so the core can be
+//! compiled to WebAssembly for a browser.
+//!
+//! Pricing logic beyond the tariff files can be plugged into a plan as a [`policy::RatePolicy`].
+//!
+//! [`api`] answers pricing requests given as JSON, for the HTTP [`server`] and, with the `python` feature, the `elec`
+//! Python extension module. [`mqtt`] publishes a bill to a broker as Home Assistant sensors.
+
+pub mod api;
+pub mod battery;
+pub mod bill;
+pub mod cap;
+pub mod carbon;
+pub mod cdr;
+pub mod compress;
+pub mod concession;
+pub mod condition;
+pub mod demand;
+pub mod discount;
+pub mod energy;
+pub mod events;
+pub mod explain;
+pub mod fees;
+pub mod forecast;
+pub mod generate;
+pub mod greenbutton;
+pub mod heatmap;
+pub mod holidays;
+pub mod html;
+pub mod influx;
+pub mod json;
+pub mod logging;
+pub mod longcsv;
+pub mod metrics;
+pub mod money;
+pub mod mqtt;
+pub mod nem12;
+pub mod parquet;
+pub mod plan;
+pub mod plandoc;
+pub mod policy;
+pub mod pricing;
+pub mod progress;
+#[cfg(feature = "python")]
+mod python;
+pub mod reconcile;
+pub mod server;
+pub mod sqlite;
+pub mod toml;
+pub mod weather;
+pub mod wholesale;
+pub mod xlsx;
@@ -0,0 +1,81 @@
+//! Logging with tracing: plain text, or structured JSON lines for log tooling, and spans timing units of work (e.g.
+//! parsing a file) with fields such as its row count.
+//!
+//! Each JSON line is tracing-subscriber's: the `timestamp`, `level`, `target` (the module), `fields` (with the
+//! `message`), its innermost `span` and the `spans` it was logged in, outermost first, each with its `name` and
+//! fields. Spans log when they start and when they close, with their `time.busy` and `time.idle`, so long batch runs
+//! can be monitored and failures traced to the file being read. Records of the `log` macros are logged as events.
+
+use tracing::Subscriber;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// Set up logging from `RUST_LOG` (by default errors only), or with `json` as JSON lines (by default info and above).
+pub fn init(json: bool) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(if json { "info" } else { "error" }));
+    let _ = subscriber(json, filter, std::io::stderr).try_init();
+}
+
+// A subscriber writing text or JSON lines to `writer`
+fn subscriber<W>(json: bool, filter: EnvFilter, writer: W) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt().with_env_filter(filter).with_writer(writer);
+    match json {
+        true => Box::new(builder.json().with_current_span(true).with_span_list(true).with_span_events(FmtSpan::NEW | FmtSpan::CLOSE).finish()),
+        false => Box::new(builder.finish()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::{parse_json, Json};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::Empty;
+
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_spans() -> anyhow::Result<()> {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let writer = buffer.clone();
+        let subscriber = subscriber(true, EnvFilter::new("info"), move || Buffer(writer.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("before");
+            let file = tracing::info_span!("parse", file = "consumption.csv", rows = Empty).entered();
+            file.record("rows", 3);
+            tracing::info_span!("check").in_scope(|| tracing::info!("reading"));
+            drop(file);
+            tracing::info!("after");
+        });
+        let lines = String::from_utf8(buffer.lock().unwrap().clone())?.lines().map(parse_json).collect::<anyhow::Result<Vec<Json>>>()?;
+        let message = |json: &Json| json.get("fields").and_then(|f| f.get("message")).cloned();
+        let spans = |json: &Json| json.get("spans").cloned();
+        let logged = lines.iter().find(|j| message(j) == Some(Json::from("reading"))).expect("reading");
+        assert_eq!(logged.get("level"), Some(&Json::from("INFO")));
+        assert_eq!(spans(logged), Some(Json::Array(vec![
+            Json::object(vec![("file", "consumption.csv".into()), ("rows", 3usize.into()), ("name", "parse".into())]),
+            Json::object(vec![("name", "check".into())]),
+        ])));
+        let before = lines.iter().find(|j| message(j) == Some(Json::from("before"))).expect("before");
+        assert_eq!(spans(before), None);
+        let closed = lines.iter().find(|j| message(j) == Some(Json::from("close")) && j.get("span").and_then(|s| s.get("name")) == Some(&Json::from("parse"))).expect("close");
+        assert!(closed.get("fields").and_then(|f| f.get("time.busy")).is_some());
+        Ok(())
+    }
+}
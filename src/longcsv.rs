@@ -0,0 +1,110 @@
+//! Reader for interval energy data in long CSV files, with a line per interval rather than a line per day.
+//!
+//! The header line names the columns (ignoring case):
+//! - `timestamp` (or `time` or `datetime`, otherwise the first column): the local start time of the interval,
+//!   see [`parse_timestamp`]
+//! - the kWh column: `consumption` or `feedin` for the direction read, or `kwh`, or the second column if there
+//!   are only two, so the same file can hold both directions
+//! - an optional `quality` column: a NEM12 quality flag (e.g. `A`, `E52`), actual if absent or empty
+//!
+//! Exports with several kWh columns per interval (e.g. one per phase or register) can be read with
+//! [`read_long_csv_columns`], summing the columns named.
+//!
+//! As for Parquet files the interval length is the smallest gap between readings and intervals without a reading
+//! (or with an empty kWh) are zero with [`crate::energy::Quality::Null`].
+
+use crate::energy::{open_input, parse_timestamp, readings_days, CsvLine, DayEnergy, Direction, Quality};
+use anyhow::{anyhow, Context, Result};
+use csv::{ReaderBuilder, StringRecord};
+use log::{debug, info};
+use std::io;
+
+/// Read the interval readings for `direction` from a long CSV file (or standard input for
+/// [`crate::energy::STDIN`]), see the module documentation for the columns.
+pub fn read_long_csv(csv_energy: &str, direction: Direction) -> Result<Vec<DayEnergy>> {
+    info!("read_long_csv: loading CSV file {}, {:?}", csv_energy, direction);
+    read_long_csv_from(csv_energy, open_input(csv_energy)?, direction)
+}
+
+/// Read long CSV readings like [`read_long_csv`] from any reader (e.g. a byte slice), `csv_energy` naming it in errors.
+pub fn read_long_csv_from<R: io::Read>(csv_energy: &str, input: R, direction: Direction) -> Result<Vec<DayEnergy>> {
+    let name = match direction {
+        Direction::Import => "consumption",
+        Direction::Export => "feedin",
+    };
+    read_readings(csv_energy, input, |headers, column| {
+        let kwh = column(&[name]).or_else(|| column(&["kwh"])).or((headers.len() == 2).then_some(1))
+            .ok_or_else(|| anyhow!("read_long_csv: {}: no {} or kwh column", csv_energy, name))?;
+        Ok(vec![kwh])
+    })
+}
+
+/// Read the interval readings from a long CSV file like [`read_long_csv`], but as the sum of the kWh in the columns
+/// named (ignoring case), e.g. the phases of a three phase meter. An interval is missing if any column is empty.
+pub fn read_long_csv_columns(csv_energy: &str, columns: &[String]) -> Result<Vec<DayEnergy>> {
+    info!("read_long_csv_columns: loading CSV file {}, columns {:?}", csv_energy, columns);
+    read_readings(csv_energy, open_input(csv_energy)?, |_, column| {
+        columns.iter().map(|c| column(&[c.trim().to_ascii_lowercase().as_str()])
+            .ok_or_else(|| anyhow!("read_long_csv_columns: {}: no {} column", csv_energy, c))).collect()
+    })
+}
+
+// Read the readings summing the kWh columns chosen from the headers, given a lookup of a column by its names
+fn read_readings<R, F>(csv_energy: &str, input: R, kwh_columns: F) -> Result<Vec<DayEnergy>> where
+R: io::Read,
+F: FnOnce(&StringRecord, &dyn Fn(&[&str]) -> Option<usize>) -> Result<Vec<usize>> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(input);
+    let headers = reader.headers()?.clone();
+    let column = |names: &[&str]| headers.iter().position(|h| names.contains(&h.trim().to_ascii_lowercase().as_str()));
+    let time = column(&["timestamp", "time", "datetime"]).unwrap_or(0);
+    let kwh = kwh_columns(&headers, &column)?;
+    let quality = column(&["quality"]);
+
+    let mut readings = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let r = record?;
+        debug!("read_long_csv: record: {:?}", r);
+        let line = CsvLine::new("read_long_csv", csv_energy, i, &headers, &r);
+        let kwh = kwh.iter()
+            .map(|k| line.optional(*k).map(|_| line.parse::<f64>(*k)).transpose())
+            .collect::<Result<Option<Vec<f64>>>>()?
+            .map(|x| x.iter().sum());
+        let quality = match quality.filter(|q| line.optional(*q).is_some()) {
+            None => Quality::Actual,
+            Some(q) => line.parse_with(q, Quality::from_flag)?,
+        };
+        readings.push((line.parse_with(time, parse_timestamp)?, kwh, quality));
+    }
+    readings_days(readings).with_context(|| format!("read_long_csv: {}", csv_energy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::energy::read_csv_energy;
+
+    #[test]
+    fn test_read_long_csv() -> Result<()> {
+        let consumption = read_long_csv("data/test/energy/long.csv", Direction::Import)?;
+        let expected = read_csv_energy("data/test/energy/consumption.csv")?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(consumption.iter().map(|d| (d.date, d.interval, d.energy.clone())).collect::<Vec<_>>(),
+            expected.iter().map(|d| (d.date, d.interval, d.energy.clone())).collect::<Vec<_>>());
+        assert_eq!(consumption[1].quality, vec![Quality::Actual, Quality::Estimated, Quality::Actual]);
+        let feedin = read_long_csv("data/test/energy/long.csv", Direction::Export)?;
+        assert_eq!(feedin.len(), 3);
+        assert_eq!(feedin[0].energy, vec![0.326, 0.198, 0.218]);
+
+        let phases = read_long_csv_columns("data/test/energy/threePhase.csv", &["Phase_A".to_string(), "phase_b".to_string(), "phase_c".to_string()])?;
+        assert_eq!(phases.len(), 3);
+        for (day, expected) in phases.iter().zip(&expected) {
+            assert!(day.energy.iter().zip(&expected.energy).all(|(a, b)| (a - b).abs() < 1e-9), "{:?}", day.energy);
+        }
+        assert_eq!(phases[1].quality[1], Quality::Estimated);
+        let phase_a = read_long_csv_columns("data/test/energy/threePhase.csv", &["phase_a".to_string()])?;
+        assert_eq!(phase_a[0].energy, vec![0.03, 0.01, 0.02]);
+        assert!(read_long_csv_columns("data/test/energy/threePhase.csv", &["phase_d".to_string()]).is_err());
+        Ok(())
+    }
+}
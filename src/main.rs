@@ -1,270 +1,2222 @@
-use anyhow::{Error, Result, anyhow, Context};
-use chrono::{NaiveDate};
-use chrono::prelude::*;
-use clap::Parser;
-use csv::ReaderBuilder;
-use sscanf::sscanf;
+use anyhow::{anyhow, Context, Result};
+use chrono::{FixedOffset, Local, NaiveDate, NaiveDateTime, Utc};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use elec::battery::{add_solar, charge_at_plug_in, charge_cheapest, peak_times, shift_load, simulate, tariff_rate, tariff_window, Battery, Ev};
+use elec::bill::{break_even, Bill, Escalation, GstInclusive};
+use elec::cap::load_bill_caps;
+use elec::carbon::{Carbon, CarbonIntensity};
+use elec::concession::{load_concessions, load_rebates};
+use elec::demand::{demand_stats, interval_demand, load_demand_tariff, peak_demand, peaks_json, peaks_text, save_duration_curve, stats_text, PowerFactor};
+use elec::discount::{load_discounts, Discount};
+use elec::energy::{check_dates, detect_format, filter_dates, merge_days, merge_exports, net_metering, open_input_counted, parse_columns, parse_date, parse_timestamp, read_csv_energy_from, read_csv_energy_lenient, read_csv_energy_missing, resample, write_csv_energy, CsvLayout, DataFormat, DayEnergy, Direction, Duplicates, Metering, Missing, Warnings, STDIN};
+use elec::events::load_events;
+use elec::explain::explain;
+use elec::fees::load_fees;
+use elec::forecast::forecast_bill;
+use elec::generate::{generate, solar_generation, Profile, Synthetic};
+use elec::greenbutton::read_green_button;
+use elec::heatmap::usage_heatmap;
+use elec::holidays::{region_holidays, Region, YEARS};
+use elec::influx::{influx_lines, write_influx, LocalTime};
+use elec::json::{parse_json, Json};
+use elec::logging;
+use elec::longcsv::{read_long_csv, read_long_csv_columns};
+use elec::metrics::SharedMetrics;
+use elec::money::Rounding;
+use elec::mqtt::publish_bill;
+use elec::nem12::read_nem12;
+use elec::parquet::read_parquet;
+use elec::plan::{compare_plans, price_channel, price_plan, price_plan_parallel, price_plan_resumed, price_plan_traced, Channel, Plan};
+use elec::plandoc::{load_plan_document, load_plan_file};
+use elec::pricing::{check_tariff_coverage, load_feedin_cap, load_public_holidays, load_region_holidays, load_supply_charges, load_tariff, load_tariff_versions, minutes_since_midnight, set_weekend, Billing, BillingPeriod, Weekend};
+use elec::progress::{with_progress, BytesRead, Progress};
+use elec::reconcile::{reconcile, reconcile_json, reconcile_text, RetailerBill};
+use elec::server::{bind, serve, serve_on, Server};
+use elec::sqlite::{read_sqlite, write_sqlite};
+use elec::toml::{load_toml, Toml};
+use elec::weather::{load_temperatures, weather_report, BASE_TEMPERATURE};
+use elec::wholesale::{MissingPrice, Wholesale};
+use notify_debouncer_full::new_debouncer;
+use notify_debouncer_full::notify::{EventKind, RecursiveMode};
+use std::collections::{BTreeSet, HashSet};
 use std::path::Path;
-use log::{debug, info};
-use env_logger;
-use std::collections::HashSet;
+use std::str::FromStr;
+use tracing::field::Empty;
+use tracing::{info_span, Span};
 
-/// Simple program to greet a person
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum InputFormat {
+    /// Detect the format of each file from its extension and content
+    Auto,
+    /// 1 line per day: date followed by the kWh for each interval
+    Csv,
+    /// 1 line per interval: timestamp, a consumption, feedin or kwh column and optionally quality,
+    /// so the same file can be given for both
+    LongCsv,
+    /// NEM12 file: consumption is read from the E (import) channels and feedin from the B (export) channels,
+    /// so the same file can be given for both
+    Nem12,
+    /// Green Button (ESPI) XML file: consumption is read from the forward and feedin from the reverse readings,
+    /// so the same file can be given for both
+    Greenbutton,
+    /// Parquet file with a row per interval: a timestamp column and a consumption, feedin or kwh column,
+    /// so the same file can be given for both
+    Parquet,
+    /// SQLite database written by import, holding both consumption and feedin, so the same file can be given for both
+    Sqlite,
+}
+
+impl InputFormat {
+    // The format of the file, detected from its content for auto
+    fn resolve(self, path: &str) -> Result<InputFormat> {
+        Ok(match self {
+            InputFormat::Auto => match detect_format(path)? {
+                DataFormat::WideCsv => InputFormat::Csv,
+                DataFormat::LongCsv => InputFormat::LongCsv,
+                DataFormat::Nem12 => InputFormat::Nem12,
+                DataFormat::GreenButton => InputFormat::Greenbutton,
+                DataFormat::Parquet => InputFormat::Parquet,
+                DataFormat::Sqlite => InputFormat::Sqlite,
+            },
+            format => format,
+        })
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum OutputFormat {
+    /// Free-text summary
+    Text,
+    /// JSON object with the cost components, totals, day count, interval length and warnings
+    Json,
+}
+
+/// Apply a Time of Use (TOU) or fixed tariff to your actual electricity data to calculate the cost
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
 
-struct Args {
-    /// Consumption Tariff CSV file
-    #[arg(short='t', long)]
-    consumption_tariff: String,
+    /// Format of the log on standard error: text, or JSON lines with spans timing each file parsed (by default at
+    /// info level, otherwise as RUST_LOG says)
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
 
-    /// Consumption Data CSV file
-    #[arg(short, long)]
-    consumption: String,
+    /// TOML file of options (long names as keys, e.g. consumption-tariff = "tariff.csv", and an optional command
+    /// and [command] tables of options for one subcommand), those given on the command line overriding it
+    #[arg(long, global = true)]
+    config: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum LogFormat {
+    /// Plain text, at the level set by RUST_LOG
+    Text,
+    /// A JSON object per line
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Calculate the cost of your energy data under a plan
+    Price(PriceOutputArgs),
+    /// Check that tariff, supply, holiday and energy data files can be loaded
+    Validate(ValidateArgs),
+    /// Add your energy data to a SQLite database, to be priced with --input-format sqlite
+    Import(ImportArgs),
+    /// Serve an HTTP API pricing uploaded energy data under an uploaded plan, returning the bill as JSON
+    Serve(ServeArgs),
+    /// Merge overlapping exports of your energy data into one CSV file, deduplicating dates exported more than once
+    /// (an error if their kWh differ)
+    Merge(MergeArgs),
+    /// Price your energy data under several plans, ranked by total cost
+    Compare(CompareArgs),
+    /// Price your energy data under every plan in a directory, ranked by annual cost with the saving on your current plan
+    BestPlan(BestPlanArgs),
+    /// The feedin rate or supply charge at which a second plan would cost the same as the first on your energy data
+    BreakEven(BreakEvenArgs),
+    /// Price your energy data like price and also write an HTML report with charts
+    Report(ReportArgs),
+    /// Price your energy data like price and compare it line by line with your retailer's bill
+    Reconcile(ReconcileArgs),
+    /// List the peak demand (kW) of each day or month and when it occurred
+    Peaks(PeaksArgs),
+    /// Percentile statistics of interval demand (kW) and optionally the load duration curve
+    Stats(StatsArgs),
+    /// Show how a plan prices a moment: the tariff rows and usage blocks matching, whether it's a public holiday and the
+    /// price of 1 kWh
+    Explain(ExplainArgs),
+    /// Heating and cooling degree days from daily temperatures, usage per degree day and each month's usage in average
+    /// weather
+    Weather(WeatherArgs),
+    /// Forecast the bill for the current billing period from the data so far and same-weekday averages
+    Forecast(ForecastArgs),
+    /// Price your energy data as it would have been with changes to your system
+    #[command(subcommand)]
+    Simulate(Simulation),
+    /// Write synthetic consumption (and with --solar feedin) data, to try plans before real data is available
+    Generate(GenerateArgs),
+}
+
+#[derive(Subcommand, Debug)]
+enum Simulation {
+    /// Add a battery charging from excess solar and discharging at peak times, compared to the actual bill
+    Battery(BatteryArgs),
+    /// Move consumption from one tariff window to another each day (e.g. running the dishwasher overnight),
+    /// compared to the actual bill
+    Shift(ShiftArgs),
+    /// Add rooftop solar generation and report the saving and payback years
+    Solar(SolarArgs),
+    /// Add electric vehicle charging in the cheapest intervals, compared to charging from plug-in time
+    Ev(EvArgs),
+}
+
+/// A plan: tariffs and supply charge
+#[derive(Args, Clone, Debug)]
+struct PlanArgs {
+    /// Consumption Tariff CSV file, repeatable as YYYYMMDD=file for the version in force from that date
+    /// (e.g. rates that changed on 1 July)
+    #[arg(short='t', long, required_unless_present_any = ["cdr_plan", "plan_document", "wholesale"])]
+    consumption_tariff: Vec<String>,
 
-    /// Feedin Tariff CSV file
+    /// Feedin Tariff CSV file, repeatable as YYYYMMDD=file for the version in force from that date
     #[arg(short='u', long)]
-    feedin_tariff: Option<String>,
+    feedin_tariff: Vec<String>,
+
+    /// Daily supply charge
+    #[arg(short, long, required_unless_present_any = ["cdr_plan", "plan_document"])]
+    daily: Option<String>,
+
+    /// CDR energy plan JSON file (as published for Energy Made Easy) instead of the consumption tariff and daily supply
+    /// charge, other plan options override or add to it
+    #[arg(long, conflicts_with_all = ["consumption_tariff", "daily"])]
+    cdr_plan: Option<String>,
+
+    /// Plan document, a JSON or TOML file of the whole plan (see schema/plan.schema.json), instead of the consumption
+    /// tariff and daily supply charge, other plan options override or add to it
+    #[arg(long = "plan", conflicts_with_all = ["consumption_tariff", "daily", "cdr_plan"])]
+    plan_document: Option<String>,
+
+    /// Demand Tariff CSV file, monthly maximum demand charges on consumption
+    #[arg(long)]
+    demand_tariff: Option<String>,
+
+    /// Power factor for kVA demand charges: a constant (e.g. 0.9) or a CSV file of each interval's power factor
+    /// laid out like the consumption data (by default 1, kVA = kW)
+    #[arg(long)]
+    power_factor: Option<String>,
+
+    #[command(flatten)]
+    billing: BillingArgs,
+
+    #[command(flatten)]
+    concession: ConcessionArgs,
+
+    /// Feedin cap CSV file, the kWh a day beyond which feedin earns a different rate (by default nothing)
+    #[arg(long)]
+    feedin_cap: Option<String>,
+
+    /// Discounts CSV file, percentage discounts on usage, supply or the whole bill
+    #[arg(long)]
+    discounts: Option<String>,
+
+    /// Leave out conditional discounts (e.g. pay on time) you don't expect to meet
+    #[arg(long)]
+    no_conditional_discounts: bool,
 
-    /// Feedin Data CSV file
+    /// Wholesale or other dynamic prices CSV (timestamp,price_per_kwh[,feedin]) or Amber API JSON file, pricing each
+    /// interval at its price (plus margins) rather than by the tariff
+    #[arg(long, visible_alias = "prices")]
+    wholesale: Option<String>,
+
+    /// $/kWh added to wholesale usage prices, e.g. network charges and the retailer's margin
+    #[arg(long, default_value_t = 0.0, requires = "wholesale")]
+    wholesale_margin: f64,
+
+    /// $/kWh added to wholesale feedin prices (negative to reduce the credit)
+    #[arg(long, default_value_t = 0.0, requires = "wholesale", allow_negative_numbers = true)]
+    wholesale_feedin_margin: f64,
+
+    /// What to do with intervals the wholesale prices don't cover: tariff (price by the tariff), error or carry-forward
+    /// (the last price applies until the next)
+    #[arg(long, value_parser = MissingPrice::from_str, default_value = "tariff", requires = "wholesale")]
+    missing_price: MissingPrice,
+
+    /// Critical peak and demand response events CSV file: dates and times with a special rate or a credit for
+    /// consumption below a baseline, layered on top of the tariff
+    #[arg(long)]
+    events: Option<String>,
+
+    /// Recurring fees CSV file: fixed fees beyond the daily supply charge, charged in each billing period (or month
+    /// without billing periods), with columns Name, $ and Recurrence (monthly, quarterly or annual)
+    #[arg(long)]
+    fees: Option<String>,
+
+    /// Bill cap CSV file: minimum charges and caps on the total of each billing period (or month without billing
+    /// periods), with columns Name, Minimum $ and Maximum $
+    #[arg(long)]
+    bill_cap: Option<String>,
+
+    /// Grid carbon intensity (kgCO2e/kWh): a constant (e.g. 0.68) or a CSV file of a month (YYYY-MM) or interval
+    /// start timestamp and its intensity, reporting the emissions of consumption and those avoided by feedin
+    #[arg(long)]
+    carbon_intensity: Option<String>,
+
+    /// Shadow carbon price ($/tonne CO2e) the net emissions are costed at, shown alongside the bill, not in its total
+    #[arg(long, requires = "carbon_intensity")]
+    carbon_price: Option<f64>,
+}
+
+/// Your energy data and the public holidays charged as Sundays
+#[derive(Args, Clone, Debug)]
+struct DataArgs {
+    /// Consumption Data CSV files (repeatable or comma separated, e.g. one per month), priced as a single dataset,
+    /// or - for standard input
+    #[arg(short, long, required = true, value_delimiter = ',')]
+    consumption: Vec<String>,
+
+    /// Feedin Data CSV files (repeatable or comma separated), or - for standard input
+    #[arg(short, long, value_delimiter = ',')]
+    feedin: Vec<String>,
+
+    /// Public Holidays: a column of dates, or dates with a Jurisdiction column filtered by --holiday-region
     #[arg(short, long)]
+    public_holidays: Option<String>,
+
+    /// Australian state or territory (NSW, VIC, QLD, SA, WA, TAS, NT, ACT) whose public holidays are charged as Sundays,
+    /// computed unless --public-holidays is given
+    #[arg(long, value_parser = Region::from_str)]
+    holiday_region: Option<Region>,
+
+    /// Days of the weekend (e.g. fri,sat) for tariff rows with Day Start weekday or weekend, which also charge
+    /// public holidays as weekend days
+    #[arg(long, value_parser = Weekend::from_str, default_value = "sat,sun")]
+    weekend: Weekend,
+
+    /// Format of the consumption and feedin data files
+    #[arg(long, value_enum, default_value_t = InputFormat::Auto)]
+    input_format: InputFormat,
+
+    /// How the meter measures solar: net (consumption imported and the surplus fed in) or gross (consumption is the
+    /// whole household load and feedin the whole generation, netted per interval into what's imported and exported)
+    #[arg(long, value_parser = Metering::from_str, default_value = "net")]
+    metering: Metering,
+
+    /// kWh columns of long CSV consumption files to sum (comma separated), e.g. phase_a,phase_b,phase_c for a three
+    /// phase meter, by default the consumption or kwh column
+    #[arg(long, value_delimiter = ',')]
+    consumption_columns: Vec<String>,
+
+    /// kWh columns of long CSV feedin files to sum (comma separated), by default the feedin or kwh column
+    #[arg(long, value_delimiter = ',')]
+    feedin_columns: Vec<String>,
+
+    /// What to do with blank intervals in CSV data files: error, zero, interpolate (within the day) or skip-day
+    #[arg(long, value_parser = Missing::from_str, default_value = "error")]
+    missing: Missing,
+
+    /// What to do with a date repeated in a data file: error, dedupe (price the first copy) or warn (price every copy);
+    /// dates out of order are warned about
+    #[arg(long, value_parser = Duplicates::from_str, default_value = "error")]
+    duplicates: Duplicates,
+
+    /// Stop at the first line of a CSV data file that can't be read (as by default), and also at a data file's dates out
+    /// of order or repeated (with --duplicates warn or dedupe) rather than warn of them
+    #[arg(long)]
+    strict: bool,
+
+    /// Skip the lines of CSV data files that can't be read, with a warning of how many, for an approximate bill from
+    /// a slightly dirty export
+    #[arg(long, conflicts_with = "strict")]
+    lenient: bool,
+
+    #[command(flatten)]
+    csv: CsvArgs,
+
+    /// Don't show progress reading consumption data (only shown when standard error is a terminal)
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Only price data from this date (YYYYMMDD or YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    from: Option<NaiveDate>,
+
+    /// Only price data up to and including this date (YYYYMMDD or YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    to: Option<NaiveDate>,
+
+    /// Sum the intervals of consumption and feedin data into intervals of this many minutes before pricing
+    /// (e.g. 30 for 5 minute data and tariffs or demand defined on 30 minute boundaries)
+    #[arg(long)]
+    resample: Option<usize>,
+}
+
+#[derive(Args, Clone, Debug)]
+struct CsvArgs {
+    /// Date format of CSV data files (e.g. %d/%m/%Y), by default detected from the first date
+    #[arg(long)]
+    date_format: Option<String>,
+
+    /// Column of the date in CSV data files (from 1)
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    date_column: u16,
+
+    /// Columns of the interval kWh in CSV data files (from 1): N-M, or N- to the end of the line,
+    /// by default those after the date column
+    #[arg(long, value_parser = parse_columns)]
+    value_columns: Option<(usize, Option<usize>)>,
+}
+
+impl CsvArgs {
+    fn layout(&self) -> CsvLayout {
+        let date_column = self.date_column as usize - 1;
+        let (first_column, last_column) = self.value_columns.unwrap_or((date_column + 1, None));
+        CsvLayout { date_format: self.date_format.clone(), date_column, first_column, last_column }
+    }
+}
+
+type Days = Box<dyn Iterator<Item = Result<DayEnergy>>>;
+type Data = (Vec<DayEnergy>, Option<Vec<DayEnergy>>, Vec<String>); // consumption, feedin if given and warnings
+
+// Count the days and intervals read in a span as they stream through, reading each day in the span, then recording
+// them and the warnings added since `before` once the days run out or fail, and closing the span
+fn in_span(mut days: impl Iterator<Item = Result<DayEnergy>> + 'static, span: Span, warnings: Warnings, before: usize) -> Days {
+    let (mut count, mut intervals, mut span) = (0usize, 0usize, Some(span));
+    Box::new(std::iter::from_fn(move || {
+        let day = match &span {
+            Some(span) => span.in_scope(|| days.next()),
+            None => days.next(),
+        };
+        match &day {
+            Some(Ok(d)) => {
+                count += 1;
+                intervals += d.energy.len();
+            },
+            _ => if let Some(span) = span.take() {
+                span.record("days", count);
+                span.record("intervals", intervals);
+                span.record("warnings", warnings.len().saturating_sub(before));
+            },
+        }
+        day
+    }))
+}
+
+// The days, then with --strict an error of the first warning added from `before` on (e.g. of dates out of order), as
+// check_dates only adds them once the days run out
+fn strict(mut days: impl Iterator<Item = Result<DayEnergy>>, warnings: Warnings, before: usize) -> impl Iterator<Item = Result<DayEnergy>> {
+    let mut done = false;
+    std::iter::from_fn(move || match days.next() {
+        None if !done => {
+            done = true;
+            warnings.get(before).map(|w| Err(anyhow!("strict: {}", w)))
+        },
+        day => day,
+    })
+}
+
+impl DataArgs {
+    // Read consumption or feedin data, keeping only the dates selected, with warnings for days skipped and
+    // repeated or out of order dates
+    fn read(&self, path: &str, direction: Direction, warnings: &Warnings, read: &BytesRead) -> Result<Days> {
+        let columns = match direction {
+            Direction::Import => &self.consumption_columns,
+            Direction::Export => &self.feedin_columns,
+        };
+        self.read_columns(path, direction, columns, warnings, read)
+    }
+
+    // Read like read, summing these kWh columns of a long CSV file if any are given
+    fn read_columns(&self, path: &str, direction: Direction, columns: &[String], warnings: &Warnings, read: &BytesRead) -> Result<Days> {
+        let format = self.input_format.resolve(path)?;
+        let span = info_span!("parse", file = path, format = ?format, direction = ?direction, days = Empty, intervals = Empty, warnings = Empty);
+        let before = warnings.len();
+        let (days, w) = span.in_scope(|| -> Result<(Days, Vec<String>)> {
+            Ok(match (format, self.missing) {
+                (InputFormat::LongCsv, _) if !columns.is_empty() => (Box::new(read_long_csv_columns(path, columns)?.into_iter().map(Ok)), Vec::new()),
+                (format, _) if !columns.is_empty() => {
+                    return Err(anyhow!("read_columns: {}: kWh columns can only be chosen in long CSV files, not {:?}", path, format));
+                },
+                (InputFormat::Csv, missing) if self.lenient => {
+                    let (days, warnings) = read_csv_energy_lenient(path, missing, &self.csv.layout())?;
+                    (Box::new(days.into_iter().map(Ok)), warnings)
+                },
+                (InputFormat::Csv, missing) if missing != Missing::Error => {
+                    let (days, warnings) = read_csv_energy_missing(path, missing, &self.csv.layout())?;
+                    (Box::new(days.into_iter().map(Ok)), warnings)
+                },
+                _ => (read_energy(path, format, direction, &self.csv.layout(), read)?, Vec::new()),
+            })
+        })?;
+        warnings.extend(w);
+        let days = check_dates(path, filter_dates(days, self.from, self.to), self.duplicates, warnings);
+        let days: Days = match self.strict {
+            true => Box::new(strict(days, warnings.clone(), warnings.len())),
+            false => Box::new(days),
+        };
+        let days = in_span(days, span, warnings.clone(), before);
+        match self.resample {
+            Some(interval) => Ok(Box::new(days.map(move |d| d.and_then(|d| resample(&d, interval))))),
+            None => Ok(days),
+        }
+    }
+
+    // Read and merge several consumption or feedin files, with warnings for skipped days and overlapping dates,
+    // showing progress through consumption unless quiet: through the bytes of a single file as it streams, or the
+    // days of several once they're merged
+    fn read_all(&self, paths: &[String], direction: Direction, warnings: &Warnings) -> Result<Days> {
+        if self.consumption.iter().chain(&self.feedin).filter(|p| *p == STDIN).count() > 1 {
+            return Err(anyhow!("read_all: standard input ('{}') can only be read once, give it for consumption or feedin", STDIN));
+        }
+        let progress = |total| Progress::new("consumption", total, direction == Direction::Import && !self.quiet);
+        match paths {
+            [path] => {
+                let read = BytesRead::default();
+                let days = self.read(path, direction, warnings, &read)?;
+                let progress = match std::fs::metadata(path) {
+                    Ok(m) if path != STDIN => progress(None).reading(&read, m.len()),
+                    _ => progress(None),
+                };
+                Ok(Box::new(with_progress(days, progress)))
+            },
+            _ => {
+                let sources = paths.iter()
+                    .map(|p| Ok((p.clone(), self.read(p, direction, warnings, &BytesRead::default())?)))
+                    .collect::<Result<Vec<_>>>()?;
+                let (days, w) = merge_days(sources)?;
+                warnings.extend(w);
+                let total = days.len();
+                Ok(Box::new(with_progress(days.into_iter().map(Ok), progress(Some(total)))))
+            },
+        }
+    }
+
+    // Read the consumption data and the feedin data if given, with warnings, netting gross metered data
+    fn read_pair(&self) -> Result<(Days, Option<Days>, Warnings)> {
+        let warnings = Warnings::default();
+        let consumption = self.read_all(&self.consumption, Direction::Import, &warnings)?;
+        let feedin = match self.feedin.is_empty() {
+            true => None,
+            false => Some(self.read_all(&self.feedin, Direction::Export, &warnings)?),
+        };
+        match (self.metering, feedin) {
+            (Metering::Net, feedin) => Ok((consumption, feedin, warnings)),
+            (Metering::Gross, None) => Err(anyhow!("read_pair: gross metering needs the generation as --feedin")),
+            (Metering::Gross, Some(generation)) => {
+                let consumption = consumption.collect::<Result<Vec<DayEnergy>>>()?;
+                let generation = generation.collect::<Result<Vec<DayEnergy>>>()?;
+                let (import, export, w) = net_metering(&consumption, &generation)?;
+                warnings.extend(w);
+                Ok((Box::new(import.into_iter().map(Ok)), Some(Box::new(export.into_iter().map(Ok))), warnings))
+            },
+        }
+    }
+
+    // Read all the consumption data and the feedin data if given, with warnings
+    fn read_both(&self) -> Result<Data> {
+        let (consumption, feedin, warnings) = self.read_pair()?;
+        Ok((consumption.collect::<Result<Vec<DayEnergy>>>()?, feedin.map(|f| f.collect::<Result<Vec<DayEnergy>>>()).transpose()?, warnings.take()))
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Component {
+    Consumption,
+    Supply,
+    Demand,
+}
+
+#[derive(Args, Clone, Debug)]
+struct PriceArgs {
+    #[command(flatten)]
+    plan: PlanArgs,
+
+    #[command(flatten)]
+    data: DataArgs,
+
+    /// Format of the results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Also show the totals for each calendar month
+    #[arg(long)]
+    monthly: bool,
+
+    /// Also show sparklines of the daily cost and kWh, averaging runs of days to fit this many characters
+    #[arg(long, num_args = 0..=1, default_missing_value = "72")]
+    chart: Option<usize>,
+
+    /// Write kWh and costs for each date to this CSV file
+    #[arg(long)]
+    daily_breakdown: Option<String>,
+
+    /// Write kWh, tariff, rate, cost and running total for each interval to this CSV file
+    #[arg(long)]
+    trace: Option<String>,
+
+    /// Write kWh and cost points for each interval and day in InfluxDB line protocol to this file, or post them to this
+    /// InfluxDB write URL (http://...)
+    #[arg(long)]
+    output_influx: Option<String>,
+
+    /// Name prefix of the InfluxDB measurements (<prefix>_interval and <prefix>_daily)
+    #[arg(long, default_value = "elec", requires = "output_influx")]
+    influx_measurement: String,
+
+    /// Offset from UTC of the data's local standard time, for the InfluxDB timestamps, e.g. +10:00
+    #[arg(long, default_value = "+00:00", requires = "output_influx")]
+    influx_utc_offset: FixedOffset,
+
+    /// The data's local times are an hour ahead in daylight saving time, from the first Sunday in October to the first
+    /// Sunday in April (as in NSW, Victoria, Tasmania, the ACT and South Australia), for the InfluxDB timestamps
+    #[arg(long, requires = "output_influx")]
+    influx_daylight_saving: bool,
+
+    /// InfluxDB API token for posting to a write URL
+    #[arg(long, requires = "output_influx")]
+    influx_token: Option<String>,
+
+    /// GST rate (e.g. 0.1) to report the subtotal ex GST, GST and total inc GST
+    #[arg(long)]
+    gst: Option<f64>,
+
+    /// Components whose tariff or supply files are ex GST (by default rates include GST)
+    #[arg(long, value_enum, requires = "gst")]
+    ex_gst: Vec<Component>,
+
+    /// Round every amount to cents: half-up (halves away from zero), half-even (halves to the even cent) or truncate
+    #[arg(long, value_parser = Rounding::from_str)]
+    rounding: Option<Rounding>,
+
+    /// Price the days of data on this many threads (not with --trace, --output-influx or --state, which price in order
+    /// on one thread)
+    #[arg(long, default_value_t = 1, conflicts_with_all = ["trace", "output_influx", "state"])]
+    threads: usize,
+
+    /// An extra metered channel priced independently, e.g. controlled load:
+    /// name=data.csv,tariff=tariff.csv[,daily=supply.csv][,columns=a+b] (repeatable), columns summing kWh columns of
+    /// a long CSV data file (e.g. a register of a multi-register export)
+    #[arg(long)]
+    channel: Vec<String>,
+
+    /// JSON file of what's been priced so far, created if it doesn't exist: only days after those already priced are
+    /// priced and added to the running bill, which is saved back to the file (on one thread)
+    #[arg(long, conflicts_with_all = ["trace", "output_influx", "channel"])]
+    state: Option<String>,
+
+    #[command(flatten)]
+    projection: ProjectionArgs,
+}
+
+/// Billing periods that usage blocks per period and demand charges reset on
+#[derive(Args, Clone, Debug)]
+struct BillingArgs {
+    /// Bill monthly or quarterly, resetting usage blocks per period and demand charges on the billing boundaries,
+    /// and showing the totals of each billing period
+    #[arg(long, value_parser = BillingPeriod::from_str)]
+    billing_period: Option<BillingPeriod>,
+
+    /// The first day of a billing period (YYYYMMDD or YYYY-MM-DD), others starting every month or quarter before
+    /// and after it, by default calendar months or quarters
+    #[arg(long, value_parser = parse_date, requires = "billing_period")]
+    anchor: Option<NaiveDate>,
+
+    /// Charge supply for every calendar day from the first to the last date of data, including days missing from
+    /// the data, so the first and last billing periods are charged pro rata and the others in full
+    #[arg(long, requires = "billing_period")]
+    pro_rata_supply: bool,
+}
+
+impl BillingArgs {
+    fn billing(&self) -> Option<Billing> {
+        self.billing_period.map(|period| Billing {
+            period,
+            anchor: self.anchor.unwrap_or(NaiveDate::from_ymd_opt(2000, 1, 1).unwrap_or_default()),
+            pro_rata: self.pro_rata_supply,
+        })
+    }
+}
+
+/// The household's government concessions and rebates
+#[derive(Args, Clone, Debug)]
+struct ConcessionArgs {
+    /// Concessions CSV file: percentage concessions on usage, supply or the whole bill in each billing period, with
+    /// columns Name, Percent, Applies To (usage, supply or bill) and optionally Cap $, Month Start and Month End
+    #[arg(long)]
+    concessions: Option<String>,
+
+    /// Rebates CSV file: one-off rebates credited in the billing period of their date, with columns Name, Date and $
+    #[arg(long)]
+    rebates: Option<String>,
+}
+
+impl ConcessionArgs {
+    fn apply(&self, plan: Plan) -> Result<Plan> {
+        let concessions = self.concessions.as_deref().map(load_concessions).transpose()?.unwrap_or_default();
+        let rebates = self.rebates.as_deref().map(load_rebates).transpose()?.unwrap_or_default();
+        Ok(plan.with_concessions(concessions, rebates))
+    }
+}
+
+/// A projection of costs over future years of price rises
+#[derive(Args, Clone, Debug)]
+struct ProjectionArgs {
+    /// Also project the total over this many years (the first as priced, taking the data to be a year)
+    /// with prices rising each year by --escalation
+    #[arg(long)]
+    years: Option<usize>,
+
+    /// Yearly rise (%) in consumption, feedin and demand tariffs for --years, e.g. 3.5 (negative for falls)
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true, requires = "years")]
+    escalation: f64,
+
+    /// Yearly rise (%) in supply charges for --years, by default --escalation
+    #[arg(long, allow_negative_numbers = true, requires = "years")]
+    supply_escalation: Option<f64>,
+}
+
+impl ProjectionArgs {
+    // The bill's projected total each year, if --years is given
+    fn project(&self, bill: &Bill) -> Option<Vec<f64>> {
+        let escalation = Escalation { tariff: self.escalation / 100.0, supply: self.supply_escalation.unwrap_or(self.escalation) / 100.0 };
+        self.years.map(|years| bill.projection(years, escalation))
+    }
+}
+
+#[derive(Args, Debug)]
+struct PriceOutputArgs {
+    #[command(flatten)]
+    price: PriceArgs,
+
+    /// Write the results to this .txt, .json or .csv file (the format chosen by the extension) rather than standard output,
+    /// replacing it only once they're complete
+    #[arg(short, long, conflicts_with = "format")]
+    output: Option<String>,
+
+    /// Keep watching the tariff, plan and data files and price again whenever one changes (until interrupted),
+    /// e.g. while editing a tariff file, by the operating system's file change notifications
+    #[arg(long)]
+    watch: bool,
+
+    /// Exit with status 3 and a message on standard error if the bill (inc GST if given) is over this many $,
+    /// e.g. for a cron job to send a notification
+    #[arg(long, conflicts_with = "watch")]
+    alert_over: Option<f64>,
+
+    /// With --watch, serve Prometheus metrics of the last bill priced at http://<ADDRESS>/metrics, e.g. 0.0.0.0:9100
+    #[arg(long, requires = "watch")]
+    metrics: Option<String>,
+
+    /// Also publish the bill to date and daily costs to this MQTT broker (host or host:port) as Home Assistant sensors
+    #[arg(long)]
+    mqtt: Option<String>,
+
+    /// MQTT username
+    #[arg(long, requires = "mqtt")]
+    mqtt_username: Option<String>,
+
+    /// MQTT password
+    #[arg(long, requires = "mqtt_username")]
+    mqtt_password: Option<String>,
+
+    /// MQTT topic prefix for the sensor states (and client id)
+    #[arg(long, requires = "mqtt", default_value = "elec")]
+    mqtt_prefix: String,
+}
+
+#[derive(Args, Debug)]
+struct ReportArgs {
+    #[command(flatten)]
+    price: PriceArgs,
+
+    /// Write a self-contained HTML page of charts and tables to this file
+    #[arg(long, required_unless_present = "heatmap")]
+    html: Option<String>,
+
+    /// Write the average consumption kWh in each hour of the day by day of the week to this .csv or .svg file
+    #[arg(long)]
+    heatmap: Option<String>,
+
+    /// Make the heatmap by month instead of by day of the week
+    #[arg(long, requires = "heatmap")]
+    heatmap_by_month: bool,
+}
+
+#[derive(Args, Debug)]
+struct ReconcileArgs {
+    #[command(flatten)]
+    price: PriceArgs,
+
+    /// TOML file of the retailer's line items: usage kWh and $ per rate, supply days and $, feedin and total
+    #[arg(long)]
+    bill: String,
+
+    /// Largest difference in kWh or $ counted as agreeing
+    #[arg(long, default_value_t = 0.01)]
+    tolerance: f64,
+}
+
+#[derive(Args, Debug)]
+struct PeaksArgs {
+    #[command(flatten)]
+    data: DataArgs,
+
+    /// The peak of each calendar month rather than each day
+    #[arg(long)]
+    monthly: bool,
+
+    /// Format of the results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+struct ExplainArgs {
+    #[command(flatten)]
+    plan: PlanArgs,
+
+    /// The moment to price (YYYY-MM-DD HH:MM)
+    #[arg(long, value_parser = parse_timestamp)]
+    at: NaiveDateTime,
+
+    /// Interval length (minutes)
+    #[arg(long, default_value_t = 30)]
+    interval: usize,
+
+    /// kWh used earlier in the day, for usage blocks per day
+    #[arg(long, default_value_t = 0.0)]
+    usage_day: f64,
+
+    /// kWh used earlier in the billing period, for usage blocks per billing period
+    #[arg(long, default_value_t = 0.0)]
+    usage_period: f64,
+
+    /// Public Holidays: a column of dates, or dates with a Jurisdiction column filtered by --holiday-region
+    #[arg(short, long)]
+    public_holidays: Option<String>,
+
+    /// Australian state or territory (NSW, VIC, QLD, SA, WA, TAS, NT, ACT) whose public holidays are charged as Sundays,
+    /// computed unless --public-holidays is given
+    #[arg(long, value_parser = Region::from_str)]
+    holiday_region: Option<Region>,
+
+    /// Days of the weekend (e.g. fri,sat) for tariff rows with Day Start weekday or weekend
+    #[arg(long, value_parser = Weekend::from_str, default_value = "sat,sun")]
+    weekend: Weekend,
+
+    /// Format of the results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+struct WeatherArgs {
+    #[command(flatten)]
+    data: DataArgs,
+
+    /// Daily temperatures CSV file: Date and Mean °C, or Date, Min °C and Max °C
+    #[arg(long)]
+    temperatures: String,
+
+    /// Base temperature (°C) of the degree days
+    #[arg(long, default_value_t = BASE_TEMPERATURE)]
+    base_temperature: f64,
+
+    /// A period per year rather than per month
+    #[arg(long)]
+    yearly: bool,
+
+    /// Format of the results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+struct ForecastArgs {
+    #[command(flatten)]
+    plan: PlanArgs,
+
+    #[command(flatten)]
+    data: DataArgs,
+
+    /// Exit with status 3 and a message on standard error if the forecast bill is over this many $
+    #[arg(long)]
+    alert_over: Option<f64>,
+
+    /// Format of the results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+struct StatsArgs {
+    #[command(flatten)]
+    data: DataArgs,
+
+    /// Write the load duration curve to this CSV file: each interval's kW from highest to lowest with the percent of time
+    #[arg(long)]
+    duration_curve: Option<String>,
+
+    /// Format of the results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+struct BestPlanArgs {
+    /// Directory of plans: each subdirectory (as for compare --plan) and CDR plan JSON file is a plan
+    #[arg(long)]
+    plans: String,
+
+    /// Your current plan, to show the saving on it: the name of one of --plans, or a plan as for compare --plan
+    #[arg(long)]
+    current: Option<String>,
+
+    #[command(flatten)]
+    data: DataArgs,
+
+    /// Format of the results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Price the days of data on this many threads
+    #[arg(long, default_value_t = 1)]
+    threads: usize,
+}
+
+#[derive(Args, Debug)]
+struct BreakEvenArgs {
+    /// Plans A and B, each as for compare --plan: how would plan B's feedin rate or supply charge need to change to cost the same as plan A
+    #[arg(long, required = true, num_args = 1, number_of_values = 1)]
+    plan: Vec<String>,
+
+    #[command(flatten)]
+    data: DataArgs,
+
+    /// Format of the results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+struct GenerateArgs {
+    /// Days of data
+    #[arg(long, default_value_t = 365)]
+    days: usize,
+
+    /// Interval length (minutes), dividing the day
+    #[arg(long, default_value_t = 30)]
+    interval: usize,
+
+    /// Household profile: family-evening-peak, working-couple, home-all-day or flat
+    #[arg(long, value_parser = Profile::from_str, default_value = "family-evening-peak")]
+    profile: Profile,
+
+    /// First date (YYYYMMDD or YYYY-MM-DD), by default so the data ends yesterday
+    #[arg(long, value_parser = parse_date)]
+    start: Option<NaiveDate>,
+
+    /// Average consumption (kWh a day) before solar, by default typical for the profile
+    #[arg(long)]
+    daily_kwh: Option<f64>,
+
+    /// Rooftop solar system size (kW), offsetting consumption with the excess fed in
+    #[arg(long, default_value_t = 0.0)]
+    solar: f64,
+
+    /// Random seed, the same seed giving the same data
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+
+    /// Consumption Data CSV file to write, or - for standard output
+    #[arg(short, long, default_value = "-")]
+    consumption: String,
+
+    /// Feedin Data CSV file to write
+    #[arg(short, long, requires = "solar")]
     feedin: Option<String>,
+}
 
-    /// Daily supply charge
+#[derive(Args, Debug)]
+struct CompareArgs {
+    /// A plan: a directory containing consumption.csv, supply.csv and optionally feedIn.csv, feedInCap.csv, demand.csv, discounts.csv, events.csv, fees.csv and billCap.csv
+    /// (or a plan.toml or plan.json plan document), a JSON or TOML plan document, a CDR energy plan JSON file, or comma separated
+    /// consumption tariff, feedin tariff (may be empty) and supply CSV files
+    #[arg(long, required = true)]
+    plan: Vec<String>,
+
+    #[command(flatten)]
+    data: DataArgs,
+
+    /// Power factor for the plans' kVA demand charges: a constant (e.g. 0.9) or a CSV file of each interval's power factor
+    #[arg(long)]
+    power_factor: Option<String>,
+
+    #[command(flatten)]
+    billing: BillingArgs,
+
+    #[command(flatten)]
+    concession: ConcessionArgs,
+
+    /// Format of the results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Price the days of data on this many threads
+    #[arg(long, default_value_t = 1)]
+    threads: usize,
+
+    #[command(flatten)]
+    projection: ProjectionArgs,
+}
+
+#[derive(Args, Debug)]
+struct BatteryArgs {
+    #[command(flatten)]
+    plan: PlanArgs,
+
+    #[command(flatten)]
+    data: DataArgs,
+
+    /// Usable capacity (kWh)
+    #[arg(long)]
+    kwh: f64,
+
+    /// Maximum charge and discharge power (kW)
+    #[arg(long)]
+    kw: f64,
+
+    /// Round trip efficiency
+    #[arg(long, default_value_t = 0.9)]
+    efficiency: f64,
+
+    /// Discharge when the consumption tariff is at least this ($/kWh), by default its highest (peak) rate
+    #[arg(long)]
+    discharge_above: Option<f64>,
+
+    /// Format of the results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+struct ShiftArgs {
+    #[command(flatten)]
+    plan: PlanArgs,
+
+    #[command(flatten)]
+    data: DataArgs,
+
+    /// kWh to move each day
+    #[arg(long)]
+    kwh: f64,
+
+    /// Name of the consumption tariff window to move consumption from, e.g. Peak
+    #[arg(long)]
+    from_tariff: String,
+
+    /// Name of the consumption tariff window to move consumption to, e.g. Off-peak
+    #[arg(long)]
+    to_tariff: String,
+
+    /// Format of the results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+struct SolarArgs {
+    #[command(flatten)]
+    plan: PlanArgs,
+
+    #[command(flatten)]
+    data: DataArgs,
+
+    /// Generation data file of the proposed system (kWh per interval, in any --input-format), by date or for a typical
+    /// year applying to any year
+    #[arg(long, required_unless_present = "kw", conflicts_with = "kw")]
+    generation: Option<String>,
+
+    /// Size of the proposed system (kW) to model its generation (as for generate --solar) rather than from --generation
+    #[arg(long)]
+    kw: Option<f64>,
+
+    /// Random seed for the modelled generation's cloudy days
+    #[arg(long, default_value_t = 1, requires = "kw")]
+    seed: u64,
+
+    /// Installed cost of the system ($), for the simple payback years
+    #[arg(long)]
+    cost: Option<f64>,
+
+    /// Format of the results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+struct EvArgs {
+    #[command(flatten)]
+    plan: PlanArgs,
+
+    #[command(flatten)]
+    data: DataArgs,
+
+    /// kWh of charging needed each day (or each week with --weekly)
+    #[arg(long)]
+    kwh: f64,
+
+    /// --kwh is needed each week (Monday to Sunday), so charging can wait for the cheapest days
+    #[arg(long)]
+    weekly: bool,
+
+    /// Maximum charge rate (kW)
+    #[arg(long, default_value_t = 7.0)]
+    kw: f64,
+
+    /// Time the vehicle is plugged in (HH:MM), charging straight away unless optimised
+    #[arg(long, value_parser = parse_time, default_value = "18:00")]
+    plug_in: i32,
+
+    /// Format of the results
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+}
+
+// A time of day HH:MM as minutes since midnight
+fn parse_time(s: &str) -> Result<i32> {
+    match minutes_since_midnight(&format!("{}:00", s.trim())) {
+        Ok(min) if (0..24 * 60).contains(&min) => Ok(min),
+        _ => Err(anyhow!("parse_time: '{}' is not a time of day HH:MM", s)),
+    }
+}
+
+#[derive(Args, Debug)]
+struct ValidateArgs {
+    /// Tariff CSV files (consumption or feedin)
+    #[arg(short='t', long)]
+    tariff: Vec<String>,
+
+    /// Plan documents (JSON or TOML), checked against schema/plan.schema.json
+    #[arg(long)]
+    plan: Vec<String>,
+
+    /// Daily supply charge CSV file
     #[arg(short, long)]
-    daily: String,
+    daily: Option<String>,
 
     /// Public Holidays
     #[arg(short, long)]
     public_holidays: Option<String>,
+
+    /// Consumption or feedin Data files
+    #[arg(short, long)]
+    energy: Vec<String>,
+
+    /// Format of the energy data files
+    #[arg(long, value_enum, default_value_t = InputFormat::Auto)]
+    input_format: InputFormat,
+
+    #[command(flatten)]
+    csv: CsvArgs,
 }
 
-fn minutes_since_midnight(hhmmss: &str) -> Result<i32> {
-    sscanf!(hhmmss, "{i32}:{i32}:{i32}")
-    .map(|(hh, mm, _ss)| mm + 60 * hh)
-    .or_else(|e| Err(anyhow!("minutes_since_midnight: error {}", e))) // convert sscanf::Error to anyhow::Error
-}
-
-#[derive(Debug)]
-struct Tariff {
-    day_start: i16,  // Day Start (0 for Monday), todo: later try u16 to see if its painful
-    day_end: i16,    // Day End (Exclusive)
-    time_start: i32, // Time Start (min since midnight)
-    time_end: i32,   // Time End (Exclusive)
-    tariff: f64,     // $/kWh
-    _name: String,    // Tariff Name
-}
-
-fn load_tariff(csv_tariff: &String) -> Result<Vec<Tariff>> {
-    info!("load_tariff: loading CSV file {}", csv_tariff);
-    let mut reader = ReaderBuilder::new()
-        .has_headers(true)
-        .from_path(Path::new(csv_tariff))?;
-    
-    reader.records()
-    .map(|record| -> Result<Tariff> {
-        let r = record?;
-        debug!("load_tariff: record: {:?}", r);
-        Ok(Tariff {
-            day_start:  r[0].parse::<i16>()?, 
-            day_end:    r[1].parse::<i16>()?,  
-            time_start: minutes_since_midnight(&r[2])?,  
-            time_end:   minutes_since_midnight(&r[3])?,  
-            tariff:     r[4].parse::<f64>()?,  
-            _name:      r[5].to_string(),
-        })
-    })
-    .collect() // 1st error, or the vector
+#[derive(Args, Debug)]
+struct ImportArgs {
+    /// SQLite database to add the data to, created if it doesn't exist
+    #[arg(long)]
+    db: String,
+
+    #[command(flatten)]
+    data: DataArgs,
 }
 
-fn load_supply_charge(csv_tariff: &String) -> Result<f64> {
-    info!("load_supply_charge: loading CSV file {}", csv_tariff);
-    let mut reader = ReaderBuilder::new()
-        .has_headers(true)
-        .from_path(Path::new(csv_tariff))?;
+#[derive(Args, Debug)]
+struct ServeArgs {
+    /// Port to listen on
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
 
-    let r = reader.records().next().context("'{}' missing data line 1")??;
-    debug!("load_supply_charge: record: {:?}", r);
-    Ok(r[0].parse::<f64>()?)
+    /// Address to listen on, e.g. 0.0.0.0 for other machines on the network
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+
+    /// Largest request body accepted, in MiB
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u16).range(1..))]
+    max_body_mib: u16,
 }
 
-fn load_public_holidays(csv: &str) -> Result<HashSet<String>> {
-    info!("load_public_holidays: loading CSV file {}", csv);
-    let mut reader = ReaderBuilder::new()
-        .has_headers(true)
-        .from_path(Path::new(csv))?;
+#[derive(Args, Debug)]
+struct MergeArgs {
+    #[command(flatten)]
+    data: DataArgs,
+
+    /// Consumption Data CSV file to write, or - for standard output
+    #[arg(short, long, default_value = "-")]
+    output: String,
+
+    /// Feedin Data CSV file to write, required with --feedin
+    #[arg(long, requires = "feedin")]
+    feedin_output: Option<String>,
+}
 
-    reader.records()
-    .map(|record| -> Result<String> {
-        let r = record?;
-        debug!("load_public_holidays: record: {:?}", r);
-        Ok(r[0].trim().to_string())
+// Read energy data, counting the bytes read of a CSV file as it streams
+fn read_energy(path: &str, format: InputFormat, direction: Direction, layout: &CsvLayout, read: &BytesRead) -> Result<Box<dyn Iterator<Item = Result<DayEnergy>>>> {
+    Ok(match format.resolve(path)? {
+        InputFormat::Auto => unreachable!("read_energy: the format is resolved"),
+        InputFormat::Csv => Box::new(read_csv_energy_from(path, open_input_counted(path, read)?, layout)?),
+        InputFormat::LongCsv => Box::new(read_long_csv(path, direction)?.into_iter().map(Ok)),
+        InputFormat::Nem12 => Box::new(read_nem12(path, direction)?.into_iter().map(Ok)),
+        InputFormat::Greenbutton => Box::new(read_green_button(path, direction)?.into_iter().map(Ok)),
+        InputFormat::Parquet => Box::new(read_parquet(path, direction)?.into_iter().map(Ok)),
+        InputFormat::Sqlite => Box::new(read_sqlite(path, direction)?.into_iter().map(Ok)),
     })
-    .collect()
-} 
-
-// Lookup $/kWh for the day of the week (0 for Monday) and time of day
-// For time of the day, we only check that the start of the consumption interval is within the tariff time interval,
-// assuming that consumption intervals always fall within single tariff intervals.
-fn lookup_tariff(dow: i16, min_since_midnight: i32, tariff: &Vec<Tariff>) -> Result<f64> {
-    tariff.iter().find(|x| 
-        x.day_start <= dow &&
-        x.day_end > dow &&
-        x.time_start <= min_since_midnight &&
-        x.time_end > min_since_midnight
-    )
-    .map(|t| t.tariff)
-    .context(format!("lookup_tariff: no tarriff for day of week {} and min_since_midnight {}", dow, min_since_midnight))
-}
-
-// Apply tariff to energy (either consumption or feedin), returning (line_count, col_count, price)
-fn price_energy<F>(csv_energy: &String, tariff: F, holidays: &HashSet<String>) -> Result<(usize, usize, f64)> where
-F: Fn(i16, i32) -> Result<f64> {
-    info!("price_energy: loading CSV file {}", csv_energy);
-    let mut reader = ReaderBuilder::new()
-        .has_headers(true)
-        .from_path(Path::new(csv_energy))?;
-
-    reader.records().fold(
-        Ok((0, 0, 0.0)), 
-        |x, record| -> Result<(usize, usize, f64)> {
-            let (line_no, num_cols, sum) = x?;
-            let r = record?;
-
-            let num_cols2 = match line_no {
-                0 => {
-                    if r.len() == 0 { 
-                        Err::<(usize, usize, f64), Error>(anyhow!(
-                            "price_energy: zero data items on first line of data"
-                        ))?; 
-                    };
-                    r.len()
+}
+
+// Holidays from the CSV file (of the region if given) if given, otherwise computed for the region if given
+fn load_holidays(data: &DataArgs) -> Result<HashSet<String>> {
+    holidays(&data.public_holidays, data.holiday_region)
+}
+
+fn holidays(public_holidays: &Option<String>, holiday_region: Option<Region>) -> Result<HashSet<String>> {
+    match (public_holidays, holiday_region) {
+        (Some(csv), region) => load_region_holidays(csv, region),
+        (None, Some(region)) => Ok(region_holidays(region, YEARS)),
+        (None, None) => Ok(HashSet::new()),
+    }
+}
+
+// Parse a channel given as name=data.csv,tariff=tariff.csv[,daily=supply.csv][,columns=a+b] into the channel, its data
+// file and the kWh columns of a long CSV data file to sum
+fn load_channel(spec: &str) -> Result<(Channel, String, Vec<String>)> {
+    let err = || anyhow!("load_channel: '{}' is not name=data.csv,tariff=tariff.csv[,daily=supply.csv][,columns=a+b]", spec);
+    let mut pairs = spec.split(',').map(|x| x.split_once('=').ok_or_else(err));
+    let (name, data) = pairs.next().ok_or_else(err)??;
+    let (mut tariff, mut daily, mut columns) = (None, None, Vec::new());
+    for pair in pairs {
+        match pair? {
+            ("tariff", t) => tariff = Some(t),
+            ("daily", d) => daily = Some(d),
+            ("columns", c) => columns = c.split('+').map(str::to_string).collect(),
+            _ => return Err(err()),
+        }
+    }
+    Ok((Channel::load(name, tariff.ok_or_else(err)?, daily)?, data.to_string(), columns))
+}
+
+impl PlanArgs {
+    // The plan's name, its consumption tariff, CDR plan or wholesale prices file
+    fn name(&self) -> &str {
+        self.cdr_plan.as_deref().or(self.plan_document.as_deref()).or(self.consumption_tariff.first().map(String::as_str)).or(self.wholesale.as_deref()).unwrap_or_default()
+    }
+}
+
+// Load the plan given by its files or CDR plan, named after the consumption tariff
+fn load_price_plan(plan: &PlanArgs) -> Result<Plan> {
+    let span = info_span!("load_plan", plan = plan.cdr_plan.as_ref().or(plan.plan_document.as_ref()).or(plan.consumption_tariff.first()), tariff_rows = Empty).entered();
+    let feedin_tariff = Some(&plan.feedin_tariff).filter(|f| !f.is_empty()).map(|f| load_tariff_versions(f)).transpose()?;
+    let loaded = match (plan.cdr_plan.as_ref().or(plan.plan_document.as_ref()), &plan.consumption_tariff, &plan.daily) {
+        (Some(file), _, _) => {
+            let mut loaded = load_plan_file(file)?;
+            if feedin_tariff.is_some() {
+                loaded.feedin_tariff = feedin_tariff;
+            }
+            if let Some(demand) = &plan.demand_tariff {
+                loaded.demand_tariff = load_demand_tariff(demand)?;
+            }
+            loaded
+        },
+        (None, consumption, Some(daily)) if !consumption.is_empty() || plan.wholesale.is_some() => {
+            let (daily_supply, supply_changes) = load_supply_charges(daily)?;
+            Plan { // no tariff if priced at wholesale
+                name: plan.name().to_string(),
+                consumption_tariff: load_tariff_versions(consumption)?,
+                feedin_tariff,
+                daily_supply,
+                supply_changes,
+                demand_tariff: plan.demand_tariff.as_deref().map(load_demand_tariff).transpose()?.unwrap_or_default(),
+                discounts: Vec::new(),
+                feedin_cap: None,
+                wholesale: None,
+                power_factor: None,
+                billing: None,
+                policies: Vec::new(),
+                events: Vec::new(),
+                fees: Vec::new(),
+                concessions: Vec::new(),
+                rebates: Vec::new(),
+                caps: Vec::new(),
+                carbon: None,
+            }
+        },
+        _ => return Err(anyhow!("load_price_plan: a consumption tariff and daily supply charge, a plan document or a CDR plan are required")),
+    };
+    let discounts = args_discounts(plan, loaded.discounts.clone())?;
+    let events = plan.events.as_deref().map(load_events).transpose()?.unwrap_or_else(|| loaded.events.clone());
+    let fees = plan.fees.as_deref().map(load_fees).transpose()?.unwrap_or_else(|| loaded.fees.clone());
+    let caps = plan.bill_cap.as_deref().map(load_bill_caps).transpose()?.unwrap_or_else(|| loaded.caps.clone());
+    let wholesale = plan.wholesale.as_deref().map(Wholesale::load).transpose()?
+        .map(|w| w.with_margins(plan.wholesale_margin, plan.wholesale_feedin_margin).with_missing(plan.missing_price));
+    let loaded = plan.concession.apply(loaded
+    .with_discounts(discounts)
+    .with_events(events)
+    .with_fees(fees)
+    .with_caps(caps)
+    .with_feedin_cap(plan.feedin_cap.as_deref().map(load_feedin_cap).transpose()?)
+    .with_wholesale(wholesale)
+    .with_power_factor(plan.power_factor.as_deref().map(PowerFactor::load).transpose()?)
+    .with_carbon(plan.carbon_intensity.as_deref().map(CarbonIntensity::load).transpose()?.map(|intensity| Carbon { intensity, price: plan.carbon_price }))
+    .with_billing(plan.billing.billing()))?;
+    span.record("tariff_rows", tariff_rows(&loaded));
+    Ok(loaded)
+}
+
+// The rows of a plan's consumption and feedin tariffs, for its log span
+fn tariff_rows(plan: &Plan) -> usize {
+    plan.consumption_tariff.len() + plan.feedin_tariff.as_ref().map_or(0, Vec::len)
+}
+
+// very similar to test_price_energy
+fn price(args: &PriceArgs) -> Result<Bill> {
+    let data = &args.data;
+    let plan = load_price_plan(&args.plan)?.with_weekend(data.weekend);
+    let holidays = load_holidays(data)?;
+    let (consumption, feedin, warnings) = data.read_pair()?;
+    let mut bill = match (&args.state, args.threads) {
+        _ if args.trace.is_some() || args.output_influx.is_some() => price_plan_traced(&plan, consumption, feedin, &holidays)?,
+        (Some(path), _) => price_resumed(path, &plan, consumption, feedin, &holidays)?,
+        (None, 0 | 1) => price_plan(&plan, consumption, feedin, &holidays)?,
+        (None, n) => {
+            let consumption = consumption.collect::<Result<Vec<DayEnergy>>>()?;
+            let feedin = feedin.map(|f| f.collect::<Result<Vec<DayEnergy>>>()).transpose()?;
+            price_plan_parallel(&plan, &consumption, feedin.as_deref(), &holidays, n)?
+        },
+    };
+    bill.warnings.extend(warnings.take());
+    for spec in &args.channel {
+        let (mut channel, path, columns) = load_channel(spec)?;
+        set_weekend(&mut channel.tariff, data.weekend);
+        let days = data.read_columns(&path, Direction::Import, &columns, &warnings, &BytesRead::default())?;
+        bill.channels.push(price_channel(&channel, days, &holidays)?);
+        bill.warnings.extend(warnings.take());
+    }
+    Ok(bill)
+}
+
+// Price the days after those in the state file if it exists, saving the state for the next run
+fn price_resumed(path: &str, plan: &Plan, consumption: Days, feedin: Option<Days>, holidays: &HashSet<String>) -> Result<Bill> {
+    let state = match Path::new(path).exists() {
+        true => {
+            let s = std::fs::read_to_string(path).with_context(|| format!("price_resumed: {}", path))?;
+            Some(parse_json(&s).with_context(|| format!("price_resumed: {}", path))?)
+        },
+        false => None,
+    };
+    let (bill, state) = price_plan_resumed(plan, state.as_ref(), consumption, feedin, holidays).with_context(|| format!("price_resumed: {}", path))?;
+    write_atomic(path, format!("{}\n", state).as_bytes())?;
+    Ok(bill)
+}
+
+// The files named by the plan and data options, including within specs like YYYYMMDD=file and channels
+fn input_files(args: &PriceArgs) -> Vec<String> {
+    let plan = &args.plan;
+    let data = &args.data;
+    // a tariff version is YYYYMMDD=file (as load_tariff_versions reads it), a channel name=data.csv,tariff=tariff.csv
+    // [,daily=supply.csv][,columns=a+b] and a power factor or carbon intensity a file or a constant
+    let version = |spec: &String| match spec.split_once('=') {
+        Some((d, path)) if d.len() == 8 && d.chars().all(|c| c.is_ascii_digit()) => path.to_string(),
+        _ => spec.clone(),
+    };
+    let channel = |spec: &String| spec.split(',').enumerate().filter_map(|(i, pair)| match pair.split_once('=')? {
+        (_, data) if i == 0 => Some(data.to_string()),
+        ("tariff" | "daily", path) => Some(path.to_string()),
+        _ => None,
+    }).collect::<Vec<_>>();
+    let file_or_constant = |spec: &Option<String>| spec.clone().filter(|s| s.parse::<f64>().is_err());
+    let paths = plan.consumption_tariff.iter().chain(&plan.feedin_tariff).map(version)
+        .chain(data.consumption.iter().chain(&data.feedin).cloned())
+        .chain(args.channel.iter().flat_map(channel))
+        .chain([file_or_constant(&plan.power_factor), file_or_constant(&plan.carbon_intensity)].into_iter().flatten())
+        .chain([&plan.daily, &plan.cdr_plan, &plan.plan_document, &plan.demand_tariff, &plan.feedin_cap, &plan.discounts, &plan.wholesale, &plan.events, &plan.fees, &plan.concession.concessions, &plan.concession.rebates, &plan.bill_cap, &data.public_holidays].into_iter().flatten().cloned());
+    let mut files = Vec::new();
+    for path in paths {
+        match Path::new(&path).is_file() {
+            true => files.push(path),
+            false => eprintln!("Warning: {} isn't a file, so changes to it aren't watched", path),
+        }
+    }
+    files.sort();
+    files.dedup();
+    files
+}
+
+// How long --watch lets a burst of file system events (e.g. an editor saving) settle before pricing again
+const WATCH_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(500);
+
+// Price and output the bill, then again each time an input file changes; errors (e.g. from a half edited tariff file)
+// are shown and priced again after the next change
+fn watch(args: &PriceOutputArgs) -> Result<()> {
+    let data = &args.price.data;
+    if data.consumption.iter().chain(&data.feedin).any(|p| p == STDIN) {
+        return Err(anyhow!("watch: standard input ('{}') can't be watched", STDIN));
+    }
+    let files = input_files(&args.price);
+    let paths = files.iter().map(|f| std::fs::canonicalize(f).with_context(|| format!("watch: {}", f))).collect::<Result<Vec<_>>>()?;
+    // watch the files' directories, so a file saved by renaming a new file over it is still seen
+    let (sender, events) = std::sync::mpsc::channel();
+    let mut debouncer = new_debouncer(WATCH_DEBOUNCE, None, sender).context("watch")?;
+    for dir in paths.iter().filter_map(|p| p.parent()).collect::<BTreeSet<_>>() {
+        debouncer.watch(dir, RecursiveMode::NonRecursive).with_context(|| format!("watch: {}", dir.display()))?;
+    }
+    let metrics = SharedMetrics::default();
+    if let Some(address) = args.metrics.clone() {
+        let listener = bind(&address)?;
+        let server = Server::metrics(metrics.clone());
+        eprintln!("Serving metrics on http://{}/metrics", address);
+        std::thread::spawn(move || if let Err(e) = serve_on(listener, server) {
+            eprintln!("Error: {:#}", e);
+        });
+    }
+    loop {
+        let result = priced_bill(&args.price).and_then(|bill| output_bill(args, &bill).and(publish_mqtt(args, &bill)).map(|_| bill));
+        if let Ok(mut m) = metrics.lock() {
+            m.record(result.as_ref().ok(), Local::now().date_naive(), Utc::now().timestamp());
+        }
+        if let Err(e) = result {
+            eprintln!("Error: {:#}", e);
+        }
+        eprintln!("Watching {} files for changes (interrupt to stop)", files.len());
+        let changed = loop {
+            let changed = match events.recv().context("watch: the file watcher stopped")? {
+                Ok(events) => {
+                    // reading the files when pricing is an access, not a change
+                    let changes = events.iter().filter(|e| !matches!(e.kind, EventKind::Access(_))).collect::<Vec<_>>();
+                    files.iter().zip(&paths).filter(|(_, p)| changes.iter().any(|e| e.paths.contains(p))).map(|(f, _)| f.as_str()).collect::<Vec<_>>()
                 },
-                _ => {
-                    if r.len() != num_cols { 
-                        Err::<(usize, usize, f64), Error>(anyhow!(
-                            "price_energy: number data items {} on line {} not equal to {} on the first line of data", 
-                            r.len(), line_no, num_cols
-                        ))?; 
-                    };
-                    num_cols
+                Err(errors) => {
+                    for e in errors {
+                        eprintln!("Error: watch: {}", e);
+                    }
+                    Vec::new()
                 },
             };
-            
-            let interval = (24 * 60)/(num_cols2 - 1); // 289 for date + 288 data points => 5 minute intervals
-            debug!("price_energy: num_cols2 {}, interval {}, record: {:?}", num_cols2, interval, r);
-            let date_str = r[0].trim();
-            let week_day = match holidays.contains(date_str) {
-                true => 6, // if it's a public holiday Sunday=6 tariff applies
-                false => {
-                    NaiveDate::parse_from_str(date_str, "%Y%m%d")
-                    .map(|d| d.weekday().num_days_from_monday() as i16)?
+            if !changed.is_empty() {
+                break changed;
+            }
+        };
+        eprintln!("\n{} changed, pricing again", changed.join(", "));
+    }
+}
+
+// Price with GST applied and the daily breakdown and trace saved if requested
+fn priced_bill(args: &PriceArgs) -> Result<Bill> {
+    let mut bill = price(args)?;
+    if let Some(rate) = args.gst {
+        let inc = |c| !args.ex_gst.contains(&c);
+        bill.apply_gst(rate, GstInclusive {
+            consumption: inc(Component::Consumption),
+            supply: inc(Component::Supply),
+            demand: inc(Component::Demand),
+        });
+    }
+    if let Some(rounding) = args.rounding {
+        bill.round(rounding);
+    }
+    if let Some(path) = &args.daily_breakdown {
+        bill.save_daily_csv(path)?;
+    }
+    if let Some(path) = &args.trace {
+        bill.save_trace_csv(path)?;
+    }
+    if let Some(target) = &args.output_influx {
+        let local = LocalTime { offset: args.influx_utc_offset, daylight_saving: args.influx_daylight_saving };
+        let lines = influx_lines(&bill, &args.influx_measurement, local);
+        match target.starts_with("http://") {
+            true => write_influx(target, args.influx_token.as_deref(), &lines)?,
+            false => std::fs::write(target, lines).with_context(|| format!("priced_bill: {}", target))?,
+        }
+    }
+    Ok(bill)
+}
+
+fn bill_output(args: &PriceArgs, bill: &Bill, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Text => {
+            let mut s = bill.to_text();
+            if args.monthly {
+                s += &format!("\n{}", bill.monthly_text());
+            }
+            if let Some(width) = args.chart {
+                s += &format!("\n{}", bill.daily_chart_text(width));
+            }
+            if let Some(projection) = args.projection.project(bill) {
+                s += &format!("\n{}", Bill::projection_text(&projection));
+            }
+            s
+        },
+        OutputFormat::Json => {
+            let mut json = bill.to_json();
+            if let Json::Object(kvs) = &mut json {
+                if args.monthly {
+                    kvs.push(("monthly".to_string(), bill.monthly_json()));
                 }
-            };
-            debug!("price_energy: date_str {}, week_day {}", date_str, week_day);
-            
-            Ok((
-                line_no + 1, 
-                num_cols2,
-                sum + r.iter().skip(1).enumerate().fold(
-                    Ok(0.0),
-                    |sum2, (i, energy_str)| -> Result<f64> {
-                        let min_since_midnight = (i * interval) as i32;
-                        debug!("price_energy: i {}, min_since_midnight {}, energy_str {}", i, min_since_midnight, energy_str);
-                        let energy = energy_str.parse::<f64>()?;
-                        let t = tariff(week_day, min_since_midnight)?;
-                        debug!("price_energy: week_day {}, min_since_midnight {}, energy kWh {}, tariff $/kWh {}", week_day, min_since_midnight, energy, t);
-                        Ok(sum2? + t * energy)
-                    })?
-            ))
-    })
+                if let Some(projection) = args.projection.project(bill) {
+                    kvs.push(("projection".to_string(), Json::Array(projection.into_iter().map(Json::from).collect())));
+                }
+            }
+            format!("{}\n", json)
+        },
+    }
 }
 
-// very similar to test_price_energy
-fn main() -> Result<()> {
-    env_logger::init();
-    let args = Args::parse();
-
-    let daily_supply = load_supply_charge(&args.daily)?;
-    
-    let holidays = args.public_holidays
-    .map(|x| load_public_holidays(&x))
-    .unwrap_or_else(|| { Ok(HashSet::new()) })?;
-    
-    let consumption_tariff = load_tariff(&args.consumption_tariff)?;
-    
-    let (line_count, _col_count, consumption_cost) = price_energy(
-        &args.consumption, 
-        |dow, min_since_midnight| lookup_tariff(dow, min_since_midnight, &consumption_tariff),
-        &holidays
-    )?;
-
-    let (_line_count2, _col_count2, feedin_cost) = match (args.feedin_tariff, args.feedin) {
-        (Some(t), Some(e)) => {
-            let tarrif = load_tariff(&t)?;
-            price_energy(
-                &e, 
-                |dow, min_since_midnight| lookup_tariff(dow, min_since_midnight, &tarrif),
-                &holidays
-            )?
+// Print the bill, or write it to the output file in the format of its extension
+fn output_bill(args: &PriceOutputArgs, bill: &Bill) -> Result<()> {
+    let Some(path) = &args.output else {
+        print!("{}", bill_output(&args.price, bill, args.price.format));
+        return Ok(());
+    };
+    let contents = match Path::new(path).extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+        Some("txt") => bill_output(&args.price, bill, OutputFormat::Text).into_bytes(),
+        Some("json") => bill_output(&args.price, bill, OutputFormat::Json).into_bytes(),
+        Some("csv") => {
+            let mut csv = Vec::new();
+            bill.write_csv(&mut csv)?;
+            csv
         },
-        (_, _) => (0, 0, 0.0)
+        _ => return Err(anyhow!("output_bill: {} is not a .txt, .json or .csv file", path)),
     };
+    write_atomic(path, &contents)
+}
+
+// Publish the bill to the MQTT broker if one is given
+fn publish_mqtt(args: &PriceOutputArgs, bill: &Bill) -> Result<()> {
+    match &args.mqtt {
+        Some(address) => publish_bill(bill, address, args.mqtt_username.as_deref(), args.mqtt_password.as_deref(), &args.mqtt_prefix),
+        None => Ok(()),
+    }
+}
 
-    let supply_cost = line_count as f64 * daily_supply;
-    println!("Consumption ${}, Feedin ${}, Supply ${}", consumption_cost, feedin_cost, supply_cost);
-    println!("Total ${}", consumption_cost + feedin_cost + supply_cost);
+// Write the file in the same directory under a temporary name then rename it, so it's never seen half written
+fn write_atomic(path: &str, contents: &[u8]) -> Result<()> {
+    let tmp = format!("{}.tmp", path);
+    std::fs::write(&tmp, contents).with_context(|| format!("write_atomic: {}", tmp))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("write_atomic: renaming {} to {}", tmp, path))
+}
+
+// Price the billing period (from the bill file unless --from and --to are given) and compare with the bill
+fn reconcile_bill(args: &ReconcileArgs) -> Result<()> {
+    let retailer = RetailerBill::load(&args.bill)?;
+    let mut price_args = args.price.clone();
+    price_args.data.from = price_args.data.from.or(retailer.from);
+    price_args.data.to = price_args.data.to.or(retailer.to);
+    let bill = priced_bill(&price_args)?;
+    let differences = reconcile(&retailer, &bill);
+    match args.price.format {
+        OutputFormat::Text => {
+            print!("{}", reconcile_text(&differences, args.tolerance));
+            for w in &bill.warnings {
+                println!("Warning: {}", w);
+            }
+        },
+        OutputFormat::Json => println!("{}", Json::object(vec![
+            ("differences", reconcile_json(&differences, args.tolerance)),
+            ("bill", bill.to_json()),
+        ])),
+    }
+    Ok(())
+}
+
+fn report(args: &ReportArgs) -> Result<()> {
+    let data = &args.price.data;
+    if args.heatmap.is_some() && data.consumption.iter().any(|p| p == STDIN) {
+        return Err(anyhow!("report: --heatmap reads the consumption data again so it can't be standard input ('{}')", STDIN));
+    }
+    let bill = priced_bill(&args.price)?;
+    if let Some(path) = &args.html {
+        bill.save_html(&format!("Electricity costs: {}", args.price.plan.name()), path)?;
+    }
+    if let Some(path) = &args.heatmap {
+        let (consumption, _, _) = data.read_pair()?;
+        usage_heatmap(consumption, args.heatmap_by_month)?.save(path)?;
+    }
+    print!("{}", bill_output(&args.price, &bill, args.price.format));
+    Ok(())
+}
+
+fn peaks(args: &PeaksArgs) -> Result<()> {
+    let data = &args.data;
+    let (consumption, feedin, warnings) = data.read_pair()?;
+    let consumption = peak_demand(consumption, args.monthly)?;
+    let feedin = feedin.map(|f| peak_demand(f, args.monthly)).transpose()?;
+    let warnings = warnings.take();
+    match args.format {
+        OutputFormat::Text => {
+            print!("{}", peaks_text(&consumption, args.monthly));
+            if let Some(feedin) = &feedin {
+                print!("\nFeedin\n{}", peaks_text(feedin, args.monthly));
+            }
+            for w in &warnings {
+                println!("Warning: {}", w);
+            }
+        },
+        OutputFormat::Json => println!("{}", Json::object(vec![
+            ("consumption", peaks_json(&consumption)),
+            ("feedin", feedin.as_deref().map_or(Json::Null, peaks_json)),
+            ("warnings", warnings.into()),
+        ])),
+    }
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use assert_float_eq::*;
-
-    #[test]
-    fn test_minutes_since_midnight() -> Result<()> {
-        assert_eq!(minutes_since_midnight("00:00:00")?, 0);
-        assert_eq!(minutes_since_midnight("12:34:56")?, 754);
-        assert_eq!(minutes_since_midnight("23:59:59")?, 1439);   
-        Ok(()) 
-    }
-
-    #[test]
-    // very similar to main
-    fn test_price_energy() -> Result<()> {
-        let daily_supply = load_supply_charge(&"data/test/tariff/supply.csv".to_string())?;
-        assert_f64_near!(daily_supply, 1.45398);
-
-        let holidays = load_public_holidays(&"data/test/publicHolidaysTest.csv".to_string())?;
-        assert!(!holidays.contains("20230807"));
-        assert!(holidays.contains("20230808"));
-        assert!(holidays.contains("20500101"));
-
-        let consumption_tariff = load_tariff(&"data/test/tariff/consumption.csv".to_string())?;
-        // println!("consumption_tariff {:?}", consumption_tariff);
-        let (line_count, col_count, consumption_cost) = price_energy(
-            &"data/test/energy/consumption.csv".to_string(), 
-            |dow, min_since_midnight| lookup_tariff(dow, min_since_midnight, &consumption_tariff),
-            &holidays
-        )?;
-        println!("line_count {}, col_count {}, consumption cost {}", line_count, col_count, consumption_cost);
-        assert_f64_near!(consumption_cost, 0.14215773);
-    
-        let feedin_tariff = load_tariff(&"data/test/tariff/feedIn.csv".to_string())?;
-        let (line_count2, col_count2, feedin_cost) = price_energy(
-            &"data/test/energy/feedIn.csv".to_string(), 
-            |dow, min_since_midnight| lookup_tariff(dow, min_since_midnight, &feedin_tariff),
-            &holidays
-        )?;
-        println!("line_count2 {}, col_count2 {}, feedin cost {}", line_count2, col_count2, feedin_cost);
-        assert_f64_near!(feedin_cost, -0.15582);
-
-        let supply_cost = line_count as f64 * daily_supply;
-        let total = consumption_cost + feedin_cost + supply_cost;
-        println!("total cost {}", total);
-        assert_f64_near!(total, 4.34827773);
+fn explain_moment(args: &ExplainArgs) -> Result<()> {
+    let plan = load_price_plan(&args.plan)?.with_weekend(args.weekend);
+    let holidays = holidays(&args.public_holidays, args.holiday_region)?;
+    let explanation = explain(&plan, &holidays, args.at, args.interval, args.usage_day, args.usage_period)?;
+    match args.format {
+        OutputFormat::Text => print!("{}", explanation.to_text()),
+        OutputFormat::Json => println!("{}", explanation.to_json()),
+    }
+    Ok(())
+}
+
+fn weather(args: &WeatherArgs) -> Result<()> {
+    let (consumption, _, warnings) = args.data.read_pair()?;
+    let report = weather_report(consumption, &load_temperatures(&args.temperatures)?, args.base_temperature, args.yearly)?;
+    let warnings = warnings.take();
+    match args.format {
+        OutputFormat::Text => {
+            print!("{}", report.to_text(args.yearly));
+            for w in &warnings {
+                println!("Warning: {}", w);
+            }
+        },
+        OutputFormat::Json => println!("{}", Json::object(vec![("weather", report.to_json()), ("warnings", warnings.into())])),
+    }
+    Ok(())
+}
+
+fn stats(args: &StatsArgs) -> Result<()> {
+    let data = &args.data;
+    let (consumption, feedin, warnings) = data.read_pair()?;
+    let mut demand = vec![("consumption", interval_demand(consumption)?)];
+    if let Some(feedin) = feedin {
+        demand.push(("feedin", interval_demand(feedin)?));
+    }
+    let warnings = warnings.take();
+    let stats = demand.iter().filter_map(|(label, kw)| demand_stats(kw).map(|s| (*label, s))).collect::<Vec<_>>();
+    if let Some(path) = &args.duration_curve {
+        save_duration_curve(&demand.iter().map(|(label, kw)| (*label, kw.as_slice())).collect::<Vec<_>>(), path)?;
+    }
+    match args.format {
+        OutputFormat::Text => {
+            print!("{}", stats_text(&stats));
+            for w in &warnings {
+                println!("Warning: {}", w);
+            }
+        },
+        OutputFormat::Json => println!("{}", Json::object(
+            stats.iter().map(|(label, s)| (*label, s.to_json()))
+            .chain([("warnings", warnings.into())])
+            .collect()
+        )),
+    }
+    Ok(())
+}
+
+// The plan's discounts and those of the discounts file, without conditional discounts if they're left out
+fn args_discounts(plan: &PlanArgs, mut discounts: Vec<Discount>) -> Result<Vec<Discount>> {
+    discounts.extend(plan.discounts.as_ref().map(|d| load_discounts(d)).transpose()?.unwrap_or_default());
+    Ok(discounts.into_iter().filter(|d| !plan.no_conditional_discounts || d.condition.is_none()).collect())
+}
+
+// Load a plan from a directory, a CDR plan JSON file or comma separated consumption tariff, feedin tariff and supply CSV files
+fn load_plan(plan: &str) -> Result<Plan> {
+    let span = info_span!("load_plan", plan, tariff_rows = Empty).entered();
+    let loaded = match plan.split(',').collect::<Vec<&str>>()[..] {
+        [file] if file.ends_with(".json") || file.ends_with(".toml") => load_plan_file(file),
+        [dir] => Plan::load_dir(dir),
+        [consumption, feedin, daily] => Plan::load(consumption, consumption, Some(feedin).filter(|x| !x.is_empty()), daily, None),
+        _ => Err(anyhow!("load_plan: '{}' is not a directory, plan document, CDR plan JSON file or consumption,feedin,supply CSV files", plan)),
+    }?;
+    span.record("tariff_rows", tariff_rows(&loaded));
+    Ok(loaded)
+}
+
+fn compare(args: &CompareArgs) -> Result<()> {
+    let power_factor = args.power_factor.as_deref().map(PowerFactor::load).transpose()?;
+    let plans = args.plan.iter().map(|p| args.concession.apply(load_plan(p)?.with_weekend(args.data.weekend).with_power_factor(power_factor.clone()).with_billing(args.billing.billing())))
+        .collect::<Result<Vec<Plan>>>()?;
+    let holidays = load_holidays(&args.data)?;
+    let (consumption, feedin, warnings) = args.data.read_both()?;
+    let mut ranked = compare_plans(&plans, &consumption, feedin.as_deref(), &holidays, args.threads)?;
+    for (_, bill) in &mut ranked {
+        bill.warnings.extend(warnings.iter().cloned());
+    }
+    // with a projection, ranked by the total over the years
+    let mut ranked = ranked.into_iter().map(|(plan, bill)| {
+        let projection = args.projection.project(&bill);
+        (plan, bill, projection)
+    }).collect::<Vec<_>>();
+    let ranking = |bill: &Bill, projection: &Option<Vec<f64>>| projection.as_ref().map_or(bill.total(), |p| p.iter().sum());
+    ranked.sort_by(|(_, a, pa), (_, b, pb)| ranking(a, pa).total_cmp(&ranking(b, pb)));
+
+    match args.format {
+        OutputFormat::Text => {
+            let cheapest = ranked.first().map(|(_, b, p)| ranking(b, p)).unwrap_or(0.0);
+            let years = args.projection.years.map(|y| format!(" {:>12}", format!("{} Years", y))).unwrap_or_default();
+            println!("{:>4} {:<40} {:>12} {:>10} {:>10} {:>10} {:>10}{} {:>10}", "Rank", "Plan", "Consumption", "Feedin", "Supply", "Demand", "Total", years, "Extra");
+            for (rank, (plan, bill, projection)) in ranked.iter().enumerate() {
+                let years = projection.as_ref().map(|p| format!(" {:>12.2}", p.iter().sum::<f64>())).unwrap_or_default();
+                println!(
+                    "{:>4} {:<40} {:>12.2} {:>10.2} {:>10.2} {:>10.2} {:>10.2}{} {:>10.2}",
+                    rank + 1, plan.name, bill.consumption, bill.feedin, bill.supply, bill.demand_total(), bill.total(), years, ranking(bill, projection) - cheapest
+                );
+                for w in &bill.warnings {
+                    println!("     Warning: {}", w);
+                }
+            }
+        },
+        OutputFormat::Json => {
+            let json = Json::Array(ranked.iter().map(|(plan, bill, projection)| {
+                let mut kvs = vec![("plan", plan.name.as_str().into()), ("bill", bill.to_json())];
+                if let Some(projection) = projection {
+                    kvs.push(("projection", Json::Array(projection.iter().map(|x| Json::from(*x)).collect())));
+                }
+                Json::object(kvs)
+            }).collect());
+            println!("{}", json);
+        },
+    }
+    Ok(())
+}
+
+// Rank the plans in the directory (and the current plan if it's elsewhere) by annual cost
+fn best_plan(args: &BestPlanArgs) -> Result<()> {
+    let (mut plans, mut warnings) = Plan::load_all(&args.plans)?;
+    let current = match &args.current {
+        None => None,
+        Some(name) => match plans.iter().position(|p| p.name == *name) {
+            Some(i) => Some(i),
+            None => {
+                plans.push(load_plan(name)?);
+                Some(plans.len() - 1)
+            },
+        },
+    };
+    if plans.is_empty() {
+        return Err(anyhow!("best_plan: no plans in {}", args.plans));
+    }
+    let plans = plans.into_iter().map(|p| p.with_weekend(args.data.weekend)).collect::<Vec<Plan>>();
+    let holidays = load_holidays(&args.data)?;
+    let (consumption, feedin, w) = args.data.read_both()?;
+    warnings.extend(w);
+    let ranked = compare_plans(&plans, &consumption, feedin.as_deref(), &holidays, args.threads)?;
+
+    let annual = |bill: &Bill| bill.total() * 365.0 / bill.days.max(1) as f64;
+    let is_current = |plan: &Plan| current.is_some_and(|i| std::ptr::eq(plan, &plans[i]));
+    let current_annual = ranked.iter().find(|(p, _)| is_current(p)).map(|(_, b)| annual(b));
+    match args.format {
+        OutputFormat::Text => {
+            println!("{} plans priced on {} days of data", ranked.len(), consumption.len());
+            println!("{:>4} {:<40} {:>10} {:>10} {:>10}", "Rank", "Plan", "Total", "Annual", "Saving");
+            for (rank, (plan, bill)) in ranked.iter().enumerate() {
+                let saving = current_annual.map(|c| format!("{:>10.2}", c - annual(bill))).unwrap_or_default();
+                let marker = if is_current(plan) { "  <- current" } else { "" };
+                println!("{:>4} {:<40} {:>10.2} {:>10.2} {}{}", rank + 1, plan.name, bill.total(), annual(bill), saving, marker);
+            }
+            for w in warnings.iter().chain(ranked.iter().flat_map(|(_, b)| b.warnings.iter())) {
+                println!("Warning: {}", w);
+            }
+        },
+        OutputFormat::Json => {
+            let plans = Json::Array(ranked.iter().enumerate().map(|(rank, (plan, bill))| {
+                let mut kvs = vec![
+                    ("rank", (rank + 1).into()),
+                    ("plan", plan.name.as_str().into()),
+                    ("current", is_current(plan).into()),
+                    ("total", bill.total().into()),
+                    ("annual", annual(bill).into()),
+                ];
+                if let Some(c) = current_annual {
+                    kvs.push(("saving", (c - annual(bill)).into()));
+                }
+                kvs.push(("bill", bill.to_json()));
+                Json::object(kvs)
+            }).collect());
+            let warnings = Json::Array(warnings.iter().map(|w| w.as_str().into()).collect());
+            println!("{}", Json::object(vec![("days", consumption.len().into()), ("plans", plans), ("warnings", warnings)]));
+        },
+    }
+    Ok(())
+}
+
+// Price plans A and B and solve for B's feedin rate and supply charges to cost the same as A
+fn break_even_plans(args: &BreakEvenArgs) -> Result<()> {
+    let [a, b] = &args.plan[..] else {
+        return Err(anyhow!("break_even_plans: give two plans, not {}", args.plan.len()));
+    };
+    let plans = [load_plan(a)?.with_weekend(args.data.weekend), load_plan(b)?.with_weekend(args.data.weekend)];
+    let holidays = load_holidays(&args.data)?;
+    let (consumption, feedin, warnings) = args.data.read_both()?;
+    let bills = plans.iter().map(|plan| {
+        let mut bill = price_plan(plan, consumption.iter().cloned().map(Ok), feedin.as_ref().map(|f| f.iter().cloned().map(Ok)), &holidays)?;
+        bill.warnings.extend(warnings.iter().cloned());
+        Ok(bill)
+    }).collect::<Result<Vec<Bill>>>()?;
+    let even = break_even(&bills[0], &bills[1]);
+    let (_, feedin_rate) = bills[1].flat_rates();
+    let daily = even.supply_scale.map(|s| s * plans[1].daily_supply);
+    match args.format {
+        OutputFormat::Text => {
+            println!("Plan A {} total ${}\nPlan B {} total ${}", plans[0].name, bills[0].total(), plans[1].name, bills[1].total());
+            match (even.feedin_rate, feedin_rate) {
+                (Some(even), Some(now)) => println!("Plan B costs the same with a flat feedin rate of {:.3} c/kWh (now {:.3} c/kWh)", even * 100.0, now * 100.0),
+                _ => println!("Plan B's feedin rate makes no difference without feedin"),
+            }
+            match (even.supply_scale, daily) {
+                (Some(scale), Some(daily)) => println!("Plan B costs the same with {:.1}% of its supply charges, ${:.5}/day (now ${}/day)", scale * 100.0, daily, plans[1].daily_supply),
+                _ => println!("Plan B has no supply charges to change"),
+            }
+            for w in bills.iter().flat_map(|b| b.warnings.iter()) {
+                println!("Warning: {}", w);
+            }
+        },
+        OutputFormat::Json => {
+            let option = |x: Option<f64>| x.map_or(Json::Null, Json::from);
+            println!("{}", Json::object(vec![
+                ("feedin_rate", option(even.feedin_rate)),
+                ("supply_scale", option(even.supply_scale)),
+                ("daily_supply", option(daily)),
+                ("a", Json::object(vec![("plan", plans[0].name.as_str().into()), ("bill", bills[0].to_json())])),
+                ("b", Json::object(vec![("plan", plans[1].name.as_str().into()), ("bill", bills[1].to_json())])),
+            ]));
+        },
+    }
+    Ok(())
+}
+
+// A plan and energy data loaded for a simulation, which prices the actual data and the simulated data
+struct SimulationData {
+    plan: Plan,
+    holidays: HashSet<String>,
+    consumption: Vec<DayEnergy>,
+    feedin: Vec<DayEnergy>, // empty if not given
+    warnings: Vec<String>,
+}
+
+impl SimulationData {
+    fn load(plan: &PlanArgs, data: &DataArgs) -> Result<SimulationData> {
+        let (consumption, feedin, warnings) = data.read_both()?;
+        Ok(SimulationData {
+            plan: load_price_plan(plan)?.with_weekend(data.weekend),
+            holidays: load_holidays(data)?,
+            consumption,
+            feedin: feedin.unwrap_or_default(),
+            warnings,
+        })
+    }
+
+    fn price(&self, consumption: &[DayEnergy], feedin: &[DayEnergy]) -> Result<Bill> {
+        let feedin = Some(feedin.iter().cloned().map(Ok)).filter(|_| !feedin.is_empty());
+        let mut bill = price_plan(&self.plan, consumption.iter().cloned().map(Ok), feedin, &self.holidays)?;
+        bill.warnings.extend(self.warnings.iter().cloned());
+        Ok(bill)
+    }
+
+    // Print the simulation's description and its bill against the actual bill
+    fn report(&self, format: OutputFormat, label: &str, text: &str, json: Vec<(&str, Json)>, actual: &Bill, simulated: &Bill) -> Result<()> {
+        let saving = actual.total() - simulated.total();
+        match format {
+            OutputFormat::Text => {
+                print!("{}", text);
+                println!("Actual total ${}\n{} total ${}\nSaving ${}", actual.total(), label, simulated.total(), saving);
+                print!("\n{}:\n{}", label, simulated.to_text());
+            },
+            OutputFormat::Json => {
+                let mut kvs = json;
+                kvs.push(("saving", saving.into()));
+                kvs.push(("actual", actual.to_json()));
+                kvs.push(("simulated", simulated.to_json()));
+                println!("{}", Json::object(kvs));
+            },
+        }
         Ok(())
     }
 }
 
+fn forecast(args: &ForecastArgs) -> Result<()> {
+    let data = SimulationData::load(&args.plan, &args.data)?;
+    // warnings of the data so far, the projected days being estimates
+    let mut forecast = forecast_bill(&data.plan, &data.consumption, &data.feedin, &data.holidays)?;
+    forecast.so_far.warnings.extend(data.warnings.iter().cloned());
+    match args.format {
+        OutputFormat::Text => {
+            print!("{}", forecast.to_text());
+            for w in &forecast.so_far.warnings {
+                println!("Warning: {}", w);
+            }
+        },
+        OutputFormat::Json => println!("{}", forecast.to_json()),
+    }
+    alert("Forecast bill", forecast.forecast.payable(), args.alert_over)
+}
+
+// The exit status when a bill is over --alert-over, distinct from errors (1) and usage errors (2)
+const ALERT_STATUS: i32 = 3;
+
+// Exit with ALERT_STATUS if the bill is over the threshold, after the results already printed
+fn alert(label: &str, total: f64, over: Option<f64>) -> Result<()> {
+    if let Some(threshold) = over.filter(|t| total > *t) {
+        std::io::Write::flush(&mut std::io::stdout())?;
+        eprintln!("Alert: {} ${:.2} is over ${:.2}", label, total, threshold);
+        std::process::exit(ALERT_STATUS);
+    }
+    Ok(())
+}
+
+fn simulate_battery(args: &BatteryArgs) -> Result<()> {
+    let data = SimulationData::load(&args.plan, &args.data)?;
+    let threshold = args.discharge_above
+        .unwrap_or_else(|| data.plan.consumption_tariff.iter().fold(f64::NEG_INFINITY, |max, t| max.max(t.tariff)));
+    let battery = Battery { kwh: args.kwh, kw: args.kw, efficiency: args.efficiency };
+    let sim = simulate(&battery, &data.consumption, &data.feedin, peak_times(&data.plan.consumption_tariff, &data.holidays, threshold))?;
+    let text = format!(
+        "Battery {} kWh, {} kW, {} efficiency, discharging at ${}/kWh or more\nCharged {} kWh from feedin, discharged {} kWh\n",
+        args.kwh, args.kw, args.efficiency, threshold, sim.charged, sim.discharged
+    );
+    let json = vec![("charged", sim.charged.into()), ("discharged", sim.discharged.into())];
+    let actual = data.price(&data.consumption, &data.feedin)?;
+    data.report(args.format, "With battery", &text, json, &actual, &data.price(&sim.consumption, &sim.feedin)?)
+}
+
+fn simulate_shift(args: &ShiftArgs) -> Result<()> {
+    let data = SimulationData::load(&args.plan, &args.data)?;
+    let window = |name| tariff_window(&data.plan.consumption_tariff, &data.holidays, name);
+    let (consumption, moved) = shift_load(&data.consumption, args.kwh, window(&args.from_tariff), window(&args.to_tariff))?;
+    let text = format!(
+        "Moving up to {} kWh a day from {} to {}\nMoved {} kWh\n",
+        args.kwh, args.from_tariff, args.to_tariff, moved
+    );
+    let actual = data.price(&data.consumption, &data.feedin)?;
+    data.report(args.format, "Shifted", &text, vec![("moved", moved.into())], &actual, &data.price(&consumption, &data.feedin)?)
+}
+
+fn simulate_solar(args: &SolarArgs) -> Result<()> {
+    let data = SimulationData::load(&args.plan, &args.data)?;
+    let generation = match (&args.generation, args.kw) {
+        (Some(path), _) => read_energy(path, args.data.input_format, Direction::Import, &args.data.csv.layout(), &BytesRead::default())?.collect::<Result<Vec<DayEnergy>>>()?,
+        (None, kw) => {
+            let dates = data.consumption.iter().map(|d| d.date).collect::<Vec<_>>();
+            let interval = data.consumption.first().map_or(30, |d| d.interval);
+            solar_generation(&dates, interval, kw.unwrap_or(0.0), args.seed)?
+        },
+    };
+    let solar = add_solar(&data.consumption, &data.feedin, &generation)?;
+    let actual = data.price(&data.consumption, &data.feedin)?;
+    let mut simulated = data.price(&solar.consumption, &solar.feedin)?;
+    if solar.missing > 0 {
+        simulated.warnings.push(format!("{} days of consumption have no generation", solar.missing));
+    }
+    let annual = (actual.total() - simulated.total()) * 365.0 / data.consumption.len().max(1) as f64;
+    let payback = args.cost.filter(|_| annual > 0.0).map(|cost| cost / annual);
+    let mut text = format!(
+        "Generated {} kWh, used {} kWh in the house and exported {} kWh\nAnnual saving ${:.2}\n",
+        solar.generated, solar.self_consumed, solar.exported, annual
+    );
+    match (args.cost, payback) {
+        (Some(cost), Some(years)) => text += &format!("Simple payback {:.1} years on ${}\n", years, cost),
+        (Some(cost), None) => text += &format!("No payback on ${} without a saving\n", cost),
+        _ => (),
+    }
+    let json = vec![
+        ("generated", solar.generated.into()),
+        ("self_consumed", solar.self_consumed.into()),
+        ("exported", solar.exported.into()),
+        ("annual_saving", annual.into()),
+        ("payback_years", payback.map_or(Json::Null, Json::from)),
+    ];
+    data.report(args.format, "With solar", &text, json, &actual, &simulated)
+}
+
+fn simulate_ev(args: &EvArgs) -> Result<()> {
+    let data = SimulationData::load(&args.plan, &args.data)?;
+    let ev = Ev { kwh: args.kwh, weekly: args.weekly, kw: args.kw, plug_in: args.plug_in };
+    let (plug_in, charged) = charge_at_plug_in(&data.consumption, &ev);
+    let (cheapest, cheapest_charged) = charge_cheapest(&data.consumption, &ev, tariff_rate(&data.plan.consumption_tariff, &data.holidays))?;
+    let (actual, plug_in, cheapest) = (data.price(&data.consumption, &data.feedin)?, data.price(&plug_in, &data.feedin)?, data.price(&cheapest, &data.feedin)?);
+    let saving = plug_in.total() - cheapest.total();
+    let annual = saving * 365.0 / data.consumption.len().max(1) as f64;
+    match args.format {
+        OutputFormat::Text => {
+            println!(
+                "EV {} kWh a {} at up to {} kW, plugged in at {:02}:{:02}\nCharged {} kWh at plug-in, {} kWh optimised",
+                args.kwh, if args.weekly { "week" } else { "day" }, args.kw, args.plug_in / 60, args.plug_in % 60, charged, cheapest_charged
+            );
+            println!("Actual total ${} without the EV\nPlug-in charging total ${}\nOptimised charging total ${}", actual.total(), plug_in.total(), cheapest.total());
+            println!("Saving ${}, ${:.2} a year", saving, annual);
+            print!("\nOptimised:\n{}", cheapest.to_text());
+        },
+        OutputFormat::Json => println!("{}", Json::object(vec![
+            ("charged", charged.into()),
+            ("optimised_charged", cheapest_charged.into()),
+            ("saving", saving.into()),
+            ("annual_saving", annual.into()),
+            ("actual", actual.to_json()),
+            ("plug_in", plug_in.to_json()),
+            ("optimised", cheapest.to_json()),
+        ])),
+    }
+    Ok(())
+}
+
+// Merge the energy data into the database, keeping the data already there for dates imported before
+fn import(args: &ImportArgs) -> Result<()> {
+    let data = &args.data;
+    let exists = Path::new(&args.db).exists();
+    let warnings = Warnings::default();
+    let mut merged = Vec::new();
+    for (paths, direction) in [(&data.consumption, Direction::Import), (&data.feedin, Direction::Export)] {
+        let existing: Days = match exists {
+            true => Box::new(read_sqlite(&args.db, direction)?.into_iter().map(Ok)),
+            false => Box::new(std::iter::empty()),
+        };
+        let days = data.read_all(paths, direction, &warnings)?;
+        let (days, w) = merge_days(vec![(args.db.clone(), existing), (paths.join(","), days)])?;
+        warnings.extend(w);
+        merged.push(days);
+    }
+    write_sqlite(&args.db, &merged[0], &merged[1])?;
+    println!("{}: {} days of consumption, {} days of feedin", args.db, merged[0].len(), merged[1].len());
+    for w in warnings.take() {
+        println!("Warning: {}", w);
+    }
+    Ok(())
+}
+
+// Write synthetic data to the files, or consumption to standard output
+fn generate_data(args: &GenerateArgs) -> Result<()> {
+    let start = args.start.unwrap_or_else(|| chrono::Local::now().date_naive() - chrono::Days::new(args.days as u64));
+    let synthetic = Synthetic {
+        profile: args.profile,
+        start,
+        days: args.days,
+        interval: args.interval,
+        daily_kwh: args.daily_kwh.unwrap_or(args.profile.daily_kwh()),
+        solar_kw: args.solar,
+        seed: args.seed,
+    };
+    let (consumption, feedin) = generate(&synthetic)?;
+    write_days(&args.consumption, &consumption)?;
+    if let Some(path) = &args.feedin {
+        write_days(path, &feedin)?;
+    }
+    Ok(())
+}
+
+// Write days as a CSV data file, or to standard output for -
+fn write_days(path: &str, days: &[DayEnergy]) -> Result<()> {
+    match path {
+        STDIN => write_csv_energy(days, std::io::stdout().lock()),
+        path => {
+            let mut csv = Vec::new();
+            write_csv_energy(days, &mut csv)?;
+            write_atomic(path, &csv)
+        },
+    }
+}
+
+// Merge the consumption files and the feedin files, reporting to standard error as the data may go to standard output
+fn merge(args: &MergeArgs) -> Result<()> {
+    let data = &args.data;
+    if !data.feedin.is_empty() && args.feedin_output.is_none() {
+        return Err(anyhow!("merge: give --feedin-output for the merged feedin"));
+    }
+    let warnings = Warnings::default();
+    let outputs = [("Consumption", &data.consumption, Direction::Import, Some(&args.output)), ("Feedin", &data.feedin, Direction::Export, args.feedin_output.as_ref())];
+    for (name, paths, direction, output) in outputs {
+        let Some(output) = output else { continue };
+        let sources = paths.iter()
+            .map(|p| Ok((p.clone(), data.read(p, direction, &warnings, &BytesRead::default())?)))
+            .collect::<Result<Vec<_>>>()?;
+        let (days, w) = merge_exports(sources)?;
+        warnings.extend(w);
+        write_days(output, &days)?;
+        if let (Some(first), Some(last)) = (days.first(), days.last()) {
+            eprintln!("{}: {} days from {} to {}", name, days.len(), first.date, last.date);
+        }
+    }
+    for w in warnings.take() {
+        eprintln!("Warning: {}", w);
+    }
+    Ok(())
+}
+
+// Load each file given, reporting all the failures rather than just the first
+fn validate(args: &ValidateArgs) -> Result<()> {
+    let mut checks: Vec<(&str, Result<String>)> = Vec::new();
+    for t in &args.tariff {
+        let check = load_tariff(t).and_then(|x| match check_tariff_coverage(&x)[..] {
+            [] => Ok(format!("{} tariff rows", x.len())),
+            ref problems => Err(anyhow!("{}", problems.join("; "))),
+        });
+        checks.push((t, check));
+    }
+    for p in &args.plan {
+        let check = load_plan_document(p).and_then(|doc| match check_tariff_coverage(&doc.plan.consumption_tariff)[..] {
+            [] => Ok(format!("plan {} with {} consumption rows and {} fees", doc.plan.name, doc.plan.consumption_tariff.len(), doc.fees.len())),
+            ref problems => Err(anyhow!("{}", problems.join("; "))),
+        });
+        checks.push((p, check));
+    }
+    if let Some(d) = &args.daily {
+        checks.push((d, load_supply_charges(d).map(|(x, changes)| match changes.len() {
+            0 => format!("daily supply charge ${}", x),
+            n => format!("daily supply charge ${} changing {} times", x, n),
+        })));
+    }
+    if let Some(p) = &args.public_holidays {
+        checks.push((p, load_public_holidays(p).map(|x| format!("{} public holidays", x.len()))));
+    }
+    for e in &args.energy {
+        let days = read_energy(e, args.input_format, Direction::Import, &args.csv.layout(), &BytesRead::default())
+            .and_then(|days| days.collect::<Result<Vec<DayEnergy>>>())
+            .map(|days| format!("{} days", days.len()));
+        checks.push((e, days));
+    }
+
+    let mut failures = 0;
+    for (file, check) in checks {
+        match check {
+            Ok(summary) => println!("{}: ok, {}", file, summary),
+            Err(e) => {
+                failures += 1;
+                println!("{}: error, {:#}", file, e);
+            },
+        }
+    }
+    match failures {
+        0 => Ok(()),
+        n => Err(anyhow!("{} files failed validation", n)),
+    }
+}
+
+// The command line with the options of its --config TOML file inserted after the subcommand (the file's `command` if
+// none is given), leaving out those also on the command line so they override the file
+fn config_args(mut args: Vec<String>) -> Result<Vec<String>> {
+    let config = args.iter().enumerate().find_map(|(i, a)| match a.strip_prefix("--config") {
+        Some("") => args.get(i + 1).cloned(),
+        Some(rest) => rest.strip_prefix('=').map(str::to_string),
+        None => None,
+    });
+    let Some(path) = config else {
+        return Ok(args);
+    };
+    let config = load_toml(&path)?;
+    let mut cli = Cli::command();
+    cli.build();
+    let names = cli.get_subcommands().map(|c| c.get_name().to_string()).collect::<Vec<_>>();
+    let position = match (args.iter().position(|a| names.contains(a)), config.get("command").and_then(Toml::as_str)) {
+        (Some(p), _) => p,
+        (None, Some(command)) => {
+            args.push(command.to_string());
+            args.len() - 1
+        },
+        (None, None) => return Ok(args), // clap reports the missing subcommand
+    };
+    let command = cli.find_subcommand(&args[position])
+        .ok_or_else(|| anyhow!("config_args: {}: '{}' is not a subcommand", path, args[position]))?;
+    let given = &args[position + 1..];
+    let mut options = Vec::new();
+    for (key, value) in config.as_table().unwrap_or_default() {
+        match value {
+            Toml::Table(kvs) if key == command.get_name() => {
+                for (k, v) in kvs {
+                    options.extend(config_option(&path, command, given, k, v)?);
+                }
+            },
+            Toml::Table(_) if names.contains(key) => {},
+            _ if key == "command" => {},
+            // options of other subcommands are left out
+            _ if !has_option(command, key) && cli.get_subcommands().any(|c| has_option(c, key)) => {},
+            _ => options.extend(config_option(&path, command, given, key, value)?),
+        }
+    }
+    args.splice(position + 1..position + 1, options);
+    Ok(args)
+}
+
+fn has_option(command: &clap::Command, key: &str) -> bool {
+    command.get_arguments().any(|a| a.get_long() == Some(key.replace('_', "-").as_str()))
+}
+
+// The command line for an option of a config file (none if it's given on the command line): --key value for each of
+// its values, or --key for a flag that's true
+fn config_option(path: &str, command: &clap::Command, given: &[String], key: &str, value: &Toml) -> Result<Vec<String>> {
+    let long = key.replace('_', "-");
+    let arg = command.get_arguments().find(|a| a.get_long() == Some(long.as_str()))
+        .ok_or_else(|| anyhow!("config_args: {}: '{}' is not an option of {}", path, key, command.get_name()))?;
+    let flag = format!("--{}", long);
+    let short = arg.get_short().map(|s| format!("-{}", s));
+    if given.iter().any(|a| *a == flag || a.starts_with(&format!("{}=", flag)) || short.as_ref().is_some_and(|s| a.starts_with(s))) {
+        return Ok(Vec::new());
+    }
+    let values = match value {
+        Toml::Array(xs) => xs.iter().collect::<Vec<_>>(),
+        x => vec![x],
+    };
+    let mut options = Vec::new();
+    for v in values {
+        let v = match v {
+            Toml::String(s) => s.clone(),
+            Toml::Integer(i) => i.to_string(),
+            Toml::Float(x) => x.to_string(),
+            Toml::Bool(b) => b.to_string(),
+            _ => return Err(anyhow!("config_args: {}: '{}' is not a string, number, boolean or array of them", path, key)),
+        };
+        match arg.get_action().takes_values() {
+            true => options.extend([flag.clone(), v]),
+            false if v == "true" => options.push(flag.clone()),
+            false => {},
+        }
+    }
+    Ok(options)
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse_from(config_args(std::env::args().collect())?);
+    logging::init(cli.log_format == LogFormat::Json);
+
+    match &cli.command {
+        Command::Price(args) if args.watch => watch(args)?,
+        Command::Price(args) => {
+            let bill = priced_bill(&args.price)?;
+            output_bill(args, &bill)?;
+            publish_mqtt(args, &bill)?;
+            alert("Bill", bill.payable(), args.alert_over)?
+        },
+        Command::Report(args) => report(args)?,
+        Command::Reconcile(args) => reconcile_bill(args)?,
+        Command::Peaks(args) => peaks(args)?,
+        Command::Stats(args) => stats(args)?,
+        Command::Explain(args) => explain_moment(args)?,
+        Command::Weather(args) => weather(args)?,
+        Command::Validate(args) => validate(args)?,
+        Command::Import(args) => import(args)?,
+        Command::Merge(args) => merge(args)?,
+        Command::Serve(args) => {
+            eprintln!("Listening on http://{}:{} (POST /price, GET /health, GET /metrics)", args.host, args.port);
+            serve(&format!("{}:{}", args.host, args.port), Server::api().with_max_body(args.max_body_mib as usize * 1024 * 1024))?
+        },
+        Command::Compare(args) => compare(args)?,
+        Command::BestPlan(args) => best_plan(args)?,
+        Command::BreakEven(args) => break_even_plans(args)?,
+        Command::Forecast(args) => forecast(args)?,
+        Command::Simulate(Simulation::Battery(args)) => simulate_battery(args)?,
+        Command::Simulate(Simulation::Shift(args)) => simulate_shift(args)?,
+        Command::Simulate(Simulation::Ev(args)) => simulate_ev(args)?,
+        Command::Simulate(Simulation::Solar(args)) => simulate_solar(args)?,
+        Command::Generate(args) => generate_data(args)?,
+    }
+    Ok(())
+}
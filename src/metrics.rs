@@ -0,0 +1,109 @@
+//! Prometheus metrics of the last bill priced by a long running `elec` (`serve` or `price --watch`), in the text
+//! exposition format served at `/metrics`, so an existing Prometheus and Grafana stack can alert on electricity spend.
+
+use crate::bill::Bill;
+use chrono::{Days, NaiveDate};
+use std::sync::{Arc, Mutex};
+
+/// Metrics shared between the thread pricing and the threads serving them.
+pub type SharedMetrics = Arc<Mutex<Metrics>>;
+
+/// The gauges of the last bill priced and counts of the runs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    pub bill_to_date: f64,               // $ of the current billing period (or calendar month), inc GST if applied
+    pub period_start: Option<NaiveDate>, // the first date of the current billing period
+    pub today_cost: f64,                 // $ for today's consumption, feedin and supply (0 without data for today)
+    pub yesterday_kwh: f64,              // kWh consumed yesterday (0 without data for yesterday)
+    pub last_date: Option<NaiveDate>,    // the last date of data
+    pub last_run: i64,                   // when the last run finished (seconds since the epoch), 0 before any
+    pub last_run_ok: bool,               // whether the last run priced a bill
+    pub runs: u64,
+    pub errors: u64,
+}
+
+impl Metrics {
+    /// Record a run at `now` (seconds since the epoch) on the local date `today`: the bill priced, or None if it failed,
+    /// keeping the gauges of the last bill. The bill to date is of the billing period (or calendar month) of the last
+    /// date of data, with GST at the bill's overall rate if applied.
+    pub fn record(&mut self, bill: Option<&Bill>, today: NaiveDate, now: i64) {
+        self.runs += 1;
+        self.last_run = now;
+        self.last_run_ok = bill.is_some();
+        let Some(bill) = bill else {
+            self.errors += 1;
+            return;
+        };
+        let period = bill.cap_periods().last().copied();
+        let gst = bill.gst.filter(|g| g.subtotal != 0.0).map_or(1.0, |g| g.total / g.subtotal);
+        self.bill_to_date = period.map_or(0.0, |(_, _, total)| total * gst);
+        self.period_start = period.map(|(start, ..)| start);
+        self.today_cost = bill.daily.get(&today).map_or(0.0, |t| t.total());
+        self.yesterday_kwh = today.checked_sub_days(Days::new(1)).and_then(|d| bill.daily.get(&d)).map_or(0.0, |t| t.kwh_in);
+        self.last_date = bill.daily.keys().next_back().copied();
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn to_text(&self) -> String {
+        let mut s = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            s += &format!("# HELP elec_{} {}\n# TYPE elec_{} {}\nelec_{} {}\n", name, help, name, kind, name, value);
+        };
+        metric("bill_to_date_dollars", "gauge", "The bill to date of the current billing period (inc GST if applied).", self.bill_to_date.to_string());
+        if let Some(date) = self.period_start {
+            let at = date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc().timestamp();
+            metric("bill_period_start_seconds", "gauge", "The first date of the current billing period (midnight UTC).", at.to_string());
+        }
+        metric("today_cost_dollars", "gauge", "The cost of today's consumption, feedin and supply.", self.today_cost.to_string());
+        metric("yesterday_kwh", "gauge", "The kWh consumed yesterday.", self.yesterday_kwh.to_string());
+        if let Some(date) = self.last_date {
+            let at = date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc().timestamp();
+            metric("last_data_date_seconds", "gauge", "The last date of data (midnight UTC).", at.to_string());
+        }
+        metric("last_run_timestamp_seconds", "gauge", "When the last run finished.", self.last_run.to_string());
+        metric("last_run_success", "gauge", "1 if the last run priced a bill, 0 if it failed.", (self.last_run_ok as u8).to_string());
+        metric("runs_total", "counter", "Runs since starting.", self.runs.to_string());
+        metric("run_errors_total", "counter", "Runs that failed since starting.", self.errors.to_string());
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::energy::{read_csv_energy, DayEnergy};
+    use crate::plan::{price_plan, Plan};
+    use anyhow::Result;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_metrics() -> Result<()> {
+        let plan = Plan::load("TOU", "data/test/tariff/consumption.csv", None, "data/test/tariff/supply.csv", None)?;
+        let bill = price_plan(&plan, read_csv_energy("data/test/energy/consumption.csv")?, None::<Vec<_>>, &HashSet::new())?;
+        let today = NaiveDate::from_ymd_opt(2023, 8, 8).unwrap();
+        let mut metrics = Metrics::default();
+        metrics.record(Some(&bill), today, 1_691_500_000);
+        assert_eq!(metrics.bill_to_date, bill.total());
+        assert_eq!(metrics.period_start, NaiveDate::from_ymd_opt(2023, 8, 1));
+        assert_eq!(metrics.today_cost, bill.daily[&today].total());
+        assert_eq!(metrics.yesterday_kwh, bill.daily[&NaiveDate::from_ymd_opt(2023, 8, 7).unwrap()].kwh_in);
+
+        // a failed run keeps the last bill's gauges
+        metrics.record(None, today, 1_691_500_060);
+        assert_eq!((metrics.bill_to_date, metrics.last_run_ok, metrics.runs, metrics.errors), (bill.total(), false, 2, 1));
+        let text = metrics.to_text();
+        assert!(text.contains("# TYPE elec_bill_to_date_dollars gauge\nelec_bill_to_date_dollars "), "{}", text);
+        assert!(text.contains("\nelec_last_data_date_seconds 1691452800\n"), "{}", text);
+        assert!(text.contains("\nelec_last_run_success 0\n") && text.contains("\nelec_run_errors_total 1\n"), "{}", text);
+        assert!(text.contains("\nelec_bill_period_start_seconds 1690848000\n"), "{}", text);
+
+        // only the month of the last date counts, not July's days
+        let days = read_csv_energy("data/test/energy/consumption.csv")?.collect::<Result<Vec<_>>>()?;
+        let july = days.iter().map(|d| DayEnergy { date: d.date - Days::new(30), ..d.clone() });
+        let bill = price_plan(&plan, july.chain(days.iter().cloned()).map(Ok), None::<Vec<_>>, &HashSet::new())?;
+        metrics.record(Some(&bill), today, 1_691_500_120);
+        let august = bill.daily.range(NaiveDate::from_ymd_opt(2023, 8, 1).unwrap()..).map(|(_, t)| t.total()).sum::<f64>();
+        assert!((metrics.bill_to_date - august).abs() < 1e-9 && metrics.bill_to_date < bill.total(), "{} {}", metrics.bill_to_date, august);
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+//! Exact fixed-point arithmetic for accumulating costs.
+//!
+//! Adding many `f64` costs drifts in the last digits (e.g. $289.17399090000015 rather than $289.1739909),
+//! so costs are summed as whole numbers of nano-dollars. Each cost (kWh × $/kWh) is rounded to the nearest
+//! nano-dollar, which is exact for kWh and rates with up to 9 decimal places between them (e.g. 3 and 6),
+//! and sums are exact however many intervals are added.
+//!
+//! Public results stay `f64`. [`add`] and [`sum`] convert `f64` amounts to nano-dollars, add them exactly and convert
+//! back, which stops the drift of repeated `f64` addition but isn't exact in general: each amount is first rounded to
+//! the nearest nano-dollar, so any further decimal places (e.g. of $1/3) are lost on every call, and only amounts under
+//! about $2 million (2^51 nano-dollars) are sure to convert to `f64` and back unchanged. Where that matters, accumulate
+//! in [`Money`] and convert once at the end.
+
+use anyhow::{anyhow, Result};
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Mul, Neg, Sub};
+use std::str::FromStr;
+
+const NANO: f64 = 1e9; // units per $
+const CENT: i64 = 10_000_000; // units per cent
+
+/// How amounts are rounded to cents, retailers differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    HalfUp,   // halves away from zero
+    HalfEven, // halves to the even cent (banker's rounding)
+    Truncate, // towards zero
+}
+
+impl FromStr for Rounding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Rounding> {
+        match s.trim().to_lowercase().as_str() {
+            "half-up" => Ok(Rounding::HalfUp),
+            "half-even" => Ok(Rounding::HalfEven),
+            "truncate" => Ok(Rounding::Truncate),
+            _ => Err(anyhow!("Rounding::from_str: '{}' is not one of half-up, half-even, truncate", s)),
+        }
+    }
+}
+
+/// An amount of money in whole nano-dollars ($10^-9).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(i64);
+
+impl Money {
+    /// The nearest whole number of nano-dollars to a $ amount.
+    ///
+    /// # Panics
+    ///
+    /// If the amount is infinite or NaN, or beyond about ±$9.2 billion (`i64::MAX` nano-dollars), rather than saturate.
+    pub fn from_f64(dollars: f64) -> Money {
+        let nanos = (dollars * NANO).round();
+        assert!(nanos.is_finite() && nanos.abs() < i64::MAX as f64, "Money::from_f64: ${} is not a finite amount within ±$9.2 billion", dollars);
+        Money(nanos as i64)
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / NANO
+    }
+
+    /// The cost of energy at a rate, rounded to the nearest nano-dollar.
+    pub fn cost(kwh: f64, rate: f64) -> Money {
+        Money::from_f64(kwh * rate)
+    }
+
+    /// The amount rounded to whole cents.
+    pub fn round_cents(self, rounding: Rounding) -> Money {
+        let (cents, rest) = (self.0 / CENT, (self.0 % CENT).abs()); // both towards zero
+        let away = match rounding {
+            Rounding::HalfUp => 2 * rest >= CENT,
+            Rounding::HalfEven => 2 * rest > CENT || (2 * rest == CENT && cents % 2 != 0),
+            Rounding::Truncate => false,
+        };
+        Money((cents + if away { self.0.signum() } else { 0 }) * CENT)
+    }
+}
+
+/// Round a $ amount to whole cents.
+pub fn round_cents(dollars: f64, rounding: Rounding) -> f64 {
+    Money::from_f64(dollars).round_cents(rounding).to_f64()
+}
+
+impl Add for Money {
+    type Output = Money;
+    fn add(self, other: Money) -> Money { Money(self.0 + other.0) }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, other: Money) { self.0 += other.0 }
+}
+
+impl Sub for Money {
+    type Output = Money;
+    fn sub(self, other: Money) -> Money { Money(self.0 - other.0) }
+}
+
+impl Neg for Money {
+    type Output = Money;
+    fn neg(self) -> Money { Money(-self.0) }
+}
+
+impl Mul<usize> for Money {
+    type Output = Money;
+    fn mul(self, n: usize) -> Money { Money(self.0 * n as i64) }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money { iter.fold(Money::default(), Add::add) }
+}
+
+/// Add two $ amounts exactly.
+pub fn add(a: f64, b: f64) -> f64 {
+    (Money::from_f64(a) + Money::from_f64(b)).to_f64()
+}
+
+/// Sum $ amounts exactly, 0 (not -0.0) for none.
+pub fn sum<I: IntoIterator<Item = f64>>(amounts: I) -> f64 {
+    amounts.into_iter().map(Money::from_f64).sum::<Money>().to_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_money() {
+        let drift = (0..1000).fold(0.0, |sum, _| sum + 0.1 * 0.3553);
+        assert_ne!(drift, 35.53);
+        assert_eq!((0..1000).map(|_| Money::cost(0.1, 0.3553)).sum::<Money>().to_f64(), 35.53);
+        assert_eq!(sum([0.1, 0.2]), 0.3);
+        assert_eq!(sum([]).to_string(), "0");
+        assert_eq!(add(-0.022820000000000003, 0.01460), -0.00822);
+        assert_eq!((Money::from_f64(1.45398) * 3).to_f64(), 4.36194);
+    }
+
+    #[test]
+    fn test_round_cents() -> Result<()> {
+        let round = |x: f64, r: &str| -> Result<Vec<f64>> { Ok(vec![round_cents(x, r.parse()?), round_cents(-x, r.parse()?)]) };
+        assert_eq!(round(1.005, "half-up")?, vec![1.01, -1.01]);
+        assert_eq!(round(1.005, "half-even")?, vec![1.0, -1.0]);
+        assert_eq!(round(1.015, "half-even")?, vec![1.02, -1.02]);
+        assert_eq!(round(1.0151, "half-even")?, vec![1.02, -1.02]);
+        assert_eq!(round(1.0199, "truncate")?, vec![1.01, -1.01]);
+        assert_eq!(round(289.1739909, "half-up")?, vec![289.17, -289.17]);
+
+        // a daily supply charge of $1.785 for 3 days is $5.355, halfway between cents, though the f64 product is below
+        let supply = 1.785 * 3.0;
+        assert_eq!(supply, 5.3549999999999995);
+        assert_eq!((Money::from_f64(1.785) * 3).to_f64(), 5.355);
+        assert_eq!(round(supply, "half-up")?, vec![5.36, -5.36]);
+        assert_eq!(round(supply, "half-even")?, vec![5.36, -5.36]);
+        assert_eq!(round(supply, "truncate")?, vec![5.35, -5.35]);
+        // a quarterly bill summed from interval costs, and a credit, as added up in a bill
+        assert_eq!(round(sum([412.3149999, 0.0000001, 87.5]), "half-up")?, vec![499.82, -499.82]);
+        assert_eq!(round(sum([412.3149999, 0.0000001, 87.5]), "half-even")?, vec![499.82, -499.82]);
+        assert_eq!(round(sum([-31.2650000, 0.0]), "half-even")?, vec![-31.26, 31.26]);
+        // a nano-dollar below half a cent rounds down, but less than half a nano-dollar below is a half once in Money
+        assert_eq!(round(0.004999999, "half-up")?, vec![0.0, 0.0]);
+        assert_eq!(round(0.0049999999, "half-up")?, vec![0.01, -0.01]);
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "not a finite amount")]
+    fn test_from_f64_nan() {
+        Money::from_f64(f64::NAN);
+    }
+
+    #[test]
+    #[should_panic(expected = "not a finite amount")]
+    fn test_from_f64_huge() {
+        Money::from_f64(1e10);
+    }
+}
@@ -0,0 +1,228 @@
+//! A minimal MQTT 3.1.1 client publishing a bill's running totals and daily costs to a broker with Home Assistant
+//! MQTT discovery, so the bill to date appears as sensors on a home dashboard.
+//!
+//! Only what publishing needs is implemented: CONNECT (with an optional username and password), PUBLISH at QoS 0 and
+//! DISCONNECT. Messages are retained, so Home Assistant sees the sensors and their last values after it restarts.
+
+use crate::bill::{Bill, Totals};
+use crate::json::Json;
+use anyhow::{anyhow, Context, Result};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// The broker's port unless another is given.
+pub const DEFAULT_PORT: u16 = 1883;
+
+/// The topic prefix Home Assistant subscribes to for discovery by default.
+pub const DISCOVERY_PREFIX: &str = "homeassistant";
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// The fixed header's remaining length: 7 bits a byte, least significant first, the top bit set if more follow
+fn remaining_length(mut len: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        bytes.push(if len > 0 { byte | 0x80 } else { byte });
+        if len == 0 {
+            return bytes;
+        }
+    }
+}
+
+// A string prefixed by its length (big endian u16)
+fn string(s: &str, packet: &mut Vec<u8>) {
+    packet.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    packet.extend_from_slice(s.as_bytes());
+}
+
+// A packet of a type (with flags) and its variable header and payload
+fn packet(first: u8, body: Vec<u8>) -> Vec<u8> {
+    let mut packet = vec![first];
+    packet.extend(remaining_length(body.len()));
+    packet.extend(body);
+    packet
+}
+
+/// A CONNECT packet with a clean session and no keep alive.
+pub fn connect_packet(client_id: &str, username: Option<&str>, password: Option<&str>) -> Vec<u8> {
+    let mut body = Vec::new();
+    string("MQTT", &mut body);
+    body.push(4); // protocol level 3.1.1
+    body.push(0x02 | username.map_or(0, |_| 0x80) | password.map_or(0, |_| 0x40));
+    body.extend_from_slice(&0u16.to_be_bytes());
+    for s in [Some(client_id), username, password].into_iter().flatten() {
+        string(s, &mut body);
+    }
+    packet(0x10, body)
+}
+
+/// A retained PUBLISH packet at QoS 0.
+pub fn publish_packet(topic: &str, payload: &str) -> Vec<u8> {
+    let mut body = Vec::new();
+    string(topic, &mut body);
+    body.extend_from_slice(payload.as_bytes());
+    packet(0x31, body)
+}
+
+/// A connection to an MQTT broker.
+pub struct Mqtt {
+    stream: TcpStream,
+}
+
+impl Mqtt {
+    /// Connect to a broker at `host` or `host:port` and wait for it to accept the connection.
+    pub fn connect(address: &str, client_id: &str, username: Option<&str>, password: Option<&str>) -> Result<Mqtt> {
+        let address = match address.rsplit_once(':') {
+            Some((_, port)) if port.parse::<u16>().is_ok() => address.to_string(),
+            _ => format!("{}:{}", address, DEFAULT_PORT),
+        };
+        let mut stream = TcpStream::connect(&address).with_context(|| format!("Mqtt::connect: {}", address))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream.write_all(&connect_packet(client_id, username, password))?;
+        let mut connack = [0u8; 4];
+        stream.read_exact(&mut connack).with_context(|| format!("Mqtt::connect: no CONNACK from {}", address))?;
+        match connack {
+            [0x20, 2, _, 0] => Ok(Mqtt { stream }),
+            [0x20, 2, _, 4 | 5] => Err(anyhow!("Mqtt::connect: {} refused the username or password", address)),
+            [0x20, 2, _, code] => Err(anyhow!("Mqtt::connect: {} refused the connection with code {}", address, code)),
+            _ => Err(anyhow!("Mqtt::connect: {} didn't reply with a CONNACK", address)),
+        }
+    }
+
+    /// Publish a retained message.
+    pub fn publish(&mut self, topic: &str, payload: &str) -> Result<()> {
+        self.stream.write_all(&publish_packet(topic, payload)).with_context(|| format!("Mqtt::publish: {}", topic))
+    }
+
+    /// Disconnect cleanly, so the broker doesn't drop the messages sent.
+    pub fn disconnect(mut self) -> Result<()> {
+        self.stream.write_all(&[0xe0, 0])?;
+        self.stream.flush()?;
+        Ok(())
+    }
+}
+
+/// A Home Assistant sensor for a bill.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sensor {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub value: f64,
+}
+
+/// The sensors for a bill: the bill to date (inc GST if applied), its consumption, feedin and supply charges, the kWh
+/// consumed and fed in, and the cost of the last day of data.
+pub fn bill_sensors(bill: &Bill) -> Vec<Sensor> {
+    let mut totals = Totals::default();
+    bill.daily.values().for_each(|t| totals.add(t));
+    let sensor = |id, name, unit, value| Sensor { id, name, unit, value };
+    vec![
+        sensor("bill_to_date", "Bill to date", "AUD", bill.payable()),
+        sensor("consumption", "Consumption charges", "AUD", bill.consumption),
+        sensor("feedin", "Feed-in credit", "AUD", bill.feedin),
+        sensor("supply", "Supply charges", "AUD", bill.supply),
+        sensor("kwh_in", "Energy consumed", "kWh", totals.kwh_in),
+        sensor("kwh_out", "Energy fed in", "kWh", totals.kwh_out),
+        sensor("daily_cost", "Daily cost", "AUD", bill.daily.values().next_back().map_or(0.0, |t| t.total())),
+    ]
+}
+
+/// The messages (topic and payload) publishing a bill: a Home Assistant discovery config under `discovery` and a
+/// state under `prefix` for each of the [`bill_sensors`], and the cost of each day as attributes of the daily cost.
+pub fn bill_messages(bill: &Bill, prefix: &str, discovery: &str) -> Vec<(String, String)> {
+    let device = Json::object(vec![
+        ("identifiers", Json::Array(vec![prefix.into()])),
+        ("name", format!("{} bill", prefix).into()),
+    ]);
+    let attributes = format!("{}/daily_cost/attributes", prefix);
+    let mut messages = Vec::new();
+    for s in bill_sensors(bill) {
+        let state = format!("{}/{}", prefix, s.id);
+        let mut config = vec![
+            ("name", s.name.into()),
+            ("unique_id", format!("{}_{}", prefix, s.id).into()),
+            ("state_topic", state.as_str().into()),
+            ("unit_of_measurement", s.unit.into()),
+            ("device_class", if s.unit == "kWh" { "energy" } else { "monetary" }.into()),
+            ("device", device.clone()),
+        ];
+        if s.id == "daily_cost" {
+            config.push(("json_attributes_topic", attributes.as_str().into()));
+        }
+        messages.push((format!("{}/sensor/{}/{}/config", discovery, prefix, s.id), Json::object(config).to_string()));
+        messages.push((state, if s.unit == "kWh" { format!("{:.3}", s.value) } else { format!("{:.2}", s.value) }));
+    }
+    let daily = bill.daily.iter().map(|(date, t)| (date.to_string(), ((t.total() * 100.0).round() / 100.0).into())).collect::<Vec<_>>();
+    let last = bill.daily.keys().next_back().map_or(Json::Null, |d| d.to_string().into());
+    messages.push((attributes, Json::object(vec![("date", last), ("daily", Json::object(daily))]).to_string()));
+    messages
+}
+
+/// Publish a bill's [`bill_messages`] to a broker at `host` or `host:port`.
+pub fn publish_bill(bill: &Bill, address: &str, username: Option<&str>, password: Option<&str>, prefix: &str) -> Result<()> {
+    let mut mqtt = Mqtt::connect(address, prefix, username, password)?;
+    for (topic, payload) in bill_messages(bill, prefix, DISCOVERY_PREFIX) {
+        mqtt.publish(&topic, &payload)?;
+    }
+    mqtt.disconnect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::energy::read_csv_energy;
+    use crate::plan::{price_plan, Plan};
+    use std::collections::HashSet;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_packets() {
+        assert_eq!(remaining_length(0), vec![0]);
+        assert_eq!(remaining_length(127), vec![127]);
+        assert_eq!(remaining_length(128), vec![0x80, 1]);
+        assert_eq!(remaining_length(16_383), vec![0xff, 0x7f]);
+        assert_eq!(remaining_length(2_097_152), vec![0x80, 0x80, 0x80, 1]);
+        assert_eq!(connect_packet("elec", None, None), b"\x10\x10\x00\x04MQTT\x04\x02\x00\x00\x00\x04elec".to_vec());
+        assert_eq!(connect_packet("e", Some("u"), Some("p"))[9], 0xc2);
+        assert_eq!(publish_packet("a/b", "1.5"), b"\x31\x08\x00\x03a/b1.5".to_vec());
+    }
+
+    #[test]
+    fn test_publish_bill() -> Result<()> {
+        let plan = Plan::load("TOU", "data/test/tariff/consumption.csv", Some("data/test/tariff/feedIn.csv"), "data/test/tariff/supply.csv", None)?;
+        let consumption = read_csv_energy("data/test/energy/consumption.csv")?;
+        let feedin = read_csv_energy("data/test/energy/feedIn.csv")?;
+        let bill = price_plan(&plan, consumption, Some(feedin), &HashSet::new())?;
+
+        let messages = bill_messages(&bill, "elec", DISCOVERY_PREFIX);
+        assert_eq!(messages.len(), 15);
+        assert_eq!(messages[0].0, "homeassistant/sensor/elec/bill_to_date/config");
+        assert!(messages[0].1.contains(r#""state_topic":"elec/bill_to_date""#), "{}", messages[0].1);
+        assert_eq!(messages[1], ("elec/bill_to_date".to_string(), format!("{:.2}", bill.total())));
+        assert_eq!(messages[14].0, "elec/daily_cost/attributes");
+        assert!(messages[14].1.contains(r#""date":"2023-08-08""#), "{}", messages[14].1);
+
+        // a broker accepting the connection and reading the messages until DISCONNECT
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?.to_string();
+        let broker = std::thread::spawn(move || -> Result<Vec<u8>> {
+            let (mut stream, _) = listener.accept()?;
+            let mut connect = [0u8; 18];
+            stream.read_exact(&mut connect)?;
+            stream.write_all(&[0x20, 2, 0, 0])?;
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received)?;
+            Ok(received)
+        });
+        publish_bill(&bill, &address, None, None, "elec")?;
+        let received = broker.join().map_err(|_| anyhow!("broker panicked"))??;
+        let expected = messages.iter().flat_map(|(t, p)| publish_packet(t, p)).chain([0xe0, 0]).collect::<Vec<_>>();
+        assert_eq!(received, expected);
+        Ok(())
+    }
+}
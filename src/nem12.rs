@@ -0,0 +1,164 @@
+//! Reader for NEM12 interval meter data files.
+//!
+//! NEM12 is the format specified by the AEMO Meter Data File Format (MDFF) for interval data.
+//! A file is a sequence of CSV records identified by the first field:
+//! - 100 header, 900 end of data
+//! - 200 NMI data details: a channel, identified by its NMI suffix (E1 import, B1 export, ...),
+//!   with its unit of measure and interval length
+//! - 300 interval data: one day of readings for the current channel, followed by the QualityMethod
+//! - 400 interval event: the quality of a range of intervals when the 300 record's QualityMethod is `V` (variable)
+//! - 500 B2B details (ignored)
+//!
+//! Readings for all selected channels are summed by date into [`DayEnergy`] in kWh.
+
+use crate::energy::{open_input, DayEnergy, Direction, Quality};
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+use csv::{ReaderBuilder, StringRecord};
+use log::{debug, info};
+use std::collections::BTreeMap;
+use std::io;
+
+// Whether a channel's NMI suffix is for the direction: E for import, B for export
+fn matches(direction: Direction, nmi_suffix: &str) -> bool {
+    match direction {
+        Direction::Import => nmi_suffix.starts_with('E'),
+        Direction::Export => nmi_suffix.starts_with('B'),
+    }
+}
+
+// Multiplier to convert a NEM12 unit of measure to kWh, None for units that aren't energy (e.g. kVArh)
+fn kwh_multiplier(uom: &str) -> Option<f64> {
+    match uom.trim().to_uppercase().as_str() {
+        "WH" => Some(0.001),
+        "KWH" => Some(1.0),
+        "MWH" => Some(1000.0),
+        _ => None,
+    }
+}
+
+// The current 200 record
+struct Channel {
+    nmi_suffix: String,
+    multiplier: f64,
+    interval: usize,
+}
+
+fn field(r: &StringRecord, i: usize) -> Result<&str> {
+    r.get(i).with_context(|| format!("read_nem12: record {:?} missing field {}", r, i))
+}
+
+/// Read the channels matching `direction` from a NEM12 file (or standard input for [`crate::energy::STDIN`]),
+/// summing channels by date.
+pub fn read_nem12(nem12: &str, direction: Direction) -> Result<Vec<DayEnergy>> {
+    info!("read_nem12: loading NEM12 file {}, {:?}", nem12, direction);
+    read_nem12_from(nem12, open_input(nem12)?, direction)
+}
+
+/// Read a NEM12 file like [`read_nem12`] from any reader (e.g. a byte slice), `nem12` naming it in errors.
+pub fn read_nem12_from<R: io::Read>(nem12: &str, input: R, direction: Direction) -> Result<Vec<DayEnergy>> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(input);
+
+    let mut days: BTreeMap<NaiveDate, DayEnergy> = BTreeMap::new();
+    let mut channel: Option<Channel> = None; // None when no 200 record yet or it's not selected
+    let mut last_date: Option<NaiveDate> = None; // date of the last 300 record, for 400 records
+    for (line_no, record) in reader.records().enumerate() {
+        let r = record?;
+        debug!("read_nem12: record: {:?}", r);
+        match field(&r, 0)?.trim() {
+            "100" => {
+                if field(&r, 1)?.trim() != "NEM12" {
+                    return Err(anyhow!("read_nem12: '{}' is not a NEM12 file, header {:?}", nem12, r));
+                }
+            },
+            "200" => {
+                let nmi_suffix = field(&r, 4)?.trim().to_string();
+                let uom = field(&r, 7)?;
+                channel = match (matches(direction, &nmi_suffix), kwh_multiplier(uom)) {
+                    (true, Some(multiplier)) => {
+                        let interval = field(&r, 8)?.trim().parse::<usize>()?;
+                        if interval == 0 || (24 * 60) % interval != 0 {
+                            return Err(anyhow!("read_nem12: invalid interval length {} on line {}", interval, line_no + 1));
+                        }
+                        Some(Channel { nmi_suffix, multiplier, interval })
+                    },
+                    _ => {
+                        debug!("read_nem12: skipping channel {} uom {}", nmi_suffix, uom);
+                        None
+                    },
+                };
+                last_date = None;
+            },
+            "300" => {
+                let Some(c) = &channel else { continue };
+                let num_intervals = (24 * 60) / c.interval;
+                let date = NaiveDate::parse_from_str(field(&r, 1)?.trim(), "%Y%m%d")?;
+                let energy = (0 .. num_intervals)
+                    .map(|i| Ok(field(&r, 2 + i)?.trim().parse::<f64>()? * c.multiplier))
+                    .collect::<Result<Vec<f64>>>()?;
+                let flag = field(&r, 2 + num_intervals)?;
+                let quality = match flag.trim() {
+                    "V" => Quality::Actual, // refined by the following 400 records
+                    f => Quality::from_flag(f)?,
+                };
+                debug!("read_nem12: channel {}, date {}, quality {:?}", c.nmi_suffix, date, quality);
+                let day = DayEnergy { date, interval: c.interval, energy, quality: vec![quality; num_intervals] };
+                add_day(&mut days, day)?;
+                last_date = Some(date);
+            },
+            "400" => {
+                let (Some(c), Some(date)) = (&channel, last_date) else { continue };
+                let start = field(&r, 1)?.trim().parse::<usize>()?; // 1-based, inclusive
+                let end = field(&r, 2)?.trim().parse::<usize>()?;
+                let quality = Quality::from_flag(field(&r, 3)?)?;
+                let day = days.get_mut(&date).context("read_nem12: 400 record without 300 record")?;
+                if start < 1 || end < start || end > day.quality.len() {
+                    return Err(anyhow!("read_nem12: channel {} invalid interval range {}-{} on line {}", c.nmi_suffix, start, end, line_no + 1));
+                }
+                // when channels are summed keep the worst quality
+                day.quality[start - 1 .. end].iter_mut().filter(|q| **q == Quality::Actual).for_each(|q| *q = quality);
+            },
+            "500" | "900" => {},
+            other => return Err(anyhow!("read_nem12: unknown record indicator {} on line {}", other, line_no + 1)),
+        }
+    }
+
+    let not_actual: usize = days.values().map(|d| d.quality.iter().filter(|q| **q != Quality::Actual).count()).sum();
+    info!("read_nem12: {} days, {} intervals not of actual quality", days.len(), not_actual);
+    Ok(days.into_values().collect())
+}
+
+// Add a channel's day into the days read so far, summing with other channels of the same date
+fn add_day(days: &mut BTreeMap<NaiveDate, DayEnergy>, day: DayEnergy) -> Result<()> {
+    match days.get_mut(&day.date) {
+        None => { days.insert(day.date, day); },
+        Some(d) => {
+            if d.interval != day.interval {
+                return Err(anyhow!("read_nem12: channels for {} have different interval lengths {} and {}", day.date, d.interval, day.interval));
+            }
+            d.energy.iter_mut().zip(day.energy).for_each(|(a, b)| *a += b);
+            d.quality.iter_mut().zip(day.quality).filter(|(a, _)| **a == Quality::Actual).for_each(|(a, b)| *a = b);
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::energy::assert_test_energy;
+
+    #[test]
+    // same energy as data/test/energy/*.csv at 30 minute intervals
+    fn test_read_nem12() -> Result<()> {
+        assert_test_energy(|direction| read_nem12("data/test/energy/nem12.csv", direction))?;
+        let consumption = read_nem12("data/test/energy/nem12.csv", Direction::Import)?;
+        assert_eq!(consumption[0].interval, 30);
+        assert_eq!(consumption[1].quality[15], Quality::Actual);
+        assert_eq!(consumption[1].quality[16], Quality::Estimated);
+        Ok(())
+    }
+}
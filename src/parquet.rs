@@ -0,0 +1,453 @@
+//! Reader for interval energy data in Apache Parquet files.
+//!
+//! The file is a table with a row per interval reading:
+//! - a timestamp column (INT64 annotated as a millisecond, microsecond or nanosecond TIMESTAMP): the local start
+//!   time of the interval, as written for timezone-naive date times
+//! - the kWh column (DOUBLE, FLOAT, INT32 or INT64): `consumption` or `feedin` for the direction read, or `kwh`
+//! - an optional `quality` column (string): a NEM12 quality flag (e.g. `A`, `E52`), actual if absent or null
+//!
+//! The interval length is the smallest gap between readings. Readings are mapped to local dates into
+//! [`DayEnergy`], intervals of a day without a reading (or with a null kWh) are zero with [`Quality::Null`].
+//!
+//! Only flat schemas are supported, with PLAIN or dictionary encoded values in v1 or v2 data pages that are
+//! uncompressed, Snappy or gzip compressed. File metadata is Thrift compact protocol encoded.
+
+use crate::compress::{gunzip, snappy};
+use crate::energy::{open_input, readings_days, DayEnergy, Direction, Quality};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, NaiveDateTime};
+use log::{debug, info};
+use std::collections::BTreeMap;
+use std::io::Read;
+
+pub(crate) const MAGIC: &[u8] = b"PAR1";
+
+// A Thrift compact protocol value, structs are keyed by field id
+#[derive(Debug, Clone, PartialEq)]
+enum Thrift {
+    Bool(bool),
+    Int(i64),
+    Double(f64),
+    Binary(Vec<u8>),
+    List(Vec<Thrift>),
+    Map(Vec<(Thrift, Thrift)>),
+    Struct(BTreeMap<i16, Thrift>),
+}
+
+impl Thrift {
+    fn field(&self, id: i16) -> Option<&Thrift> {
+        match self {
+            Thrift::Struct(fields) => fields.get(&id),
+            _ => None,
+        }
+    }
+
+    fn int(&self, id: i16) -> Option<i64> {
+        match self.field(id) {
+            Some(Thrift::Int(n)) => Some(*n),
+            _ => None,
+        }
+    }
+
+    fn list(&self, id: i16) -> &[Thrift] {
+        match self.field(id) {
+            Some(Thrift::List(items)) => items,
+            _ => &[],
+        }
+    }
+
+    fn string(&self, id: i16) -> Option<String> {
+        match self.field(id) {
+            Some(Thrift::Binary(b)) => Some(String::from_utf8_lossy(b).to_string()),
+            _ => None,
+        }
+    }
+}
+
+// Deepest nesting of Thrift structs, lists and maps, well beyond Parquet's metadata, so a corrupt file can't overflow
+// the stack
+const MAX_DEPTH: usize = 64;
+
+// Decoder for the Thrift compact protocol
+struct Compact<'a> {
+    data: &'a [u8],
+    at: usize,
+    depth: usize, // structs, lists and maps the decoder is in
+}
+
+impl<'a> Compact<'a> {
+    fn new(data: &'a [u8], at: usize) -> Compact<'a> {
+        Compact { data, at, depth: 0 }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        let bytes = self.at.checked_add(n).and_then(|end| self.data.get(self.at..end))
+            .ok_or_else(|| anyhow!("Compact::bytes: truncated data reading {} bytes at {}", n, self.at))?;
+        self.at += n;
+        Ok(bytes)
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut n = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.bytes(1)?[0];
+            n |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(anyhow!("Compact::varint: too long at {}", self.at))
+    }
+
+    fn zigzag(&mut self) -> Result<i64> {
+        let n = self.varint()?;
+        Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+
+    // A value of a type, at most MAX_DEPTH deep
+    fn value(&mut self, kind: u8) -> Result<Thrift> {
+        if self.depth == MAX_DEPTH {
+            return Err(anyhow!("Compact::value: nested more than {} deep at {}", MAX_DEPTH, self.at));
+        }
+        self.depth += 1;
+        let value = self.decode(kind);
+        self.depth -= 1;
+        value
+    }
+
+    fn decode(&mut self, kind: u8) -> Result<Thrift> {
+        Ok(match kind {
+            1 => Thrift::Bool(true), // in a struct field header
+            2 => Thrift::Bool(false),
+            3 => Thrift::Int(self.bytes(1)?[0] as i8 as i64),
+            4..=6 => Thrift::Int(self.zigzag()?),
+            7 => Thrift::Double(f64::from_le_bytes(self.bytes(8)?.try_into()?)),
+            8 => {
+                let len = self.varint()? as usize;
+                Thrift::Binary(self.bytes(len)?.to_vec())
+            },
+            9 | 10 => {
+                let header = self.bytes(1)?[0];
+                let (size, kind) = match header >> 4 {
+                    15 => (self.varint()? as usize, header & 0x0f),
+                    size => (size as usize, header & 0x0f),
+                };
+                Thrift::List((0..size).map(|_| match kind {
+                    1 | 2 => Ok(Thrift::Bool(self.bytes(1)?[0] == 1)), // bools in a list are a byte each
+                    _ => self.value(kind),
+                }).collect::<Result<_>>()?)
+            },
+            11 => {
+                let size = self.varint()? as usize;
+                let kinds = if size > 0 { self.bytes(1)?[0] } else { 0 };
+                Thrift::Map((0..size).map(|_| Ok((self.value(kinds >> 4)?, self.value(kinds & 0x0f)?))).collect::<Result<_>>()?)
+            },
+            12 => {
+                let mut fields = BTreeMap::new();
+                let mut id: i16 = 0;
+                loop {
+                    let header = self.bytes(1)?[0];
+                    if header == 0 {
+                        break Thrift::Struct(fields);
+                    }
+                    id = match header >> 4 {
+                        0 => self.zigzag()? as i16,
+                        delta => id + delta as i16,
+                    };
+                    fields.insert(id, self.value(header & 0x0f)?);
+                }
+            },
+            k => return Err(anyhow!("Compact::value: unknown type {} at {}", k, self.at)),
+        })
+    }
+}
+
+// A leaf column of the schema
+#[derive(Debug)]
+struct Column {
+    name: String,
+    physical: i64, // 1 INT32, 2 INT64, 4 FLOAT, 5 DOUBLE, 6 BYTE_ARRAY (others unsupported)
+    optional: bool,
+    nanos: Option<i64>, // nanoseconds per unit of a TIMESTAMP
+}
+
+fn column(element: &Thrift) -> Result<Column> {
+    let name = element.string(4).unwrap_or_default();
+    if element.int(5).unwrap_or(0) > 0 || element.int(3) == Some(2) {
+        return Err(anyhow!("column: {} is nested or repeated, only flat schemas are supported", name));
+    }
+    let logical_unit = element.field(10).and_then(|l| l.field(8)).and_then(|t| t.field(2)).and_then(|unit| match unit {
+        Thrift::Struct(fields) => fields.keys().next().copied(),
+        _ => None,
+    });
+    let nanos = match (logical_unit, element.int(6)) {
+        (Some(1), _) | (None, Some(9)) => Some(1_000_000), // MILLIS, TIMESTAMP_MILLIS
+        (Some(2), _) | (None, Some(10)) => Some(1_000),    // MICROS, TIMESTAMP_MICROS
+        (Some(3), _) => Some(1),                           // NANOS
+        _ => None,
+    };
+    Ok(Column { name, physical: element.int(1).unwrap_or(-1), optional: element.int(3) == Some(1), nanos })
+}
+
+// A decoded value
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Int(i64),
+    Double(f64),
+    Bytes(Vec<u8>),
+}
+
+// Decode `count` PLAIN encoded values
+fn plain(data: &[u8], physical: i64, count: usize) -> Result<Vec<Value>> {
+    let mut at: usize = 0;
+    let mut take = |n: usize| -> Result<&[u8]> {
+        let bytes = at.checked_add(n).and_then(|end| data.get(at..end)).ok_or_else(|| anyhow!("plain: truncated data reading {} bytes at {}", n, at))?;
+        at += n;
+        Ok(bytes)
+    };
+    let mut values = Vec::new(); // grown as values are read, as the count may be corrupt
+    for _ in 0..count {
+        values.push(match physical {
+            1 => Value::Int(i32::from_le_bytes(take(4)?.try_into()?) as i64),
+            2 => Value::Int(i64::from_le_bytes(take(8)?.try_into()?)),
+            4 => Value::Double(f32::from_le_bytes(take(4)?.try_into()?) as f64),
+            5 => Value::Double(f64::from_le_bytes(take(8)?.try_into()?)),
+            6 => {
+                let len = u32::from_le_bytes(take(4)?.try_into()?) as usize;
+                Value::Bytes(take(len)?.to_vec())
+            },
+            t => return Err(anyhow!("plain: physical type {} is not supported", t)),
+        });
+    }
+    Ok(values)
+}
+
+// Decode `count` values of the RLE/bit-packing hybrid encoding (used for levels and dictionary indices)
+fn rle_hybrid(data: &[u8], bit_width: usize, count: usize) -> Result<Vec<u32>> {
+    if bit_width > 32 {
+        return Err(anyhow!("rle_hybrid: bit width {} is more than 32", bit_width));
+    }
+    let mut compact = Compact::new(data, 0);
+    let mut out = Vec::new();
+    while out.len() < count {
+        let header = usize::try_from(compact.varint()?)?;
+        if header & 1 == 0 { // a run of one value
+            let bytes = compact.bytes(bit_width.div_ceil(8))?;
+            let value = bytes.iter().rev().fold(0, |n, b| (n << 8) | *b as u32);
+            out.extend(std::iter::repeat_n(value, (header >> 1).min(count - out.len())));
+        } else { // groups of 8 bit-packed values, least significant bit first
+            let bytes = compact.bytes((header >> 1).checked_mul(bit_width).context("rle_hybrid: bit-packed run too long")?)?;
+            let values = (header >> 1).saturating_mul(8).min(count - out.len()); // a width of 0 reads no bytes
+            out.extend((0..values).map(|i| {
+                (0..bit_width).fold(0, |n, b| {
+                    let bit = i * bit_width + b;
+                    n | (((bytes[bit / 8] >> (bit % 8)) & 1) as u32) << b
+                })
+            }));
+        }
+    }
+    out.truncate(count);
+    Ok(out)
+}
+
+fn uncompress(codec: i64, data: &[u8], size: usize) -> Result<Vec<u8>> {
+    let out = match codec {
+        0 => data.to_vec(),
+        1 => snappy(data)?,
+        2 => gunzip(data)?,
+        c => return Err(anyhow!("uncompress: codec {} is not supported, only uncompressed, Snappy and gzip", c)),
+    };
+    if out.len() != size {
+        return Err(anyhow!("uncompress: {} bytes but the page header says {}", out.len(), size));
+    }
+    Ok(out)
+}
+
+// A count, size or offset of the metadata (0 if absent), which mustn't be negative
+fn non_negative(n: Option<i64>, what: &str) -> Result<usize> {
+    let n = n.unwrap_or(0);
+    usize::try_from(n).map_err(|_| anyhow!("column_chunk: {} {} is negative", what, n))
+}
+
+// Decode the values (None for null) of a column chunk
+fn column_chunk(data: &[u8], chunk: &Thrift, column: &Column) -> Result<Vec<Option<Value>>> {
+    let meta = chunk.field(3).context("column_chunk: no column metadata")?;
+    let codec = meta.int(4).unwrap_or(0);
+    let num_values = non_negative(meta.int(5), "number of values")?;
+    let data_offset = meta.int(9).context("column_chunk: no data page offset")?;
+    let mut at = non_negative(Some(meta.int(11).filter(|d| *d > 0 && *d < data_offset).unwrap_or(data_offset)), "page offset")?;
+    let mut dictionary = Vec::new();
+    let mut values = Vec::with_capacity(num_values.min(data.len()));
+    while values.len() < num_values {
+        let mut compact = Compact::new(data, at);
+        let header = compact.value(12)?;
+        let (kind, size) = (header.int(1).unwrap_or(-1), non_negative(header.int(2), "page size")?);
+        let compressed = compact.bytes(non_negative(header.int(3), "compressed page size")?)?;
+        at = compact.at;
+        debug!("column_chunk: {} page type {}, {} bytes", column.name, kind, compressed.len());
+        let (count, encoding, defined, page) = match kind {
+            2 => {
+                let page = uncompress(codec, compressed, size)?;
+                dictionary = plain(&page, column.physical, non_negative(header.field(7).and_then(|d| d.int(1)), "dictionary size")?)?;
+                continue;
+            },
+            0 => {
+                let page = uncompress(codec, compressed, size)?;
+                let h = header.field(5).context("column_chunk: no data page header")?;
+                let count = non_negative(h.int(1), "number of values")?;
+                let (defined, start) = if column.optional {
+                    let len = u32::from_le_bytes(page.get(0..4).context("column_chunk: truncated levels")?.try_into()?) as usize;
+                    (rle_hybrid(&page[4..], 1, count)?, 4 + len)
+                } else {
+                    (vec![1; count], 0)
+                };
+                (count, h.int(2).unwrap_or(0), defined, page.get(start..).context("column_chunk: truncated levels")?.to_vec())
+            },
+            3 => {
+                let h = header.field(8).context("column_chunk: no data page v2 header")?;
+                let count = non_negative(h.int(1), "number of values")?;
+                let (rep_len, def_len) = (non_negative(h.int(6), "repetition levels size")?, non_negative(h.int(5), "definition levels size")?);
+                let levels_len = rep_len.checked_add(def_len).context("column_chunk: levels too long")?;
+                let levels = compressed.get(..levels_len).context("column_chunk: truncated levels")?;
+                let defined = if column.optional { rle_hybrid(&levels[rep_len..], 1, count)? } else { vec![1; count] };
+                let values = &compressed[levels_len..];
+                let page = match h.field(7) {
+                    Some(Thrift::Bool(false)) => values.to_vec(),
+                    _ => uncompress(codec, values, size.checked_sub(levels_len).context("column_chunk: levels longer than the page")?)?,
+                };
+                (count, h.int(4).unwrap_or(0), defined, page)
+            },
+            k => return Err(anyhow!("column_chunk: {} has unsupported page type {}", column.name, k)),
+        };
+        let present = defined.iter().filter(|d| **d == 1).count();
+        let decoded = match encoding {
+            0 => plain(&page, column.physical, present)?,
+            2 | 8 => {
+                let width = *page.first().context("column_chunk: empty dictionary indices")? as usize;
+                rle_hybrid(&page[1..], width, present)?.into_iter()
+                    .map(|i| dictionary.get(i as usize).cloned().ok_or_else(|| anyhow!("column_chunk: dictionary index {} out of range", i)))
+                    .collect::<Result<Vec<Value>>>()?
+            },
+            e => return Err(anyhow!("column_chunk: {} has unsupported encoding {}", column.name, e)),
+        };
+        let mut decoded = decoded.into_iter();
+        values.extend(defined.iter().take(count).map(|d| if *d == 1 { decoded.next() } else { None }));
+    }
+    Ok(values)
+}
+
+// The values of a named column over all row groups
+fn read_column(data: &[u8], meta: &Thrift, columns: &[Column], name: &str) -> Result<Option<Vec<Option<Value>>>> {
+    match columns.iter().position(|c| c.name == name) {
+        None => Ok(None),
+        Some(i) => Ok(Some(meta.list(4).iter().try_fold(Vec::new(), |mut values, group| -> Result<_> {
+            let chunk = group.list(1).get(i).context("read_column: row group is missing a column")?;
+            values.extend(column_chunk(data, chunk, &columns[i])?);
+            Ok(values)
+        })?)),
+    }
+}
+
+/// Read the interval readings for `direction` from a Parquet file (or standard input for [`crate::energy::STDIN`]),
+/// see the module documentation for the columns.
+pub fn read_parquet(parquet_file: &str, direction: Direction) -> Result<Vec<DayEnergy>> {
+    info!("read_parquet: loading Parquet file {}, {:?}", parquet_file, direction);
+    let mut data = Vec::new();
+    open_input(parquet_file)?.read_to_end(&mut data)?;
+    parse_parquet(&data, direction).with_context(|| format!("read_parquet: {}", parquet_file))
+}
+
+/// Parse Parquet file contents, see [`read_parquet`].
+pub fn parse_parquet(data: &[u8], direction: Direction) -> Result<Vec<DayEnergy>> {
+    if data.len() < 12 || !data.starts_with(MAGIC) || !data.ends_with(MAGIC) {
+        return Err(anyhow!("parse_parquet: not a Parquet file"));
+    }
+    let len = u32::from_le_bytes(data[data.len() - 8..data.len() - 4].try_into()?) as usize;
+    let start = (data.len() - 8).checked_sub(len).context("parse_parquet: truncated metadata")?;
+    let meta = Compact::new(&data[start..data.len() - 8], 0).value(12)?;
+    let columns = meta.list(2).iter().skip(1).map(column).collect::<Result<Vec<Column>>>()?; // the first is the root
+    debug!("parse_parquet: columns {:?}", columns);
+
+    let timestamp = columns.iter().find(|c| c.physical == 2 && c.nanos.is_some()).context("parse_parquet: no INT64 TIMESTAMP column")?;
+    let name = match direction {
+        Direction::Import => "consumption",
+        Direction::Export => "feedin",
+    };
+    let kwh_name = [name, "kwh"].into_iter().find(|n| columns.iter().any(|c| c.name == *n))
+        .ok_or_else(|| anyhow!("parse_parquet: no {} or kwh column", name))?;
+    let times = read_column(data, &meta, &columns, &timestamp.name)?.unwrap_or_default();
+    let kwhs = read_column(data, &meta, &columns, kwh_name)?.unwrap_or_default();
+    let qualities = read_column(data, &meta, &columns, "quality")?.unwrap_or_else(|| vec![None; times.len()]);
+    if kwhs.len() != times.len() || qualities.len() != times.len() {
+        return Err(anyhow!("parse_parquet: columns have different numbers of rows"));
+    }
+
+    let nanos = timestamp.nanos.unwrap_or(1);
+    let readings = times.iter().zip(kwhs).zip(qualities).map(|((t, kwh), quality)| {
+        let time = match t {
+            Some(Value::Int(t)) => DateTime::from_timestamp(t.div_euclid(1_000_000_000 / nanos), (t.rem_euclid(1_000_000_000 / nanos) * nanos) as u32)
+                .map(|t| t.naive_utc()).with_context(|| format!("parse_parquet: invalid timestamp {}", t))?,
+            _ => return Err(anyhow!("parse_parquet: null timestamp")),
+        };
+        let kwh = match kwh {
+            Some(Value::Double(kwh)) => Some(kwh),
+            Some(Value::Int(kwh)) => Some(kwh as f64),
+            Some(Value::Bytes(_)) => return Err(anyhow!("parse_parquet: {} column isn't numeric", kwh_name)),
+            None => None,
+        };
+        let quality = match quality {
+            Some(Value::Bytes(flag)) => Quality::from_flag(&String::from_utf8_lossy(&flag))?,
+            _ => Quality::Actual,
+        };
+        Ok((time, kwh, quality))
+    }).collect::<Result<Vec<(NaiveDateTime, Option<f64>, Quality)>>>()?;
+    readings_days(readings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::energy::assert_test_energy;
+
+    #[test]
+    // same energy as data/test/energy/*.csv and greenbutton.xml
+    fn test_read_parquet() -> Result<()> {
+        assert_test_energy(|direction| read_parquet("data/test/energy/energy.parquet", direction))?;
+        let consumption = read_parquet("data/test/energy/energy.parquet", Direction::Import)?;
+        assert_eq!(consumption[0].interval, 480);
+        assert_eq!(consumption[1].quality, vec![Quality::Actual, Quality::Estimated, Quality::Actual]);
+        assert!(parse_parquet(b"Date/Time,00:00\n", Direction::Import).is_err());
+        Ok(())
+    }
+
+    #[test]
+    // corrupt or truncated files are errors, not panics
+    fn test_malformed_parquet() -> Result<()> {
+        let data = std::fs::read("data/test/energy/energy.parquet")?;
+        for i in 0..data.len() {
+            for byte in [0x00, 0x7f, 0x80, 0xff] {
+                let mut corrupt = data.clone();
+                corrupt[i] = byte;
+                let _ = parse_parquet(&corrupt, Direction::Import);
+            }
+            if i < data.len() - MAGIC.len() {
+                let mut truncated = data[..i].to_vec();
+                truncated.extend_from_slice(MAGIC);
+                assert!(parse_parquet(&truncated, Direction::Import).is_err());
+            }
+        }
+        assert!(rle_hybrid(&[2, 0, 0, 0, 0, 0], 33, 1).is_err());
+        assert_eq!(rle_hybrid(&[0xfe, 0xff, 0xff, 0xff, 0x0f, 7], 8, 2)?, vec![7, 7]); // a long run, only the count kept
+        assert_eq!(rle_hybrid(&[0xff, 0xff, 0xff, 0xff, 0x0f], 0, 3)?, vec![0, 0, 0]); // a long bit-packed run of width 0
+        assert!(Compact::new(&data, 1).bytes(usize::MAX).is_err());
+
+        // deeply nested metadata is an error, not a stack overflow
+        let mut nested = MAGIC.to_vec();
+        nested.extend(vec![0x1c; 200_000]); // a struct field holding a struct, and so on
+        nested.extend((200_000u32).to_le_bytes());
+        nested.extend(MAGIC);
+        let e = parse_parquet(&nested, Direction::Import).unwrap_err().to_string();
+        assert!(e.contains("nested more than 64 deep"), "{}", e);
+        Ok(())
+    }
+}
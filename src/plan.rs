@@ -0,0 +1,1145 @@
+//! A plan: the tariffs and charges of an electricity offer, and pricing energy data under it.
+
+use crate::bill::{Bill, ByName, ChannelCharge, Totals, TraceRow};
+use crate::cap::{load_bill_caps, BillCap};
+use crate::carbon::{Carbon, Emissions};
+use crate::concession::{Concession, Rebate};
+use crate::demand::{load_demand_tariff, load_demand_tariff_from, DemandCharge, DemandMeter, DemandTariff, PowerFactor};
+use crate::discount::{discount_lines, load_discounts, Discount};
+use crate::energy::{DayEnergy, Direction, Quality};
+use crate::events::{layer_events, load_events, Event};
+use crate::fees::{load_fees, RecurringFee};
+use crate::json::Json;
+use crate::money::{self, Money};
+use crate::plandoc::{load_plan_document, load_plan_file};
+use crate::policy::{Rate, RatePolicy};
+use crate::pricing::{load_feedin_cap, Billing, load_supply_charge, load_supply_charges, load_supply_charges_from, load_tariff, load_tariff_from, price_intervals, price_intervals_from, price_intervals_parallel, set_weekend, FeedinCap, Interval, Tariff, TariffIndex, Weekend};
+use crate::wholesale::Wholesale;
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use log::info;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::Path;
+
+/// The tariffs and charges of an electricity plan.
+#[derive(Debug)]
+pub struct Plan {
+    pub name: String,
+    pub consumption_tariff: Vec<Tariff>,
+    pub feedin_tariff: Option<Vec<Tariff>>,
+    pub daily_supply: f64, // $/day
+    pub supply_changes: Vec<(NaiveDate, f64)>, // $/day from each date, in date order, after daily_supply
+    pub demand_tariff: Vec<DemandTariff>,
+    pub discounts: Vec<Discount>,
+    pub feedin_cap: Option<FeedinCap>,
+    pub wholesale: Option<Wholesale>, // prices energy instead of the tariff rows for intervals with prices
+    pub power_factor: Option<PowerFactor>, // for kVA demand charges, taken as 1 if None
+    pub billing: Option<Billing>, // billing periods usage blocks and demand months reset on, None for all the data
+    pub policies: Vec<Box<dyn RatePolicy>>, // price intervals before wholesale prices and the tariff, see with_policy
+    pub events: Vec<Event>, // critical peak and demand response events layered on top of consumption's rates
+    pub fees: Vec<RecurringFee>, // recurring fees charged in each billing period (or month)
+    pub concessions: Vec<Concession>, // the household's government concessions, see with_concessions
+    pub rebates: Vec<Rebate>, // the household's one-off rebates
+    pub caps: Vec<BillCap>, // minimum charges and caps on the total of each billing period (or month)
+    pub carbon: Option<Carbon>, // grid carbon intensity for the emissions of the energy priced, None for no emissions
+}
+
+impl Plan {
+    /// Load a plan from its CSV files (without discounts or a feedin cap).
+    pub fn load(name: &str, consumption_tariff: &str, feedin_tariff: Option<&str>, daily: &str, demand_tariff: Option<&str>) -> Result<Plan> {
+        info!("Plan::load: {}", name);
+        let (daily_supply, supply_changes) = load_supply_charges(daily)?;
+        Ok(Plan {
+            name: name.to_string(),
+            consumption_tariff: load_tariff(consumption_tariff)?,
+            feedin_tariff: feedin_tariff.map(load_tariff).transpose()?,
+            daily_supply,
+            supply_changes,
+            demand_tariff: demand_tariff.map(load_demand_tariff).transpose()?.unwrap_or_default(),
+            discounts: Vec::new(),
+            feedin_cap: None,
+            wholesale: None,
+            power_factor: None,
+            billing: None,
+            policies: Vec::new(),
+            events: Vec::new(),
+            fees: Vec::new(),
+            concessions: Vec::new(),
+            rebates: Vec::new(),
+            caps: Vec::new(),
+            carbon: None,
+        })
+    }
+
+    /// A plan from the contents of its CSV files like [`Plan::load`], e.g. uploaded in a browser without a filesystem.
+    pub fn from_bytes(name: &str, consumption_tariff: &[u8], feedin_tariff: Option<&[u8]>, daily: &[u8], demand_tariff: Option<&[u8]>) -> Result<Plan> {
+        info!("Plan::from_bytes: {}", name);
+        let (daily_supply, supply_changes) = load_supply_charges_from("daily supply", daily)?;
+        Ok(Plan {
+            name: name.to_string(),
+            consumption_tariff: load_tariff_from("consumption tariff", consumption_tariff)?,
+            feedin_tariff: feedin_tariff.map(|t| load_tariff_from("feedin tariff", t)).transpose()?,
+            daily_supply,
+            supply_changes,
+            demand_tariff: demand_tariff.map(|t| load_demand_tariff_from("demand tariff", t)).transpose()?.unwrap_or_default(),
+            discounts: Vec::new(),
+            feedin_cap: None,
+            wholesale: None,
+            power_factor: None,
+            billing: None,
+            policies: Vec::new(),
+            events: Vec::new(),
+            fees: Vec::new(),
+            concessions: Vec::new(),
+            rebates: Vec::new(),
+            caps: Vec::new(),
+            carbon: None,
+        })
+    }
+
+    /// Load a plan from a directory containing `consumption.csv`, `supply.csv` and optionally
+    /// `feedIn.csv`, `feedInCap.csv`, `demand.csv`, `discounts.csv`, `wholesale.csv`, `events.csv`, `fees.csv` and `billCap.csv` (as in `data/NB/*Tariff`), named after the directory,
+    /// or from the directory's `plan.toml` or `plan.json` plan document (see [`crate::plandoc`]) if it has one.
+    pub fn load_dir(dir: &str) -> Result<Plan> {
+        let path = Path::new(dir);
+        if !path.is_dir() {
+            return Err(anyhow!("Plan::load_dir: '{}' is not a directory", dir));
+        }
+        let file = |name: &str| path.join(name).to_string_lossy().to_string();
+        let optional = |name: &str| Some(file(name)).filter(|f| Path::new(f).exists());
+        if let Some(document) = optional("plan.toml").or(optional("plan.json")) {
+            return Ok(load_plan_document(&document)?.plan);
+        }
+        let name = path.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or(dir.to_string());
+        let mut plan = Plan::load(&name, &file("consumption.csv"), optional("feedIn.csv").as_deref(), &file("supply.csv"), optional("demand.csv").as_deref())?;
+        plan.discounts = optional("discounts.csv").map(|d| load_discounts(&d)).transpose()?.unwrap_or_default();
+        plan.feedin_cap = optional("feedInCap.csv").map(|c| load_feedin_cap(&c)).transpose()?;
+        plan.wholesale = optional("wholesale.csv").map(|w| Wholesale::load(&w)).transpose()?;
+        plan.events = optional("events.csv").map(|e| load_events(&e)).transpose()?.unwrap_or_default();
+        plan.fees = optional("fees.csv").map(|f| load_fees(&f)).transpose()?.unwrap_or_default();
+        plan.caps = optional("billCap.csv").map(|c| load_bill_caps(&c)).transpose()?.unwrap_or_default();
+        Ok(plan)
+    }
+
+    /// Load every plan in a directory: each subdirectory (see [`Plan::load_dir`]) and JSON or TOML plan document or
+    /// CDR plan JSON file (see [`crate::plandoc::load_plan_file`]), in name order, with a warning for each that can't be loaded.
+    pub fn load_all(dir: &str) -> Result<(Vec<Plan>, Vec<String>)> {
+        let mut paths = std::fs::read_dir(dir).map_err(|e| anyhow!("Plan::load_all: {}: {}", dir, e))?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>>>()?;
+        paths.sort();
+        let (mut plans, mut warnings) = (Vec::new(), Vec::new());
+        for path in paths {
+            let name = path.to_string_lossy().to_string();
+            let plan = match path.extension().and_then(|e| e.to_str()) {
+                _ if path.is_dir() => Plan::load_dir(&name),
+                Some(e) if e.eq_ignore_ascii_case("json") || e.eq_ignore_ascii_case("toml") => load_plan_file(&name),
+                _ => continue,
+            };
+            match plan {
+                Ok(plan) => plans.push(plan),
+                Err(e) => warnings.push(format!("{}: plan skipped: {:#}", name, e)),
+            }
+        }
+        Ok((plans, warnings))
+    }
+
+    /// The plan with these discounts.
+    pub fn with_discounts(self, discounts: Vec<Discount>) -> Plan {
+        Plan { discounts, ..self }
+    }
+
+    /// The plan with this feedin cap.
+    pub fn with_feedin_cap(self, feedin_cap: Option<FeedinCap>) -> Plan {
+        Plan { feedin_cap, ..self }
+    }
+
+    /// The plan with its tariff rows for weekdays and the weekend (see [`set_weekend`]) resolved against this weekend.
+    pub fn with_weekend(mut self, weekend: Weekend) -> Plan {
+        set_weekend(&mut self.consumption_tariff, weekend);
+        if let Some(feedin) = self.feedin_tariff.as_mut() {
+            set_weekend(feedin, weekend);
+        }
+        self
+    }
+
+    /// The daily supply charge ($/day) on a date.
+    pub fn supply_on(&self, date: NaiveDate) -> f64 {
+        self.supply_changes.iter().rev().find(|(d, _)| *d <= date).map_or(self.daily_supply, |(_, x)| *x)
+    }
+
+    /// The plan with wholesale prices.
+    pub fn with_wholesale(self, wholesale: Option<Wholesale>) -> Plan {
+        Plan { wholesale, ..self }
+    }
+
+    /// The plan with the power factor of the energy data for kVA demand charges.
+    pub fn with_power_factor(self, power_factor: Option<PowerFactor>) -> Plan {
+        Plan { power_factor, ..self }
+    }
+
+    /// The plan pricing intervals by a policy, before its wholesale prices and tariff and any policies added before.
+    /// Intervals the policy doesn't price fall through to the next, and feedin is only priced with a feedin tariff
+    /// or wholesale feedin prices.
+    pub fn with_policy(mut self, policy: impl RatePolicy + 'static) -> Plan {
+        self.policies.push(Box::new(policy));
+        self
+    }
+
+    /// The plan with critical peak pricing and demand response events, layered on top of the rates of consumption.
+    pub fn with_events(self, events: Vec<Event>) -> Plan {
+        Plan { events, ..self }
+    }
+
+    /// The plan with recurring fees, such as a monthly membership fee, charged in each billing period.
+    pub fn with_fees(self, fees: Vec<RecurringFee>) -> Plan {
+        Plan { fees, ..self }
+    }
+
+    /// The plan with a household's government concessions and one-off rebates, credited in each billing period.
+    pub fn with_concessions(self, concessions: Vec<Concession>, rebates: Vec<Rebate>) -> Plan {
+        Plan { concessions, rebates, ..self }
+    }
+
+    /// The plan with minimum charges and caps on the total of each billing period, or each month without billing periods.
+    pub fn with_caps(self, caps: Vec<BillCap>) -> Plan {
+        Plan { caps, ..self }
+    }
+
+    /// The plan with the grid's carbon intensity, reporting the emissions of consumption and those avoided by feedin.
+    pub fn with_carbon(self, carbon: Option<Carbon>) -> Plan {
+        Plan { carbon, ..self }
+    }
+
+    /// The rates of an interval of consumption (by `tariff`, the indexed consumption tariff or a channel's) or feedin:
+    /// by the plan's policies, at its wholesale price or by the tariff (the first pricing it), with any event on top of
+    /// consumption's rates.
+    pub fn rates(&self, interval: &Interval, tariff: &TariffIndex, direction: Direction) -> Result<Vec<Rate>> {
+        let mut priced = None;
+        for policy in self.policies.iter().map(|p| p.as_ref()).chain(self.wholesale.as_ref().map(|w| w as &dyn RatePolicy)) {
+            priced = policy.rates(interval, direction)?;
+            if priced.is_some() {
+                break;
+            }
+        }
+        let rates = match priced {
+            Some(rates) => rates,
+            None => tariff.rates(interval, direction)?.unwrap_or_default(),
+        };
+        Ok(match direction {
+            Direction::Import => layer_events(&self.events, interval, rates),
+            Direction::Export => rates,
+        })
+    }
+
+    /// The plan with billing periods, which usage blocks per period and demand charges reset on.
+    pub fn with_billing(self, billing: Option<Billing>) -> Plan {
+        Plan { billing, ..self }
+    }
+
+    /// The feedin tariff, or no rows if feedin is only priced at wholesale; None if feedin isn't priced.
+    pub(crate) fn feedin_rows(&self) -> Option<&[Tariff]> {
+        match (&self.feedin_tariff, &self.wholesale) {
+            (Some(t), _) => Some(t),
+            (None, Some(w)) if w.has_feedin() => Some(&[]),
+            (None, _) => None,
+        }
+    }
+}
+
+/// An extra metered channel, e.g. controlled load for hot water, with its own tariff and supply charge.
+#[derive(Debug)]
+pub struct Channel {
+    pub name: String,
+    pub tariff: Vec<Tariff>,
+    pub daily_supply: f64, // $/day, 0 if the channel has no supply charge of its own
+}
+
+impl Channel {
+    /// Load a channel's tariff and optional supply charge from CSV files.
+    pub fn load(name: &str, tariff: &str, daily: Option<&str>) -> Result<Channel> {
+        info!("Channel::load: {}", name);
+        Ok(Channel {
+            name: name.to_string(),
+            tariff: load_tariff(tariff)?,
+            daily_supply: daily.map(load_supply_charge).transpose()?.unwrap_or(0.0),
+        })
+    }
+}
+
+// Cost of an interval, adding it to the totals for its tariff names
+fn cost_interval(i: &Interval, tariff: &TariffIndex, by_name: &mut ByName) -> Result<f64> {
+    let mut cost = Money::default();
+    for (t, kwh) in tariff.portions(i)? {
+        let c = Money::cost(kwh, t.tariff);
+        by_name.add_in(&t.name, || t.tou_period(), kwh, c.to_f64());
+        cost += c;
+    }
+    Ok(cost.to_f64())
+}
+
+// Count intervals that are not of actual quality as they pass through
+fn count_not_actual<'a>(days: impl IntoIterator<Item = Result<DayEnergy>> + 'a, count: &'a mut usize) -> impl Iterator<Item = Result<DayEnergy>> + 'a {
+    days.into_iter().inspect(move |day| if let Ok(d) = day {
+        *count += d.quality.iter().filter(|q| **q != Quality::Actual).count();
+    })
+}
+
+// What's been priced of consumption or feedin, carried from one incremental run to the next
+#[derive(Debug, Clone, Copy, Default)]
+struct Priced {
+    last: Option<NaiveDate>, // the last date priced
+    usage: f64,              // kWh priced, summed in date order
+    days: usize,
+    columns: usize,          // as returned by price_intervals
+    cost: f64,
+}
+
+impl Priced {
+    fn add(&mut self, (days, columns, cost): (usize, usize, f64)) {
+        self.days += days;
+        if days > 0 {
+            self.columns = columns;
+        }
+        self.cost = money::add(self.cost, cost);
+    }
+
+    fn totals(&self) -> (usize, usize, f64) {
+        (self.days, self.columns, self.cost)
+    }
+
+    fn to_json(self) -> Json {
+        Json::object(vec![
+            ("last", self.last.map_or(Json::Null, |d| d.to_string().into())),
+            ("usage", self.usage.into()),
+            ("days", self.days.into()),
+            ("columns", self.columns.into()),
+            ("cost", self.cost.into()),
+        ])
+    }
+
+    fn from_json(json: &Json) -> Result<Priced> {
+        Ok(Priced {
+            last: json.get("last").and_then(Json::as_str).map(state_date).transpose()?,
+            usage: json.number("usage")?,
+            days: json.number("days")? as usize,
+            columns: json.number("columns")? as usize,
+            cost: json.number("cost")?,
+        })
+    }
+}
+
+fn state_date(s: &str) -> Result<NaiveDate> {
+    s.parse().map_err(|e| anyhow!("state_date: '{}': {}", s, e))
+}
+
+// Skip the days up to the last date already priced, counting the rest into what's priced as they pass through
+fn unpriced<'a>(days: impl IntoIterator<Item = Result<DayEnergy>> + 'a, priced: &'a mut Priced) -> impl Iterator<Item = Result<DayEnergy>> + 'a {
+    let after = priced.last;
+    days.into_iter()
+        .filter(move |day| !matches!((day, after), (Ok(d), Some(after)) if d.date <= after))
+        .inspect(move |day| if let Ok(d) = day {
+            priced.last = priced.last.max(Some(d.date));
+            priced.usage = d.energy.iter().fold(priced.usage, |sum, e| sum + e);
+        })
+}
+
+// What's accumulated while pricing under a plan, one per thread when pricing in parallel
+struct PlanMeter<'a> {
+    plan: &'a Plan,
+    consumption_tariff: TariffIndex<'a>,
+    feedin_tariff: TariffIndex<'a>, // no rows if feedin isn't priced by a tariff
+    demand: DemandMeter<'a>,
+    daily: BTreeMap<NaiveDate, Totals>,
+    consumption_by_name: ByName,
+    feedin_by_name: ByName,
+    trace: Option<Vec<TraceRow>>, // None if not tracing
+    starts: BTreeMap<Direction, (NaiveDate, i32, bool)>, // last interval traced and whether clocks went back before it
+    period_usage: BTreeMap<(Direction, NaiveDate), f64>, // kWh priced before each billing period started
+    emissions: Emissions,
+}
+
+impl<'a> PlanMeter<'a> {
+    fn new(plan: &'a Plan) -> PlanMeter<'a> {
+        PlanMeter {
+            plan,
+            consumption_tariff: TariffIndex::new(&plan.consumption_tariff),
+            feedin_tariff: TariffIndex::new(plan.feedin_rows().unwrap_or_default()),
+            demand: DemandMeter::new(&plan.demand_tariff, plan.power_factor.as_ref()).with_billing(plan.billing),
+            daily: BTreeMap::new(),
+            consumption_by_name: ByName::default(),
+            feedin_by_name: ByName::default(),
+            trace: None,
+            starts: BTreeMap::new(),
+            period_usage: BTreeMap::new(),
+            emissions: Emissions { price: plan.carbon.as_ref().and_then(|c| c.price), ..Emissions::default() },
+        }
+    }
+
+    // The interval with its usage in the billing period counted from the start of its billing period
+    fn in_billing_period(&mut self, i: &Interval, direction: Direction) -> Interval {
+        match self.plan.billing {
+            None => i.clone(),
+            Some(billing) => {
+                // the first interval of a period when priced in order, see period_usage for runs of days
+                let before = *self.period_usage.entry((direction, billing.period_start(i.date))).or_insert(i.usage_period);
+                Interval { usage_period: i.usage_period - before, ..i.clone() }
+            },
+        }
+    }
+
+    // Cost of an interval by the plan's policies, at its wholesale price or by the tariff (the first pricing it) with
+    // any event on top, adding it to the totals for its names and the trace
+    fn cost(&mut self, i: &Interval, direction: Direction) -> Result<f64> {
+        let interval = self.in_billing_period(i, direction);
+        let tariff = match direction {
+            Direction::Import => &self.consumption_tariff,
+            Direction::Export => &self.feedin_tariff,
+        };
+        let mut cost = Money::default();
+        for rate in self.plan.rates(&interval, tariff, direction)? {
+            cost += match rate {
+                Rate::Energy { name, kwh, rate } => self.add(i, direction, &name, kwh, rate),
+                Rate::Charge { name, amount } => self.record(i, direction, &name, 0.0, 0.0, Money::from_f64(amount)),
+                Rate::Demand { name, .. } => return Err(anyhow!("PlanMeter::cost: demand rate {} for energy, demand is charged by the demand tariff", name)),
+            };
+        }
+        Ok(cost.to_f64())
+    }
+
+    // Add energy priced at a rate to the totals for its name and the trace, returning the cost
+    fn add(&mut self, i: &Interval, direction: Direction, name: &str, kwh: f64, rate: f64) -> Money {
+        self.record(i, direction, name, kwh, rate, Money::cost(kwh, rate))
+    }
+
+    // Add a cost to the totals for its name and the trace, returning it
+    fn record(&mut self, i: &Interval, direction: Direction, name: &str, kwh: f64, rate: f64, cost: Money) -> Money {
+        let (by_name, tariff) = match direction {
+            Direction::Import => (&mut self.consumption_by_name, &self.consumption_tariff),
+            Direction::Export => (&mut self.feedin_by_name, &self.feedin_tariff),
+        };
+        // a name of a tariff row is in the row's time of use period, others (e.g. an event or wholesale) in none
+        by_name.add_in(name, || tariff.tariff().iter().find(|t| t.name == name).and_then(Tariff::tou_period), kwh, cost.to_f64());
+        if let Some(trace) = &mut self.trace {
+            let repeated = self.starts.get(&direction).is_some_and(|s| s.2);
+            trace.push(TraceRow { date: i.date, minute: i.min_since_midnight, repeated, direction, kwh, name: name.to_string(), rate, cost: cost.to_f64() });
+        }
+        cost
+    }
+
+    // Note the start of an interval when tracing: priced in order, an interval starting no later than the one before it
+    // on the same date is in the hour repeated when clocks go back, and the rest of the date after it
+    fn start(&mut self, i: &Interval, direction: Direction) {
+        if self.trace.is_some() {
+            let repeated = match self.starts.get(&direction) {
+                Some(&(date, minute, repeated)) if date == i.date => repeated || i.min_since_midnight <= minute,
+                _ => false,
+            };
+            self.starts.insert(direction, (i.date, i.min_since_midnight, repeated));
+        }
+    }
+
+    fn consumption(&mut self, i: &Interval) -> Result<f64> {
+        self.demand.observe(i);
+        if let Some(carbon) = &self.plan.carbon {
+            self.emissions.observe(carbon, i, false);
+        }
+        self.start(i, Direction::Import);
+        let cost = self.cost(i, Direction::Import)?;
+        let d = self.daily.entry(i.date).or_default();
+        d.kwh_in += i.energy;
+        d.consumption = money::add(d.consumption, cost);
+        d.supply = self.plan.supply_on(i.date);
+        Ok(cost)
+    }
+
+    fn feedin(&mut self, i: &Interval) -> Result<f64> {
+        if let Some(carbon) = &self.plan.carbon {
+            self.emissions.observe(carbon, i, true);
+        }
+        self.start(i, Direction::Export);
+        let within = match self.plan.feedin_cap {
+            Some(cap) if i.energy > 0.0 => (cap.kwh - i.usage_day).min(i.energy).max(0.0),
+            _ => i.energy,
+        };
+        let mut cost = self.cost(&Interval { energy: within, ..i.clone() }, Direction::Export)?;
+        if let (Some(cap), true) = (self.plan.feedin_cap, i.energy > within) {
+            cost = money::add(cost, self.add(i, Direction::Export, "Beyond cap", i.energy - within, cap.rate).to_f64());
+        }
+        let d = self.daily.entry(i.date).or_default();
+        d.kwh_out += i.energy;
+        d.feedin = money::add(d.feedin, cost);
+        Ok(cost)
+    }
+
+    // Add what a meter for later days accumulated
+    fn merge(&mut self, other: PlanMeter<'a>) {
+        self.demand.merge(other.demand);
+        for (date, t) in other.daily {
+            let d = self.daily.entry(date).or_default();
+            let supply = d.supply.max(t.supply); // charged once per day
+            d.add(&t);
+            d.supply = supply;
+        }
+        self.consumption_by_name.merge(&other.consumption_by_name);
+        self.feedin_by_name.merge(&other.feedin_by_name);
+        self.emissions.merge(&other.emissions);
+    }
+
+    // Charge supply for every calendar day from the first to the last date of data, as a bill for the billing periods
+    // would, with a warning of the days charged without consumption data
+    fn supply_every_day(&mut self) -> Option<String> {
+        let (first, last) = (*self.daily.keys().next()?, *self.daily.keys().last()?);
+        let mut added = 0;
+        for date in first.iter_days().take_while(|d| *d <= last) {
+            let d = self.daily.entry(date).or_default();
+            if d.kwh_in == 0.0 && d.supply == 0.0 {
+                added += 1;
+            }
+            d.supply = self.plan.supply_on(date);
+        }
+        (added > 0).then(|| format!("supply charged for {} days between {} and {} without consumption data (--pro-rata-supply)", added, first, last))
+    }
+
+    // Price the consumption and feedin days after those already priced, returning what's priced in all with warnings
+    fn price_days<C, F>(&mut self, consumption: C, feedin: Option<F>, holidays: &HashSet<String>, (mut c, mut f): (Priced, Priced)) -> Result<((Priced, Priced), Vec<String>)> where
+    C: IntoIterator<Item = Result<DayEnergy>>,
+    F: IntoIterator<Item = Result<DayEnergy>> {
+        let plan = self.plan;
+        let mut warnings = Vec::new();
+
+        let mut not_actual = 0;
+        let usage = c.usage;
+        let priced = price_intervals_from(count_not_actual(unpriced(consumption, &mut c), &mut not_actual), usage, |i| self.consumption(i), holidays)?;
+        c.add(priced);
+        if not_actual > 0 {
+            warnings.push(format!("{} consumption intervals are not actual readings", not_actual));
+        }
+
+        match (plan.feedin_rows(), feedin) {
+            (Some(_), Some(e)) => {
+                let mut not_actual = 0;
+                let usage = f.usage;
+                let priced = price_intervals_from(count_not_actual(unpriced(e, &mut f), &mut not_actual), usage, |i| self.feedin(i), holidays)?;
+                f.add(priced);
+                if not_actual > 0 {
+                    warnings.push(format!("{} feedin intervals are not actual readings", not_actual));
+                }
+            },
+            (None, None) => (),
+            (_, _) => warnings.push(FEEDIN_NOT_PRICED.to_string()),
+        }
+        Ok(((c, f), warnings))
+    }
+
+    // What's been priced and accumulated, for a later run to continue from
+    fn state(&mut self, (c, f): (Priced, Priced)) -> Json {
+        Json::object(vec![
+            ("plan", self.plan.name.as_str().into()),
+            ("consumption", c.to_json()),
+            ("feedin", f.to_json()),
+            ("daily", Json::Array(self.daily.iter().map(|(date, t)| Json::object(vec![
+                ("date", date.to_string().into()),
+                ("kwh_in", t.kwh_in.into()),
+                ("kwh_out", t.kwh_out.into()),
+                ("consumption", t.consumption.into()),
+                ("feedin", t.feedin.into()),
+                ("supply", t.supply.into()),
+            ])).collect())),
+            ("consumption_by_name", self.consumption_by_name.to_json()),
+            ("feedin_by_name", self.feedin_by_name.to_json()),
+            ("period_usage", Json::Array(self.period_usage.iter().map(|((direction, start), kwh)| Json::object(vec![
+                ("feedin", (*direction == Direction::Export).into()),
+                ("start", start.to_string().into()),
+                ("kwh", (*kwh).into()),
+            ])).collect())),
+            ("demand", self.demand.state()),
+            ("emissions", self.emissions.to_json()),
+        ])
+    }
+
+    // A meter continuing from the state an earlier run under the same plan saved
+    fn from_state(plan: &'a Plan, json: &Json) -> Result<(PlanMeter<'a>, (Priced, Priced))> {
+        let name = json.string("plan")?;
+        if name != plan.name {
+            return Err(anyhow!("PlanMeter::from_state: the state is of plan {}, not {}", name, plan.name));
+        }
+        let mut meter = PlanMeter::new(plan);
+        for d in json.get("daily").and_then(Json::as_array).unwrap_or_default() {
+            meter.daily.insert(state_date(d.string("date")?)?, Totals {
+                kwh_in: d.number("kwh_in")?,
+                kwh_out: d.number("kwh_out")?,
+                consumption: d.number("consumption")?,
+                feedin: d.number("feedin")?,
+                supply: d.number("supply")?,
+            });
+        }
+        meter.consumption_by_name = ByName::from_json(json.get("consumption_by_name").unwrap_or(&Json::Null))?;
+        meter.feedin_by_name = ByName::from_json(json.get("feedin_by_name").unwrap_or(&Json::Null))?;
+        for u in json.get("period_usage").and_then(Json::as_array).unwrap_or_default() {
+            let direction = if u.get("feedin") == Some(&Json::Bool(true)) { Direction::Export } else { Direction::Import };
+            meter.period_usage.insert((direction, state_date(u.string("start")?)?), u.number("kwh")?);
+        }
+        meter.demand = meter.demand.with_state(json.get("demand").unwrap_or(&Json::Null))?;
+        if let Some(emissions) = json.get("emissions") {
+            meter.emissions = Emissions::from_json(emissions, meter.emissions.price)?;
+        }
+        let priced = |key| json.get(key).map(Priced::from_json).transpose().map(Option::unwrap_or_default);
+        Ok((meter, (priced("consumption")?, priced("feedin")?)))
+    }
+
+    // The bill for the priced (day_count, col_count, price) of consumption and feedin
+    fn bill(mut self, consumption: (usize, usize, f64), feedin: (usize, usize, f64), mut warnings: Vec<String>) -> Bill {
+        let (line_count, col_count, consumption_cost) = consumption;
+        if self.plan.billing.is_some_and(|b| b.pro_rata) {
+            warnings.extend(self.supply_every_day());
+        }
+        let missing_power_factor = self.demand.missing_power_factor();
+        if missing_power_factor > 0 {
+            warnings.push(format!("kVA demand: {} intervals have no power factor, taken as 1 (kVA = kW)", missing_power_factor));
+        }
+        if self.emissions.missing > 0 {
+            warnings.push(format!("emissions: {} intervals have no carbon intensity, not counted", self.emissions.missing));
+        }
+        let demand = self.demand.charges();
+        warnings.extend(partial_demand_history(&demand));
+        let demand_total = money::sum(demand.iter().map(|d| d.charge));
+        // each day's charge, once for each date however many times it's repeated in the data
+        let supply = money::sum(self.daily.values().map(|d| d.supply));
+        let plan = self.plan;
+        let mut bill = Bill {
+            discounts: discount_lines(&self.plan.discounts, consumption_cost, supply, demand_total),
+            consumption: consumption_cost,
+            feedin: feedin.2,
+            supply,
+            demand,
+            billing: self.plan.billing,
+            emissions: self.plan.carbon.as_ref().map(|_| self.emissions),
+            days: line_count,
+            interval: if col_count > 1 { (24 * 60) / (col_count - 1) } else { 0 },
+            warnings,
+            daily: self.daily,
+            consumption_by_name: self.consumption_by_name,
+            feedin_by_name: self.feedin_by_name,
+            trace: self.trace.map(|mut t| {
+                t.sort_by_key(|r| (r.date, r.repeated, r.minute)); // consumption and feedin interleaved
+                t
+            }).unwrap_or_default(),
+            ..Bill::default()
+        };
+        bill.apply_fees(&plan.fees);
+        bill.apply_concessions(&plan.concessions, &plan.rebates);
+        bill.apply_caps(&plan.caps);
+        bill
+    }
+}
+
+const FEEDIN_NOT_PRICED: &str = "feedin not priced, both a feedin tariff and feedin data are required";
+
+/// Price consumption and optionally feedin under a plan, with holidays priced as Sundays.
+///
+/// Supply is charged for the days of consumption data.
+pub fn price_plan<C, F>(plan: &Plan, consumption: C, feedin: Option<F>, holidays: &HashSet<String>) -> Result<Bill> where
+C: IntoIterator<Item = Result<DayEnergy>>,
+F: IntoIterator<Item = Result<DayEnergy>> {
+    price_with_meter(PlanMeter::new(plan), consumption, feedin, holidays)
+}
+
+/// Price like [`price_plan`], also recording each priced portion of an interval in the bill's `trace`.
+pub fn price_plan_traced<C, F>(plan: &Plan, consumption: C, feedin: Option<F>, holidays: &HashSet<String>) -> Result<Bill> where
+C: IntoIterator<Item = Result<DayEnergy>>,
+F: IntoIterator<Item = Result<DayEnergy>> {
+    price_with_meter(PlanMeter { trace: Some(Vec::new()), ..PlanMeter::new(plan) }, consumption, feedin, holidays)
+}
+
+// A warning for each demand tariff row taking the maximum over several months charged with fewer months of data
+fn partial_demand_history(demand: &[DemandCharge]) -> Vec<String> {
+    let mut partial: Vec<(&str, usize, usize, NaiveDate)> = Vec::new(); // (name, window, months charged, first month)
+    for d in demand.iter().filter(|d| d.months < d.window) {
+        match partial.iter_mut().find(|p| p.0 == d.name && p.1 == d.window) {
+            Some(p) => p.2 += 1,
+            None => partial.push((&d.name, d.window, 1, d.month)),
+        }
+    }
+    partial.into_iter().map(|(name, window, count, first)| format!(
+        "{}: charged on fewer than {} months of demand history in {} of the months (from {}), so may be understated",
+        name, window, count, first.format("%Y-%m"))).collect()
+}
+
+fn price_with_meter<C, F>(mut meter: PlanMeter, consumption: C, feedin: Option<F>, holidays: &HashSet<String>) -> Result<Bill> where
+C: IntoIterator<Item = Result<DayEnergy>>,
+F: IntoIterator<Item = Result<DayEnergy>> {
+    let ((c, f), warnings) = meter.price_days(consumption, feedin, holidays, Default::default())?;
+    Ok(meter.bill(c.totals(), f.totals(), warnings))
+}
+
+/// Price like [`price_plan`] continuing from the state saved by an earlier run under the same plan (None for the
+/// first run), returning the bill for all the days priced and the state to save for the next run.
+///
+/// Only the consumption and feedin days after the last dates already priced are priced, so a growing dataset can be
+/// priced as it grows without pricing it all again. Usage blocks, billing periods and demand charges carry on from
+/// the earlier runs, but warnings are only of the days priced in this run.
+pub fn price_plan_resumed<C, F>(plan: &Plan, state: Option<&Json>, consumption: C, feedin: Option<F>, holidays: &HashSet<String>) -> Result<(Bill, Json)> where
+C: IntoIterator<Item = Result<DayEnergy>>,
+F: IntoIterator<Item = Result<DayEnergy>> {
+    let (mut meter, priced) = match state {
+        Some(json) => PlanMeter::from_state(plan, json)?,
+        None => (PlanMeter::new(plan), Default::default()),
+    };
+    let ((c, f), warnings) = meter.price_days(consumption, feedin, holidays, priced)?;
+    let state = meter.state((c, f));
+    info!("price_plan_resumed: {} days of consumption to {:?}", c.days, c.last);
+    Ok((meter.bill(c.totals(), f.totals(), warnings), state))
+}
+
+/// Price consumption and optionally feedin under a plan like [`price_plan`], with the days split between `threads` threads
+/// (see [`price_intervals_parallel`]).
+///
+/// Each run of days starts with the usage before each billing period, so usage blocks carry across runs, and the meters
+/// of the runs are merged in date order, charging supply once for a date split between two runs.
+pub fn price_plan_parallel(plan: &Plan, consumption: &[DayEnergy], feedin: Option<&[DayEnergy]>, holidays: &HashSet<String>, threads: usize) -> Result<Bill> {
+    let not_actual = |days: &[DayEnergy]| days.iter().fold(0, |n, d| n + d.quality.iter().filter(|q| **q != Quality::Actual).count());
+    let mut warnings = Vec::new();
+
+    let usage = period_usage(plan, consumption, Direction::Import);
+    let init = || PlanMeter { period_usage: usage.clone(), ..PlanMeter::new(plan) };
+    let (lines, cols, price, meters) = price_intervals_parallel(consumption, threads, init, |m, i| m.consumption(i), holidays)?;
+    let mut meter = merged(plan, meters);
+    if not_actual(consumption) > 0 {
+        warnings.push(format!("{} consumption intervals are not actual readings", not_actual(consumption)));
+    }
+
+    let feedin = match (plan.feedin_rows(), feedin) {
+        (Some(_), Some(e)) => {
+            let usage = period_usage(plan, e, Direction::Export);
+            let init = || PlanMeter { period_usage: usage.clone(), ..PlanMeter::new(plan) };
+            let (lines, cols, price, meters) = price_intervals_parallel(e, threads, init, |m, i| m.feedin(i), holidays)?;
+            meter.merge(merged(plan, meters));
+            if not_actual(e) > 0 {
+                warnings.push(format!("{} feedin intervals are not actual readings", not_actual(e)));
+            }
+            (lines, cols, price)
+        },
+        (None, None) => (0, 0, 0.0),
+        (_, _) => {
+            warnings.push(FEEDIN_NOT_PRICED.to_string());
+            (0, 0, 0.0)
+        },
+    };
+    Ok(meter.bill((lines, cols, price), feedin, warnings))
+}
+
+// The kWh before each billing period of the days, summed in the same order as when priced, so each run of days
+// priced on a thread knows the usage in the billing period before it
+fn period_usage(plan: &Plan, days: &[DayEnergy], direction: Direction) -> BTreeMap<(Direction, NaiveDate), f64> {
+    let mut starts = BTreeMap::new();
+    if let Some(billing) = plan.billing {
+        let mut usage = 0.0;
+        for day in days {
+            starts.entry((direction, billing.period_start(day.date))).or_insert(usage);
+            usage = day.energy.iter().fold(usage, |sum, e| sum + e);
+        }
+    }
+    starts
+}
+
+// The meters of runs of days in date order merged into one
+fn merged<'a>(plan: &'a Plan, meters: Vec<PlanMeter<'a>>) -> PlanMeter<'a> {
+    meters.into_iter().fold(PlanMeter::new(plan), |mut all, m| {
+        all.merge(m);
+        all
+    })
+}
+
+/// Price the consumption of an extra channel independently of the main meter, with holidays priced as Sundays.
+pub fn price_channel<C>(channel: &Channel, consumption: C, holidays: &HashSet<String>) -> Result<ChannelCharge> where
+C: IntoIterator<Item = Result<DayEnergy>> {
+    let mut kwh = 0.0;
+    let mut by_name = ByName::default();
+    let mut dates = BTreeSet::new();
+    let tariff = TariffIndex::new(&channel.tariff);
+    let (days, _col_count, cost) = price_intervals(
+        consumption,
+        |i| {
+            kwh += i.energy;
+            dates.insert(i.date);
+            cost_interval(i, &tariff, &mut by_name)
+        },
+        holidays
+    )?;
+    Ok(ChannelCharge {
+        name: channel.name.clone(),
+        kwh,
+        consumption: cost,
+        supply: (Money::from_f64(channel.daily_supply) * dates.len()).to_f64(), // once for a repeated date
+        days,
+    })
+}
+
+/// Price the same energy data under each plan, returning the plans and bills ranked by total cost (cheapest first).
+///
+/// With more than one thread each plan is priced with [`price_plan_parallel`].
+pub fn compare_plans<'a>(plans: &'a [Plan], consumption: &[DayEnergy], feedin: Option<&[DayEnergy]>, holidays: &HashSet<String>, threads: usize) -> Result<Vec<(&'a Plan, Bill)>> {
+    let mut bills = plans.iter()
+        .map(|plan| {
+            let bill = match threads {
+                0 | 1 => price_plan(
+                    plan,
+                    consumption.iter().cloned().map(Ok),
+                    feedin.map(|f| f.iter().cloned().map(Ok)),
+                    holidays
+                )?,
+                n => price_plan_parallel(plan, consumption, feedin, holidays, n)?,
+            };
+            Ok((plan, bill))
+        })
+        .collect::<Result<Vec<(&Plan, Bill)>>>()?;
+    bills.sort_by(|a, b| a.1.total().total_cmp(&b.1.total()));
+    Ok(bills)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bill::GstInclusive;
+    use crate::carbon::CarbonIntensity;
+    use crate::concession::{load_concessions, load_rebates};
+    use crate::energy::read_csv_energy;
+    use crate::json::parse_json;
+    use crate::pricing::{load_public_holidays, BillingPeriod};
+    use assert_float_eq::*;
+
+    #[test]
+    fn test_compare_plans() -> Result<()> {
+        let holidays = load_public_holidays("data/test/publicHolidaysTest.csv")?;
+        let consumption = read_csv_energy("data/test/energy/consumption.csv")?.collect::<Result<Vec<_>>>()?;
+        let feedin = read_csv_energy("data/test/energy/feedIn.csv")?.collect::<Result<Vec<_>>>()?;
+        let plans = vec![
+            Plan::load_dir("data/test/tariff")?,
+            Plan::load("Block", "data/test/tariff/consumptionBlock.csv", None, "data/test/tariff/supply.csv", None)?,
+        ];
+        let ranked = compare_plans(&plans, &consumption, Some(&feedin), &holidays, 1)?;
+        assert_eq!(ranked.iter().map(|(p, _)| p.name.as_str()).collect::<Vec<_>>(), vec!["tariff", "Block"]);
+        assert_f64_near!(ranked[0].1.total(), 4.34827773);
+        assert_eq!(ranked[1].1.warnings, vec!["feedin not priced, both a feedin tariff and feedin data are required"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_from_bytes() -> Result<()> {
+        let (consumption, feedin, supply, demand) = ("data/test/tariff/consumption.csv", "data/test/tariff/feedIn.csv", "data/test/tariff/supply.csv", "data/test/tariff/peakDemand.csv");
+        let plan = Plan::from_bytes("Plan", &std::fs::read(consumption)?, Some(&std::fs::read(feedin)?), &std::fs::read(supply)?, Some(&std::fs::read(demand)?))?;
+        let loaded = Plan::load("Plan", consumption, Some(feedin), supply, Some(demand))?;
+        assert_eq!(format!("{:?}", plan), format!("{:?}", loaded));
+        assert!(Plan::from_bytes("Plan", b"not,a\ntariff", None, &std::fs::read(supply)?, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_all() -> Result<()> {
+        let (plans, warnings) = Plan::load_all("data/test")?;
+        assert_eq!(plans.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), vec!["Test TOU", "tariff"]); // plan/plan.toml
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("data/test/bill.toml: plan skipped: "), "{}", warnings[0]);
+        assert!(warnings[1].starts_with("data/test/energy: plan skipped: "), "{}", warnings[1]);
+        let (plans, warnings) = Plan::load_all("data/test/tariff")?;
+        assert_eq!((plans.len(), warnings.len()), (1, 0)); // cdrPlan.json
+        assert!(Plan::load_all("data/test/missing").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_feedin_tiers() -> Result<()> {
+        let feedin = read_csv_energy("data/test/energy/feedIn.csv")?;
+        let plan = Plan::load("Tiered", "data/test/tariff/consumption.csv", Some("data/test/tariff/feedInTiered.csv"), "data/test/tariff/supply.csv", None)?;
+        let bill = price_plan(&plan, std::iter::empty(), Some(feedin), &HashSet::new())?;
+        // each day: 0.326 and 0.174 of 0.198 kWh in the first tier, the rest of 0.198 and 0.218 kWh in the second
+        assert_float_absolute_eq!(bill.feedin, 3.0 * (0.5 * -0.10 + 0.242 * -0.05), 1e-12);
+        assert_float_absolute_eq!(bill.feedin_by_name.0[1].kwh, 3.0 * 0.242, 1e-12);
+
+        // nothing for more than 0.6 kWh a day
+        let plan = plan.with_feedin_cap(Some(FeedinCap { kwh: 0.6, rate: 0.0 }));
+        let bill = price_plan(&plan, std::iter::empty(), Some(read_csv_energy("data/test/energy/feedIn.csv")?), &HashSet::new())?;
+        assert_float_absolute_eq!(bill.feedin, 3.0 * (0.5 * -0.10 + 0.1 * -0.05), 1e-12);
+        assert_float_absolute_eq!(bill.feedin_by_name.0[2].kwh, 3.0 * 0.142, 1e-12);
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_plan_traced() -> Result<()> {
+        let plan = Plan::load("Block", "data/test/tariff/consumptionBlock.csv", Some("data/test/tariff/feedIn.csv"), "data/test/tariff/supply.csv", None)?;
+        let bill = price_plan_traced(&plan, read_csv_energy("data/test/energy/consumption.csv")?, Some(read_csv_energy("data/test/energy/feedIn.csv")?), &HashSet::new())?;
+        assert_eq!(bill.trace.len(), 3 * 7); // the 08:00 consumption crosses the block threshold
+        let mut csv = Vec::new();
+        bill.write_trace_csv(&mut csv)?;
+        let csv = String::from_utf8(csv)?;
+        assert_eq!(csv.lines().nth(2), Some("2023-08-06 00:00,feedin,0.326,Solar feed-in,-0.07,-0.02282,-0.00822"));
+        let running = csv.lines().last().and_then(|l| l.rsplit(',').next()).unwrap_or_default().parse::<f64>()?;
+        assert_eq!(running, money::add(bill.consumption, bill.feedin));
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_wholesale() -> Result<()> {
+        let plan = Plan::load("Wholesale", "data/test/tariff/consumption.csv", None, "data/test/tariff/supply.csv", None)?
+            .with_wholesale(Some(Wholesale::load("data/test/wholesale.csv")?.with_margins(0.1, 0.0)));
+        let bill = price_plan(&plan, read_csv_energy("data/test/energy/consumption.csv")?, Some(read_csv_energy("data/test/energy/feedIn.csv")?), &HashSet::new())?;
+        // each day: 0.073 and 0.028 kWh at 0.20, 0.063 kWh at 0.35 (half 0.50 from 16:00 to 20:00), plus the margin
+        assert_float_absolute_eq!(bill.consumption, 3.0 * (0.101 * 0.2 + 0.063 * 0.35 + 0.164 * 0.1), 1e-12);
+        // 0.198 kWh at -0.03 (half -0.01 from 10:00 to 14:00), the rest at -0.05
+        assert_float_absolute_eq!(bill.feedin, 3.0 * (0.198 * -0.03 + 0.544 * -0.05), 1e-12);
+        assert_eq!(bill.consumption_by_name.0.iter().map(|x| x.name.as_str()).collect::<Vec<_>>(), vec!["Wholesale"]);
+        assert!(bill.warnings.is_empty());
+
+        // the tariff for days without prices
+        let consumption = read_csv_energy("data/test/energy/consumption.csv")?.map(|d| d.map(|d| DayEnergy { date: d.date + chrono::Duration::days(1), ..d }));
+        let bill = price_plan(&plan, consumption, None::<Vec<Result<DayEnergy>>>, &HashSet::new())?;
+        assert_eq!(bill.consumption_by_name.0.iter().map(|x| x.name.as_str()).collect::<Vec<_>>(), vec!["Wholesale", "Off-peak", "Peak", "Shoulder"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_supply_changes() -> Result<()> {
+        let plan = Plan::load("Versions", "data/test/tariff/consumption.csv", None, "data/test/tariff/supplyVersions.csv", None)?;
+        let bill = price_plan(&plan, read_csv_energy("data/test/energy/consumption.csv")?, None::<Vec<Result<DayEnergy>>>, &HashSet::new())?;
+        assert_float_absolute_eq!(bill.supply, 1.0 + 1.1 + 1.25, 1e-12);
+        assert_eq!(bill.daily.values().map(|d| d.supply).collect::<Vec<_>>(), vec![1.0, 1.1, 1.25]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_channel() -> Result<()> {
+        let channel = Channel::load("hotwater", "data/test/tariff/controlledLoad.csv", None)?;
+        let charge = price_channel(&channel, read_csv_energy("data/test/energy/consumption.csv")?, &HashSet::new())?;
+        assert_eq!(charge.days, 3);
+        assert_float_absolute_eq!(charge.kwh, 0.492, 1e-9);
+        assert_float_absolute_eq!(charge.total(), 0.0984, 1e-9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_plan_parallel() -> Result<()> {
+        let holidays = load_public_holidays("data/test/publicHolidaysTest.csv")?;
+        let consumption = read_csv_energy("data/test/energy/consumption.csv")?.collect::<Result<Vec<_>>>()?;
+        let plan = Plan::load("Block", "data/test/tariff/consumptionBlockPeriod.csv", None, "data/test/tariff/supply.csv", Some("data/test/tariff/peakDemand.csv"))?
+            .with_carbon(Some(Carbon { intensity: CarbonIntensity::load("data/test/carbonMonthly.csv")?, price: None }));
+        let bill = price_plan(&plan, consumption.iter().cloned().map(Ok), None::<Vec<Result<DayEnergy>>>, &holidays)?;
+        for threads in [2, 3, 8] {
+            let parallel = price_plan_parallel(&plan, &consumption, None, &holidays, threads)?;
+            assert_float_absolute_eq!(parallel.emissions.unwrap().consumption, bill.emissions.unwrap().consumption, 1e-12);
+            assert_float_absolute_eq!(parallel.total(), bill.total(), 1e-12);
+            assert_eq!(parallel.demand, bill.demand);
+            assert_eq!(parallel.daily.len(), bill.daily.len());
+            assert_eq!(parallel.days, bill.days);
+        }
+        Ok(())
+    }
+
+    #[test]
+    // a day's consumption 0.073 kWh at 0.8 kgCO2e/kWh, 0.028 at 0.2 and 0.063 at 0.6, and feedin 0.326, 0.198 and 0.218
+    fn test_emissions() -> Result<()> {
+        let consumption = read_csv_energy("data/test/energy/consumption.csv")?.collect::<Result<Vec<_>>>()?;
+        let feedin = read_csv_energy("data/test/energy/feedIn.csv")?.collect::<Result<Vec<_>>>()?;
+        let carbon = Carbon { intensity: CarbonIntensity::load("data/test/carbon.csv")?, price: Some(50.0) };
+        let plan = Plan::load("TOU", "data/test/tariff/consumption.csv", Some("data/test/tariff/feedIn.csv"), "data/test/tariff/supply.csv", None)?
+            .with_carbon(Some(carbon));
+        let bill = price_plan(&plan, consumption.iter().cloned().map(Ok), Some(feedin.iter().cloned().map(Ok)), &HashSet::new())?;
+        let e = bill.emissions.unwrap();
+        assert_f64_near!(e.consumption, 0.1018 * 3.0);
+        assert_f64_near!(e.avoided, 0.4312 * 3.0);
+        assert_f64_near!(e.cost().unwrap(), (0.1018 - 0.4312) * 3.0 / 1000.0 * 50.0);
+        assert_eq!(bill.total(), price_plan(&plan.with_carbon(None), consumption.iter().cloned().map(Ok), Some(feedin.iter().cloned().map(Ok)), &HashSet::new())?.total());
+        Ok(())
+    }
+
+    #[test]
+    // Monday 7th 16:00 at $1.50 rather than $0.40 and Tuesday 8th 16:00 0.017 kWh below the 0.08 kWh baseline credited $0.50/kWh
+    fn test_events() -> Result<()> {
+        let plan = Plan::load("Flat", "data/test/tariff/consumptionFlat.csv", None, "data/test/tariff/supply.csv", None)?
+            .with_events(load_events("data/test/tariff/criticalPeak.csv")?);
+        let bill = price_plan(&plan, read_csv_energy("data/test/energy/consumption.csv")?, None::<Vec<_>>, &HashSet::new())?;
+        assert_f64_near!(bill.consumption, 0.164 * 3.0 * 0.4 + 0.063 * 1.1 - 0.017 * 0.5);
+        let names = bill.consumption_by_name.0.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["Flat", "Critical peak", "Peak reduction credit"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recurring_fees() -> Result<()> {
+        let plan = Plan::load("Flat", "data/test/tariff/consumptionFlat.csv", None, "data/test/tariff/supply.csv", None)?
+            .with_fees(load_fees("data/test/tariff/recurringFees.csv")?);
+        let price = |plan: &Plan| price_plan(plan, read_csv_energy("data/test/energy/consumption.csv")?, None::<Vec<_>>, &HashSet::new());
+        // one calendar month: the membership fee and a twelfth of the annual fee
+        let bill = price(&plan)?;
+        assert_float_absolute_eq!(bill.fee_total(), 15.0 + 5.0, 1e-9);
+        assert_float_absolute_eq!(bill.total(), 3.0 * (0.164 * 0.4 + 1.45398) + 20.0, 1e-9);
+
+        // quarterly billing periods from the 7th: 20230806 in one, 20230807 and 20230808 in the next
+        let d = |day| NaiveDate::from_ymd_opt(2023, 8, day).unwrap();
+        let plan = plan.with_billing(Some(Billing { period: BillingPeriod::Quarterly, anchor: d(7), pro_rata: false }))
+            .with_caps(vec![BillCap { name: "Bill cap".to_string(), minimum: None, maximum: Some(60.0) }]);
+        let bill = price(&plan)?;
+        assert_eq!(bill.fees.iter().map(|f| (f.name.as_str(), f.start, f.amount)).collect::<Vec<_>>(), vec![
+            ("Membership", NaiveDate::from_ymd_opt(2023, 5, 7).unwrap(), 45.0), ("Annual fee", NaiveDate::from_ymd_opt(2023, 5, 7).unwrap(), 15.0),
+            ("Membership", d(7), 45.0), ("Annual fee", d(7), 15.0),
+        ]);
+        // the cap applies to each period's total with its fees
+        assert_float_absolute_eq!(bill.total(), 60.0 * 2.0, 1e-9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_concessions() -> Result<()> {
+        let plan = Plan::load("Flat", "data/test/tariff/consumptionFlat.csv", None, "data/test/tariff/supply.csv", None)?
+            .with_concessions(load_concessions("data/test/tariff/pensionerConcessions.csv")?, load_rebates("data/test/tariff/billRelief.csv")?);
+        let mut bill = price_plan(&plan, read_csv_energy("data/test/energy/consumption.csv")?, None::<Vec<_>>, &HashSet::new())?;
+        // 17.5% off usage of 0.164 kWh a day at 0.40, 10% off the whole bill in winter, and the rebate in August
+        let (usage, supply) = (3.0 * 0.164 * 0.4, 3.0 * 1.45398);
+        let names = bill.concessions.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["Energy concession", "Winter concession", "Energy bill relief"]);
+        assert_float_absolute_eq!(bill.concession_total(), -(usage * 0.175 + (usage + supply) * 0.1 + 75.0), 1e-9);
+        // concessions aren't taxed
+        bill.apply_gst(0.1, GstInclusive { consumption: false, supply: false, demand: false });
+        assert_float_absolute_eq!(bill.gst.unwrap().gst, (usage + supply) * 0.1, 1e-9);
+
+        // out of season
+        let plan = plan.with_concessions(vec![Concession { months: Some((11, 2)), ..load_concessions("data/test/tariff/pensionerConcessions.csv")?[1].clone() }], Vec::new());
+        let bill = price_plan(&plan, read_csv_energy("data/test/energy/consumption.csv")?, None::<Vec<_>>, &HashSet::new())?;
+        assert!(bill.concessions.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bill_caps() -> Result<()> {
+        let cap = |name: &str, minimum, maximum| BillCap { name: name.to_string(), minimum, maximum };
+        let plan = Plan::load("Flat", "data/test/tariff/consumptionFlat.csv", None, "data/test/tariff/supply.csv", None)?
+            .with_caps(vec![cap("Bill cap", None, Some(4.0))]);
+        let price = |plan: &Plan| price_plan(plan, read_csv_energy("data/test/energy/consumption.csv")?, None::<Vec<_>>, &HashSet::new());
+        // one calendar month of 3 days, 0.164 kWh a day at 0.40 and supply at 1.45398 a day
+        let bill = price(&plan)?;
+        assert_eq!(bill.caps.len(), 1);
+        assert_float_absolute_eq!(bill.cap_total(), 4.0 - 3.0 * (0.164 * 0.4 + 1.45398), 1e-9);
+        assert_float_absolute_eq!(bill.total(), 4.0, 1e-9);
+
+        // billing periods from the 7th: a minimum charge in each
+        let d = |day| NaiveDate::from_ymd_opt(2023, 8, day).unwrap();
+        let plan = plan.with_caps(vec![cap("Minimum charge", Some(5.0), None)]).with_billing(Some(Billing::monthly(d(7))));
+        let bill = price(&plan)?;
+        assert_eq!(bill.caps.iter().map(|c| (c.start, c.end)).collect::<Vec<_>>(), vec![
+            (NaiveDate::from_ymd_opt(2023, 7, 7).unwrap(), d(6)), (d(7), NaiveDate::from_ymd_opt(2023, 9, 6).unwrap()),
+        ]);
+        assert_float_absolute_eq!(bill.total(), 10.0, 1e-9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_billing_periods() -> Result<()> {
+        let holidays = load_public_holidays("data/test/publicHolidaysTest.csv")?;
+        let consumption = read_csv_energy("data/test/energy/consumption.csv")?.collect::<Result<Vec<_>>>()?;
+        let plan = Plan::load("Block", "data/test/tariff/consumptionBlockPeriod.csv", None, "data/test/tariff/supply.csv", Some("data/test/tariff/peakDemand.csv"))?;
+        let price = |plan: &Plan| price_plan(plan, consumption.iter().cloned().map(Ok), None::<Vec<Result<DayEnergy>>>, &holidays);
+        // 0.164 kWh a day, the first 0.3 kWh in the billing period at 0.20 and the rest at 0.30
+        let bill = price(&plan)?;
+        assert_float_absolute_eq!(bill.consumption, 0.3 * 0.2 + 0.192 * 0.3, 1e-12);
+        assert!(bill.billing_periods().is_empty());
+
+        // billing periods from the 7th: 20230806 in one, 20230807 and 20230808 in the next
+        let d = |day| NaiveDate::from_ymd_opt(2023, 8, day).unwrap();
+        let plan = plan.with_billing(Some(Billing::monthly(d(7))));
+        let bill = price(&plan)?;
+        assert_float_absolute_eq!(bill.consumption, 0.164 * 0.2 + (0.3 * 0.2 + 0.028 * 0.3), 1e-12);
+        // demand on 20230807 (20230808 is a holiday) per day for the 2 days of the billing month
+        assert_eq!(bill.demand.iter().map(|c| (c.month, c.days)).collect::<Vec<_>>(), vec![(d(7), 2)]);
+        let periods = bill.billing_periods();
+        assert_eq!(periods.iter().map(|p| (p.0, p.1)).collect::<Vec<_>>(), vec![
+            (NaiveDate::from_ymd_opt(2023, 7, 7).unwrap(), d(6)),
+            (d(7), NaiveDate::from_ymd_opt(2023, 9, 6).unwrap()),
+        ]);
+        assert_float_absolute_eq!(periods[1].2.consumption, 0.3 * 0.2 + 0.028 * 0.3, 1e-12);
+        assert_f64_near!(periods[1].3, bill.demand_total());
+        for threads in [2, 3] {
+            let parallel = price_plan_parallel(&plan, &consumption, None, &holidays, threads)?;
+            assert_float_absolute_eq!(parallel.total(), bill.total(), 1e-12);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_plan_parallel_chunks() -> Result<()> {
+        let days = read_csv_energy("data/test/energy/consumption.csv")?.collect::<Result<Vec<_>>>()?;
+        let d = |month, day| NaiveDate::from_ymd_opt(2023, month, day).unwrap();
+        // 0.164 kWh a day from 1 August to 14 October, with 31 August repeated, so runs of days start and end within
+        // billing periods, within their first 0.3 kWh block and between the two 31 August rows
+        let consumption = d(8, 1).iter_days().take_while(|date| *date <= d(10, 14))
+            .flat_map(|date| match date == d(8, 31) {
+                true => vec![date; 2],
+                false => vec![date],
+            })
+            .enumerate()
+            .map(|(i, date)| DayEnergy { date, ..days[i % days.len()].clone() })
+            .collect::<Vec<_>>();
+        let plan = Plan::load("Block", "data/test/tariff/consumptionBlockPeriod.csv", None, "data/test/tariff/supply.csv", None)?
+            .with_billing(Some(Billing::monthly(d(8, 15))));
+        let bill = price_plan(&plan, consumption.iter().cloned().map(Ok), None::<Vec<Result<DayEnergy>>>, &HashSet::new())?;
+        let periods = bill.billing_periods();
+        assert_eq!(periods.len(), 3);
+        for threads in 2..=16 {
+            let parallel = price_plan_parallel(&plan, &consumption, None, &HashSet::new(), threads)?;
+            assert_eq!((parallel.consumption, parallel.supply, parallel.total()), (bill.consumption, bill.supply, bill.total()), "{} threads", threads);
+            assert_eq!(parallel.days, bill.days);
+            for (p, b) in parallel.billing_periods().iter().zip(&periods) {
+                assert_eq!((p.0, p.1, p.2.consumption, p.2.supply), (b.0, b.1, b.2.consumption, b.2.supply), "{} threads", threads);
+                assert_float_absolute_eq!(p.2.kwh_in, b.2.kwh_in, 1e-9);
+            }
+            assert_eq!(parallel.daily.keys().collect::<Vec<_>>(), bill.daily.keys().collect::<Vec<_>>());
+            for (p, b) in parallel.daily.values().zip(bill.daily.values()) {
+                assert_eq!((p.consumption, p.supply), (b.consumption, b.supply), "{} threads", threads);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_supply_days() -> Result<()> {
+        let days = read_csv_energy("data/test/energy/consumption.csv")?.collect::<Result<Vec<_>>>()?;
+        let d = |day| NaiveDate::from_ymd_opt(2023, 8, day).unwrap();
+        // 20230807 repeated and 20230810 after a gap
+        let consumption = [days[0].clone(), days[1].clone(), days[1].clone(), DayEnergy { date: d(10), ..days[2].clone() }];
+        let plan = Plan::load("Flat", "data/test/tariff/consumption.csv", None, "data/test/tariff/supply.csv", None)?;
+        let price = |plan: &Plan| price_plan(plan, consumption.iter().cloned().map(Ok), None::<Vec<Result<DayEnergy>>>, &HashSet::new());
+        let bill = price(&plan)?;
+        assert_eq!(bill.days, 4);
+        assert_float_absolute_eq!(bill.supply, 3.0 * 1.45398, 1e-12);
+
+        let plan = plan.with_billing(Some(Billing { pro_rata: true, ..Billing::monthly(d(1)) }));
+        let bill = price(&plan)?;
+        assert_float_absolute_eq!(bill.supply, 5.0 * 1.45398, 1e-12);
+        assert_eq!(bill.daily.len(), 5);
+        assert_eq!(bill.warnings, vec!["supply charged for 2 days between 2023-08-06 and 2023-08-10 without consumption data (--pro-rata-supply)"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_price_plan_resumed() -> Result<()> {
+        let consumption = read_csv_energy("data/test/energy/consumption.csv")?.collect::<Result<Vec<_>>>()?;
+        let feedin = read_csv_energy("data/test/energy/feedIn.csv")?.collect::<Result<Vec<_>>>()?;
+        let plan = Plan::load("Blocks", "data/test/tariff/consumptionBlockPeriod.csv", Some("data/test/tariff/feedIn.csv"),
+            "data/test/tariff/supply.csv", Some("data/test/tariff/peakDemand.csv"))?
+            .with_billing(Some(Billing::monthly(NaiveDate::from_ymd_opt(2023, 8, 7).unwrap())));
+        let holidays = HashSet::new();
+        let days = |days: &[DayEnergy]| days.iter().cloned().map(Ok).collect::<Vec<Result<DayEnergy>>>();
+        let all = price_plan(&plan, days(&consumption), Some(days(&feedin)), &holidays)?;
+
+        // consumption to the 7th and feedin to the 6th, then all of it
+        let (_, state) = price_plan_resumed(&plan, None, days(&consumption[..2]), Some(days(&feedin[..1])), &holidays)?;
+        let state = parse_json(&state.to_string())?;
+        let (bill, state) = price_plan_resumed(&plan, Some(&state), days(&consumption), Some(days(&feedin)), &holidays)?;
+        assert_eq!(bill.to_json(), all.to_json());
+        let (again, _) = price_plan_resumed(&plan, Some(&state), days(&consumption), Some(days(&feedin)), &holidays)?;
+        assert_eq!(again.to_json(), all.to_json());
+
+        let other = Plan::load("Flat", "data/test/tariff/consumption.csv", None, "data/test/tariff/supply.csv", None)?;
+        assert!(price_plan_resumed(&other, Some(&state), days(&consumption), None::<Vec<Result<DayEnergy>>>, &holidays).is_err());
+        Ok(())
+    }
+}
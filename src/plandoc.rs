@@ -0,0 +1,270 @@
+//! Plan documents: a whole plan (tariffs, feed-in, supply charge, demand charges, discounts, fees and metadata) in
+//! one JSON or TOML file rather than separate CSV files, validated against the published schema
+//! `schema/plan.schema.json` ([`PLAN_SCHEMA`]).
+//!
+//! Fields follow the columns of the CSV files (see [`crate::pricing::load_tariff`]) with times as `HH:MM`:
+//!
+//! ```toml
+//! name = "Example TOU"
+//! daily_supply = 1.45398
+//! metadata = { retailer = "Example Energy", reviewed = "2024-07-01" }
+//!
+//! [[consumption]]
+//! name = "Peak"
+//! day_start = "weekday"
+//! time_start = "14:00"
+//! time_end = "20:00"
+//! rate = 0.41745
+//!
+//! [[feedin]]
+//! name = "Feed-in"
+//! day_start = 0
+//! day_end = 7
+//! time_start = "00:00"
+//! time_end = "24:00"
+//! rate = -0.05
+//! ```
+//!
+//! Fees with a `recurrence` (`monthly`, `quarterly` or `annual`) are charged in each billing period (see
+//! [`crate::fees`]); others are listed in the plan document but not included in bills, as they depend on how the
+//! account is run.
+
+use crate::cdr::cdr_plan;
+use crate::condition::Condition;
+use crate::demand::{DemandPer, DemandTariff, DemandUnit};
+use crate::discount::{Discount, DiscountOn};
+use crate::energy::open_input;
+use crate::fees::{Recurrence, RecurringFee};
+use crate::json::{parse_json, validate, Json};
+use crate::plan::Plan;
+use crate::pricing::{effective_ranges, minutes_since_midnight, parse_block, parse_date_range, parse_days, DateBound, DateRange, DayClass, FeedinCap, Tariff, TouPeriod};
+use crate::toml::parse_toml;
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+use log::info;
+use std::io::Read;
+use std::str::FromStr;
+
+/// The JSON Schema of plan documents.
+pub const PLAN_SCHEMA: &str = include_str!("../schema/plan.schema.json");
+
+/// A fee charged depending on how the account is run (e.g. a paper bill fee), not included in bills.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fee {
+    pub name: String,
+    pub amount: f64, // $ each time it applies
+    pub condition: Option<String>,
+}
+
+/// A plan with the fees and metadata of its plan document.
+#[derive(Debug)]
+pub struct PlanDocument {
+    pub plan: Plan,
+    pub metadata: Vec<(String, Json)>,
+    pub fees: Vec<Fee>,
+}
+
+// A field as the text of its CSV column, None if it's absent
+fn text(row: &Json, key: &str) -> Option<String> {
+    match row.get(key)? {
+        Json::String(s) => Some(s.clone()),
+        Json::Number(x) => Some(x.to_string()),
+        _ => None,
+    }
+}
+
+// Minutes since midnight of a time HH:MM or HH:MM:SS
+fn time(row: &Json, key: &str) -> Result<i32> {
+    let t = row.string(key)?;
+    match t.matches(':').count() {
+        1 => minutes_since_midnight(&format!("{}:00", t)),
+        _ => minutes_since_midnight(t),
+    }.with_context(|| format!("time: {} '{}' is not HH:MM", key, t))
+}
+
+// A date YYYYMMDD or YYYY-MM-DD
+fn date(row: &Json, key: &str) -> Result<NaiveDate> {
+    let d = row.string(key)?;
+    NaiveDate::parse_from_str(d, "%Y%m%d").or_else(|_| NaiveDate::parse_from_str(d, "%Y-%m-%d"))
+        .map_err(|_| anyhow!("date: {} '{}' is not YYYYMMDD or YYYY-MM-DD", key, d))
+}
+
+fn rows<'a>(json: &'a Json, key: &str) -> &'a [Json] {
+    json.get(key).and_then(Json::as_array).unwrap_or_default()
+}
+
+fn tariff_row(row: &Json) -> Result<Tariff> {
+    let (day_start, day_end, day_class) = parse_days(&text(row, "day_start").unwrap_or_default(), &text(row, "day_end").unwrap_or_default())?;
+    let dates = parse_date_range(text(row, "date_start").as_deref(), text(row, "date_end").as_deref())?;
+    if day_class == Some(DayClass::Date) && dates.is_none() {
+        return Err(anyhow!("tariff_row: day_start 'date' needs a date_start or date_end"));
+    }
+    Ok(Tariff {
+        day_start,
+        day_end,
+        day_class,
+        time_start: time(row, "time_start")?,
+        time_end: time(row, "time_end")?,
+        tariff: row.number("rate")?,
+        name: row.string("name")?.to_string(),
+        block: parse_block(text(row, "block_start").as_deref(), text(row, "block_end").as_deref(), text(row, "block_per").as_deref())?,
+        dates,
+        effective: row.get("effective_from").map(|_| -> Result<DateRange> {
+            Ok(DateRange { start: Some(DateBound::Date(date(row, "effective_from")?)), end: None })
+        }).transpose()?,
+        condition: row.get("condition").and_then(Json::as_str).map(Condition::from_str).transpose()?,
+        period: row.get("period").and_then(Json::as_str).map(TouPeriod::from_str).transpose()?,
+    })
+}
+
+fn tariff(rows: &[Json]) -> Result<Vec<Tariff>> {
+    rows.iter().enumerate()
+        .map(|(i, row)| tariff_row(row).with_context(|| format!("tariff: row {}", i + 1)))
+        .collect::<Result<Vec<Tariff>>>()
+        .map(effective_ranges)
+}
+
+fn demand_row(row: &Json) -> Result<DemandTariff> {
+    Ok(DemandTariff {
+        day_start: row.number("day_start")? as i16,
+        day_end: row.number("day_end")? as i16,
+        time_start: time(row, "time_start")?,
+        time_end: time(row, "time_end")?,
+        rate: row.number("rate")?,
+        name: row.string("name")?.to_string(),
+        per: row.get("per").and_then(Json::as_str).map(DemandPer::from_str).transpose()?.unwrap_or(DemandPer::Month),
+        months: row.get("months").and_then(Json::as_f64).map_or(1, |m| m as usize),
+        unit: row.get("unit").and_then(Json::as_str).map(DemandUnit::from_str).transpose()?.unwrap_or(DemandUnit::Kw),
+    })
+}
+
+fn demand_tariff(rows: &[Json]) -> Result<Vec<DemandTariff>> {
+    rows.iter().enumerate().map(|(i, row)| demand_row(row).with_context(|| format!("demand_tariff: row {}", i + 1))).collect()
+}
+
+fn condition(row: &Json) -> Option<String> {
+    row.get("condition").and_then(Json::as_str).map(str::trim).filter(|c| !c.is_empty()).map(str::to_string)
+}
+
+/// The plan in a plan document, an error listing every way it doesn't match the schema.
+pub fn plan_document(json: &Json) -> Result<PlanDocument> {
+    let problems = validate(json, &parse_json(PLAN_SCHEMA)?);
+    if !problems.is_empty() {
+        return Err(anyhow!("plan_document: {}", problems.join("; ")));
+    }
+    let name = json.string("name")?.to_string();
+    let mut supply_changes = rows(json, "supply_changes").iter()
+        .map(|c| Ok((date(c, "effective_from")?, c.number("daily_supply")?)))
+        .collect::<Result<Vec<(NaiveDate, f64)>>>()?;
+    supply_changes.sort_by_key(|(d, _)| *d);
+    let discounts = rows(json, "discounts").iter()
+        .map(|d| Ok(Discount { name: d.string("name")?.to_string(), percent: d.number("percent")?, on: DiscountOn::from_str(d.string("on")?)?, condition: condition(d) }))
+        .collect::<Result<Vec<Discount>>>()?;
+    let plan = Plan {
+        name,
+        consumption_tariff: tariff(rows(json, "consumption")).context("plan_document: consumption")?,
+        feedin_tariff: json.get("feedin").map(|_| tariff(rows(json, "feedin")).context("plan_document: feedin")).transpose()?,
+        daily_supply: json.number("daily_supply")?,
+        supply_changes,
+        demand_tariff: demand_tariff(rows(json, "demand")).context("plan_document: demand")?,
+        discounts,
+        feedin_cap: json.get("feedin_cap").map(|c| Ok::<_, anyhow::Error>(FeedinCap { kwh: c.number("kwh")?, rate: c.get("rate").and_then(Json::as_f64).unwrap_or(0.0) })).transpose()?,
+        wholesale: None,
+        power_factor: None,
+        billing: None,
+        policies: Vec::new(),
+        events: Vec::new(),
+        fees: recurring_fees(json)?,
+        concessions: Vec::new(),
+        rebates: Vec::new(),
+        caps: Vec::new(),
+        carbon: None,
+    };
+    let fees = rows(json, "fees").iter().filter(|f| f.get("recurrence").is_none())
+        .map(|f| Ok(Fee { name: f.string("name")?.to_string(), amount: f.number("amount")?, condition: condition(f) }))
+        .collect::<Result<Vec<Fee>>>()?;
+    if !fees.is_empty() {
+        info!("plan_document: {} has fees not included in bills: {}", plan.name, fees.iter().map(|f| format!("{} ${}", f.name, f.amount)).collect::<Vec<_>>().join(", "));
+    }
+    let metadata = match json.get("metadata") {
+        Some(Json::Object(kvs)) => kvs.clone(),
+        _ => Vec::new(),
+    };
+    Ok(PlanDocument { plan, metadata, fees })
+}
+
+// The fees with a recurrence
+fn recurring_fees(json: &Json) -> Result<Vec<RecurringFee>> {
+    rows(json, "fees").iter().filter(|f| f.get("recurrence").is_some())
+        .map(|f| Ok(RecurringFee { name: f.string("name")?.to_string(), amount: f.number("amount")?, recurrence: Recurrence::from_str(f.string("recurrence")?)? }))
+        .collect()
+}
+
+// A JSON or (by its extension) TOML file as JSON
+fn read_document(path: &str) -> Result<Json> {
+    let mut s = String::new();
+    open_input(path)?.read_to_string(&mut s)?;
+    match path.to_lowercase().ends_with(".toml") {
+        true => Ok(parse_toml(&s)?.to_json()),
+        false => parse_json(&s),
+    }
+}
+
+/// Load a plan document from a JSON or TOML file (by its extension), see [`plan_document`].
+pub fn load_plan_document(path: &str) -> Result<PlanDocument> {
+    info!("load_plan_document: loading {}", path);
+    read_document(path).and_then(|json| plan_document(&json)).with_context(|| format!("load_plan_document: {}", path))
+}
+
+/// Load the plan in a JSON or TOML file: a plan document, or a CDR energy plan (see [`crate::cdr::cdr_plan`]).
+pub fn load_plan_file(path: &str) -> Result<Plan> {
+    info!("load_plan_file: loading {}", path);
+    read_document(path).and_then(|json| match json.get("data").unwrap_or(&json).get("electricityContract") {
+        Some(_) => cdr_plan(&json),
+        None => Ok(plan_document(&json)?.plan),
+    }).with_context(|| format!("load_plan_file: {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_document() -> Result<()> {
+        let doc = load_plan_document("data/test/plan/plan.toml")?;
+        let csv = Plan::load("Test TOU", "data/test/tariff/consumption.csv", Some("data/test/tariff/feedIn.csv"), "data/test/tariff/supply.csv", None)?;
+        assert_eq!(format!("{:?}", doc.plan), format!("{:?}", csv));
+        assert_eq!(doc.metadata, vec![("retailer".to_string(), "Test Energy".into()), ("reviewed".to_string(), "2024-07-01".into())]);
+        assert_eq!(doc.fees, vec![Fee { name: "Paper bill".to_string(), amount: 1.75, condition: Some("bills by post".to_string()) }]);
+        assert_eq!(format!("{:?}", Plan::load_dir("data/test/plan")?), format!("{:?}", csv));
+
+        let json = parse_json(r#"{
+            "name": "Blocks", "daily_supply": 1, "supply_changes": [{"effective_from": "2024-07-01", "daily_supply": 1.1}],
+            "consumption": [
+                {"name": "Block 1", "day_start": "weekday", "time_start": "00:00", "time_end": "24:00", "rate": 0.2, "block_end": 10},
+                {"name": "Block 2", "day_start": "weekday", "time_start": "00:00", "time_end": "24:00", "rate": 0.3, "block_start": 10},
+                {"name": "Weekend", "day_start": "weekend", "time_start": "00:00", "time_end": "24:00", "rate": 0.25}
+            ],
+            "demand": [{"name": "Peak demand", "day_start": 0, "day_end": 5, "time_start": "15:00", "time_end": "21:00", "rate": 0.5, "per": "day"}],
+            "discounts": [{"name": "Pay on time", "percent": 5, "on": "bill", "condition": "pay on time"}],
+            "feedin_cap": {"kwh": 10},
+            "fees": [{"name": "Membership", "amount": 15, "recurrence": "monthly"}]
+        }"#)?;
+        let plan = plan_document(&json)?.plan;
+        assert_eq!(plan.supply_on(NaiveDate::from_ymd_opt(2024, 7, 1).unwrap()), 1.1);
+        assert_eq!(plan.consumption_tariff[1].block.map(|b| (b.start, b.end)), Some((10.0, f64::INFINITY)));
+        assert_eq!((plan.demand_tariff[0].per, plan.discounts[0].on), (DemandPer::Day, DiscountOn::Bill));
+        assert_eq!(plan.feedin_cap, Some(FeedinCap { kwh: 10.0, rate: 0.0 }));
+        assert_eq!(plan.fees, vec![RecurringFee { name: "Membership".to_string(), amount: 15.0, recurrence: Recurrence::Monthly }]);
+
+        let e = plan_document(&parse_json(r#"{"name": "Bad", "daily_supply": -1, "consumption": [{"name": "Usage"}], "rates": []}"#)?).unwrap_err();
+        assert_eq!(e.to_string(), "plan_document: /daily_supply: -1 is less than 0; /consumption/0: day_start is required; \
+            /consumption/0: time_start is required; /consumption/0: time_end is required; /consumption/0: rate is required; /: rates is not allowed");
+        let e = plan_document(&parse_json(r#"{"name": "Bad", "daily_supply": 1, "consumption": [
+            {"name": "Usage", "day_start": 0, "day_end": 7, "time_start": "noon", "time_end": "24:00", "rate": 0.3}
+        ]}"#)?).unwrap_err();
+        assert!(format!("{:#}", e).starts_with("plan_document: consumption: tariff: row 1: time: time_start 'noon' is not HH:MM"), "{:#}", e);
+        assert!(load_plan_file("data/test/tariff/cdrPlan.json").is_ok());
+        Ok(())
+    }
+}
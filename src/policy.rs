@@ -0,0 +1,128 @@
+//! Rate policies: how each interval of energy is priced, for pricing logic the tariff files can't express.
+//!
+//! A [`RatePolicy`] is given an [`Interval`], which carries the running usage before it in its day and billing period,
+//! and returns the [`Rate`]s it's charged at. The built in policies are implemented on it: time of use and block
+//! tariffs (a slice of [`Tariff`] rows), wholesale prices ([`Wholesale`]) and demand tariff rows ([`DemandTariff`],
+//! whose rates [`crate::demand::DemandMeter`] charges on the maximum demand).
+//!
+//! A plan prices each interval by its own policies (see [`crate::plan::Plan::with_policy`]) in order, then its
+//! wholesale prices, then its tariff, the first returning rates pricing the interval.
+
+use crate::demand::DemandTariff;
+use crate::energy::Direction;
+use crate::pricing::{tariff_portions, Interval, Tariff, TariffIndex};
+use crate::wholesale::Wholesale;
+use anyhow::Result;
+use std::fmt;
+
+/// What some or all of an interval is charged.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rate {
+    /// kWh of the interval at $/kWh (negative to credit feedin)
+    Energy { name: String, kwh: f64, rate: f64 },
+    /// A $ amount, e.g. a fee on usage above a threshold
+    Charge { name: String, amount: f64 },
+    /// The interval's demand counts towards the maximum demand charged at $/kW (or $/kVA)
+    Demand { name: String, rate: f64 },
+}
+
+/// Pricing of an interval of consumption or feedin.
+pub trait RatePolicy: fmt::Debug + Send + Sync {
+    /// The rates an interval is charged at, None if the policy doesn't price it (leaving it to the next policy).
+    fn rates(&self, interval: &Interval, direction: Direction) -> Result<Option<Vec<Rate>>>;
+}
+
+/// Time of use and block tariffs, see [`tariff_portions`]: the tariff rows priced a portion of the interval each.
+impl RatePolicy for [Tariff] {
+    fn rates(&self, interval: &Interval, _direction: Direction) -> Result<Option<Vec<Rate>>> {
+        Ok(Some(tariff_portions(interval, self)?.into_iter()
+            .map(|(t, kwh)| Rate::Energy { name: t.name.clone(), kwh, rate: t.tariff })
+            .collect()))
+    }
+}
+
+impl RatePolicy for Vec<Tariff> {
+    fn rates(&self, interval: &Interval, direction: Direction) -> Result<Option<Vec<Rate>>> {
+        self.as_slice().rates(interval, direction)
+    }
+}
+
+/// Time of use and block tariffs priced from the rows indexed to apply, as for a slice of them.
+impl RatePolicy for TariffIndex<'_> {
+    fn rates(&self, interval: &Interval, _direction: Direction) -> Result<Option<Vec<Rate>>> {
+        Ok(Some(self.portions(interval)?.into_iter()
+            .map(|(t, kwh)| Rate::Energy { name: t.name.clone(), kwh, rate: t.tariff })
+            .collect()))
+    }
+}
+
+/// The whole interval at its wholesale price, None where there's no price, see [`Wholesale::rate`].
+impl RatePolicy for Wholesale {
+    fn rates(&self, interval: &Interval, direction: Direction) -> Result<Option<Vec<Rate>>> {
+        let name = match direction {
+            Direction::Import => "Wholesale",
+            Direction::Export => "Wholesale feed-in",
+        };
+        Ok(self.rate(interval, direction)?.map(|rate| vec![Rate::Energy { name: name.to_string(), kwh: interval.energy, rate }]))
+    }
+}
+
+/// The demand rate of consumption starting within the row's days and times, public holidays being Sundays.
+impl RatePolicy for DemandTariff {
+    fn rates(&self, interval: &Interval, direction: Direction) -> Result<Option<Vec<Rate>>> {
+        let (dow, min) = (interval.dow, interval.min_since_midnight);
+        let within = self.day_start <= dow && self.day_end > dow && self.time_start <= min && self.time_end > min;
+        Ok((within && direction == Direction::Import).then(|| vec![Rate::Demand { name: self.name.clone(), rate: self.rate }]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demand::load_demand_tariff;
+    use crate::energy::read_csv_energy;
+    use crate::plan::{price_plan, Plan};
+    use crate::pricing::load_tariff;
+    use assert_float_eq::*;
+    use chrono::NaiveDate;
+    use std::collections::HashSet;
+
+    // $0.40/kWh with 5c/kWh off any usage over 0.1 kWh in the day
+    #[derive(Debug)]
+    struct Rebate;
+
+    impl RatePolicy for Rebate {
+        fn rates(&self, i: &Interval, _direction: Direction) -> Result<Option<Vec<Rate>>> {
+            let over = (i.usage_day + i.energy - 0.1f64.max(i.usage_day)).max(0.0);
+            Ok((over > 0.0).then(|| vec![
+                Rate::Energy { name: "Flat".to_string(), kwh: i.energy, rate: 0.4 },
+                Rate::Charge { name: "Rebate".to_string(), amount: -0.05 * over },
+            ]))
+        }
+    }
+
+    #[test]
+    fn test_rate_policy() -> Result<()> {
+        let interval = Interval {
+            date: NaiveDate::from_ymd_opt(2023, 8, 7).unwrap(), dow: 0, holiday: false, min_since_midnight: 960, length: 480,
+            energy: 0.063, usage_day: 0.101, usage_period: 0.265,
+        };
+        let tariff = load_tariff("data/test/tariff/consumptionFlat.csv")?;
+        let rates = tariff.rates(&interval, Direction::Import)?.unwrap();
+        assert!(matches!(&rates[..], [Rate::Energy { kwh, .. }] if *kwh == 0.063));
+        let demand = load_demand_tariff("data/test/tariff/peakDemand.csv")?;
+        assert_eq!(demand[0].rates(&interval, Direction::Import)?, Some(vec![Rate::Demand { name: "Peak demand".to_string(), rate: 0.5 }]));
+        assert_eq!(demand[0].rates(&interval, Direction::Export)?, None);
+        assert_eq!(Wholesale::default().rates(&interval, Direction::Import)?, None);
+
+        // the policy prices the intervals crossing 0.1 kWh in the day (0.064 kWh over each day), the flat tariff the rest
+        let plan = Plan::load("Rebate", "data/test/tariff/consumptionFlat.csv", None, "data/test/tariff/supply.csv", None)?.with_policy(Rebate);
+        let flat = Plan::load("Flat", "data/test/tariff/consumptionFlat.csv", None, "data/test/tariff/supply.csv", None)?;
+        let price = |plan: &Plan| price_plan(plan, read_csv_energy("data/test/energy/consumption.csv")?, None::<Vec<_>>, &HashSet::new());
+        assert_f64_near!(price(&flat)?.consumption, 0.164 * 3.0 * 0.4);
+        let bill = price(&plan)?;
+        assert_f64_near!(bill.consumption, 0.164 * 3.0 * 0.4 - 0.05 * 0.064 * 3.0);
+        assert_f64_near!(bill.consumption_by_name.0.iter().find(|x| x.name == "Rebate").unwrap().cost, -0.05 * 0.064 * 3.0);
+        Ok(())
+    }
+}
@@ -0,0 +1,1355 @@
+//! Tariff loading and pricing of interval energy data.
+//!
+//! The entry point is [`price_energy`], which applies a tariff lookup function
+//! (typically built from [`lookup_tariff`] and the rows returned by [`load_tariff`]) to each interval of a consumption
+//! or feed-in CSV file. [`price_intervals`] prices each interval with its usage so far, e.g. by a [`TariffIndex`]
+//! of the rows with their usage blocks, date ranges and conditions.
+
+use anyhow::{anyhow, Context, Result};
+use crate::condition::Condition;
+use crate::energy::{open_input, read_csv_energy, CsvLine, DayEnergy};
+use crate::holidays::Region;
+use crate::money::{self, Money};
+use chrono::prelude::*;
+use chrono::{Months, NaiveDate};
+use csv::ReaderBuilder;
+use log::{debug, info};
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use sscanf::sscanf;
+use std::collections::HashSet;
+use std::io;
+use std::str::FromStr;
+
+/// Parse a `hh:mm:ss` time of day into minutes since midnight (seconds are ignored).
+pub fn minutes_since_midnight(hhmmss: &str) -> Result<i32> {
+    sscanf!(hhmmss, "{i32}:{i32}:{i32}")
+    .map(|(hh, mm, _ss)| mm + 60 * hh)
+    .map_err(|e| anyhow!("minutes_since_midnight: error {}", e)) // convert sscanf::Error to anyhow::Error
+}
+
+/// What a usage block threshold is measured against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockPer {
+    Day,    // usage so far today
+    Period, // usage so far in the billing period
+}
+
+/// How often bills are issued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BillingPeriod {
+    Monthly,
+    Quarterly,
+}
+
+impl FromStr for BillingPeriod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<BillingPeriod> {
+        match s.trim().to_lowercase().as_str() {
+            "monthly" => Ok(BillingPeriod::Monthly),
+            "quarterly" => Ok(BillingPeriod::Quarterly),
+            _ => Err(anyhow!("BillingPeriod::from_str: '{}' is not one of monthly, quarterly", s)),
+        }
+    }
+}
+
+/// Billing periods: a period starts on the anchor date and every month or quarter before and after it
+/// (on the same day of the month, or the last day of shorter months).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Billing {
+    pub period: BillingPeriod,
+    pub anchor: NaiveDate,
+    pub pro_rata: bool, // charge supply for every day of the billing periods from the first to the last date of data
+}
+
+impl Billing {
+    /// Monthly billing periods from the anchor date.
+    pub fn monthly(anchor: NaiveDate) -> Billing {
+        Billing { period: BillingPeriod::Monthly, anchor, pro_rata: false }
+    }
+
+    /// The months in each billing period.
+    pub fn months(&self) -> i32 {
+        match self.period {
+            BillingPeriod::Monthly => 1,
+            BillingPeriod::Quarterly => 3,
+        }
+    }
+
+    // The start of the nth period from the anchor (negative before it)
+    fn nth(&self, n: i32) -> NaiveDate {
+        let months = Months::new((n * self.months()).unsigned_abs());
+        match n < 0 {
+            true => self.anchor.checked_sub_months(months),
+            false => self.anchor.checked_add_months(months),
+        }.unwrap_or(self.anchor)
+    }
+
+    /// The billing period containing a date: its first date and the first date of the next period.
+    pub fn period(&self, date: NaiveDate) -> (NaiveDate, NaiveDate) {
+        let months = (date.year() - self.anchor.year()) * 12 + date.month() as i32 - self.anchor.month() as i32;
+        let mut n = months.div_euclid(self.months());
+        if self.nth(n) > date {
+            n -= 1; // earlier in the month than the anchor's day
+        }
+        (self.nth(n), self.nth(n + 1))
+    }
+
+    /// The first date of the billing period containing a date.
+    pub fn period_start(&self, date: NaiveDate) -> NaiveDate {
+        self.period(date).0
+    }
+}
+
+/// A usage block: the tariff row applies to the usage from `start` to `end` (exclusive) kWh.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Block {
+    pub start: f64,
+    pub end: f64, // f64::INFINITY for no upper limit
+    pub per: BlockPer,
+}
+
+/// A bound of a range of dates a tariff row applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateBound {
+    Date(NaiveDate),   // a specific date (`YYYYMMDD`)
+    MonthDay(u32, u32), // the same month and day every year (`MMDD`), for seasons
+}
+
+/// The dates a tariff row applies to, from `start` to `end` (exclusive).
+///
+/// Month/day ranges recur every year and wrap past the end of the year when `end` is before `start`,
+/// e.g. 1201 to 0301 for summer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateRange {
+    pub start: Option<DateBound>, // None for no lower limit
+    pub end: Option<DateBound>,   // None for no upper limit
+}
+
+impl DateRange {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        let month_day = (date.month(), date.day());
+        match (self.start, self.end) {
+            (Some(DateBound::MonthDay(sm, sd)), Some(DateBound::MonthDay(em, ed))) if (em, ed) <= (sm, sd) =>
+                (sm, sd) <= month_day || month_day < (em, ed),
+            (start, end) => {
+                let after_start = match start {
+                    None => true,
+                    Some(DateBound::Date(d)) => d <= date,
+                    Some(DateBound::MonthDay(m, d)) => (m, d) <= month_day,
+                };
+                let before_end = match end {
+                    None => true,
+                    Some(DateBound::Date(d)) => date < d,
+                    Some(DateBound::MonthDay(m, d)) => month_day < (m, d),
+                };
+                after_start && before_end
+            },
+        }
+    }
+}
+
+fn parse_date_bound(x: &str) -> Result<DateBound> {
+    match x.len() {
+        8 => Ok(DateBound::Date(NaiveDate::parse_from_str(x, "%Y%m%d")?)),
+        4 => {
+            let (m, d) = (x[0..2].parse::<u32>()?, x[2..4].parse::<u32>()?);
+            // 2024 is a leap year so 0229 is valid
+            NaiveDate::from_ymd_opt(2024, m, d).with_context(|| format!("parse_date_bound: invalid month and day '{}'", x))?;
+            Ok(DateBound::MonthDay(m, d))
+        },
+        _ => Err(anyhow!("parse_date_bound: date '{}' is not YYYYMMDD or MMDD", x)),
+    }
+}
+
+pub(crate) fn parse_date_range(start: Option<&str>, end: Option<&str>) -> Result<Option<DateRange>> {
+    match (non_empty(start), non_empty(end)) {
+        (None, None) => Ok(None),
+        (start, end) => Ok(Some(DateRange {
+            start: start.map(parse_date_bound).transpose()?,
+            end: end.map(parse_date_bound).transpose()?,
+        })),
+    }
+}
+
+/// One row of a tariff table: the price applying within a range of days of the week and times of day,
+/// optionally limited to a block of usage, a range of dates and the dates its version of the tariff is in force.
+#[derive(Debug)]
+pub struct Tariff {
+    pub day_start: i16,  // Day Start (0 for Monday), todo: later try u16 to see if its painful
+    pub day_end: i16,    // Day End (Exclusive)
+    pub day_class: Option<DayClass>, // Day Start `weekday` or `weekend` in place of the day range
+    pub time_start: i32, // Time Start (min since midnight)
+    pub time_end: i32,   // Time End (Exclusive)
+    pub tariff: f64,     // $/kWh
+    pub name: String,    // Tariff Name
+    pub block: Option<Block>, // None applies to all usage
+    pub dates: Option<DateRange>, // None applies to all dates
+    pub effective: Option<DateRange>, // dates the row's tariff version is in force (Date bounds), None for all dates
+    pub condition: Option<Condition>, // applies only to intervals it holds for, None for all
+    pub period: Option<TouPeriod>, // Period for the totals by time of use, None to go by the row's days, times and name
+}
+
+impl Tariff {
+    /// Whether the row applies on a date: within its date range and its version in force.
+    pub fn applies_on(&self, date: NaiveDate) -> bool {
+        self.dates.is_none_or(|d| d.contains(date)) && self.effective.is_none_or(|e| e.contains(date))
+    }
+
+    /// The row's time of use period: its Period if given, none for a row of all day every day (a flat rate, whatever
+    /// it's called), otherwise the period its name says (see [`TouPeriod::from_name`]) if any.
+    pub fn tou_period(&self) -> Option<TouPeriod> {
+        let all_week = self.day_class.is_none() && self.day_start == 0 && self.day_end == 7 && self.time_start == 0 && self.time_end == 24 * 60;
+        match self.period {
+            Some(period) => Some(period),
+            None if all_week => None,
+            None => TouPeriod::from_name(&self.name),
+        }
+    }
+
+    /// Whether the row's days include a day of the week (0 for Monday), a Day End before the Day Start
+    /// (e.g. 5 to 1 for Saturday to Monday) wrapping past the end of the week.
+    pub fn includes_day(&self, dow: i16, holiday: bool) -> bool {
+        match self.day_class {
+            Some(c) => c.includes(dow, holiday),
+            None if self.day_end < self.day_start => self.day_start <= dow || dow < self.day_end,
+            None => self.day_start <= dow && dow < self.day_end,
+        }
+    }
+
+    /// Whether the row's times include a time of day (minutes since midnight), a row ending before it starts
+    /// (e.g. 22:00 to 07:00) wrapping past midnight.
+    pub fn includes_time(&self, min_since_midnight: i32) -> bool {
+        if self.time_end < self.time_start {
+            self.time_start <= min_since_midnight || min_since_midnight < self.time_end
+        } else {
+            self.time_start <= min_since_midnight && min_since_midnight < self.time_end
+        }
+    }
+
+    /// The row's days, e.g. `Mon-Fri`, `Sat-Mon` or `weekday`.
+    pub fn days_text(&self) -> String {
+        match self.day_class {
+            Some(DayClass::Weekday(_)) => "weekday".to_string(),
+            Some(DayClass::Weekend(_)) => "weekend".to_string(),
+            Some(DayClass::Date) => "date".to_string(),
+            None if self.day_end <= self.day_start => format!("{}-{}", DAY_NAMES[self.day_start as usize % 7], DAY_NAMES[(self.day_end as usize + 6) % 7]),
+            None => days_text(self.day_start as usize, self.day_end as usize),
+        }
+    }
+
+    /// The row's times, e.g. `17:00-20:00`.
+    pub fn times_text(&self) -> String {
+        format!("{}-{}", time_text(self.time_start as usize), time_text(self.time_end as usize))
+    }
+}
+
+/// The days of the week that are the weekend, for tariff rows with Day Start `weekday` or `weekend`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Weekend(u8); // a bit for each day of the week, bit 0 for Monday
+
+impl Default for Weekend {
+    /// Saturday and Sunday.
+    fn default() -> Self {
+        Weekend(0b110_0000)
+    }
+}
+
+impl Weekend {
+    /// Whether the day of the week (0 for Monday) is a weekend day.
+    pub fn contains(self, dow: i16) -> bool {
+        (0..7).contains(&dow) && self.0 & 1 << dow != 0
+    }
+}
+
+impl FromStr for Weekend {
+    type Err = anyhow::Error;
+
+    /// Comma separated days: names (e.g. `fri,sat` or `Friday,Saturday`) or numbers (0 for Monday).
+    fn from_str(s: &str) -> Result<Self> {
+        let mut days = 0;
+        for day in s.split(',').map(str::trim) {
+            let dow = match day.parse::<usize>() {
+                Ok(n) => Some(n).filter(|n| *n < 7),
+                Err(_) => DAY_NAMES.iter().position(|d| day.len() >= 3 && day.get(..3).is_some_and(|x| x.eq_ignore_ascii_case(d))),
+            };
+            days |= 1 << dow.ok_or_else(|| anyhow!("Weekend::from_str: '{}' is not a day of the week", day))?;
+        }
+        Ok(Weekend(days))
+    }
+}
+
+/// A time of use period, for totals of the kWh and cost in each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TouPeriod {
+    Peak,
+    Shoulder,
+    OffPeak,
+}
+
+impl TouPeriod {
+    pub const ALL: [TouPeriod; 3] = [TouPeriod::Peak, TouPeriod::Shoulder, TouPeriod::OffPeak];
+
+    /// The period's name: `Peak`, `Shoulder` or `Off-peak`.
+    pub fn name(self) -> &'static str {
+        match self {
+            TouPeriod::Peak => "Peak",
+            TouPeriod::Shoulder => "Shoulder",
+            TouPeriod::OffPeak => "Off-peak",
+        }
+    }
+
+    /// The period a tariff name says (ignoring case, e.g. "Off-peak (all weekend and public holidays)" or
+    /// "Super off peak"), None if it says none.
+    pub fn from_name(name: &str) -> Option<TouPeriod> {
+        let words = name.to_lowercase().replace(['-', '_'], " ");
+        if words.contains("off peak") || words.contains("offpeak") {
+            Some(TouPeriod::OffPeak)
+        } else if words.contains("shoulder") {
+            Some(TouPeriod::Shoulder)
+        } else if words.contains("peak") {
+            Some(TouPeriod::Peak)
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for TouPeriod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<TouPeriod> {
+        match s.trim().to_lowercase().replace(['-', '_', ' '], "").as_str() {
+            "peak" => Ok(TouPeriod::Peak),
+            "shoulder" => Ok(TouPeriod::Shoulder),
+            "offpeak" => Ok(TouPeriod::OffPeak),
+            _ => Err(anyhow!("TouPeriod::from_str: Period '{}' is not 'peak', 'shoulder' or 'off-peak'", s)),
+        }
+    }
+}
+
+/// The days of a tariff row given as `weekday` or `weekend` under a weekend definition, or `date` for the dates
+/// of its date range, rather than a range of days of the week.
+///
+/// Public holidays are weekend days.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DayClass {
+    Weekday(Weekend),
+    Weekend(Weekend),
+    Date, // any day of the week, taking precedence over rows for days of the week
+}
+
+impl DayClass {
+    /// Whether the days include the day of the week (0 for Monday), or a public holiday.
+    pub fn includes(self, dow: i16, holiday: bool) -> bool {
+        match self {
+            DayClass::Weekday(w) => !holiday && !w.contains(dow),
+            DayClass::Weekend(w) => holiday || w.contains(dow),
+            DayClass::Date => true,
+        }
+    }
+}
+
+// The day range, or class of days if given as `weekday`, `weekend` or `date` (with Day End empty or the same)
+pub(crate) fn parse_days(start: &str, end: &str) -> Result<(i16, i16, Option<DayClass>)> {
+    let (start, end) = (start.trim().to_ascii_lowercase(), end.trim().to_ascii_lowercase());
+    let class = match start.as_str() {
+        "weekday" => DayClass::Weekday(Weekend::default()),
+        "weekend" => DayClass::Weekend(Weekend::default()),
+        "date" => DayClass::Date,
+        _ => {
+            let day = |d: &str, column: &str| match d.parse::<i16>() {
+                Ok(day) if (0..=7).contains(&day) => Ok(day),
+                _ => Err(anyhow!("parse_days: {} '{}' is not a day from 0 (Monday) to 7", column, d)),
+            };
+            return Ok((day(&start, "Day Start")?, day(&end, "Day End")?, None));
+        },
+    };
+    match end.is_empty() || end == start {
+        true => Ok((0, 7, Some(class))),
+        false => Err(anyhow!("parse_days: Day End '{}' must be empty or '{}' with Day Start '{}'", end, start, start)),
+    }
+}
+
+/// Resolve the tariff rows with Day Start `weekday` or `weekend` against a weekend definition
+/// (rows are loaded with Saturday and Sunday).
+pub fn set_weekend(tariff: &mut [Tariff], weekend: Weekend) {
+    for t in tariff.iter_mut() {
+        t.day_class = t.day_class.map(|c| match c {
+            DayClass::Weekday(_) => DayClass::Weekday(weekend),
+            DayClass::Weekend(_) => DayClass::Weekend(weekend),
+            DayClass::Date => DayClass::Date,
+        });
+    }
+}
+
+fn non_empty(x: Option<&str>) -> Option<&str> {
+    x.map(str::trim).filter(|x| !x.is_empty())
+}
+
+pub(crate) fn parse_block(start: Option<&str>, end: Option<&str>, per: Option<&str>) -> Result<Option<Block>> {
+    match (non_empty(start), non_empty(end), non_empty(per)) {
+        (None, None, None) => Ok(None),
+        (start, end, per) => Ok(Some(Block {
+            start: start.map(str::parse::<f64>).transpose()?.unwrap_or(0.0),
+            end: end.map(str::parse::<f64>).transpose()?.unwrap_or(f64::INFINITY),
+            per: match per {
+                None | Some("day") => BlockPer::Day,
+                Some("period") => BlockPer::Period,
+                Some(x) => return Err(anyhow!("parse_block: Block Per '{}' is not 'day' or 'period'", x)),
+            },
+        })),
+    }
+}
+
+/// Load a tariff table from a CSV file with columns:
+/// Day Start (0 for Monday), Day End (Exclusive), Time Start, Time End (Exclusive), $/kWh, Name
+/// and optionally Block Start (kWh), Block End (kWh, Exclusive), Block Per (`day` or `period`),
+/// Date Start, Date End (Exclusive).
+/// A Day End before the Day Start wraps past the end of the week, e.g. 5 to 1 for Saturday to Monday.
+/// A Time End before the Time Start wraps past midnight, e.g. 22:00 to 07:00 for overnight off-peak.
+/// Empty block columns default to 0, no limit and `day`; a row with all three empty applies to all usage.
+/// Dates are `YYYYMMDD` or `MMDD` (every year); a row with both empty applies to all dates.
+/// An optional column Effective From (`YYYYMMDD`) gives the date the row's version of the tariff comes into force,
+/// see [`load_tariff_versions`], an optional column Condition an expression limiting the row to the intervals
+/// it holds for (see [`crate::condition`]), e.g. `usage_period > 500`, and after it an optional column Period (`peak`,
+/// `shoulder` or `off-peak`) for the totals by time of use, see [`Tariff::tou_period`].
+/// Day Start may be `weekday` or `weekend` (with Day End empty or the same) for the days of a weekend definition,
+/// Saturday and Sunday unless changed by [`set_weekend`], or `date` for every date in the row's date range
+/// (e.g. Christmas Day), which takes precedence over the rows for days of the week.
+pub fn load_tariff(csv_tariff: &str) -> Result<Vec<Tariff>> {
+    info!("load_tariff: loading CSV file {}", csv_tariff);
+    load_tariff_from(csv_tariff, open_input(csv_tariff)?)
+}
+
+/// Load a tariff like [`load_tariff`] from any reader (e.g. a byte slice), `csv_tariff` naming it in errors.
+pub fn load_tariff_from<R: io::Read>(csv_tariff: &str, input: R) -> Result<Vec<Tariff>> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(input);
+
+    let headers = reader.headers()?.clone();
+    reader.records().enumerate()
+    .map(|(i, record)| -> Result<Tariff> {
+        let r = record?;
+        debug!("load_tariff: record: {:?}", r);
+        let line = CsvLine::new("load_tariff", csv_tariff, i, &headers, &r);
+        let (day_start, day_end, day_class) = parse_days(line.get(0), line.get(1)).with_context(|| line.at(0))?;
+        let dates = parse_date_range(r.get(9), r.get(10)).with_context(|| line.at_line())?;
+        if day_class == Some(DayClass::Date) && dates.is_none() {
+            return Err(anyhow!("{}: Day Start 'date' needs a Date Start or Date End", line.at_line()));
+        }
+        Ok(Tariff {
+            day_start,
+            day_end,
+            day_class,
+            time_start: line.parse_with(2, minutes_since_midnight)?,
+            time_end:   line.parse_with(3, minutes_since_midnight)?,
+            tariff:     line.parse::<f64>(4)?,
+            name:       line.get(5).to_string(),
+            block:      parse_block(r.get(6), r.get(7), r.get(8)).with_context(|| line.at_line())?,
+            dates,
+            effective:  line.optional(11).map(|_| -> Result<DateRange> {
+                let d = line.parse_with(11, |d| Ok(NaiveDate::parse_from_str(d, "%Y%m%d")?))?;
+                Ok(DateRange { start: Some(DateBound::Date(d)), end: None })
+            }).transpose()?,
+            condition: line.optional(12).map(|_| line.parse::<Condition>(12)).transpose()?,
+            period: line.optional(13).map(|_| line.parse::<TouPeriod>(13)).transpose()?,
+        })
+    })
+    .collect::<Result<Vec<Tariff>>>() // 1st error, or the vector
+    .map(effective_ranges)
+}
+
+// Each row's version in force from its Effective From until the next later one; rows without one are
+// the version before the first
+pub(crate) fn effective_ranges(mut tariff: Vec<Tariff>) -> Vec<Tariff> {
+    let start = |t: &Tariff| match t.effective.and_then(|e| e.start) {
+        Some(DateBound::Date(d)) => Some(d),
+        _ => None,
+    };
+    let mut dates = tariff.iter().filter_map(start).collect::<Vec<_>>();
+    dates.sort();
+    dates.dedup();
+    if dates.is_empty() {
+        return tariff;
+    }
+    for t in tariff.iter_mut() {
+        let from = start(t);
+        let until = dates.iter().find(|d| from.is_none_or(|f| f < **d));
+        t.effective = Some(DateRange { start: from.map(DateBound::Date), end: until.map(|d| DateBound::Date(*d)) });
+    }
+    tariff
+}
+
+/// Load the versions of a tariff from CSV files (see [`load_tariff`]), each `YYYYMMDD=file` for the version in
+/// force from that date or just `file` for the version before the first date, e.g. rates that changed on 1 July.
+///
+/// Each row applies from its version's date (or its own Effective From) until the next version comes into force.
+pub fn load_tariff_versions<S: AsRef<str>>(files: &[S]) -> Result<Vec<Tariff>> {
+    let mut tariff = Vec::new();
+    for file in files.iter().map(AsRef::as_ref) {
+        let (from, path) = match file.split_once('=') {
+            Some((d, path)) if d.len() == 8 && d.chars().all(|c| c.is_ascii_digit()) =>
+                (Some(NaiveDate::parse_from_str(d, "%Y%m%d").with_context(|| format!("load_tariff_versions: invalid date '{}'", d))?), path),
+            _ => (None, file),
+        };
+        tariff.extend(load_tariff(path)?.into_iter().map(|t| match (from, t.effective) {
+            (Some(d), None) => Tariff { effective: Some(DateRange { start: Some(DateBound::Date(d)), end: None }), ..t },
+            _ => t,
+        }));
+    }
+    Ok(effective_ranges(tariff))
+}
+
+/// A daily cap on feedin: energy exported beyond `kwh` in a day earns `rate` rather than the feedin tariff.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeedinCap {
+    pub kwh: f64,  // kWh per day
+    pub rate: f64, // $/kWh beyond the cap, 0 for no credit
+}
+
+/// Load a feedin cap from the first data line of a CSV file with columns: Cap (kWh/day) and optionally
+/// $/kWh beyond the cap (empty or missing for 0).
+pub fn load_feedin_cap(csv: &str) -> Result<FeedinCap> {
+    info!("load_feedin_cap: loading CSV file {}", csv);
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(open_input(csv)?);
+
+    let headers = reader.headers()?.clone();
+    let r = reader.records().next().with_context(|| format!("'{}' missing data line 1", csv))??;
+    debug!("load_feedin_cap: record: {:?}", r);
+    let line = CsvLine::new("load_feedin_cap", csv, 0, &headers, &r);
+    Ok(FeedinCap {
+        kwh: line.parse::<f64>(0)?,
+        rate: line.optional(1).map(|_| line.parse::<f64>(1)).transpose()?.unwrap_or(0.0),
+    })
+}
+
+/// Load the daily supply charge ($/day) from the first data line of a CSV file.
+pub fn load_supply_charge(csv_tariff: &str) -> Result<f64> {
+    info!("load_supply_charge: loading CSV file {}", csv_tariff);
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(open_input(csv_tariff)?);
+
+    let headers = reader.headers()?.clone();
+    let r = reader.records().next().with_context(|| format!("'{}' missing data line 1", csv_tariff))??;
+    debug!("load_supply_charge: record: {:?}", r);
+    CsvLine::new("load_supply_charge", csv_tariff, 0, &headers, &r).parse::<f64>(0)
+}
+
+/// Load a daily supply charge that changes over time from a CSV file with columns: $/Day and optionally
+/// Effective From (`YYYYMMDD`), returning the charge before the first change and the (date, $/day) changes in date order.
+///
+/// The charge before the first change is that of the line without a date, or the earliest if every line has one.
+pub fn load_supply_charges(csv_tariff: &str) -> Result<(f64, Vec<(NaiveDate, f64)>)> {
+    info!("load_supply_charges: loading CSV file {}", csv_tariff);
+    load_supply_charges_from(csv_tariff, open_input(csv_tariff)?)
+}
+
+/// Load supply charges like [`load_supply_charges`] from any reader (e.g. a byte slice), `csv_tariff` naming it in errors.
+pub fn load_supply_charges_from<R: io::Read>(csv_tariff: &str, input: R) -> Result<(f64, Vec<(NaiveDate, f64)>)> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(input);
+
+    let headers = reader.headers()?.clone();
+    let mut base = None;
+    let mut changes = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let r = record?;
+        debug!("load_supply_charges: record: {:?}", r);
+        let line = CsvLine::new("load_supply_charges", csv_tariff, i, &headers, &r);
+        let daily = line.parse::<f64>(0)?;
+        match line.optional(1) {
+            None => base = Some(daily),
+            Some(_) => changes.push((line.parse_with(1, |d| Ok(NaiveDate::parse_from_str(d, "%Y%m%d")?))?, daily)),
+        }
+    }
+    changes.sort_by_key(|(d, _)| *d);
+    match base.or(changes.first().map(|(_, x)| *x)) {
+        Some(base) => Ok((base, changes)),
+        None => Err(anyhow!("'{}' missing data line 1", csv_tariff)),
+    }
+}
+
+/// Load public holiday dates (`YYYYMMDD`, first column) which are charged at Sunday rates.
+pub fn load_public_holidays(csv: &str) -> Result<HashSet<String>> {
+    load_region_holidays(csv, None)
+}
+
+/// Load public holiday dates (`YYYYMMDD`) for a region, from a file like the data.gov.au Australian Public Holidays
+/// dataset with a header naming the columns: `Date` (otherwise the first column) and optionally `Holiday Name` and
+/// `Jurisdiction` (e.g. `nsw`, or `State` or `Region`). With a region and a jurisdiction column only the rows of the
+/// region and those for every region (`national`, `aus` or empty) are loaded, otherwise every row is.
+pub fn load_region_holidays(csv: &str, region: Option<Region>) -> Result<HashSet<String>> {
+    info!("load_public_holidays: loading CSV file {}, region {:?}", csv, region);
+    load_region_holidays_from(csv, open_input(csv)?, region)
+}
+
+/// Load public holidays like [`load_region_holidays`] from any reader (e.g. a byte slice), `csv` naming it in errors.
+pub fn load_region_holidays_from<R: io::Read>(csv: &str, input: R, region: Option<Region>) -> Result<HashSet<String>> {
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .from_reader(input);
+    let headers = reader.headers()?.clone();
+    let column = |names: &[&str]| headers.iter().position(|h| names.contains(&h.trim().to_ascii_lowercase().as_str()));
+    let date = column(&["date"]).unwrap_or(0);
+    let jurisdiction = column(&["jurisdiction", "state", "region"]);
+
+    let mut holidays = HashSet::new();
+    for (i, record) in reader.records().enumerate() {
+        let r = record?;
+        debug!("load_public_holidays: record: {:?}", r);
+        let line = CsvLine::new("load_public_holidays", csv, i, &headers, &r);
+        let applies = match (region, jurisdiction.map(|j| (j, line.get(j)))) {
+            (Some(region), Some((column, j))) if !["", "national", "aus"].contains(&j.to_ascii_lowercase().as_str()) => {
+                line.parse::<Region>(column)? == region
+            },
+            _ => true,
+        };
+        if applies {
+            line.parse_with(date, |d| Ok(NaiveDate::parse_from_str(d, "%Y%m%d")?))?;
+            holidays.insert(line.get(date).to_string());
+        }
+    }
+    Ok(holidays)
+}
+
+const DAY_NAMES: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+// Days from `start` to `end` (exclusive), e.g. "Mon-Fri"
+fn days_text(start: usize, end: usize) -> String {
+    match end - start {
+        1 => DAY_NAMES[start].to_string(),
+        _ => format!("{}-{}", DAY_NAMES[start], DAY_NAMES[end - 1]),
+    }
+}
+
+// Minutes since midnight as hh:mm
+fn time_text(min: usize) -> String {
+    format!("{:02}:{:02}", min / 60, min % 60)
+}
+
+/// Check that the tariff rows cover every day of the week and minute of the day exactly once, returning
+/// a description of each gap and overlap (empty if there are none).
+///
+/// Rows with different usage blocks, date ranges, versions or conditions may cover the same times, only rows with the
+/// same block, dates, version and condition overlap. Rows are numbered by their line in the CSV file (the header is line 1).
+/// Rows with Day Start `date` or a condition only cover some intervals so they don't fill gaps in the week.
+pub fn check_tariff_coverage(tariff: &[Tariff]) -> Vec<String> {
+    let includes = |t: &Tariff, day: usize| t.includes_day(day as i16, false);
+    // a row wrapping past midnight covers two ranges of the day
+    let time_ranges = |t: &Tariff| {
+        let (t0, t1) = (t.time_start.clamp(0, 1440) as usize, t.time_end.clamp(0, 1440) as usize);
+        if t1 < t0 { vec![(0, t1), (t0, 1440)] } else { vec![(t0, t1)] }
+    };
+
+    let mut problems = Vec::new();
+    for (i, a) in tariff.iter().enumerate() {
+        for (j, b) in tariff.iter().enumerate().skip(i + 1) {
+            if a.block != b.block || a.dates != b.dates || a.effective != b.effective || a.condition != b.condition {
+                continue;
+            }
+            for (at0, at1) in time_ranges(a) {
+                for (bt0, bt1) in time_ranges(b) {
+                    let (t0, t1) = (at0.max(bt0), at1.min(bt1));
+                    if t0 >= t1 {
+                        continue;
+                    }
+                    let mut day = 0;
+                    while day < 7 {
+                        let d0 = day;
+                        while day < 7 && includes(a, day) && includes(b, day) {
+                            day += 1;
+                        }
+                        if d0 < day {
+                            problems.push(format!(
+                                "rows {} ({}) and {} ({}) overlap on {} from {} to {}",
+                                i + 2, a.name, j + 2, b.name, days_text(d0, day), time_text(t0), time_text(t1)
+                            ));
+                        }
+                        day += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    // uncovered times of each day, then the same gaps on consecutive days reported together
+    let gaps = (0..7).map(|day| {
+        let covered = |min: usize| tariff.iter().any(|t| {
+            t.day_class != Some(DayClass::Date) && t.condition.is_none() && includes(t, day) && time_ranges(t).iter().any(|(t0, t1)| *t0 <= min && min < *t1)
+        });
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        for min in (0..1440).filter(|m| !covered(*m)) {
+            match runs.last_mut() {
+                Some((_, end)) if *end == min => *end = min + 1,
+                _ => runs.push((min, min + 1)),
+            }
+        }
+        runs
+    }).collect::<Vec<_>>();
+    let mut start = 0;
+    for day in 1..=7 {
+        if day == 7 || gaps[day] != gaps[start] {
+            for (t0, t1) in &gaps[start] {
+                problems.push(format!("no tariff on {} from {} to {}", days_text(start, day), time_text(*t0), time_text(*t1)));
+            }
+            start = day;
+        }
+    }
+    problems
+}
+
+/// The first tariff row applying at a date and time (minutes since midnight), ignoring usage blocks and rows with a
+/// condition, with holidays priced as Sundays (or the weekend) and rows with Day Start `date` taking precedence.
+pub fn tariff_at<'a>(tariff: &'a [Tariff], holidays: &HashSet<String>, date: NaiveDate, min_since_midnight: i32) -> Option<&'a Tariff> {
+    let (dow, holiday) = (day_of_week(date, holidays), is_holiday(date, holidays));
+    tariff.iter()
+        .filter(|x| tou_matches(x, dow, holiday, min_since_midnight) && x.applies_on(date) && x.condition.is_none())
+        .min_by_key(|x| x.day_class != Some(DayClass::Date))
+}
+
+// Whether the tariff row's days and times include the day of week (6 for public holidays) and time of day
+fn tou_matches(x: &Tariff, dow: i16, holiday: bool, min_since_midnight: i32) -> bool {
+    x.includes_day(dow, holiday) &&
+    x.includes_time(min_since_midnight)
+}
+
+/// Lookup $/kWh for the day of the week (0 for Monday) and time of day.
+///
+/// For time of the day, we only check that the start of the consumption interval is within the tariff time interval,
+/// assuming that consumption intervals always fall within single tariff intervals.
+/// A row whose Time End is before its Time Start (e.g. 22:00 to 07:00) wraps past midnight, on each of its days,
+/// and one whose Day End is before its Day Start (e.g. 5 to 1, Saturday to Monday) wraps past the end of the week.
+/// Usage blocks, date ranges and tariff versions are ignored, the first matching row is used; see [`cost_tariff`] for those.
+/// Public holidays (day 6) are Sundays for rows with Day Start `weekday` or `weekend`, rows with Day Start `date` or a
+/// condition are skipped.
+pub fn lookup_tariff(dow: i16, min_since_midnight: i32, tariff: &[Tariff]) -> Result<f64> {
+    tariff.iter().find(|x| x.day_class != Some(DayClass::Date) && x.condition.is_none() && tou_matches(x, dow, false, min_since_midnight))
+    .map(|t| t.tariff)
+    .with_context(|| format!("lookup_tariff: no tarriff for day of week {} and min_since_midnight {}", dow, min_since_midnight))
+}
+
+/// A tariff's rows expanded when it's loaded into a table by day (each day of the week, a public holiday or not) and
+/// bucket of minutes of the day, so the rows that may price an interval are looked up in O(1) rather than by a scan of
+/// every row.
+///
+/// The buckets are as long as the greatest common divisor of the rows' start and end times (e.g. 30 minutes for a
+/// tariff changing on the half hour, 1440 for a flat rate), so no bucket straddles a change of rate and intervals of any
+/// length (1, 5, 15 or 30 minutes) are looked up by the bucket their start is in, as [`tariff_portions`] matches them.
+///
+/// [`TariffIndex::portions`] and [`TariffIndex::cost`] give the same result as [`tariff_portions`] and
+/// [`cost_tariff`], with usage blocks, date ranges, versions, conditions and Day Start `date` rows applied to the
+/// candidate rows. A plan's tariffs are indexed each time it's priced, as [`set_weekend`] changes the days rows apply
+/// on after they're loaded.
+#[derive(Debug, Clone)]
+pub struct TariffIndex<'a> {
+    tariff: &'a [Tariff],
+    bucket: usize,         // minutes in each bucket, dividing the day and every row's start and end time
+    buckets: usize,        // buckets in a day
+    rows: Vec<Vec<usize>>, // by day (day of week * 2, plus 1 on a public holiday) then bucket, the rows matching in order
+}
+
+const DAY_MINUTES: usize = 24 * 60;
+
+// The greatest common divisor
+fn gcd(a: usize, b: usize) -> usize {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
+}
+
+impl<'a> TariffIndex<'a> {
+    pub fn new(tariff: &'a [Tariff]) -> TariffIndex<'a> {
+        let bucket = tariff.iter().flat_map(|t| [t.time_start, t.time_end]).filter_map(|m| usize::try_from(m).ok()).fold(DAY_MINUTES, gcd);
+        let buckets = DAY_MINUTES / bucket;
+        let rows = (0..14).flat_map(|day| (0..buckets).map(move |b| (day, (b * bucket) as i32)))
+            .map(|(day, min)| tariff.iter().enumerate().filter(|(_, x)| tou_matches(x, day / 2, day % 2 == 1, min)).map(|(r, _)| r).collect())
+            .collect();
+        TariffIndex { tariff, bucket, buckets, rows }
+    }
+
+    /// The tariff rows indexed.
+    pub fn tariff(&self) -> &'a [Tariff] {
+        self.tariff
+    }
+
+    // The rows whose days and times include the interval, None for a day or time outside the index
+    fn candidates(&self, interval: &Interval) -> Option<&[usize]> {
+        match (usize::try_from(interval.dow), usize::try_from(interval.min_since_midnight)) {
+            (Ok(dow), Ok(min)) if dow < 7 && min < DAY_MINUTES => {
+                Some(&self.rows[(dow * 2 + interval.holiday as usize) * self.buckets + min / self.bucket])
+            },
+            _ => None,
+        }
+    }
+
+    // Call `f` with each row charged for a portion of the interval's energy and its kWh
+    fn each_portion<F: FnMut(&'a Tariff, f64)>(&self, interval: &Interval, f: F) -> Result<()> {
+        match self.candidates(interval) {
+            Some(rows) => each_portion(interval, rows.iter().map(|r| &self.tariff[*r]), f),
+            None => each_portion(interval, self.tariff.iter().filter(|x| tou_matches(x, interval.dow, interval.holiday, interval.min_since_midnight)), f),
+        }
+    }
+
+    /// The tariff rows applying to an interval's energy and the kWh charged at each, as [`tariff_portions`].
+    pub fn portions(&self, interval: &Interval) -> Result<Vec<(&'a Tariff, f64)>> {
+        let mut portions = Vec::with_capacity(1);
+        self.each_portion(interval, |t, kwh| portions.push((t, kwh)))?;
+        Ok(portions)
+    }
+
+    /// Cost ($) of an interval's energy, as [`cost_tariff`].
+    pub fn cost(&self, interval: &Interval) -> Result<f64> {
+        let mut cost = 0.0;
+        self.each_portion(interval, |t, kwh| cost += kwh * t.tariff)?;
+        Ok(cost)
+    }
+}
+
+/// An interval of energy being priced, with the usage before it for block tariffs.
+#[derive(Debug, Clone)]
+pub struct Interval {
+    pub date: NaiveDate,
+    pub dow: i16,                // day of week (0 for Monday), 6 for public holidays
+    pub holiday: bool,           // a public holiday
+    pub min_since_midnight: i32, // start of the interval
+    pub length: usize,           // interval length (minutes)
+    pub energy: f64,             // kWh
+    pub usage_day: f64,          // kWh before this interval today
+    pub usage_period: f64,       // kWh before this interval in the billing period
+}
+
+/// Cost ($) of an interval's energy under a tariff table, including usage blocks.
+///
+/// See [`tariff_portions`] for how rows are chosen, and [`TariffIndex`] to price many intervals.
+pub fn cost_tariff(interval: &Interval, tariff: &[Tariff]) -> Result<f64> {
+    let mut cost = 0.0;
+    each_portion(interval, tariff.iter().filter(|x| tou_matches(x, interval.dow, interval.holiday, interval.min_since_midnight)), |t, kwh| cost += kwh * t.tariff)?;
+    Ok(cost)
+}
+
+/// The tariff rows applying to an interval's energy and the kWh charged at each.
+///
+/// Only rows whose date range and tariff version include the interval's date and whose condition holds apply, and if
+/// any of those have Day Start `date` only they do, then if any of those have a condition only they do.
+/// Rows with a block covering the usage so far take precedence over rows without a block.
+/// Energy crossing a block threshold is split between the blocks.
+pub fn tariff_portions<'a>(interval: &Interval, tariff: &'a [Tariff]) -> Result<Vec<(&'a Tariff, f64)>> {
+    let mut portions = Vec::with_capacity(1);
+    each_portion(interval, tariff.iter().filter(|x| tou_matches(x, interval.dow, interval.holiday, interval.min_since_midnight)), |t, kwh| portions.push((t, kwh)))?;
+    Ok(portions)
+}
+
+// Call `f` with each row charged for a portion of an interval's energy and its kWh, from the rows whose days and times
+// include the interval, as described for tariff_portions
+fn each_portion<'a, I, F>(interval: &Interval, rows: I, mut f: F) -> Result<()> where
+I: Iterator<Item = &'a Tariff> + Clone,
+F: FnMut(&'a Tariff, f64) {
+    let rows = rows.filter(|x| x.applies_on(interval.date) && x.condition.as_ref().is_none_or(|c| c.holds(interval)));
+    let dates = rows.clone().any(|x| x.day_class == Some(DayClass::Date));
+    let rows = rows.filter(move |x| !dates || x.day_class == Some(DayClass::Date));
+    let conditions = rows.clone().any(|x| x.condition.is_some());
+    let rows = rows.filter(move |x| !conditions || x.condition.is_some());
+    if rows.clone().next().is_none() {
+        return Err(anyhow!("tariff_portions: no tarriff for day of week {} and min_since_midnight {}", interval.dow, interval.min_since_midnight));
+    }
+
+    let mut remaining = interval.energy;
+    let mut used = 0.0; // kWh of this interval already charged
+    loop {
+        let usage = |per: BlockPer| used + match per {
+            BlockPer::Day => interval.usage_day,
+            BlockPer::Period => interval.usage_period,
+        };
+        let row = rows.clone()
+            .find(|x| x.block.is_some_and(|b| b.start <= usage(b.per) && usage(b.per) < b.end))
+            .or_else(|| rows.clone().find(|x| x.block.is_none()))
+            .with_context(|| format!("tariff_portions: no tarriff block for {:?}", interval))?;
+        debug!("tariff_portions: {:?}, remaining kWh {}, tariff {}", interval, remaining, row.name);
+        match row.block {
+            Some(b) if remaining > 0.0 && remaining > b.end - usage(b.per) => {
+                let portion = b.end - usage(b.per);
+                f(row, portion);
+                used += portion;
+                remaining -= portion;
+            },
+            _ => {
+                f(row, remaining);
+                return Ok(());
+            },
+        }
+    }
+}
+
+/// Apply tariff to energy (either consumption or feedin), returning (line_count, col_count, price).
+///
+/// `tariff` maps (day of week, minutes since midnight) to $/kWh, e.g. a closure over [`lookup_tariff`].
+/// Dates in `holidays` are priced as Sundays.
+pub fn price_energy<F>(csv_energy: &str, tariff: F, holidays: &HashSet<String>) -> Result<(usize, usize, f64)> where
+F: Fn(i16, i32) -> Result<f64> {
+    price_days(read_csv_energy(csv_energy)?, tariff, holidays)
+}
+
+/// Apply tariff to days of energy from any reader (see [`crate::energy`]), returning (day_count, col_count, price).
+///
+/// col_count is the number of intervals in the last day plus one for the date, as for a wide CSV line.
+pub fn price_days<I, F>(days: I, tariff: F, holidays: &HashSet<String>) -> Result<(usize, usize, f64)> where
+I: IntoIterator<Item = Result<DayEnergy>>,
+F: Fn(i16, i32) -> Result<f64> {
+    price_intervals(days, |i| Ok(tariff(i.dow, i.min_since_midnight)? * i.energy), holidays)
+}
+
+/// Apply a cost function to each interval of days of energy, returning (day_count, col_count, price).
+///
+/// `cost` maps an [`Interval`] to $, e.g. a closure over [`TariffIndex::cost`].
+/// The billing period for usage blocks is all of `days`.
+pub fn price_intervals<I, F>(days: I, cost: F, holidays: &HashSet<String>) -> Result<(usize, usize, f64)> where
+I: IntoIterator<Item = Result<DayEnergy>>,
+F: FnMut(&Interval) -> Result<f64> {
+    price_intervals_from(days, 0.0, cost, holidays)
+}
+
+/// Price days like [`price_intervals`] on `threads` threads with rayon, each pricing a contiguous run of days (a
+/// chunk of `days`) with its own state from `init`, returning (day_count, col_count, price, states).
+///
+/// Each run starts with the usage in the billing period before it, summed in date order first, so usage blocks carry
+/// across runs. The runs are reduced in date order, so the states are returned in date order for the caller to merge,
+/// and costs are summed exactly (see [`crate::money`]) so the result is the same as from [`price_intervals`].
+pub fn price_intervals_parallel<S, I, F>(days: &[DayEnergy], threads: usize, init: I, cost: F, holidays: &HashSet<String>) -> Result<(usize, usize, f64, Vec<S>)> where
+S: Send,
+I: Fn() -> S + Sync,
+F: Fn(&mut S, &Interval) -> Result<f64> + Sync {
+    let chunk_len = days.len().div_ceil(threads.max(1)).max(1);
+    // usage in the billing period before each run of days, summed in the same order as price_intervals
+    let mut usage_period = 0.0;
+    let starts = days.chunks(chunk_len).map(|chunk| {
+        let start = usage_period;
+        usage_period = chunk.iter().flat_map(|d| d.energy.iter()).fold(usage_period, |sum, e| sum + e);
+        start
+    }).collect::<Vec<f64>>();
+
+    let pool = ThreadPoolBuilder::new().num_threads(threads.max(1)).build().map_err(|e| anyhow!("price_intervals_parallel: {}", e))?;
+    let priced = pool.install(|| days.par_chunks(chunk_len).zip(starts).map(|(chunk, start)| -> Result<(usize, usize, f64, Vec<S>)> {
+        let mut state = init();
+        let (lines, cols, price) = price_intervals_from(chunk.iter().cloned().map(Ok), start, |i| cost(&mut state, i), holidays)?;
+        Ok((lines, cols, price, vec![state]))
+    }).try_reduce(
+        || (0, 0, 0.0, Vec::new()),
+        // an earlier and a later run: the later run's col_count unless it's empty
+        |(lines, cols, price, mut states), (later_lines, later_cols, later_price, later_states)| {
+            states.extend(later_states);
+            Ok((lines + later_lines, if later_lines > 0 { later_cols } else { cols }, money::add(price, later_price), states))
+        },
+    ))?;
+    debug!("price_intervals_parallel: {} days in {} runs", days.len(), priced.3.len());
+    Ok(priced)
+}
+
+/// Whether the date is one of the public holidays.
+pub fn is_holiday(date: NaiveDate, holidays: &HashSet<String>) -> bool {
+    holidays.contains(&date.format("%Y%m%d").to_string())
+}
+
+/// Day of week (0 for Monday) for tariff lookup, 6 (Sunday) for public holidays.
+pub fn day_of_week(date: NaiveDate, holidays: &HashSet<String>) -> i16 {
+    match is_holiday(date, holidays) {
+        true => 6, // if it's a public holiday Sunday=6 tariff applies
+        false => date.weekday().num_days_from_monday() as i16,
+    }
+}
+
+/// Price days like [`price_intervals`] with `usage_period` kWh already used in the billing period before the first
+/// day, e.g. by the days priced in an earlier run.
+pub fn price_intervals_from<I, F>(days: I, mut usage_period: f64, mut cost: F, holidays: &HashSet<String>) -> Result<(usize, usize, f64)> where
+I: IntoIterator<Item = Result<DayEnergy>>,
+F: FnMut(&Interval) -> Result<f64> {
+    let (line_no, num_cols, sum) = days.into_iter().try_fold(
+        (0, 0, Money::default()),
+        |(line_no, _num_cols, sum), day| -> Result<(usize, usize, Money)> {
+            let day = day?;
+            let week_day = day_of_week(day.date, holidays);
+            let holiday = is_holiday(day.date, holidays);
+            debug!("price_intervals: date {}, week_day {}, interval {}", day.date, week_day, day.interval);
+
+            let mut usage_day = 0.0;
+            Ok((
+                line_no + 1,
+                (24 * 60) / day.interval + 1, // as on a day without a daylight saving changeover
+                sum + day.energy.iter().zip(day.minutes()).try_fold(
+                    Money::default(),
+                    |sum2, (energy, minute)| -> Result<Money> {
+                        if !energy.is_finite() {
+                            return Err(anyhow!("price_intervals: {} at minute {}: {} kWh is not a finite amount to price", day.date, minute, energy));
+                        }
+                        let interval = Interval {
+                            date: day.date,
+                            dow: week_day,
+                            holiday,
+                            min_since_midnight: minute,
+                            length: day.interval,
+                            energy: *energy,
+                            usage_day,
+                            usage_period,
+                        };
+                        let c = cost(&interval)?;
+                        debug!("price_intervals: {:?}, cost $ {}", interval, c);
+                        usage_day += energy;
+                        usage_period += energy;
+                        Ok(sum2 + Money::from_f64(c))
+                    })?
+            ))
+    })?;
+    Ok((line_no, num_cols, sum.to_f64()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_float_eq::*;
+
+    #[test]
+    fn test_billing_period() -> Result<()> {
+        let d = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let monthly = Billing::monthly(d(2023, 7, 15));
+        assert_eq!(monthly.period(d(2023, 7, 15)), (d(2023, 7, 15), d(2023, 8, 15)));
+        assert_eq!(monthly.period(d(2023, 7, 14)), (d(2023, 6, 15), d(2023, 7, 15)));
+        assert_eq!(monthly.period(d(2022, 1, 31)), (d(2022, 1, 15), d(2022, 2, 15)));
+        let quarterly = Billing { period: BillingPeriod::from_str("Quarterly")?, anchor: d(2023, 7, 15), pro_rata: false };
+        assert_eq!(quarterly.period(d(2023, 10, 14)), (d(2023, 7, 15), d(2023, 10, 15)));
+        assert_eq!(quarterly.period(d(2023, 5, 1)), (d(2023, 4, 15), d(2023, 7, 15)));
+        assert_eq!(quarterly.period(d(2024, 1, 15)), (d(2024, 1, 15), d(2024, 4, 15)));
+        // the last day of shorter months
+        assert_eq!(Billing::monthly(d(2023, 1, 31)).period(d(2023, 3, 1)), (d(2023, 2, 28), d(2023, 3, 31)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_minutes_since_midnight() -> Result<()> {
+        assert_eq!(minutes_since_midnight("00:00:00")?, 0);
+        assert_eq!(minutes_since_midnight("12:34:56")?, 754);
+        assert_eq!(minutes_since_midnight("23:59:59")?, 1439);
+        Ok(())
+    }
+
+    #[test]
+    // very similar to main
+    fn test_price_energy() -> Result<()> {
+        let daily_supply = load_supply_charge("data/test/tariff/supply.csv")?;
+        assert_f64_near!(daily_supply, 1.45398);
+
+        let holidays = load_public_holidays("data/test/publicHolidaysTest.csv")?;
+        assert!(!holidays.contains("20230807"));
+        assert!(holidays.contains("20230808"));
+        assert!(holidays.contains("20500101"));
+
+        let consumption_tariff = load_tariff("data/test/tariff/consumption.csv")?;
+        // println!("consumption_tariff {:?}", consumption_tariff);
+        let (line_count, col_count, consumption_cost) = price_energy(
+            "data/test/energy/consumption.csv",
+            |dow, min_since_midnight| lookup_tariff(dow, min_since_midnight, &consumption_tariff),
+            &holidays
+        )?;
+        println!("line_count {}, col_count {}, consumption cost {}", line_count, col_count, consumption_cost);
+        assert_f64_near!(consumption_cost, 0.14215773);
+
+        let feedin_tariff = load_tariff("data/test/tariff/feedIn.csv")?;
+        let (line_count2, col_count2, feedin_cost) = price_energy(
+            "data/test/energy/feedIn.csv",
+            |dow, min_since_midnight| lookup_tariff(dow, min_since_midnight, &feedin_tariff),
+            &holidays
+        )?;
+        println!("line_count2 {}, col_count2 {}, feedin cost {}", line_count2, col_count2, feedin_cost);
+        assert_f64_near!(feedin_cost, -0.15582);
+
+        let supply_cost = line_count as f64 * daily_supply;
+        let total = consumption_cost + feedin_cost + supply_cost;
+        println!("total cost {}", total);
+        assert_f64_near!(total, 4.34827773);
+        Ok(())
+    }
+
+    #[test]
+    // 0.164 kWh per day: the first 0.1 kWh at $0.20, the rest at $0.30
+    fn test_cost_tariff_blocks() -> Result<()> {
+        let tariff = load_tariff("data/test/tariff/consumptionBlock.csv")?;
+        assert_eq!(tariff[0].block, Some(Block { start: 0.0, end: 0.1, per: BlockPer::Day }));
+        let (_, _, cost) = price_intervals(
+            read_csv_energy("data/test/energy/consumption.csv")?,
+            |i| cost_tariff(i, &tariff),
+            &HashSet::new()
+        )?;
+        assert_float_absolute_eq!(cost, 3.0 * (0.1 * 0.20 + 0.064 * 0.30), 1e-12);
+
+        let interval = Interval {
+            date: NaiveDate::from_ymd_opt(2023, 8, 7).unwrap(),
+            dow: 0,
+            holiday: false,
+            min_since_midnight: 480,
+            length: 480,
+            energy: 0.028,
+            usage_day: 0.073,
+            usage_period: 0.073,
+        };
+        let portions = tariff_portions(&interval, &tariff)?;
+        assert_eq!(portions.iter().map(|(t, _)| t.name.as_str()).collect::<Vec<_>>(), vec!["Block 1", "Block 2"]);
+        assert_float_absolute_eq!(portions[0].1, 0.027, 1e-12);
+        assert_float_absolute_eq!(portions[1].1, 0.001, 1e-12);
+
+        // first 0.3 kWh in the period at $0.20
+        let tariff = load_tariff("data/test/tariff/consumptionBlockPeriod.csv")?;
+        let (_, _, cost) = price_intervals(
+            read_csv_energy("data/test/energy/consumption.csv")?,
+            |i| cost_tariff(i, &tariff),
+            &HashSet::new()
+        )?;
+        assert_float_absolute_eq!(cost, 0.3 * 0.20 + (3.0 * 0.164 - 0.3) * 0.30, 1e-12);
+        Ok(())
+    }
+
+    #[test]
+    fn test_date_range() -> Result<()> {
+        let d = |s| NaiveDate::parse_from_str(s, "%Y%m%d").unwrap();
+        let summer = parse_date_range(Some("1201"), Some("0301"))?.unwrap();
+        assert!(summer.contains(d("20231201")));
+        assert!(summer.contains(d("20240229")));
+        assert!(!summer.contains(d("20240301")));
+        assert!(!summer.contains(d("20230807")));
+        let from = parse_date_range(Some("20230807"), None)?.unwrap();
+        assert!(!from.contains(d("20230806")));
+        assert!(from.contains(d("20230807")));
+
+        // low season rate for 20230806 and 20230807, high season rate from 20230808
+        let tariff = load_tariff("data/test/tariff/consumptionSeasonal.csv")?;
+        let (_, _, cost) = price_intervals(
+            read_csv_energy("data/test/energy/consumption.csv")?,
+            |i| cost_tariff(i, &tariff),
+            &HashSet::new()
+        )?;
+        assert_float_absolute_eq!(cost, 2.0 * 0.164 * 0.25 + 0.164 * 0.35, 1e-12);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tariff_versions() -> Result<()> {
+        let price = |tariff: &[Tariff]| price_intervals(read_csv_energy("data/test/energy/consumption.csv")?, |i| cost_tariff(i, tariff), &HashSet::new());
+        // Old for 20230806, New from 20230807
+        let tariff = load_tariff("data/test/tariff/consumptionVersions.csv")?;
+        assert_eq!(tariff[0].effective, parse_date_range(None, Some("20230807"))?);
+        assert_float_absolute_eq!(price(&tariff)?.2, 0.164 * 0.20 + 2.0 * 0.164 * 0.30, 1e-12);
+        // and Flat from 20230808
+        let tariff = load_tariff_versions(&["data/test/tariff/consumptionVersions.csv", "20230808=data/test/tariff/consumptionFlat.csv"])?;
+        assert_eq!(tariff[1].effective, parse_date_range(Some("20230807"), Some("20230808"))?);
+        assert_float_absolute_eq!(price(&tariff)?.2, 0.164 * (0.20 + 0.30 + 0.40), 1e-12);
+        assert!(check_tariff_coverage(&tariff).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_supply_charges() -> Result<()> {
+        assert_eq!(load_supply_charges("data/test/tariff/supply.csv")?, (1.45398, Vec::new()));
+        let (base, changes) = load_supply_charges("data/test/tariff/supplyVersions.csv")?;
+        assert_eq!(base, 1.0);
+        assert_eq!(changes, vec![(NaiveDate::from_ymd_opt(2023, 8, 7).unwrap(), 1.1), (NaiveDate::from_ymd_opt(2023, 8, 8).unwrap(), 1.25)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_region_holidays() -> Result<()> {
+        let file = "data/test/publicHolidaysRegions.csv";
+        let dates = |region| -> Result<Vec<String>> {
+            let mut dates = load_region_holidays(file, region)?.into_iter().collect::<Vec<_>>();
+            dates.sort();
+            Ok(dates)
+        };
+        assert_eq!(dates(Some(Region::Nsw))?, vec!["20230101", "20230102", "20230126", "20230612", "20231225"]);
+        assert_eq!(dates(Some(Region::Vic))?, vec!["20230126", "20230313", "20231225"]);
+        assert_eq!(dates(None)?.len(), 7);
+        assert_eq!(load_region_holidays("data/test/publicHolidaysTest.csv", Some(Region::Wa))?, load_public_holidays("data/test/publicHolidaysTest.csv")?);
+        Ok(())
+    }
+
+    #[test]
+    // weekdays 0.073 kWh Off-peak at $0.10 and 0.091 kWh Peak at $0.30, weekend days and holidays 0.164 kWh at $0.20
+    fn test_weekend() -> Result<()> {
+        assert_eq!(Weekend::from_str("fri,sat")?, Weekend::from_str("Friday, Saturday")?);
+        assert_eq!(Weekend::from_str("4,5")?, Weekend::from_str("FRI,sat")?);
+        assert_eq!(Weekend::from_str("sat,sun")?, Weekend::default());
+        assert!(Weekend::from_str("fr").is_err());
+        assert!(Weekend::from_str("7").is_err());
+        assert!(load_tariff("data/test/tariff/consumption.csv")?.iter().all(|t| t.day_class.is_none()));
+
+        let holidays = load_public_holidays("data/test/publicHolidaysTest.csv")?; // Tuesday 8th
+        let mut tariff = load_tariff("data/test/tariff/consumptionWeekend.csv")?;
+        assert!(check_tariff_coverage(&tariff).is_empty());
+        let cost = |tariff: &[Tariff], holidays: &HashSet<String>| -> Result<f64> {
+            Ok(price_intervals(read_csv_energy("data/test/energy/consumption.csv")?, |i| cost_tariff(i, tariff), holidays)?.2)
+        };
+        assert_f64_near!(cost(&tariff, &holidays)?, 0.0328 + 0.0346 + 0.0328);
+        set_weekend(&mut tariff, Weekend::from_str("fri,sat")?); // Sunday 6th is a weekday
+        assert_f64_near!(cost(&tariff, &holidays)?, 0.0346 + 0.0346 + 0.0328);
+        assert_f64_near!(cost(&tariff, &HashSet::new())?, 0.0346 * 3.0);
+        assert_eq!(check_tariff_coverage(&tariff[..2]), vec!["no tariff on Fri-Sat from 00:00 to 24:00"]);
+        Ok(())
+    }
+
+    #[test]
+    // Monday 7th is free, Sunday 6th and Tuesday 8th are priced as in test_price_energy
+    fn test_date_rows() -> Result<()> {
+        let tariff = load_tariff("data/test/tariff/consumptionDates.csv")?;
+        assert!(check_tariff_coverage(&tariff).is_empty());
+        let cost = price_intervals(read_csv_energy("data/test/energy/consumption.csv")?, |i| cost_tariff(i, &tariff), &HashSet::new())?.2;
+        assert_f64_near!(cost, 0.04416192 + 0.05383389);
+        let at = |d: &str, min| tariff_at(&tariff, &HashSet::new(), NaiveDate::parse_from_str(d, "%Y%m%d").unwrap(), min).map(|t| t.name.as_str());
+        assert_eq!((at("20230807", 600), at("20230808", 600)), (Some("Event day (free)"), Some("Shoulder")));
+        assert_eq!(lookup_tariff(0, 600, &tariff)?, 0.35695);
+        Ok(())
+    }
+
+    #[test]
+    fn test_not_a_number() -> Result<()> {
+        let tariff = load_tariff("data/test/tariff/consumption.csv")?;
+        let mut days = read_csv_energy("data/test/energy/consumption.csv")?.collect::<Result<Vec<_>>>()?;
+        days[1].energy[2] = f64::NAN;
+        let priced = price_intervals(days.into_iter().map(Ok), |i| cost_tariff(i, &tariff), &HashSet::new());
+        assert_eq!(priced.unwrap_err().to_string(), "price_intervals: 2023-08-07 at minute 960: NaN kWh is not a finite amount to price");
+        Ok(())
+    }
+
+    #[test]
+    // the index gives the rows and kWh of a scan of the rows, or its error, with usage blocks, dates and conditions
+    fn test_tariff_index() -> Result<()> {
+        let holidays = load_public_holidays("data/test/publicHolidaysTest.csv")?;
+        for file in ["consumption", "consumptionBlock", "consumptionBlockPeriod", "consumptionDates", "consumptionCondition", "consumptionWeekWrap", "consumptionOvernight", "consumptionSeasonal", "consumptionVersions", "consumptionWeekend"] {
+            let tariff = load_tariff(&format!("data/test/tariff/{}.csv", file))?;
+            let index = TariffIndex::new(&tariff);
+            let names = |portions: Result<Vec<(&Tariff, f64)>>| portions.map(|p| p.iter().map(|(t, kwh)| (t.name.clone(), *kwh)).collect::<Vec<_>>()).map_err(|e| e.to_string());
+            for date in NaiveDate::from_ymd_opt(2023, 8, 6).unwrap().iter_days().take(8) {
+                for (min, usage) in (0..24 * 60).step_by(5).zip([0.0, 0.05, 0.5, 600.0].into_iter().cycle()) {
+                    let interval = Interval {
+                        date,
+                        dow: day_of_week(date, &holidays),
+                        holiday: is_holiday(date, &holidays),
+                        min_since_midnight: min,
+                        length: 5,
+                        energy: 0.2,
+                        usage_day: usage,
+                        usage_period: usage * 10.0,
+                    };
+                    assert_eq!(names(index.portions(&interval)), names(tariff_portions(&interval, &tariff)), "{} {:?}", file, interval);
+                    assert_eq!(index.cost(&interval).ok(), cost_tariff(&interval, &tariff).ok());
+                }
+            }
+        }
+        let tariff = load_tariff("data/test/tariff/consumption.csv")?;
+        // rates change on the hour, so hourly buckets, and on the quarter hour with peak from 17:15
+        assert_eq!((TariffIndex::new(&tariff).bucket, TariffIndex::new(&tariff).buckets), (60, 24));
+        let peak = TariffIndex::new(&tariff[3..4]); // Peak 17:00 to 20:00 weekdays
+        assert_eq!((peak.bucket, peak.buckets), (60, 24));
+        let mut quarter = load_tariff("data/test/tariff/consumption.csv")?;
+        quarter[3].time_start = 17 * 60 + 15;
+        assert_eq!(TariffIndex::new(&quarter).bucket, 15);
+        let at = |min| Interval { date: NaiveDate::from_ymd_opt(2023, 8, 7).unwrap(), dow: 0, holiday: false, min_since_midnight: min, length: 30, energy: 1.0, usage_day: 0.0, usage_period: 0.0 };
+        assert_eq!(peak.cost(&at(17 * 60 + 2))?, 0.41745); // not an interval start
+        assert!(peak.cost(&at(600)).is_err());
+        assert!(peak.cost(&at(24 * 60 + 60)).is_err()); // outside the day, scanned
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = load_tariff("data/test/tariff/consumptionBad.csv").unwrap_err();
+        assert_eq!(format!("{:#}", err), "load_tariff: data/test/tariff/consumptionBad.csv: line 3: column 5 ($/kWh) '0.4l745': invalid float literal");
+        assert_eq!(parse_days("5", "0").unwrap(), (5, 0, None));
+        for (start, end) in [("0", "9"), ("-1", "5"), ("0", "x")] {
+            assert!(parse_days(start, end).is_err(), "{}-{}", start, end);
+        }
+    }
+
+    #[test]
+    // a row's Period, otherwise its name unless it's all day every day
+    fn test_tou_period() -> Result<()> {
+        let periods = |tariff: &[Tariff]| tariff.iter().map(Tariff::tou_period).collect::<Vec<_>>();
+        use TouPeriod::*;
+        assert_eq!(periods(&load_tariff("data/test/tariff/consumption.csv")?), vec![Some(OffPeak), Some(Peak), Some(Shoulder), Some(Peak), Some(Shoulder), Some(OffPeak), Some(OffPeak)]);
+        assert_eq!(periods(&load_tariff("data/NB/redEnergy2024SingleTariff/consumption.csv")?), vec![None]); // "Shoulder always"
+        let csv = "Day Start,Day End,Time Start,Time End,$/kWh,Name,,,,,,,Condition,Period\n\
+            0,7,00:00:00,24:00:00,0.20,Anytime,,,,,,,,off-peak\n\
+            0,5,14:00:00,20:00:00,0.40,Evening,,,,,,,,Peak\n\
+            5,7,00:00:00,24:00:00,0.30,Weekend peak,,,,,,,,\n";
+        assert_eq!(periods(&load_tariff_from("csv", csv.as_bytes())?), vec![Some(OffPeak), Some(Peak), Some(Peak)]);
+        assert!(load_tariff_from("csv", "Day Start,Day End,Time Start,Time End,$/kWh,Name,,,,,,,Condition,Period\n0,7,00:00:00,24:00:00,0.2,x,,,,,,,,super\n".as_bytes()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    // Off-peak 22:00 to 07:00 in one row prices the same as the two rows in consumption.csv
+    fn test_overnight() -> Result<()> {
+        let tariff = load_tariff("data/test/tariff/consumptionOvernight.csv")?;
+        assert!(check_tariff_coverage(&tariff).is_empty());
+        assert_eq!((lookup_tariff(0, 23 * 60, &tariff)?, lookup_tariff(0, 60, &tariff)?, lookup_tariff(0, 7 * 60, &tariff)?), (0.26928, 0.26928, 0.41745));
+        let holidays = load_public_holidays("data/test/publicHolidaysTest.csv")?;
+        let (_, _, cost) = price_energy("data/test/energy/consumption.csv", |dow, min| lookup_tariff(dow, min, &tariff), &holidays)?;
+        assert_f64_near!(cost, 0.14215773);
+        let mut tariff = tariff;
+        tariff[0].time_end = 8 * 60; // Off-peak to 08:00 overlaps Peak
+        assert_eq!(check_tariff_coverage(&tariff), vec!["rows 2 (Off-peak) and 3 (Peak) overlap on Mon-Fri from 07:00 to 08:00"]);
+        Ok(())
+    }
+
+    #[test]
+    // Sunday 6th is the first weekend at $0.10, Monday 7th's 16:00 interval is over 0.1 kWh at $0.30 and
+    // the rest at $0.20, Tuesday 8th is a holiday so a weekend day at $0.20, or like Monday without holidays
+    fn test_conditions() -> Result<()> {
+        let tariff = load_tariff("data/test/tariff/consumptionCondition.csv")?;
+        assert!(tariff[0].condition.is_none());
+        assert_eq!(tariff[2].condition.as_ref().map(|c| c.to_string()), Some("weekend and week == 1".to_string()));
+        assert!(check_tariff_coverage(&tariff).is_empty());
+        let cost = |holidays: &HashSet<String>| -> Result<f64> {
+            Ok(price_intervals(read_csv_energy("data/test/energy/consumption.csv")?, |i| cost_tariff(i, &tariff), holidays)?.2)
+        };
+        let monday = 0.101 * 0.20 + 0.063 * 0.30;
+        assert_f64_near!(cost(&load_public_holidays("data/test/publicHolidaysTest.csv")?)?, 0.0164 + monday + 0.0328);
+        assert_f64_near!(cost(&HashSet::new())?, 0.0164 + monday * 2.0);
+        assert_eq!(lookup_tariff(0, 600, &tariff)?, 0.20);
+        Ok(())
+    }
+
+    #[test]
+    // Off-peak all day from Saturday to Monday in one row, so Monday 7th is all off-peak
+    fn test_week_wrap() -> Result<()> {
+        let tariff = load_tariff("data/test/tariff/consumptionWeekWrap.csv")?;
+        assert!(check_tariff_coverage(&tariff).is_empty());
+        assert_eq!((lookup_tariff(0, 600, &tariff)?, lookup_tariff(1, 600, &tariff)?, lookup_tariff(6, 600, &tariff)?), (0.26928, 0.35695, 0.26928));
+        let (_, _, cost) = price_energy("data/test/energy/consumption.csv", |dow, min| lookup_tariff(dow, min, &tariff), &HashSet::new())?;
+        assert_f64_near!(cost, (0.164 * 2.0 + 0.073) * 0.26928 + 0.028 * 0.41745 + 0.063 * 0.35695);
+        let index = TariffIndex::new(&tariff);
+        let (_, _, index_cost) = price_intervals(read_csv_energy("data/test/energy/consumption.csv")?, |i| index.cost(i), &HashSet::new())?;
+        assert_eq!(index_cost, cost);
+        let mut tariff = tariff;
+        tariff[5].day_end = 2; // Saturday to Tuesday overlaps Tuesday's rows
+        assert_eq!(check_tariff_coverage(&tariff)[0], "rows 2 (Off-peak) and 7 (Off-peak (Saturday to Monday)) overlap on Tue from 00:00 to 07:00");
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_tariff_coverage() -> Result<()> {
+        assert!(check_tariff_coverage(&load_tariff("data/test/tariff/consumption.csv")?).is_empty());
+        assert!(check_tariff_coverage(&load_tariff("data/test/tariff/consumptionBlock.csv")?).is_empty());
+        let mut tariff = load_tariff("data/test/tariff/consumption.csv")?;
+        tariff[1].time_end = 10 * 60; // Peak 07:00 to 10:00 overlaps Shoulder
+        tariff[6].day_start = 6;      // no weekend row for Saturday
+        assert_eq!(check_tariff_coverage(&tariff), vec![
+            "rows 3 (Peak) and 4 (Shoulder) overlap on Mon-Fri from 09:00 to 10:00",
+            "no tariff on Sat from 00:00 to 24:00",
+        ]);
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+//! A progress bar on standard error while days of energy data are read and priced, so long runs (e.g. years of 1
+//! minute data) aren't silent: days and intervals so far, the elapsed time and, when the size of the file being read
+//! or the number of days is known, indicatif's estimate of the time left.
+
+use crate::energy::DayEnergy;
+use anyhow::Result;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressFinish, ProgressStyle};
+use std::cell::Cell;
+use std::io::{self, IsTerminal, Read};
+use std::rc::Rc;
+
+/// The bytes read so far from a file, shared between the [`Counting`] reader and the [`Progress`] bar.
+#[derive(Debug, Clone, Default)]
+pub struct BytesRead(Rc<Cell<u64>>);
+
+impl BytesRead {
+    pub fn get(&self) -> u64 {
+        self.0.get()
+    }
+}
+
+/// A reader counting the bytes read through it.
+pub struct Counting<R> {
+    inner: R,
+    read: BytesRead,
+}
+
+impl<R: Read> Counting<R> {
+    pub fn new(inner: R, read: &BytesRead) -> Counting<R> {
+        Counting { inner, read: read.clone() }
+    }
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.0.set(self.read.0.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// Progress through days of energy data, an indicatif bar on standard error if it's a terminal.
+#[derive(Debug)]
+pub struct Progress {
+    bar: ProgressBar,
+    file: Option<BytesRead>, // bytes read so far of the file being read, if its size is known
+    days: usize,
+    intervals: usize,
+}
+
+impl Progress {
+    /// A progress bar labelled e.g. `consumption`, through `total` days if known, shown only if `enabled` (i.e. not
+    /// `--quiet`) and standard error is a terminal.
+    pub fn new(label: &str, total: Option<usize>, enabled: bool) -> Progress {
+        let target = match enabled && io::stderr().is_terminal() {
+            true => ProgressDrawTarget::stderr(),
+            false => ProgressDrawTarget::hidden(),
+        };
+        let bar = ProgressBar::with_draw_target(total.map(|t| t as u64), target).with_style(match total {
+            Some(_) => style("[{bar:30}] {pos}/{len} days, {msg}, {elapsed}, {eta} left"),
+            None => style("{spinner} {msg}, {elapsed}"),
+        });
+        let progress = Progress { bar: bar.with_prefix(label.to_string()).with_finish(ProgressFinish::AndClear), file: None, days: 0, intervals: 0 };
+        progress.bar.set_message(progress.message());
+        progress
+    }
+
+    /// Progress through a file of `size` bytes as it's read, counting `read`, rather than through a known number of days.
+    pub fn reading(mut self, read: &BytesRead, size: u64) -> Progress {
+        self.bar.set_length(size);
+        self.bar.set_style(style("[{bar:30}] {bytes}/{total_bytes}, {msg}, {elapsed}, {eta} left"));
+        self.file = Some(read.clone());
+        self
+    }
+
+    /// Count a day, moving the bar on (indicatif limits how often it's redrawn).
+    pub fn tick(&mut self, day: &DayEnergy) {
+        self.days += 1;
+        self.intervals += day.energy.len();
+        match &self.file {
+            Some(read) => self.bar.set_position(read.get()),
+            None => self.bar.set_position(self.days as u64),
+        }
+        self.bar.set_message(self.message());
+    }
+
+    /// The days and intervals so far, e.g. `120 days, 5760 intervals`.
+    pub fn message(&self) -> String {
+        format!("{} days, {} intervals", self.days, self.intervals)
+    }
+
+    /// How far through the file's bytes or the days the bar is, and their total if known.
+    pub fn position(&self) -> (u64, Option<u64>) {
+        (self.bar.position(), self.bar.length())
+    }
+}
+
+// A bar style of the label and then `template`, e.g. `consumption: [=====>    ] 120/365 days, ...`
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(&format!("{{prefix}}: {}", template)).expect("progress template").progress_chars("=> ")
+}
+
+/// The days, counted by `progress` as they're read.
+pub fn with_progress<I>(days: I, mut progress: Progress) -> impl Iterator<Item = Result<DayEnergy>> where
+I: IntoIterator<Item = Result<DayEnergy>> {
+    days.into_iter().inspect(move |day| {
+        if let Ok(day) = day {
+            progress.tick(day);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::energy::read_csv_energy;
+
+    #[test]
+    fn test_progress() -> Result<()> {
+        let days = read_csv_energy("data/test/energy/consumption.csv")?.collect::<Result<Vec<_>>>()?;
+        let (mut known, mut unknown) = (Progress::new("consumption", Some(12), false), Progress::new("consumption", None, false));
+        days.iter().for_each(|d| {
+            known.tick(d);
+            unknown.tick(d);
+        });
+        assert_eq!(known.message(), "3 days, 9 intervals");
+        assert_eq!(known.position(), (3, Some(12)));
+        assert_eq!(unknown.position(), (3, None));
+        assert_eq!(with_progress(days.iter().cloned().map(Ok), Progress::new("consumption", None, false)).count(), 3);
+
+        // through a file as it's read
+        let read = BytesRead::default();
+        let mut file = Progress::new("consumption", None, false).reading(&read, 4_000_000);
+        assert_eq!(file.position(), (0, Some(4_000_000)));
+        let mut counting = Counting::new(io::repeat(b'0').take(1_000_000), &read);
+        io::copy(&mut counting, &mut io::sink())?;
+        days.iter().for_each(|d| file.tick(d));
+        assert_eq!(file.message(), "3 days, 9 intervals");
+        assert_eq!(file.position(), (1_000_000, Some(4_000_000)));
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+//! The `elec` Python extension module (with the `python` feature, built by maturin), pricing with the JSON requests
+//! in [`crate::api`] and returning the responses as Python dicts and lists.
+//!
+//! Each file may be given as a path, its contents as a `str` or `bytes`, or a pandas DataFrame (written as CSV without
+//! its index, so energy data in a DataFrame has the columns of a wide CSV file: Date/Time then one per interval).
+//! Errors are raised as `elec.ElecError`. Pricing releases the GIL.
+
+use crate::api;
+use crate::json::Json;
+use anyhow::Result;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict, PyFloat, PyInt, PyList, PyString, PyTuple};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+create_exception!(elec, ElecError, PyException, "An error from the pricing library.");
+
+fn error(e: anyhow::Error) -> PyErr {
+    ElecError::new_err(format!("{:#}", e))
+}
+
+// The contents of a file given as a path, its contents or a DataFrame
+fn contents(file: &Bound<'_, PyAny>) -> PyResult<Json> {
+    let read = |path: &Path| std::fs::read_to_string(path).map_err(|e| ElecError::new_err(format!("{}: {}", path.display(), e)));
+    if file.hasattr("to_csv")? {
+        let kwargs = PyDict::new(file.py());
+        kwargs.set_item("index", false)?;
+        return Ok(file.call_method("to_csv", (), Some(&kwargs))?.extract::<String>()?.into());
+    }
+    if let Ok(bytes) = file.extract::<Cow<[u8]>>() {
+        return String::from_utf8(bytes.into_owned()).map(Json::from).map_err(|e| ElecError::new_err(format!("contents: not UTF-8: {}", e)));
+    }
+    if let Ok(text) = file.extract::<String>() {
+        return match !text.contains('\n') && Path::new(&text).is_file() {
+            true => read(Path::new(&text)).map(Json::from),
+            false => Ok(text.into()),
+        };
+    }
+    read(&file.extract::<PathBuf>()?).map(Json::from)
+}
+
+// A request of the files given, leaving out those that are None
+fn files(files: &[(&str, Option<&Bound<'_, PyAny>>)]) -> PyResult<Vec<(String, Json)>> {
+    files.iter().filter_map(|(k, v)| v.map(|v| contents(v).map(|c| (k.to_string(), c)))).collect()
+}
+
+fn holidays(request: &mut Vec<(String, Json)>, public_holidays: Option<&Bound<'_, PyAny>>, holiday_region: Option<&str>) -> PyResult<()> {
+    request.extend(files(&[("public_holidays", public_holidays)])?);
+    if let Some(region) = holiday_region {
+        request.push(("holiday_region".to_string(), region.into()));
+    }
+    Ok(())
+}
+
+// A Python value as JSON, e.g. a CDR plan given as a dict
+fn to_json(value: &Bound<'_, PyAny>) -> PyResult<Json> {
+    Ok(if value.is_none() {
+        Json::Null
+    } else if let Ok(b) = value.cast::<PyBool>() {
+        Json::Bool(b.is_true())
+    } else if value.is_instance_of::<PyInt>() || value.is_instance_of::<PyFloat>() {
+        Json::Number(value.extract()?)
+    } else if let Ok(s) = value.cast::<PyString>() {
+        Json::String(s.to_str()?.to_string())
+    } else if let Ok(d) = value.cast::<PyDict>() {
+        Json::Object(d.iter().map(|(k, v)| Ok((k.extract::<String>()?, to_json(&v)?))).collect::<PyResult<_>>()?)
+    } else if value.is_instance_of::<PyList>() || value.is_instance_of::<PyTuple>() {
+        Json::Array(value.try_iter()?.map(|v| to_json(&v?)).collect::<PyResult<_>>()?)
+    } else {
+        return Err(ElecError::new_err(format!("to_json: can't convert {} to JSON", value.get_type().name()?)));
+    })
+}
+
+// A JSON response as Python values, whole numbers as ints
+fn to_python<'py>(py: Python<'py>, json: &Json) -> PyResult<Bound<'py, PyAny>> {
+    Ok(match json {
+        Json::Null => py.None().into_bound(py),
+        Json::Bool(b) => PyBool::new(py, *b).to_owned().into_any(),
+        Json::Number(n) if n.fract() == 0.0 && n.abs() < 9e15 => (*n as i64).into_pyobject(py)?.into_any(),
+        Json::Number(n) => PyFloat::new(py, *n).into_any(),
+        Json::String(s) => PyString::new(py, s).into_any(),
+        Json::Array(a) => PyList::new(py, a.iter().map(|j| to_python(py, j)).collect::<PyResult<Vec<_>>>()?)?.into_any(),
+        Json::Object(pairs) => {
+            let dict = PyDict::new(py);
+            for (k, v) in pairs {
+                dict.set_item(k, to_python(py, v)?)?;
+            }
+            dict.into_any()
+        },
+    })
+}
+
+// Answer a request with the GIL released
+fn call<'py>(py: Python<'py>, request: Vec<(String, Json)>, f: fn(&Json) -> Result<Json>) -> PyResult<Bound<'py, PyAny>> {
+    let request = Json::Object(request);
+    let response = py.detach(|| f(&request)).map_err(error)?;
+    to_python(py, &response)
+}
+
+/// The bill (as `elec price --format json`) with its monthly totals, for a plan given by its tariff files or a CDR
+/// plan object (a dict).
+#[pyfunction]
+#[pyo3(signature = (consumption, consumption_tariff=None, daily=None, feedin_tariff=None, demand_tariff=None, feedin=None, cdr_plan=None, public_holidays=None, holiday_region=None))]
+#[allow(clippy::too_many_arguments)]
+fn price<'py>(
+    py: Python<'py>, consumption: &Bound<'py, PyAny>, consumption_tariff: Option<&Bound<'py, PyAny>>, daily: Option<&Bound<'py, PyAny>>,
+    feedin_tariff: Option<&Bound<'py, PyAny>>, demand_tariff: Option<&Bound<'py, PyAny>>, feedin: Option<&Bound<'py, PyAny>>,
+    cdr_plan: Option<&Bound<'py, PyAny>>, public_holidays: Option<&Bound<'py, PyAny>>, holiday_region: Option<&str>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let mut request = files(&[
+        ("consumption", Some(consumption)), ("consumption_tariff", consumption_tariff), ("daily", daily),
+        ("feedin_tariff", feedin_tariff), ("demand_tariff", demand_tariff), ("feedin", feedin),
+    ])?;
+    if let Some(plan) = cdr_plan {
+        request.push(("cdr_plan".to_string(), to_json(plan)?));
+    }
+    holidays(&mut request, public_holidays, holiday_region)?;
+    call(py, request, api::price)
+}
+
+/// Bills for plans, a dict of plan name to a dict of its files (as the keyword arguments of `price`), ranked cheapest
+/// first, each with its rank and plan name.
+#[pyfunction]
+#[pyo3(signature = (plans, consumption, feedin=None, public_holidays=None, holiday_region=None))]
+fn compare<'py>(
+    py: Python<'py>, plans: &Bound<'py, PyDict>, consumption: &Bound<'py, PyAny>, feedin: Option<&Bound<'py, PyAny>>,
+    public_holidays: Option<&Bound<'py, PyAny>>, holiday_region: Option<&str>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let mut request = files(&[("consumption", Some(consumption)), ("feedin", feedin)])?;
+    let mut by_name = Vec::new();
+    for (name, plan) in plans.iter() {
+        let mut plan_files = Vec::new();
+        for (k, v) in plan.cast::<PyDict>()?.iter() {
+            let k = k.extract::<String>()?;
+            if !v.is_none() {
+                let value = if k == "cdr_plan" { to_json(&v)? } else { contents(&v)? };
+                plan_files.push((k, value));
+            }
+        }
+        by_name.push((name.extract::<String>()?, Json::Object(plan_files)));
+    }
+    request.push(("plans".to_string(), Json::Object(by_name)));
+    holidays(&mut request, public_holidays, holiday_region)?;
+    call(py, request, api::compare)
+}
+
+/// The rows of a tariff file: name, days (0 for Monday, end exclusive), times (minutes since midnight, end exclusive)
+/// and tariff ($/kWh).
+#[pyfunction]
+fn load_tariff<'py>(py: Python<'py>, tariff: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
+    call(py, files(&[("tariff", Some(tariff))])?, api::load_tariff)
+}
+
+/// The cost of energy data under a tariff alone, without supply charges: days, columns and cost.
+#[pyfunction]
+#[pyo3(signature = (tariff, energy, public_holidays=None, holiday_region=None))]
+fn price_energy<'py>(
+    py: Python<'py>, tariff: &Bound<'py, PyAny>, energy: &Bound<'py, PyAny>, public_holidays: Option<&Bound<'py, PyAny>>,
+    holiday_region: Option<&str>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let mut request = files(&[("tariff", Some(tariff)), ("energy", Some(energy))])?;
+    holidays(&mut request, public_holidays, holiday_region)?;
+    call(py, request, api::price_energy)
+}
+
+/// Electricity pricing from Python, calling the same pricing logic as the elec command line tool.
+#[pymodule]
+#[pyo3(name = "elec")]
+fn elec_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("ElecError", m.py().get_type::<ElecError>())?;
+    m.add_function(wrap_pyfunction!(price, m)?)?;
+    m.add_function(wrap_pyfunction!(compare, m)?)?;
+    m.add_function(wrap_pyfunction!(load_tariff, m)?)?;
+    m.add_function(wrap_pyfunction!(price_energy, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,245 @@
+//! Reconciling a retailer's bill against the bill recalculated from your energy data.
+//!
+//! The retailer's line items are given in a TOML file:
+//!
+//! ```toml
+//! from = 2023-08-06   # optional billing period, used unless --from and --to are given
+//! to = 2023-08-08
+//! total = 1.23        # optional
+//!
+//! [[usage]]           # one per rate, without a name for all consumption
+//! name = "Peak"
+//! kwh = 0.219
+//! amount = 0.14
+//!
+//! [supply]
+//! days = 3
+//! amount = 3.3
+//!
+//! [[feedin]]          # credits are negative, as in the price output
+//! kwh = 2.226
+//! amount = -0.16
+//! ```
+//!
+//! Usage and feedin items are matched to the tariff names of the recalculated bill (ignoring case).
+//! Every quantity and amount given is compared, missing ones are left out.
+
+use crate::bill::{Bill, ByName};
+use crate::json::Json;
+use crate::money;
+use crate::toml::{load_toml, Toml};
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+
+/// A line item of a retailer's bill, e.g. Peak usage.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineItem {
+    pub name: Option<String>, // tariff name, None for the whole component
+    pub quantity: Option<f64>, // kWh, or days for supply
+    pub amount: Option<f64>,   // $
+}
+
+/// The line items of a retailer's bill.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RetailerBill {
+    pub from: Option<NaiveDate>, // first day of the billing period
+    pub to: Option<NaiveDate>,   // last day of the billing period (inclusive)
+    pub usage: Vec<LineItem>,
+    pub supply: Option<LineItem>,
+    pub feedin: Vec<LineItem>,
+    pub total: Option<f64>,
+}
+
+// A table's number for `key`, failing on any key not in `keys` (most likely a typo)
+fn number(table: &Toml, key: &str, keys: &[&str], item: &str) -> Result<Option<f64>> {
+    let kvs = table.as_table().ok_or_else(|| anyhow!("{} is not a table", item))?;
+    if let Some((k, _)) = kvs.iter().find(|(k, _)| !keys.contains(&k.as_str())) {
+        return Err(anyhow!("{} has '{}', not one of {}", item, k, keys.join(", ")));
+    }
+    table.get(key).map(|v| v.as_f64().ok_or_else(|| anyhow!("{} {} is not a number", item, key))).transpose()
+}
+
+fn line_item(table: &Toml, quantity: &str, item: &str) -> Result<LineItem> {
+    let keys = ["name", quantity, "amount"];
+    Ok(LineItem {
+        name: table.get("name").map(|n| n.as_str().map(str::to_string).ok_or_else(|| anyhow!("{} name is not a string", item))).transpose()?,
+        quantity: number(table, quantity, &keys, item)?,
+        amount: number(table, "amount", &keys, item)?,
+    })
+}
+
+impl RetailerBill {
+    /// Load the line items of a bill from a TOML file, see the module documentation.
+    pub fn load(path: &str) -> Result<RetailerBill> {
+        RetailerBill::from_toml(&load_toml(path)?).with_context(|| format!("RetailerBill::load: {}", path))
+    }
+
+    pub fn from_toml(toml: &Toml) -> Result<RetailerBill> {
+        let keys = ["from", "to", "total", "usage", "supply", "feedin"];
+        let date = |key: &str| toml.get(key).map(|d| {
+            d.as_str().and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok()).ok_or_else(|| anyhow!("{} is not a date YYYY-MM-DD", key))
+        }).transpose();
+        let items = |key: &str, quantity: &str| -> Result<Vec<LineItem>> {
+            match toml.get(key) {
+                None => Ok(Vec::new()),
+                Some(Toml::Array(xs)) => xs.iter().enumerate().map(|(i, x)| line_item(x, quantity, &format!("{} item {}", key, i + 1))).collect(),
+                Some(_) => Err(anyhow!("{} is not an array of tables, use [[{}]]", key, key)),
+            }
+        };
+        Ok(RetailerBill {
+            from: date("from")?,
+            to: date("to")?,
+            usage: items("usage", "kwh")?,
+            supply: toml.get("supply").map(|s| line_item(s, "days", "supply")).transpose()?,
+            feedin: items("feedin", "kwh")?,
+            total: number(toml, "total", &keys, "bill")?,
+        })
+    }
+}
+
+/// A quantity or amount on the retailer's bill next to the recalculated one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub line: String,        // e.g. Usage Peak
+    pub unit: &'static str,  // kWh, days or $
+    pub bill: f64,           // the retailer's figure
+    pub calculated: f64,     // recalculated
+}
+
+impl Difference {
+    /// How much more the recalculated figure is than the retailer's.
+    pub fn difference(&self) -> f64 {
+        money::add(self.calculated, -self.bill)
+    }
+
+    /// Whether the figures agree to within `tolerance`.
+    pub fn agrees(&self, tolerance: f64) -> bool {
+        self.difference().abs() <= tolerance + 1e-9
+    }
+}
+
+// Compare line items with the per name totals of one component, adding a line for any name the bill leaves out
+fn compare_items(label: &str, items: &[LineItem], by_name: &ByName, kwh: f64, cost: f64, out: &mut Vec<Difference>) {
+    let mut push = |line: String, quantity: (Option<f64>, f64), amount: (Option<f64>, f64)| {
+        if let Some(bill) = quantity.0 {
+            out.push(Difference { line: line.clone(), unit: "kWh", bill, calculated: quantity.1 });
+        }
+        if let Some(bill) = amount.0 {
+            out.push(Difference { line, unit: "$", bill, calculated: amount.1 });
+        }
+    };
+    for item in items {
+        let (k, c) = match &item.name {
+            None => (kwh, cost),
+            Some(name) => by_name.0.iter().find(|x| x.name.eq_ignore_ascii_case(name)).map_or((0.0, 0.0), |x| (x.kwh, x.cost)),
+        };
+        let line = item.name.as_ref().map_or(label.to_string(), |n| format!("{} {}", label, n));
+        push(line, (item.quantity, k), (item.amount, c));
+    }
+    if items.iter().any(|i| i.name.is_some()) {
+        for x in by_name.0.iter().filter(|x| !items.iter().any(|i| i.name.as_ref().is_some_and(|n| x.name.eq_ignore_ascii_case(n)))) {
+            push(format!("{} {}", label, x.name), (Some(0.0), x.kwh), (Some(0.0), x.cost));
+        }
+    }
+}
+
+/// Compare each quantity and amount on the retailer's bill with the recalculated bill.
+pub fn reconcile(retailer: &RetailerBill, bill: &Bill) -> Vec<Difference> {
+    let mut out = Vec::new();
+    let kwh = |by_name: &ByName| by_name.0.iter().fold(0.0, |sum, x| sum + x.kwh);
+    compare_items("Usage", &retailer.usage, &bill.consumption_by_name, kwh(&bill.consumption_by_name), bill.consumption, &mut out);
+    if let Some(supply) = &retailer.supply {
+        if let Some(days) = supply.quantity {
+            out.push(Difference { line: "Supply".to_string(), unit: "days", bill: days, calculated: bill.days as f64 });
+        }
+        if let Some(amount) = supply.amount {
+            out.push(Difference { line: "Supply".to_string(), unit: "$", bill: amount, calculated: bill.supply });
+        }
+    }
+    compare_items("Feedin", &retailer.feedin, &bill.feedin_by_name, kwh(&bill.feedin_by_name), bill.feedin, &mut out);
+    if let Some(total) = retailer.total {
+        out.push(Difference { line: "Total".to_string(), unit: "$", bill: total, calculated: bill.total() });
+    }
+    out
+}
+
+/// The retailer's and recalculated figures side by side, marking those that differ by more than `tolerance`.
+pub fn reconcile_text(differences: &[Difference], tolerance: f64) -> String {
+    let width = differences.iter().map(|d| d.line.chars().count()).max().unwrap_or(0).max(4);
+    let mut s = format!("{:<w$} {:>5} {:>12} {:>12} {:>12}\n", "Line", "Unit", "Bill", "Calculated", "Difference", w = width);
+    for d in differences {
+        let places = match d.unit {
+            "$" => 2,
+            "days" => 0,
+            _ => 3,
+        };
+        s += &format!(
+            "{:<w$} {:>5} {:>12.p$} {:>12.p$} {:>+12.p$}{}\n",
+            d.line, d.unit, d.bill, d.calculated, d.difference(),
+            if d.agrees(tolerance) { "" } else { "  <- differs" }, w = width, p = places
+        );
+    }
+    let differ = differences.iter().filter(|d| !d.agrees(tolerance)).count();
+    s += &match differ {
+        0 => format!("All {} figures agree to within {}\n", differences.len(), tolerance),
+        n => format!("{} of {} figures differ by more than {}\n", n, differences.len(), tolerance),
+    };
+    s
+}
+
+/// The differences as a JSON array.
+pub fn reconcile_json(differences: &[Difference], tolerance: f64) -> Json {
+    Json::Array(differences.iter().map(|d| Json::object(vec![
+        ("line", d.line.as_str().into()),
+        ("unit", d.unit.into()),
+        ("bill", d.bill.into()),
+        ("calculated", d.calculated.into()),
+        ("difference", d.difference().into()),
+        ("agrees", d.agrees(tolerance).into()),
+    ])).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bill::NameTotals;
+    use crate::toml::parse_toml;
+
+    #[test]
+    fn test_reconcile() -> Result<()> {
+        let retailer = RetailerBill::from_toml(&parse_toml(r#"
+            from = 2023-08-06
+            total = 10.0
+            [[usage]]
+            name = "peak"
+            kwh = 2.0
+            amount = 1.0
+            [supply]
+            days = 3
+            amount = 3.3
+            [[feedin]]
+            amount = -0.5
+        "#)?)?;
+        assert_eq!(retailer.from, NaiveDate::from_ymd_opt(2023, 8, 6));
+        let name = |name: &str, kwh, cost| NameTotals { name: name.to_string(), period: None, kwh, cost };
+        let bill = Bill {
+            consumption: 1.5,
+            feedin: -0.5,
+            supply: 3.3,
+            days: 3,
+            consumption_by_name: ByName(vec![name("Peak", 2.0, 1.004), name("Off-peak", 1.0, 0.496)]),
+            feedin_by_name: ByName(vec![name("Feed-in", 5.0, -0.5)]),
+            ..Bill::default()
+        };
+        let differences = reconcile(&retailer, &bill);
+        let differ = differences.iter().filter(|d| !d.agrees(0.01)).map(|d| (d.line.as_str(), d.unit)).collect::<Vec<_>>();
+        assert_eq!(differ, vec![("Usage Off-peak", "kWh"), ("Usage Off-peak", "$"), ("Total", "$")]);
+        assert_eq!(differences.len(), 8);
+        let text = reconcile_text(&differences, 0.01);
+        assert!(text.contains("Usage Off-peak     $         0.00         0.50        +0.50  <- differs\n"));
+        assert!(text.ends_with("3 of 8 figures differ by more than 0.01\n"));
+
+        assert!(RetailerBill::from_toml(&parse_toml("[supply]\ndayz = 3")?).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,362 @@
+//! A minimal HTTP server pricing uploaded energy data under an uploaded plan, for a dashboard or web page to use the
+//! pricing engine without the command line.
+//!
+//! `POST /price` takes a JSON object of file contents as strings (see [`crate::api`] for its fields)
+//! and returns the bill as JSON (as `elec price --format json`) with its `monthly` totals. Errors are returned as
+//! `{"error": "..."}` with status 400. `GET /health` returns `{"status": "ok"}`. Each connection is handled on its
+//! own thread and closed after the response. The uploaded files are read in memory, never written to disk.
+//!
+//! So a slow or hostile client can't tie the server up, connections time out after [`TIMEOUT`] without reading or
+//! writing and a client has [`DEADLINE`] to send its whole request (and again to read the response), the request line
+//! and headers are limited to [`MAX_HEAD`] bytes and the body to [`DEFAULT_MAX_BODY`] (see [`Server::with_max_body`]),
+//! and beyond [`MAX_CONNECTIONS`] at once further connections are answered with status 503.
+//!
+//! `GET /metrics` serves the [`Metrics`] of the last bill priced, in Prometheus' text format. `price --watch` serves
+//! only `/metrics` and `/health` for the bill it prices.
+
+use crate::api::{bill_json, price_bill};
+use crate::json::{parse_json, Json};
+use crate::metrics::{Metrics, SharedMetrics};
+use chrono::{Local, Utc};
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Largest request body accepted by default (bytes), a year of 5 minute data being around 1 MiB.
+pub const DEFAULT_MAX_BODY: usize = 8 * 1024 * 1024;
+
+/// Largest request line and headers accepted (bytes).
+pub const MAX_HEAD: u64 = 64 * 1024;
+
+/// Most connections handled at once.
+pub const MAX_CONNECTIONS: usize = 64;
+
+/// How long a connection may go without reading or writing before it's dropped.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a client has to send its whole request, and again to read the whole response, however slowly it trickles.
+pub const DEADLINE: Duration = Duration::from_secs(120);
+
+/// An HTTP request: method, path (without any query) and body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: String,
+}
+
+// Read a line of the request line and headers, which together are limited to MAX_HEAD bytes
+fn read_head_line<R: BufRead>(head: &mut std::io::Take<R>) -> Result<String> {
+    let mut line = String::new();
+    head.read_line(&mut line)?;
+    if head.limit() == 0 && !line.ends_with('\n') {
+        return Err(anyhow!("read_request: the request line and headers are more than {} bytes", MAX_HEAD));
+    }
+    Ok(line)
+}
+
+/// Read a request: the request line, headers (only Content-Length is used) and a body of at most `max_body` bytes.
+pub fn read_request<R: BufRead>(reader: R, max_body: usize) -> Result<Request> {
+    let mut head = reader.take(MAX_HEAD);
+    let line = read_head_line(&mut head)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target),
+        _ => return Err(anyhow!("read_request: '{}' is not a request line", line.trim())),
+    };
+    let path = target.split('?').next().unwrap_or_default().to_string();
+    let mut length = 0;
+    loop {
+        let header = read_head_line(&mut head)?;
+        if header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().map_err(|_| anyhow!("read_request: Content-Length '{}' is not a number", value.trim()))?;
+            }
+        }
+    }
+    if length > max_body {
+        return Err(anyhow!("read_request: a body of {} bytes is more than {}", length, max_body));
+    }
+    let mut body = Vec::new();
+    head.into_inner().take(length as u64).read_to_end(&mut body)?;
+    if body.len() < length {
+        return Err(anyhow!("read_request: the body ended after {} of {} bytes", body.len(), length));
+    }
+    Ok(Request { method, path, body: String::from_utf8(body).context("read_request: the body is not UTF-8")? })
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+/// What a server answers: the metrics it serves, whether it prices uploads with `POST /price` and the largest request
+/// body it accepts (bytes).
+#[derive(Debug, Clone)]
+pub struct Server {
+    pub metrics: SharedMetrics,
+    pub api: bool,
+    pub max_body: usize,
+}
+
+impl Server {
+    /// A server of the pricing API, its metrics those of the last upload priced.
+    pub fn api() -> Server {
+        Server { metrics: Arc::new(Mutex::new(Metrics::default())), api: true, max_body: DEFAULT_MAX_BODY }
+    }
+
+    /// A server of the metrics of bills priced elsewhere.
+    pub fn metrics(metrics: SharedMetrics) -> Server {
+        Server { metrics, api: false, max_body: DEFAULT_MAX_BODY }
+    }
+
+    /// Accept request bodies of up to `max_body` bytes rather than [`DEFAULT_MAX_BODY`].
+    pub fn with_max_body(self, max_body: usize) -> Server {
+        Server { max_body, ..self }
+    }
+
+    /// The status, content type and body of the response to a request.
+    pub fn respond(&self, request: &Request) -> (u16, &'static str, String) {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/metrics") => {
+                let text = self.metrics.lock().map_or_else(|e| e.into_inner().to_text(), |m| m.to_text());
+                (200, "text/plain; version=0.0.4", text)
+            },
+            (method, path) => {
+                let (status, json) = self.respond_json(method, path, &request.body);
+                (status, "application/json", format!("{}\n", json))
+            },
+        }
+    }
+
+    fn respond_json(&self, method: &str, path: &str, body: &str) -> (u16, Json) {
+        let error = |status, message: String| (status, Json::object(vec![("error", message.into())]));
+        match (method, path) {
+            ("GET", "/health") => (200, Json::object(vec![("status", "ok".into())])),
+            ("POST", "/price") if self.api => {
+                let bill = parse_json(body).and_then(|json| price_bill(&json));
+                if let Ok(mut metrics) = self.metrics.lock() {
+                    metrics.record(bill.as_ref().ok(), Local::now().date_naive(), Utc::now().timestamp());
+                }
+                match bill {
+                    Ok(bill) => (200, bill_json(&bill)),
+                    Err(e) => error(400, format!("{:#}", e)),
+                }
+            },
+            (_, "/health" | "/metrics") => error(405, format!("{} is not allowed on {}", method, path)),
+            (_, "/price") if self.api => error(405, format!("{} is not allowed on {}", method, path)),
+            _ => error(404, format!("no such endpoint {}", path)),
+        }
+    }
+
+    // Read a request from the connection and write the response, allowing pages from other origins to call the API
+    fn handle(&self, stream: TcpStream) -> Result<()> {
+        let request = read_request(BufReader::new(Deadline::new(&stream)), self.max_body);
+        let (status, content_type, body) = match &request {
+            Ok(r) if r.method == "OPTIONS" => (204, "application/json", String::new()), // a browser's preflight check
+            Ok(r) => {
+                let response = self.respond(r);
+                info!("handle: {} {} {}", r.method, r.path, response.0);
+                response
+            },
+            Err(e) => (400, "application/json", error_json(&format!("{:#}", e))),
+        };
+        respond_to(stream, status, content_type, &body)
+    }
+}
+
+fn error_json(message: &str) -> String {
+    format!("{}\n", Json::object(vec![("error", message.into())]))
+}
+
+// A connection read or written within DEADLINE from when it's made, each read or write within TIMEOUT
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    until: Instant,
+}
+
+impl<'a> Deadline<'a> {
+    fn new(stream: &'a TcpStream) -> Deadline<'a> {
+        Deadline { stream, until: Instant::now() + DEADLINE }
+    }
+
+    // The time left for the next read or write, an error once the deadline has passed
+    fn left(&self) -> std::io::Result<Duration> {
+        match self.until.checked_duration_since(Instant::now()) {
+            Some(left) if !left.is_zero() => Ok(left.min(TIMEOUT)),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::TimedOut, format!("not done within {} seconds", DEADLINE.as_secs()))),
+        }
+    }
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stream.set_read_timeout(Some(self.left()?))?;
+        (&mut &*self.stream).read(buf)
+    }
+}
+
+impl Write for Deadline<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.set_write_timeout(Some(self.left()?))?;
+        (&mut &*self.stream).write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        (&mut &*self.stream).flush()
+    }
+}
+
+// Write a response and close the connection
+fn respond_to(stream: TcpStream, status: u16, content_type: &str, body: &str) -> Result<()> {
+    let mut stream = Deadline::new(&stream);
+    write!(stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\n\
+         Access-Control-Allow-Methods: GET, POST, OPTIONS\r\nAccess-Control-Allow-Headers: Content-Type\r\nConnection: close\r\n\r\n{}",
+        status, reason(status), content_type, body.len(), body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+// A connection counted against MAX_CONNECTIONS until it's dropped
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn take(connections: &Arc<AtomicUsize>) -> Option<Slot> {
+        connections.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n < MAX_CONNECTIONS).then_some(n + 1)).ok()?;
+        Some(Slot(connections.clone()))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Serve on `address` (e.g. `127.0.0.1:8080`) until the process is stopped.
+pub fn serve(address: &str, server: Server) -> Result<()> {
+    serve_on(bind(address)?, server)
+}
+
+/// Listen on `address`, so an error binding it (e.g. the port in use) can be reported before serving on another thread.
+pub fn bind(address: &str) -> Result<TcpListener> {
+    TcpListener::bind(address).with_context(|| format!("serve: {}", address))
+}
+
+/// Serve connections to a listener from [`bind`] until the process is stopped.
+pub fn serve_on(listener: TcpListener, server: Server) -> Result<()> {
+    info!("serve: listening on {}", listener.local_addr()?);
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let Some(slot) = Slot::take(&connections) else {
+                    warn!("serve: more than {} connections, refusing one", MAX_CONNECTIONS);
+                    let _ = respond_to(stream, 503, "application/json", &error_json("too many connections, try again later"));
+                    continue;
+                };
+                let server = server.clone();
+                std::thread::spawn(move || {
+                    let _slot = slot;
+                    if let Err(e) = server.handle(stream) {
+                        warn!("serve: {:#}", e);
+                    }
+                });
+            },
+            Err(e) => warn!("serve: {}", e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_request() -> Result<()> {
+        let request = read_request("POST /price?x=1 HTTP/1.1\r\nHost: localhost\r\ncontent-length: 4\r\n\r\n{}\r\nextra".as_bytes(), DEFAULT_MAX_BODY)?;
+        assert_eq!(request, Request { method: "POST".to_string(), path: "/price".to_string(), body: "{}\r\n".to_string() });
+        assert!(read_request("\r\n".as_bytes(), DEFAULT_MAX_BODY).is_err());
+
+        // headers too long, and a body shorter than its Content-Length
+        let long = format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", "x".repeat(MAX_HEAD as usize));
+        let e = read_request(long.as_bytes(), DEFAULT_MAX_BODY).unwrap_err().to_string();
+        assert!(e.contains("more than 65536 bytes"), "{}", e);
+        let e = read_request("POST /price HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}".as_bytes(), DEFAULT_MAX_BODY).unwrap_err().to_string();
+        assert!(e.contains("ended after 2 of 10 bytes"), "{}", e);
+        let e = read_request("POST /price HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}".as_bytes(), 8).unwrap_err().to_string();
+        assert!(e.contains("a body of 10 bytes is more than 8"), "{}", e);
+
+        // a connection past its deadline can't be read or written however quickly its client sends
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let client = TcpStream::connect(listener.local_addr()?)?;
+        (&client).write_all(b"GET /health HTTP/1.1\r\n\r\n")?;
+        let (stream, _) = listener.accept()?;
+        let mut late = Deadline { stream: &stream, until: Instant::now() };
+        assert_eq!(late.read(&mut [0; 16]).map_err(|e| e.kind()), Err(std::io::ErrorKind::TimedOut));
+        assert_eq!(late.write(b"HTTP/1.1").map_err(|e| e.kind()), Err(std::io::ErrorKind::TimedOut));
+        assert_eq!(read_request(BufReader::new(Deadline::new(&stream)), DEFAULT_MAX_BODY)?.path, "/health");
+
+        // connections beyond the limit aren't counted until one is dropped
+        let connections = Arc::new(AtomicUsize::new(0));
+        let slots = (0..MAX_CONNECTIONS).filter_map(|_| Slot::take(&connections)).collect::<Vec<_>>();
+        assert_eq!(slots.len(), MAX_CONNECTIONS);
+        assert!(Slot::take(&connections).is_none());
+        drop(slots);
+        assert!(Slot::take(&connections).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_respond() -> Result<()> {
+        let read = |path: &str| std::fs::read_to_string(path).map(Json::from);
+        let body = Json::object(vec![
+            ("consumption_tariff", read("data/test/tariff/consumption.csv")?),
+            ("daily", read("data/test/tariff/supply.csv")?),
+            ("consumption", read("data/test/energy/consumption.csv")?),
+            ("holiday_region", "NSW".into()),
+        ]);
+        let request = |method: &str, path: &str, body: String| Request { method: method.to_string(), path: path.to_string(), body };
+        let server = Server::api();
+        let respond = |r: &Request| -> (u16, Json) {
+            let (status, _, body) = server.respond(r);
+            (status, parse_json(&body).unwrap_or(Json::Null))
+        };
+        let (status, json) = respond(&request("POST", "/price", body.to_string()));
+        assert_eq!(status, 200, "{}", json);
+        assert_eq!(json.get("days").and_then(Json::as_f64), Some(3.0));
+        assert_eq!(json.get("monthly").and_then(Json::as_array).map(<[Json]>::len), Some(1));
+
+        let (status, json) = respond(&request("POST", "/price", r#"{"daily": "x"}"#.to_string()));
+        assert_eq!((status, json.string("error")?), (400, "required: consumption_tariff or cdr_plan is required"));
+        assert_eq!(respond(&request("GET", "/health", String::new())).0, 200);
+        assert_eq!(respond(&request("GET", "/price", String::new())).0, 405);
+        assert_eq!(respond(&request("GET", "/", String::new())).0, 404);
+
+        // the metrics of the last upload priced, then of a bill priced elsewhere without the API
+        let (status, content_type, text) = server.respond(&request("GET", "/metrics", String::new()));
+        assert_eq!((status, content_type), (200, "text/plain; version=0.0.4"));
+        assert!(text.contains("\nelec_runs_total 2\n") && text.contains("\nelec_last_run_success 0\n"), "{}", text);
+        assert!(!text.contains("\nelec_bill_to_date_dollars 0\n"), "{}", text);
+        let metrics = Server::metrics(SharedMetrics::default());
+        assert_eq!(metrics.respond(&request("GET", "/metrics", String::new())).0, 200);
+        assert_eq!(metrics.respond(&request("POST", "/price", String::new())).0, 404);
+        Ok(())
+    }
+}
@@ -0,0 +1,503 @@
+//! SQLite database storage of interval energy data.
+//!
+//! Years of data are imported once into a database (`elec import --db meter.sqlite`) which is then read
+//! directly, rather than reparsing the original files for every analysis. The database holds one table:
+//!
+//! ```sql
+//! CREATE TABLE reading (direction TEXT, date TEXT, minute INTEGER, interval INTEGER, kwh REAL, quality TEXT)
+//! ```
+//!
+//! with a row per interval: `direction` is `consumption` or `feedin`, `date` is `YYYY-MM-DD`, `minute` is the
+//! local start time (minutes since midnight, repeated when clocks go back), `interval` is the interval length
+//! (minutes) and `quality` is a NEM12 quality flag (e.g. `A`, `E`). Being a standard SQLite 3 file it can also
+//! be queried with other tools, e.g. monthly totals with `sqlite3`.
+//!
+//! The database is written in full on each import as a single table b-tree ([`write_sqlite`]). The reader
+//! ([`read_sqlite`]) walks the table b-tree of any SQLite 3 file, including ones changed by other tools,
+//! as long as it isn't in WAL mode with changes still in the `-wal` file.
+
+use crate::energy::{open_input, DayEnergy, Direction, Quality};
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+use log::{debug, info};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+
+pub(crate) const MAGIC: &[u8] = b"SQLite format 3\0";
+const PAGE_SIZE: usize = 4096;
+const TABLE: &str = "reading";
+const SCHEMA: &str = "CREATE TABLE reading (direction TEXT NOT NULL, date TEXT NOT NULL, minute INTEGER NOT NULL, interval INTEGER NOT NULL, kwh REAL, quality TEXT NOT NULL)";
+const LEAF: u8 = 0x0d;     // table b-tree leaf page
+const INTERIOR: u8 = 0x05; // table b-tree interior page
+
+/// A value of a column in a SQLite record.
+#[derive(Debug, Clone, PartialEq)]
+enum Field {
+    Null,
+    Int(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Import => "consumption",
+        Direction::Export => "feedin",
+    }
+}
+
+// Read a SQLite varint (big-endian 7 bits a byte, the 9th byte has 8 bits), returning it and its length
+fn varint(data: &[u8], at: usize) -> Result<(u64, usize)> {
+    let mut n = 0;
+    for i in 0..9 {
+        let b = *data.get(at + i).ok_or_else(|| anyhow!("varint: truncated at {}", at))? as u64;
+        if i == 8 {
+            return Ok((n << 8 | b, 9));
+        }
+        n = n << 7 | (b & 0x7f);
+        if b & 0x80 == 0 {
+            return Ok((n, i + 1));
+        }
+    }
+    unreachable!("varint: returns by the 9th byte")
+}
+
+fn put_varint(out: &mut Vec<u8>, n: u64) {
+    if n > 0x00ff_ffff_ffff_ffff {
+        out.extend((0..8).rev().map(|i| (n >> (8 + 7 * i)) as u8 | 0x80));
+        out.push(n as u8);
+        return;
+    }
+    let groups = (1..=8).find(|g| n >> (7 * g) == 0).unwrap_or(8);
+    out.extend((0..groups).rev().map(|i| ((n >> (7 * i)) & 0x7f) as u8 | if i > 0 { 0x80 } else { 0 }));
+}
+
+fn be(data: &[u8], at: usize, n: usize) -> Result<u64> {
+    let bytes = data.get(at..at + n).ok_or_else(|| anyhow!("be: truncated data reading {} bytes at {}", n, at))?;
+    Ok(bytes.iter().fold(0, |v, b| v << 8 | *b as u64))
+}
+
+// Decode a record: a header of serial types then the values
+fn record(payload: &[u8]) -> Result<Vec<Field>> {
+    let (header_len, mut at) = varint(payload, 0)?;
+    let mut body = header_len as usize;
+    let mut fields = Vec::new();
+    while at < header_len as usize {
+        let (serial, len) = varint(payload, at)?;
+        at += len;
+        let size = match serial {
+            0 | 8 | 9 => 0,
+            1..=4 => serial as usize,
+            5 => 6,
+            6 | 7 => 8,
+            10 | 11 => return Err(anyhow!("record: reserved serial type {}", serial)),
+            n => (n as usize - 12) / 2,
+        };
+        let bytes = payload.get(body..body + size).ok_or_else(|| anyhow!("record: truncated value at {}", body))?;
+        fields.push(match serial {
+            0 => Field::Null,
+            1..=6 => Field::Int((be(bytes, 0, size)? << (64 - 8 * size)) as i64 >> (64 - 8 * size)), // sign extend
+            7 => Field::Real(f64::from_bits(be(bytes, 0, 8)?)),
+            8 => Field::Int(0),
+            9 => Field::Int(1),
+            n if n % 2 == 0 => Field::Blob(bytes.to_vec()),
+            _ => Field::Text(String::from_utf8_lossy(bytes).to_string()),
+        });
+        body += size;
+    }
+    Ok(fields)
+}
+
+fn put_record(out: &mut Vec<u8>, fields: &[Field]) {
+    let mut header = Vec::new();
+    let mut body = Vec::new();
+    for field in fields {
+        let serial = match field {
+            Field::Null => 0,
+            Field::Int(n) => {
+                let size = [1, 2, 3, 4, 6].into_iter().find(|s| (-(1 << (8 * s - 1))..1 << (8 * s - 1)).contains(n)).unwrap_or(8);
+                body.extend_from_slice(&n.to_be_bytes()[8 - size..]);
+                match size {
+                    6 => 5,
+                    8 => 6,
+                    s => s as u64,
+                }
+            },
+            Field::Real(x) => {
+                body.extend_from_slice(&x.to_be_bytes());
+                7
+            },
+            Field::Text(s) => {
+                body.extend_from_slice(s.as_bytes());
+                s.len() as u64 * 2 + 13
+            },
+            Field::Blob(b) => {
+                body.extend_from_slice(b);
+                b.len() as u64 * 2 + 12
+            },
+        };
+        put_varint(&mut header, serial);
+    }
+    let mut len = header.len() + 1;
+    if len > 0x7f { // the header length counts its own varint
+        len += 1;
+    }
+    put_varint(out, len as u64);
+    out.extend(header);
+    out.extend(body);
+}
+
+// The pages of a SQLite file
+struct Pages<'a> {
+    data: &'a [u8],
+    size: usize,
+    usable: usize, // page size less the reserved bytes at the end of each page
+    count: usize,  // pages in the file
+}
+
+impl<'a> Pages<'a> {
+    fn page(&self, n: u64) -> Result<&'a [u8]> {
+        let start = (n as usize).checked_sub(1).context("Pages::page: page 0")?.checked_mul(self.size);
+        start.and_then(|start| self.data.get(start..start + self.size)).ok_or_else(|| anyhow!("Pages::page: page {} is beyond the end of the file", n))
+    }
+
+    // Payload of a cell with `len` bytes, the first of them at `at` in `page`, following overflow pages if need be
+    fn payload(&self, page: &[u8], at: usize, len: usize) -> Result<Vec<u8>> {
+        let max_local = self.usable - 35;
+        let local = match len {
+            _ if len <= max_local => len,
+            _ => {
+                let min_local = (self.usable - 12) * 32 / 255 - 23;
+                let k = min_local + (len - min_local) % (self.usable - 4);
+                if k <= max_local { k } else { min_local }
+            },
+        };
+        let mut payload = page.get(at..at + local).context("Pages::payload: truncated cell")?.to_vec();
+        let mut next = if local < len { be(page, at + local, 4)? } else { 0 };
+        let mut walked = 0;
+        while payload.len() < len {
+            walked += 1;
+            if walked > self.count {
+                return Err(anyhow!("Pages::payload: overflow pages from page {} loop, the file may be corrupt", next));
+            }
+            let overflow = self.page(next)?;
+            let take = (len - payload.len()).min(self.usable - 4);
+            payload.extend_from_slice(&overflow[4..4 + take]);
+            next = be(overflow, 0, 4)?;
+        }
+        Ok(payload)
+    }
+
+    // Append the records of the table b-tree rooted at page `n` to `rows`, in rowid order
+    fn table(&self, n: u64, depth: usize, rows: &mut Vec<Vec<Field>>) -> Result<()> {
+        if depth > 32 {
+            return Err(anyhow!("Pages::table: b-tree is too deep at page {}, the file may be corrupt", n));
+        }
+        let page = self.page(n)?;
+        let header = if n == 1 { 100 } else { 0 };
+        let cells = be(page, header + 3, 2)? as usize;
+        let pointers = header + if page[header] == INTERIOR { 12 } else { 8 };
+        for i in 0..cells {
+            let at = be(page, pointers + 2 * i, 2)? as usize;
+            match page[header] {
+                LEAF => {
+                    let (len, a) = varint(page, at)?;
+                    let (_rowid, b) = varint(page, at + a)?;
+                    rows.push(record(&self.payload(page, at + a + b, len as usize)?)?);
+                },
+                INTERIOR => self.table(be(page, at, 4)?, depth + 1, rows)?,
+                t => return Err(anyhow!("Pages::table: page {} has type {}, not a rowid table b-tree page", n, t)),
+            }
+        }
+        if page[header] == INTERIOR {
+            self.table(be(page, header + 8, 4)?, depth + 1, rows)?;
+        }
+        Ok(())
+    }
+}
+
+// A row of the table for a date: minute, kWh and quality
+type Reading = (i64, f64, Quality);
+
+// The column names of a CREATE TABLE statement
+fn columns(sql: &str) -> Vec<String> {
+    let (start, end) = (sql.find('(').unwrap_or(0), sql.rfind(')').unwrap_or(sql.len()));
+    sql.get(start + 1..end).unwrap_or("").split(',')
+        .filter_map(|c| c.split_whitespace().next())
+        .map(|c| c.trim_matches(|x| "\"'`[]".contains(x)).to_lowercase())
+        .collect()
+}
+
+/// Read the interval readings for `direction` from a SQLite database written by [`write_sqlite`]
+/// (or standard input for [`crate::energy::STDIN`]), see the module documentation for the table.
+pub fn read_sqlite(db: &str, direction: Direction) -> Result<Vec<DayEnergy>> {
+    info!("read_sqlite: loading SQLite database {}, {:?}", db, direction);
+    let wal = format!("{}-wal", db);
+    if std::fs::metadata(&wal).is_ok_and(|m| m.len() > 0) {
+        return Err(anyhow!("read_sqlite: {} has changes in {}, checkpoint them first (e.g. sqlite3 {} 'PRAGMA journal_mode=DELETE')", db, wal, db));
+    }
+    let mut data = Vec::new();
+    open_input(db)?.read_to_end(&mut data)?;
+    parse_sqlite(&data, direction).with_context(|| format!("read_sqlite: {}", db))
+}
+
+/// Parse SQLite database contents, see [`read_sqlite`].
+pub fn parse_sqlite(data: &[u8], direction: Direction) -> Result<Vec<DayEnergy>> {
+    if data.len() < 100 || !data.starts_with(MAGIC) {
+        return Err(anyhow!("parse_sqlite: not a SQLite 3 database"));
+    }
+    if be(data, 56, 4)? != 1 {
+        return Err(anyhow!("parse_sqlite: text encoding {} is not UTF-8", be(data, 56, 4)?));
+    }
+    let size = match be(data, 16, 2)? {
+        1 => 65536,
+        s => s as usize,
+    };
+    if !size.is_power_of_two() || size < 512 {
+        return Err(anyhow!("parse_sqlite: page size {} is not a power of two from 512 to 65536", size));
+    }
+    let usable = size - data[20] as usize;
+    if usable < 480 {
+        return Err(anyhow!("parse_sqlite: {} bytes reserved at the end of each {} byte page leave less than 480", data[20], size));
+    }
+    let pages = Pages { data, size, usable, count: data.len() / size };
+
+    let mut schema = Vec::new();
+    pages.table(1, 0, &mut schema)?;
+    let (root, sql) = schema.iter().find_map(|r| match &r[..] {
+        [Field::Text(kind), Field::Text(name), _, Field::Int(root), Field::Text(sql), ..] if kind == "table" && name.eq_ignore_ascii_case(TABLE) => Some((*root, sql)),
+        _ => None,
+    }).ok_or_else(|| anyhow!("parse_sqlite: no {} table", TABLE))?;
+    let columns = columns(sql);
+    debug!("parse_sqlite: table {} at page {} with columns {:?}", TABLE, root, columns);
+    let index = |name: &str| columns.iter().position(|c| c == name).ok_or_else(|| anyhow!("parse_sqlite: {} table has no {} column", TABLE, name));
+    let [dir_i, date_i, minute_i, interval_i, kwh_i, quality_i] = ["direction", "date", "minute", "interval", "kwh", "quality"].map(index);
+    let (dir_i, date_i, minute_i, interval_i, kwh_i, quality_i) = (dir_i?, date_i?, minute_i?, interval_i?, kwh_i?, quality_i?);
+
+    let mut rows = Vec::new();
+    pages.table(root as u64, 0, &mut rows)?;
+    let name = direction_name(direction);
+    let mut days: BTreeMap<NaiveDate, (usize, Vec<Reading>)> = BTreeMap::new();
+    for row in &rows {
+        let field = |i: usize| row.get(i).unwrap_or(&Field::Null); // columns added later may be missing
+        if !matches!(field(dir_i), Field::Text(d) if d == name) {
+            continue;
+        }
+        let (date, minute, interval) = match (field(date_i), field(minute_i), field(interval_i)) {
+            (Field::Text(date), Field::Int(minute), Field::Int(interval)) if *interval > 0 =>
+                (NaiveDate::parse_from_str(date, "%Y-%m-%d").with_context(|| format!("parse_sqlite: date '{}'", date))?, *minute, *interval as usize),
+            r => return Err(anyhow!("parse_sqlite: row {:?} doesn't have a date, minute and interval length", r)),
+        };
+        let (kwh, quality) = match (field(kwh_i), field(quality_i)) {
+            (Field::Null, _) => (0.0, Quality::Null),
+            (Field::Real(kwh), Field::Text(flag)) => (*kwh, Quality::from_flag(flag)?),
+            (Field::Int(kwh), Field::Text(flag)) => (*kwh as f64, Quality::from_flag(flag)?),
+            (kwh, quality) => return Err(anyhow!("parse_sqlite: {} on {} has kWh {:?} and quality {:?}", minute, date, kwh, quality)),
+        };
+        let day = days.entry(date).or_insert((interval, Vec::new()));
+        if day.0 != interval {
+            return Err(anyhow!("parse_sqlite: {} has {} and {} minute intervals", date, day.0, interval));
+        }
+        day.1.push((minute, kwh, quality));
+    }
+
+    let days = days.into_iter().map(|(date, (interval, mut readings))| {
+        readings.sort_by_key(|r| r.0); // stable, so the hour repeated when clocks go back stays in order
+        let mut day = DayEnergy::with_interval(date, interval, readings.iter().map(|r| r.1).collect())?;
+        day.quality = readings.iter().map(|r| r.2).collect();
+        Ok(day)
+    }).collect::<Result<Vec<DayEnergy>>>()?;
+    info!("parse_sqlite: {} {} days from {} rows", days.len(), name, rows.len());
+    Ok(days)
+}
+
+// A table b-tree page of `kind` holding `cells` (already in key order)
+fn btree_page(kind: u8, cells: &[Vec<u8>], rightmost: Option<u32>) -> Vec<u8> {
+    let header = if kind == INTERIOR { 12 } else { 8 };
+    let mut page = vec![0; PAGE_SIZE];
+    let mut content = PAGE_SIZE;
+    for (i, cell) in cells.iter().enumerate() {
+        content -= cell.len();
+        page[content..content + cell.len()].copy_from_slice(cell);
+        page[header + 2 * i..header + 2 * i + 2].copy_from_slice(&(content as u16).to_be_bytes());
+    }
+    page[0] = kind;
+    page[3..5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    page[5..7].copy_from_slice(&(content as u16).to_be_bytes());
+    if let Some(right) = rightmost {
+        page[8..12].copy_from_slice(&right.to_be_bytes());
+    }
+    page
+}
+
+// Fill leaf pages with (cell, rowid), appending them to `pages` (numbered from 1),
+// returning the page numbers and the greatest rowid on each
+fn leaf_pages(pages: &mut Vec<Vec<u8>>, cells: Vec<(Vec<u8>, u64)>) -> Vec<(u32, u64)> {
+    let mut out = Vec::new();
+    let (mut page, mut used, mut key) = (Vec::new(), 8, 0);
+    for (cell, rowid) in cells {
+        if used + 2 + cell.len() > PAGE_SIZE {
+            pages.push(btree_page(LEAF, &page, None));
+            out.push((pages.len() as u32, key));
+            (page, used) = (Vec::new(), 8);
+        }
+        used += 2 + cell.len();
+        page.push(cell);
+        key = rowid;
+    }
+    pages.push(btree_page(LEAF, &page, None)); // an empty table is an empty leaf
+    out.push((pages.len() as u32, key));
+    out
+}
+
+// Interior pages over `children` (page number and greatest rowid), evenly filled so each has at least two
+fn interior_pages(pages: &mut Vec<Vec<u8>>, children: &[(u32, u64)]) -> Vec<(u32, u64)> {
+    let max = (PAGE_SIZE - 12) / (2 + 4 + 9) + 1; // cells of a pointer, child page and up to 9 byte varint, plus the right-most child
+    let per_page = children.len().div_ceil(children.len().div_ceil(max));
+    children.chunks(per_page).map(|chunk| {
+        let (last, cells) = chunk.split_last().expect("chunks aren't empty");
+        let cells = cells.iter().map(|(page, key)| {
+            let mut cell = page.to_be_bytes().to_vec();
+            put_varint(&mut cell, *key);
+            cell
+        }).collect::<Vec<_>>();
+        pages.push(btree_page(INTERIOR, &cells, Some(last.0)));
+        (pages.len() as u32, last.1)
+    }).collect()
+}
+
+/// Write a SQLite database holding `consumption` and `feedin` as a table with a row per interval,
+/// see the module documentation. Any existing database at `db` is replaced.
+pub fn write_sqlite(db: &str, consumption: &[DayEnergy], feedin: &[DayEnergy]) -> Result<()> {
+    info!("write_sqlite: writing {} consumption and {} feedin days to {}", consumption.len(), feedin.len(), db);
+    let data = sqlite_bytes(consumption, feedin);
+    let tmp = format!("{}.tmp", db);
+    std::fs::write(Path::new(&tmp), data).map_err(|e| anyhow!("write_sqlite: {}: {}", tmp, e))?;
+    std::fs::rename(&tmp, db).map_err(|e| anyhow!("write_sqlite: {}: {}", db, e))
+}
+
+/// The contents of a SQLite database holding `consumption` and `feedin`, see [`write_sqlite`].
+pub fn sqlite_bytes(consumption: &[DayEnergy], feedin: &[DayEnergy]) -> Vec<u8> {
+    let rows = [(Direction::Import, consumption), (Direction::Export, feedin)].into_iter()
+        .flat_map(|(direction, days)| days.iter().flat_map(move |day| (0..day.energy.len()).map(move |i| vec![
+            Field::Text(direction_name(direction).to_string()),
+            Field::Text(day.date.format("%Y-%m-%d").to_string()),
+            Field::Int(day.minute(i) as i64),
+            Field::Int(day.interval as i64),
+            if day.energy[i].is_nan() { Field::Null } else { Field::Real(day.energy[i]) },
+            Field::Text(day.quality[i].flag().to_string()),
+        ])));
+    let cells = rows.enumerate().map(|(i, row)| {
+        let (rowid, mut record, mut cell) = (i as u64 + 1, Vec::new(), Vec::new());
+        put_record(&mut record, &row);
+        put_varint(&mut cell, record.len() as u64);
+        put_varint(&mut cell, rowid);
+        cell.extend(record);
+        (cell, rowid)
+    }).collect::<Vec<_>>();
+
+    // page 1 is the schema, then the leaves and each level of interior pages up to the root
+    let mut pages = vec![vec![0; PAGE_SIZE]];
+    let mut level = leaf_pages(&mut pages, cells);
+    while level.len() > 1 {
+        level = interior_pages(&mut pages, &level);
+    }
+    let root = level[0].0;
+    debug!("sqlite_bytes: {} pages, root {}", pages.len(), root);
+
+    let mut record = Vec::new();
+    put_record(&mut record, &[Field::Text("table".into()), Field::Text(TABLE.into()), Field::Text(TABLE.into()), Field::Int(root as i64), Field::Text(SCHEMA.into())]);
+    let mut cell = Vec::new();
+    put_varint(&mut cell, record.len() as u64);
+    put_varint(&mut cell, 1);
+    cell.extend(record);
+    let num_pages = pages.len() as u32;
+    let page = &mut pages[0];
+    let content = PAGE_SIZE - cell.len();
+    page[content..].copy_from_slice(&cell);
+    page[100] = LEAF;
+    page[103..105].copy_from_slice(&1u16.to_be_bytes());
+    page[105..107].copy_from_slice(&(content as u16).to_be_bytes());
+    page[108..110].copy_from_slice(&(content as u16).to_be_bytes());
+
+    let header = &mut page[..100];
+    header[..16].copy_from_slice(MAGIC);
+    header[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+    header[18..24].copy_from_slice(&[1, 1, 0, 64, 32, 32]); // legacy journal, no reserved bytes, payload fractions
+    header[24..28].copy_from_slice(&1u32.to_be_bytes());    // file change counter
+    header[28..32].copy_from_slice(&num_pages.to_be_bytes());
+    header[40..44].copy_from_slice(&1u32.to_be_bytes());    // schema cookie
+    header[44..48].copy_from_slice(&4u32.to_be_bytes());    // schema format
+    header[56..60].copy_from_slice(&1u32.to_be_bytes());    // UTF-8
+    header[92..96].copy_from_slice(&1u32.to_be_bytes());    // version valid for the change counter
+    header[96..100].copy_from_slice(&3_040_000u32.to_be_bytes());
+    pages.concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::energy::{parse_date, read_csv_energy};
+    use crate::parquet::read_parquet;
+
+    #[test]
+    fn test_varint_record() -> Result<()> {
+        for n in [0, 0x7f, 0x80, 0x3fff, 0x4000, 1 << 56, u64::MAX] {
+            let mut out = Vec::new();
+            put_varint(&mut out, n);
+            assert_eq!(varint(&out, 0)?, (n, out.len()));
+        }
+        let fields = vec![Field::Null, Field::Int(-1), Field::Int(1440), Field::Int(-(1 << 40)), Field::Real(0.073), Field::Text("feedin".into()), Field::Blob(vec![1, 2])];
+        let mut out = Vec::new();
+        put_record(&mut out, &fields);
+        assert_eq!(record(&out)?, fields);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_sqlite() -> Result<()> {
+        // written by the sqlite3 tool with 1024 byte pages, same readings as energy.parquet
+        let file = "data/test/energy/energy.sqlite";
+        for direction in [Direction::Import, Direction::Export] {
+            assert_eq!(read_sqlite(file, direction)?, read_parquet("data/test/energy/energy.parquet", direction)?);
+        }
+        assert!(parse_sqlite(b"Date/Time,00:00\n", Direction::Import).is_err());
+
+        // a corrupt page size or reserved space is an error, not a panic
+        let data = std::fs::read(file)?;
+        for (patch, error) in [(&[(16, 0)][..], "page size 0"), (&[(17, 1)], "page size 1025"), (&[(16, 2), (20, 0x40)], "64 bytes reserved")] {
+            let mut corrupt = data.clone();
+            patch.iter().for_each(|(at, byte)| corrupt[*at] = *byte);
+            let e = parse_sqlite(&corrupt, Direction::Import).unwrap_err().to_string();
+            assert!(e.contains(error), "{}", e);
+        }
+
+        // overflow pages looping back on themselves
+        let mut data = vec![0; 2 * 512];
+        data[348..352].copy_from_slice(&2u32.to_be_bytes());
+        data[512..516].copy_from_slice(&2u32.to_be_bytes());
+        let pages = Pages { data: &data, size: 512, usable: 512, count: 2 };
+        let e = pages.payload(&data[..512], 0, 10_000).unwrap_err().to_string();
+        assert!(e.contains("loop"), "{}", e);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_sqlite() -> Result<()> {
+        let consumption = read_csv_energy("data/test/energy/consumptionDst.csv")?.collect::<Result<Vec<_>>>()?;
+        let data = sqlite_bytes(&consumption, &[]);
+        assert_eq!(parse_sqlite(&data, Direction::Import)?, consumption);
+        assert!(parse_sqlite(&data, Direction::Export)?.is_empty());
+
+        // enough 5 minute intervals for interior pages two levels deep
+        let from = parse_date("20230101")?;
+        let feedin = (0..800).map(|d| DayEnergy::new(from + chrono::Days::new(d), (0..288).map(|i| (d * 288 + i) as f64 / 1000.0).collect()))
+            .collect::<Result<Vec<_>>>()?;
+        let data = sqlite_bytes(&consumption, &feedin);
+        assert!(data.len() / PAGE_SIZE > 2000);
+        assert_eq!(parse_sqlite(&data, Direction::Export)?, feedin);
+        Ok(())
+    }
+}
@@ -0,0 +1,412 @@
+//! Minimal TOML for configuration files.
+//!
+//! Supports comments, bare, quoted and dotted keys, basic and literal strings, integers, floats, booleans,
+//! arrays, inline tables, `[table]` and `[[array of tables]]` headers. Dates and times (e.g. `2023-08-06`)
+//! are kept as strings. Multi-line strings are not supported.
+
+use crate::energy::open_input;
+use crate::json::Json;
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use std::io::Read;
+
+/// Deepest nesting of tables and arrays parsed, so a hostile document can't overflow the stack.
+pub const MAX_DEPTH: usize = 128;
+
+/// A TOML value, tables keep their keys in the order they're defined.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Toml {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    Array(Vec<Toml>),
+    Table(Vec<(String, Toml)>),
+}
+
+impl Toml {
+    /// The value of `key` in a table.
+    pub fn get(&self, key: &str) -> Option<&Toml> {
+        self.as_table()?.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Toml::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// A float or an integer as `f64`.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Toml::Float(x) => Some(*x),
+            Toml::Integer(n) => Some(*n as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Toml::Integer(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Toml::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Toml]> {
+        match self {
+            Toml::Array(xs) => Some(xs),
+            _ => None,
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&[(String, Toml)]> {
+        match self {
+            Toml::Table(kvs) => Some(kvs),
+            _ => None,
+        }
+    }
+
+    /// The value as JSON, e.g. to read a configuration in either format (integers become numbers).
+    pub fn to_json(&self) -> Json {
+        match self {
+            Toml::String(s) => Json::String(s.clone()),
+            Toml::Integer(x) => Json::Number(*x as f64),
+            Toml::Float(x) => Json::Number(*x),
+            Toml::Bool(b) => Json::Bool(*b),
+            Toml::Array(xs) => Json::Array(xs.iter().map(Toml::to_json).collect()),
+            Toml::Table(kvs) => Json::Object(kvs.iter().map(|(k, v)| (k.clone(), v.to_json())).collect()),
+        }
+    }
+}
+
+// Recursive descent over the document, tracking the line for errors
+struct Parser<'a> {
+    s: &'a str,
+    at: usize,
+    depth: usize, // tables and arrays below the root the parser is in
+}
+
+impl<'a> Parser<'a> {
+    fn line(&self) -> usize {
+        self.s[..self.at].matches('\n').count() + 1
+    }
+
+    fn err(&self, msg: &str) -> anyhow::Error {
+        anyhow!("parse_toml: line {}: {}", self.line(), msg)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.s[self.at..].chars().next()
+    }
+
+    fn eat(&mut self, prefix: &str) -> bool {
+        match self.s[self.at..].starts_with(prefix) {
+            true => {
+                self.at += prefix.len();
+                true
+            },
+            false => false,
+        }
+    }
+
+    fn expect(&mut self, prefix: &str) -> Result<()> {
+        match self.eat(prefix) {
+            true => Ok(()),
+            false => Err(self.err(&format!("expected '{}'", prefix))),
+        }
+    }
+
+    // Skip spaces and tabs, and newlines and comments too if `lines`
+    fn skip(&mut self, lines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' => self.at += 1,
+                '\r' | '\n' if lines => self.at += 1,
+                '#' if lines => self.at += self.s[self.at..].find('\n').unwrap_or(self.s.len() - self.at),
+                _ => break,
+            }
+        }
+    }
+
+    // The rest of the line must be blank or a comment
+    fn end_of_line(&mut self) -> Result<()> {
+        self.skip(false);
+        if self.peek() == Some('#') {
+            self.at += self.s[self.at..].find('\n').unwrap_or(self.s.len() - self.at);
+        }
+        match self.peek() {
+            None | Some('\n') | Some('\r') => Ok(()),
+            Some(c) => Err(self.err(&format!("unexpected '{}' after a value", c))),
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let literal = self.peek() == Some('\'');
+        self.at += 1;
+        let mut s = String::new();
+        loop {
+            let c = self.peek().ok_or_else(|| self.err("unterminated string"))?;
+            self.at += c.len_utf8();
+            match c {
+                '\n' => return Err(self.err("unterminated string")),
+                '\'' if literal => return Ok(s),
+                '"' if !literal => return Ok(s),
+                '\\' if !literal => {
+                    let e = self.peek().ok_or_else(|| self.err("unterminated string"))?;
+                    self.at += 1;
+                    s.push(match e {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        '"' => '"',
+                        '\\' => '\\',
+                        'u' => {
+                            let hex = self.s.get(self.at..self.at + 4).ok_or_else(|| self.err("short \\u escape"))?;
+                            self.at += 4;
+                            u32::from_str_radix(hex, 16).ok().and_then(char::from_u32).ok_or_else(|| self.err("invalid \\u escape"))?
+                        },
+                        e => return Err(self.err(&format!("unknown escape '\\{}'", e))),
+                    });
+                },
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn key(&mut self) -> Result<Vec<String>> {
+        let mut path = Vec::new();
+        loop {
+            self.skip(false);
+            path.push(match self.peek() {
+                Some('"') | Some('\'') => self.string()?,
+                _ => {
+                    let len = self.s[self.at..].find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-')).unwrap_or(self.s.len() - self.at);
+                    if len == 0 {
+                        return Err(self.err("expected a key"));
+                    }
+                    self.at += len;
+                    self.s[self.at - len..self.at].to_string()
+                },
+            });
+            self.skip(false);
+            if !self.eat(".") {
+                return Ok(path);
+            }
+            if self.depth + path.len() == MAX_DEPTH {
+                return Err(self.err(&format!("nested more than {} deep", MAX_DEPTH)));
+            }
+        }
+    }
+
+    // Parse `levels` deeper in tables and arrays, at most MAX_DEPTH deep
+    fn nested<T>(&mut self, levels: usize, parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.depth + levels > MAX_DEPTH {
+            return Err(self.err(&format!("nested more than {} deep", MAX_DEPTH)));
+        }
+        self.depth += levels;
+        let t = parse(self);
+        self.depth -= levels;
+        t
+    }
+
+    fn value(&mut self) -> Result<Toml> {
+        self.skip(false);
+        match self.peek() {
+            Some('"') | Some('\'') => Ok(Toml::String(self.string()?)),
+            Some('[') => self.nested(1, Parser::array),
+            Some('{') => self.nested(1, Parser::inline_table),
+            _ => {
+                let len = self.s[self.at..].find(|c: char| !(c.is_ascii_alphanumeric() || "_+-.:".contains(c))).unwrap_or(self.s.len() - self.at);
+                let token = &self.s[self.at..self.at + len];
+                self.at += len;
+                let digits = token.replace('_', "");
+                match token {
+                    "" => Err(self.err("expected a value")),
+                    "true" => Ok(Toml::Bool(true)),
+                    "false" => Ok(Toml::Bool(false)),
+                    // a date YYYY-MM-DD (perhaps with a time) or a time HH:MM:SS
+                    _ if token.len() >= 8 && token.as_bytes()[4] == b'-' || token.as_bytes().get(2) == Some(&b':') => Ok(Toml::String(token.to_string())),
+                    _ => digits.parse::<i64>().map(Toml::Integer)
+                        .or_else(|_| digits.parse::<f64>().map(Toml::Float))
+                        .map_err(|_| self.err(&format!("'{}' is not a value", token))),
+                }
+            },
+        }
+    }
+
+    fn array(&mut self) -> Result<Toml> {
+        self.at += 1;
+        let mut xs = Vec::new();
+        loop {
+            self.skip(true);
+            if self.eat("]") {
+                return Ok(Toml::Array(xs));
+            }
+            xs.push(self.value()?);
+            self.skip(true);
+            if !self.eat(",") {
+                self.skip(true);
+                self.expect("]")?;
+                return Ok(Toml::Array(xs));
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Toml> {
+        self.at += 1;
+        let mut table = Toml::Table(Vec::new());
+        self.skip(false);
+        if self.eat("}") {
+            return Ok(table);
+        }
+        loop {
+            let key = self.key()?;
+            self.expect("=")?;
+            let value = self.nested(key.len() - 1, Parser::value)?; // in the tables of a dotted key
+            self.insert(&mut table, &key, value)?;
+            self.skip(false);
+            if !self.eat(",") {
+                self.expect("}")?;
+                return Ok(table);
+            }
+        }
+    }
+
+    // The table at `path` below `table`, creating tables as needed, and the last table of an array of tables
+    fn table<'t>(&self, table: &'t mut Toml, path: &[String]) -> Result<&'t mut Vec<(String, Toml)>> {
+        let mut table = table;
+        for key in path {
+            let Toml::Table(kvs) = table else { unreachable!("parse_toml: descends tables only") };
+            let i = match kvs.iter().position(|(k, _)| k == key) {
+                Some(i) => i,
+                None => {
+                    kvs.push((key.clone(), Toml::Table(Vec::new())));
+                    kvs.len() - 1
+                },
+            };
+            table = match &mut kvs[i].1 {
+                Toml::Array(xs) => xs.last_mut().filter(|x| matches!(x, Toml::Table(_))).ok_or_else(|| self.err(&format!("'{}' is not a table", key)))?,
+                t @ Toml::Table(_) => t,
+                _ => return Err(self.err(&format!("'{}' is not a table", key))),
+            };
+        }
+        match table {
+            Toml::Table(kvs) => Ok(kvs),
+            _ => unreachable!("parse_toml: descends tables only"),
+        }
+    }
+
+    fn insert(&self, table: &mut Toml, key: &[String], value: Toml) -> Result<()> {
+        let (last, path) = key.split_last().expect("keys aren't empty");
+        let kvs = self.table(table, path)?;
+        if kvs.iter().any(|(k, _)| k == last) {
+            return Err(self.err(&format!("'{}' is defined twice", key.join("."))));
+        }
+        kvs.push((last.clone(), value));
+        Ok(())
+    }
+}
+
+/// Parse a TOML document into a table.
+pub fn parse_toml(s: &str) -> Result<Toml> {
+    let mut p = Parser { s, at: 0, depth: 0 };
+    let mut root = Toml::Table(Vec::new());
+    let mut current = Vec::new();
+    loop {
+        p.skip(true);
+        match p.peek() {
+            None => return Ok(root),
+            Some('[') if p.eat("[[") => {
+                current = p.key()?;
+                p.expect("]]")?;
+                let (last, path) = current.split_last().expect("keys aren't empty");
+                let kvs = p.table(&mut root, path)?;
+                match kvs.iter_mut().find(|(k, _)| k == last) {
+                    Some((_, Toml::Array(xs))) => xs.push(Toml::Table(Vec::new())),
+                    Some(_) => return Err(p.err(&format!("'{}' is not an array of tables", current.join(".")))),
+                    None => kvs.push((last.clone(), Toml::Array(vec![Toml::Table(Vec::new())]))),
+                }
+            },
+            Some('[') => {
+                p.at += 1;
+                current = p.key()?;
+                p.expect("]")?;
+                p.table(&mut root, &current)?;
+            },
+            Some(_) => {
+                let key = p.key()?;
+                p.expect("=")?;
+                let value = p.nested(current.len() + key.len() - 1, Parser::value)?; // in the tables of the header and key
+                let path = current.iter().chain(&key).cloned().collect::<Vec<String>>();
+                p.insert(&mut root, &path, value)?;
+            },
+        }
+        p.end_of_line()?;
+    }
+}
+
+/// Load a TOML file (or standard input for [`crate::energy::STDIN`]).
+pub fn load_toml(path: &str) -> Result<Toml> {
+    info!("load_toml: loading TOML file {}", path);
+    let mut s = String::new();
+    open_input(path)?.read_to_string(&mut s)?;
+    parse_toml(&s).with_context(|| format!("load_toml: {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_toml() -> Result<()> {
+        let toml = parse_toml(r#"
+            # a comment
+            from = 2023-08-06 # a local date
+            "quoted key" = 'C:\path'
+            a.b = [1, 2.5, -3_000,
+                   "x\ty"]
+
+            [supply]
+            days = 3
+            inline = { amount = 1.5, ok = true }
+
+            [[usage]]
+            name = "Peak"
+            [[usage]]
+            name = "Off-peak"
+        "#)?;
+        assert_eq!(toml.get("from").and_then(Toml::as_str), Some("2023-08-06"));
+        assert_eq!(toml.get("quoted key").and_then(Toml::as_str), Some(r"C:\path"));
+        assert_eq!(toml.get("a").and_then(|a| a.get("b")), Some(&Toml::Array(vec![
+            Toml::Integer(1), Toml::Float(2.5), Toml::Integer(-3000), Toml::String("x\ty".to_string()),
+        ])));
+        let supply = toml.get("supply").context("supply")?;
+        assert_eq!(supply.get("days").and_then(Toml::as_i64), Some(3));
+        assert_eq!(supply.get("inline").and_then(|i| i.get("amount")).and_then(Toml::as_f64), Some(1.5));
+        let usage = toml.get("usage").and_then(Toml::as_array).context("usage")?;
+        assert_eq!(usage.iter().map(|u| u.get("name").and_then(Toml::as_str)).collect::<Vec<_>>(), vec![Some("Peak"), Some("Off-peak")]);
+        assert_eq!(supply.to_json().to_string(), r#"{"days":3,"inline":{"amount":1.5,"ok":true}}"#);
+
+        assert!(parse_toml("a = 1\na = 2").is_err());
+        assert!(parse_toml("a = 1 2").is_err());
+        assert!(parse_toml("a = \"open").is_err());
+
+        // deep nesting is an error, not a stack overflow
+        let nested = |depth: usize| format!("rate = {}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(parse_toml(&nested(MAX_DEPTH)).is_ok());
+        let dotted = |depth: usize| vec!["a"; depth].join(".");
+        for deep in [nested(200_000), format!("rate = {}", "{a = ".repeat(200_000)), format!("{} = 1", dotted(200_000)), format!("[{}]\n{} = 1", dotted(100), dotted(100))] {
+            let e = parse_toml(&deep).unwrap_err().to_string();
+            assert!(e.contains("nested more than 128 deep"), "{}", e);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+//! Weather normalisation: heating and cooling degree days from daily temperatures, how much consumption rises with
+//! each, and each month's (or year's) usage as it would have been in average weather, so that a cold winter isn't
+//! mistaken for worsening efficiency.
+//!
+//! A day's heating degree days are how far its mean temperature is below the base temperature (18 °C by default, as
+//! used by the Bureau of Meteorology) and its cooling degree days how far it's above. Daily kWh is fitted by least
+//! squares as a base load plus kWh per heating degree day and kWh per cooling degree day.
+
+use crate::energy::{open_input, parse_date, CsvLine, DayEnergy};
+use crate::json::Json;
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, NaiveDate};
+use csv::ReaderBuilder;
+use log::{debug, info};
+use std::collections::BTreeMap;
+
+/// The base temperature (°C) of degree days by default.
+pub const BASE_TEMPERATURE: f64 = 18.0;
+
+/// Load daily mean temperatures (°C) from a CSV file with columns: Date (`YYYYMMDD` or `YYYY-MM-DD`) and Mean °C, or
+/// Date, Min °C and Max °C (the mean being their average, as in Bureau of Meteorology daily data).
+pub fn load_temperatures(csv: &str) -> Result<BTreeMap<NaiveDate, f64>> {
+    info!("load_temperatures: loading CSV file {}", csv);
+    let mut reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_reader(open_input(csv)?);
+
+    let headers = reader.headers()?.clone();
+    reader.records().enumerate()
+    .map(|(i, record)| -> Result<(NaiveDate, f64)> {
+        let r = record?;
+        debug!("load_temperatures: record: {:?}", r);
+        let line = CsvLine::new("load_temperatures", csv, i, &headers, &r);
+        let mean = match line.optional(2) {
+            Some(_) => (line.parse::<f64>(1)? + line.parse::<f64>(2)?) / 2.0,
+            None => line.parse::<f64>(1)?,
+        };
+        Ok((line.parse_with(0, parse_date)?, mean))
+    })
+    .collect()
+}
+
+/// Heating and cooling degree days.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DegreeDays {
+    pub heating: f64,
+    pub cooling: f64,
+}
+
+impl DegreeDays {
+    /// The degree days of a day with a mean temperature, from a base temperature.
+    pub fn of(mean: f64, base: f64) -> DegreeDays {
+        DegreeDays { heating: (base - mean).max(0.0), cooling: (mean - base).max(0.0) }
+    }
+}
+
+/// How daily consumption depends on the weather.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WeatherFit {
+    pub base_kwh: f64,    // kWh a day with no degree days
+    pub heating_kwh: f64, // kWh per heating degree day
+    pub cooling_kwh: f64, // kWh per cooling degree day
+}
+
+/// A month's (or year's) consumption and weather.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeatherPeriod {
+    pub period: NaiveDate, // the first day of the month or year
+    pub days: usize,       // with data and a temperature
+    pub kwh: f64,
+    pub degree_days: DegreeDays,
+    pub normalised_kwh: Option<f64>, // kWh a day in average weather, None without a fit
+}
+
+impl WeatherPeriod {
+    /// kWh per degree day (heating and cooling), None without degree days.
+    pub fn kwh_per_degree_day(&self) -> Option<f64> {
+        let dd = self.degree_days.heating + self.degree_days.cooling;
+        (dd > 0.0).then(|| self.kwh / dd)
+    }
+}
+
+/// Consumption against the weather: the fit over every day and each period's usage.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeatherReport {
+    pub base_temperature: f64,
+    pub fit: Option<WeatherFit>, // None if there are too few days, or too little variation in the weather, to fit
+    pub average: DegreeDays,     // a day of the average weather over all the days
+    pub periods: Vec<WeatherPeriod>,
+    pub missing: Vec<NaiveDate>, // days of data without a temperature, left out
+}
+
+/// Compare days of consumption with daily mean temperatures, a period per month or per year if `yearly`.
+///
+/// Each period's normalised kWh is its kWh a day less the fitted kWh for the difference between its degree days a
+/// day and those of the average day, so periods can be compared as if they'd had the same weather.
+pub fn weather_report<I>(days: I, temperatures: &BTreeMap<NaiveDate, f64>, base: f64, yearly: bool) -> Result<WeatherReport> where
+I: IntoIterator<Item = Result<DayEnergy>> {
+    let mut daily = Vec::new();
+    let mut missing = Vec::new();
+    for day in days {
+        let day = day?;
+        match temperatures.get(&day.date) {
+            Some(mean) => daily.push((day.date, day.energy.iter().sum::<f64>(), DegreeDays::of(*mean, base))),
+            None => missing.push(day.date),
+        }
+    }
+    if daily.is_empty() {
+        return Err(anyhow!("weather_report: no days of data have a temperature"));
+    }
+    let n = daily.len() as f64;
+    let average = DegreeDays {
+        heating: daily.iter().map(|(_, _, dd)| dd.heating).sum::<f64>() / n,
+        cooling: daily.iter().map(|(_, _, dd)| dd.cooling).sum::<f64>() / n,
+    };
+    let fit = fit_weather(&daily.iter().map(|(_, kwh, dd)| (*kwh, *dd)).collect::<Vec<_>>());
+
+    let mut periods: BTreeMap<NaiveDate, WeatherPeriod> = BTreeMap::new();
+    for (date, kwh, dd) in &daily {
+        let period = match yearly {
+            true => NaiveDate::from_ymd_opt(date.year(), 1, 1),
+            false => date.with_day(1),
+        }.unwrap_or(*date);
+        let p = periods.entry(period).or_insert(WeatherPeriod { period, days: 0, kwh: 0.0, degree_days: DegreeDays::default(), normalised_kwh: None });
+        p.days += 1;
+        p.kwh += kwh;
+        p.degree_days.heating += dd.heating;
+        p.degree_days.cooling += dd.cooling;
+    }
+    let periods = periods.into_values().map(|p| {
+        let days = p.days as f64;
+        let normalised_kwh = fit.map(|f| p.kwh / days
+            - f.heating_kwh * (p.degree_days.heating / days - average.heating)
+            - f.cooling_kwh * (p.degree_days.cooling / days - average.cooling));
+        WeatherPeriod { normalised_kwh, ..p }
+    }).collect();
+    Ok(WeatherReport { base_temperature: base, fit, average, periods, missing })
+}
+
+/// Fit daily (kWh, degree days) by least squares to a base load and kWh per heating and cooling degree day.
+///
+/// Heating or cooling is left out of the fit (0 kWh per degree day) if no day has any. None if the rest can't be
+/// fitted: fewer days than terms, or degree days the same every day.
+pub fn fit_weather(daily: &[(f64, DegreeDays)]) -> Option<WeatherFit> {
+    let heating = daily.iter().any(|(_, dd)| dd.heating > 0.0);
+    let cooling = daily.iter().any(|(_, dd)| dd.cooling > 0.0);
+    let terms = |dd: &DegreeDays| {
+        let mut x = vec![1.0];
+        if heating { x.push(dd.heating) }
+        if cooling { x.push(dd.cooling) }
+        x
+    };
+    let k = terms(&DegreeDays::default()).len();
+    if daily.len() < k {
+        return None;
+    }
+    // normal equations (X'X) b = X'y, augmented with X'y
+    let mut m = vec![vec![0.0; k + 1]; k];
+    for (kwh, dd) in daily {
+        let x = terms(dd);
+        for i in 0..k {
+            for j in 0..k {
+                m[i][j] += x[i] * x[j];
+            }
+            m[i][k] += x[i] * kwh;
+        }
+    }
+    let b = solve(m)?;
+    let mut b = b.into_iter();
+    Some(WeatherFit {
+        base_kwh: b.next()?,
+        heating_kwh: if heating { b.next()? } else { 0.0 },
+        cooling_kwh: if cooling { b.next()? } else { 0.0 },
+    })
+}
+
+// Solve linear equations given as an augmented matrix by Gaussian elimination with partial pivoting, None if singular
+fn solve(mut m: Vec<Vec<f64>>) -> Option<Vec<f64>> {
+    let k = m.len();
+    for c in 0..k {
+        let pivot = (c..k).max_by(|a, b| m[*a][c].abs().total_cmp(&m[*b][c].abs()))?;
+        if m[pivot][c].abs() < 1e-9 {
+            return None;
+        }
+        m.swap(c, pivot);
+        let pivot_row = m[c].clone();
+        for row in m.iter_mut().skip(c + 1) {
+            let f = row[c] / pivot_row[c];
+            for (x, p) in row.iter_mut().zip(&pivot_row).skip(c) {
+                *x -= f * p;
+            }
+        }
+    }
+    let mut x = vec![0.0; k];
+    for r in (0..k).rev() {
+        x[r] = (m[r][k] - (r + 1..k).map(|j| m[r][j] * x[j]).sum::<f64>()) / m[r][r];
+    }
+    Some(x)
+}
+
+impl WeatherReport {
+    /// A table of the periods, with the fit.
+    pub fn to_text(&self, yearly: bool) -> String {
+        let mut s = format!("{:<8} {:>5} {:>10} {:>8} {:>8} {:>10} {:>10} {:>12}\n",
+            if yearly { "Year" } else { "Month" }, "Days", "kWh", "HDD", "CDD", "kWh/DD", "kWh/day", "Normalised");
+        for p in &self.periods {
+            let period = p.period.format(if yearly { "%Y" } else { "%Y-%m" }).to_string();
+            let optional = |x: Option<f64>, decimals| x.map_or("-".to_string(), |x| format!("{:.*}", decimals, x));
+            s += &format!("{:<8} {:>5} {:>10.3} {:>8.1} {:>8.1} {:>10} {:>10.3} {:>12}\n",
+                period, p.days, p.kwh, p.degree_days.heating, p.degree_days.cooling,
+                optional(p.kwh_per_degree_day(), 3), p.kwh / p.days as f64, optional(p.normalised_kwh, 3));
+        }
+        s += &format!("Degree days from a base of {} °C, the average day {:.2} HDD and {:.2} CDD\n", self.base_temperature, self.average.heating, self.average.cooling);
+        match self.fit {
+            Some(f) => s += &format!("Base load {:.3} kWh/day, {:.3} kWh per HDD and {:.3} kWh per CDD: normalised is kWh/day in the average day's weather\n", f.base_kwh, f.heating_kwh, f.cooling_kwh),
+            None => s += "Too few days or too little variation in the weather to fit daily kWh to degree days\n",
+        }
+        if let Some(first) = self.missing.first() {
+            s += &format!("Warning: {} days have no temperature (the first {}) and are left out\n", self.missing.len(), first);
+        }
+        s
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("base_temperature", self.base_temperature.into()),
+            ("fit", self.fit.map_or(Json::Null, |f| Json::object(vec![
+                ("base_kwh", f.base_kwh.into()),
+                ("heating_kwh", f.heating_kwh.into()),
+                ("cooling_kwh", f.cooling_kwh.into()),
+            ]))),
+            ("average_hdd", self.average.heating.into()),
+            ("average_cdd", self.average.cooling.into()),
+            ("periods", Json::Array(self.periods.iter().map(|p| Json::object(vec![
+                ("period", p.period.to_string().into()),
+                ("days", p.days.into()),
+                ("kwh", p.kwh.into()),
+                ("hdd", p.degree_days.heating.into()),
+                ("cdd", p.degree_days.cooling.into()),
+                ("kwh_per_degree_day", p.kwh_per_degree_day().map_or(Json::Null, Json::from)),
+                ("normalised_kwh", p.normalised_kwh.map_or(Json::Null, Json::from)),
+            ])).collect())),
+            ("missing", Json::Array(self.missing.iter().map(|d| d.to_string().into()).collect())),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_float_eq::*;
+
+    #[test]
+    fn test_weather_report() -> Result<()> {
+        let temperatures = load_temperatures("data/test/temperatures.csv")?;
+        assert_eq!(temperatures.get(&NaiveDate::from_ymd_opt(2023, 8, 1).unwrap()), Some(&20.0));
+        assert_eq!(DegreeDays::of(20.0, 18.0), DegreeDays { heating: 0.0, cooling: 2.0 });
+
+        // 4 kWh a day, 0.5 more per heating degree day and 0.3 per cooling degree day, and a day without a temperature
+        let days = temperatures.iter().map(|(date, mean)| {
+            let dd = DegreeDays::of(*mean, BASE_TEMPERATURE);
+            DayEnergy::new(*date, vec![(4.0 + 0.5 * dd.heating + 0.3 * dd.cooling) / 2.0; 2])
+        }).chain([DayEnergy::new(NaiveDate::from_ymd_opt(2023, 8, 9).unwrap(), vec![1.0; 48])]);
+        let report = weather_report(days, &temperatures, BASE_TEMPERATURE, false)?;
+        let fit = report.fit.unwrap();
+        assert_f64_near!(fit.base_kwh, 4.0, 40);
+        assert_f64_near!(fit.heating_kwh, 0.5, 40);
+        assert_f64_near!(fit.cooling_kwh, 0.3, 40);
+        assert_eq!(report.missing, vec![NaiveDate::from_ymd_opt(2023, 8, 9).unwrap()]);
+
+        // July was colder so used more, but the same in average weather
+        let (july, august) = (&report.periods[0], &report.periods[1]);
+        assert_eq!((july.days, august.days), (6, 8));
+        assert!(july.kwh / 6.0 > august.kwh / 8.0);
+        assert_f64_near!(july.normalised_kwh.unwrap(), august.normalised_kwh.unwrap(), 40);
+
+        // no fit with a single day
+        let one = weather_report([DayEnergy::new(NaiveDate::from_ymd_opt(2023, 8, 1).unwrap(), vec![1.0; 48])], &temperatures, BASE_TEMPERATURE, true)?;
+        assert_eq!((one.fit, one.periods[0].normalised_kwh), (None, None));
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+//! Dynamic pricing: energy priced at the price of each interval from a price file, such as wholesale
+//! pass-through (as by Amber Electric) prices plus margins, rather than by the rows of a time of use tariff.
+//!
+//! Prices are loaded from a CSV file of timestamps and prices or from the JSON of the Amber API's prices
+//! endpoint (`GET https://api.amber.com.au/v1/sites/{siteId}/prices`), see [`Wholesale::load`].
+//! Each interval of energy data is priced at the mean of the prices of the price intervals it overlaps,
+//! weighted by the overlap, so 5 or 30 minute prices can price data of any interval length.
+//! [`MissingPrice`] says what happens to an interval without prices.
+
+use crate::energy::{open_input, parse_timestamp, Direction};
+use crate::json::{parse_json, Json};
+use crate::pricing::Interval;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, NaiveDateTime};
+use csv::ReaderBuilder;
+use log::{debug, info};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::str::FromStr;
+
+/// What to do with an interval of energy the prices don't cover.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingPrice {
+    #[default]
+    Tariff,       // price it by the plan's tariff rows
+    Error,        // abort pricing
+    CarryForward, // each price applies until the next one, an error before the first
+}
+
+impl FromStr for MissingPrice {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<MissingPrice> {
+        match s.trim().to_lowercase().as_str() {
+            "tariff" => Ok(MissingPrice::Tariff),
+            "error" => Ok(MissingPrice::Error),
+            "carry-forward" => Ok(MissingPrice::CarryForward),
+            _ => Err(anyhow!("MissingPrice::from_str: '{}' is not one of tariff, error, carry-forward", s)),
+        }
+    }
+}
+
+/// Prices by interval and the margins added to them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Wholesale {
+    pub usage: BTreeMap<NaiveDateTime, f64>,  // $/kWh including GST by the start of each price interval
+    pub feedin: BTreeMap<NaiveDateTime, f64>, // $/kWh, negative for a credit, empty if feedin isn't priced here
+    pub length: i64,        // minutes per price interval
+    pub margin: f64,        // $/kWh added to usage prices, e.g. network charges and the retailer's margin
+    pub feedin_margin: f64, // $/kWh added to feedin prices, negative to reduce the credit
+    pub missing: MissingPrice,
+}
+
+// Minutes between consecutive starts, the length of the price intervals (30 if there's only one)
+fn interval_length(prices: &BTreeMap<NaiveDateTime, f64>) -> i64 {
+    prices.keys().zip(prices.keys().skip(1)).map(|(a, b)| (*b - *a).num_minutes()).min().unwrap_or(30)
+}
+
+impl Wholesale {
+    /// Load prices from Amber API JSON (a `.json` file) or a CSV file, without margins.
+    ///
+    /// The CSV columns are: the timestamp of the start of the price interval (`YYYY-MM-DD HH:MM`, optionally with
+    /// seconds, `T` or an RFC 3339 offset, in the time of the energy data), $/kWh and optionally feedin $/kWh
+    /// (negative for a credit, empty if not priced here), e.g. `timestamp,price_per_kwh`.
+    /// The price intervals are as long as the shortest time between consecutive starts.
+    pub fn load(path: &str) -> Result<Wholesale> {
+        if path.ends_with(".json") {
+            info!("Wholesale::load: loading JSON file {}", path);
+            let mut s = String::new();
+            open_input(path)?.read_to_string(&mut s)?;
+            return parse_json(&s).and_then(|json| Wholesale::from_amber(&json)).with_context(|| format!("Wholesale::load: {}", path));
+        }
+        info!("Wholesale::load: loading CSV file {}", path);
+        let mut reader = ReaderBuilder::new()
+            .has_headers(true)
+            .from_reader(open_input(path)?);
+
+        let (mut usage, mut feedin) = (BTreeMap::new(), BTreeMap::new());
+        for record in reader.records() {
+            let r = record?;
+            debug!("Wholesale::load: record: {:?}", r);
+            let start = parse_timestamp(&r[0])?;
+            usage.insert(start, r[1].trim().parse::<f64>()?);
+            if let Some(f) = r.get(2).map(str::trim).filter(|x| !x.is_empty()) {
+                feedin.insert(start, f.parse::<f64>()?);
+            }
+        }
+        Ok(Wholesale { length: interval_length(&usage), usage, feedin, ..Wholesale::default() })
+    }
+
+    /// Wholesale prices from the Amber API's array of intervals, using each interval's `channelType`
+    /// (`general` for usage, `feedIn`; others are ignored), `nemTime` (the end of the interval in NEM time),
+    /// `duration` (minutes) and `perKwh` (c/kWh including GST and Amber's margins, negative for a feedin credit).
+    pub fn from_amber(json: &Json) -> Result<Wholesale> {
+        let intervals = json.as_array().ok_or_else(|| anyhow!("Wholesale::from_amber: not an array of intervals"))?;
+        let (mut usage, mut feedin) = (BTreeMap::new(), BTreeMap::new());
+        let mut length = None;
+        for (n, interval) in intervals.iter().enumerate() {
+            let field = |key: &str| interval.get(key).ok_or_else(|| anyhow!("Wholesale::from_amber: interval {} has no {}", n + 1, key));
+            let duration = field("duration")?.as_f64().context("Wholesale::from_amber: duration is not a number")? as i64;
+            let end = field("nemTime")?.as_str().and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .with_context(|| format!("Wholesale::from_amber: interval {} nemTime is not a date and time", n + 1))?;
+            let start = end.naive_local() - Duration::minutes(duration);
+            let rate = field("perKwh")?.as_f64().context("Wholesale::from_amber: perKwh is not a number")? / 100.0;
+            match interval.get("channelType").and_then(Json::as_str).unwrap_or("general") {
+                "general" => usage.insert(start, rate),
+                "feedIn" => feedin.insert(start, rate),
+                _ => continue,
+            };
+            length = Some(length.map_or(duration, |l: i64| l.min(duration)));
+        }
+        Ok(Wholesale { length: length.unwrap_or(30), usage, feedin, ..Wholesale::default() })
+    }
+
+    /// These prices with margins ($/kWh) added to usage and feedin prices.
+    pub fn with_margins(self, margin: f64, feedin_margin: f64) -> Wholesale {
+        Wholesale { margin, feedin_margin, ..self }
+    }
+
+    /// These prices with what to do with intervals they don't cover.
+    pub fn with_missing(self, missing: MissingPrice) -> Wholesale {
+        Wholesale { missing, ..self }
+    }
+
+    /// Whether feedin is priced here.
+    pub fn has_feedin(&self) -> bool {
+        !self.feedin.is_empty()
+    }
+
+    /// The $/kWh (including margins) for an interval's energy: the mean of the prices of the price intervals
+    /// it overlaps, weighted by the overlap. None to price the interval by the tariff, if the prices don't cover
+    /// all of the interval (see [`MissingPrice`]) or there are none for the direction.
+    pub fn rate(&self, i: &Interval, direction: Direction) -> Result<Option<f64>> {
+        let (prices, margin) = match direction {
+            Direction::Import => (&self.usage, self.margin),
+            Direction::Export => (&self.feedin, self.feedin_margin),
+        };
+        if prices.is_empty() {
+            return Ok(None);
+        }
+        let start = i.date.and_hms_opt(0, 0, 0).context("Wholesale::rate: invalid date")? + Duration::minutes(i.min_since_midnight as i64);
+        let end = start + Duration::minutes(i.length as i64);
+        let carry = self.missing == MissingPrice::CarryForward;
+        let first = match carry {
+            true => prices.range(..=start).next_back().map_or(start, |(t, _)| *t),
+            false => start - Duration::minutes(self.length - 1),
+        };
+        let (mut covered, mut sum) = (0, 0.0);
+        let mut overlapping = prices.range(first..end).peekable();
+        while let Some((t, rate)) = overlapping.next() {
+            let until = match (carry, overlapping.peek()) {
+                (true, Some((next, _))) => **next,
+                (true, None) => end,
+                (false, _) => *t + Duration::minutes(self.length),
+            };
+            let overlap = (end.min(until) - start.max(*t)).num_minutes().max(0);
+            covered += overlap;
+            sum += overlap as f64 * rate;
+        }
+        match (covered == i.length as i64 && covered > 0, self.missing) {
+            (true, _) => Ok(Some(sum / covered as f64 + margin)),
+            (false, MissingPrice::Tariff) => Ok(None),
+            (false, _) => Err(anyhow!("Wholesale::rate: no {} price for all of {} to {}",
+                if direction == Direction::Import { "usage" } else { "feedin" }, start.format("%Y-%m-%d %H:%M"), end.format("%H:%M"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_float_eq::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_wholesale_rate() -> Result<()> {
+        let wholesale = Wholesale::load("data/test/wholesale.csv")?.with_margins(0.1, 0.0);
+        assert_eq!(wholesale.length, 30);
+        let interval = |date: NaiveDate, min_since_midnight, length| Interval { date, dow: 0, holiday: false, min_since_midnight, length, energy: 1.0, usage_day: 0.0, usage_period: 0.0 };
+        let date = NaiveDate::from_ymd_opt(2023, 8, 6).unwrap();
+        assert_f64_near!(wholesale.rate(&interval(date, 16 * 60, 8 * 60), Direction::Import)?.unwrap(), 0.35 + 0.1);
+        assert_f64_near!(wholesale.rate(&interval(date, 17 * 60 + 55, 5), Direction::Import)?.unwrap(), 0.5 + 0.1);
+        assert_f64_near!(wholesale.rate(&interval(date, 8 * 60, 8 * 60), Direction::Export)?.unwrap(), -0.03);
+
+        // after the last price
+        let after = interval(NaiveDate::from_ymd_opt(2023, 8, 9).unwrap(), 0, 30);
+        assert_eq!(wholesale.rate(&after, Direction::Import)?, None);
+        let wholesale = wholesale.with_missing(MissingPrice::Error);
+        assert!(wholesale.rate(&after, Direction::Import).is_err());
+        let wholesale = wholesale.with_missing(MissingPrice::CarryForward);
+        assert_f64_near!(wholesale.rate(&after, Direction::Import)?.unwrap(), 0.2 + 0.1);
+        assert!(wholesale.rate(&interval(date.pred_opt().unwrap(), 0, 30), Direction::Import).is_err());
+
+        // hourly prices with a gap, carried forward
+        let mut gaps = Wholesale { length: 60, missing: MissingPrice::CarryForward, ..Wholesale::default() };
+        gaps.usage.insert(parse_timestamp("2023-08-06T00:00:00")?, 0.1);
+        gaps.usage.insert(parse_timestamp("2023-08-06T03:00:00+10:00")?, 0.4);
+        assert_f64_near!(gaps.rate(&interval(date, 0, 4 * 60), Direction::Import)?.unwrap(), 0.175);
+        assert_eq!(gaps.with_missing(MissingPrice::Tariff).rate(&interval(date, 0, 4 * 60), Direction::Import)?, None);
+
+        let amber = Wholesale::from_amber(&parse_json(r#"[
+            {"type": "ActualInterval", "duration": 30, "perKwh": 24.33, "nemTime": "2023-08-06T00:30:00+10:00", "channelType": "general"},
+            {"type": "ActualInterval", "duration": 30, "perKwh": -5.5, "nemTime": "2023-08-06T00:30:00+10:00", "channelType": "feedIn"},
+            {"type": "ActualInterval", "duration": 30, "perKwh": 12.0, "nemTime": "2023-08-06T00:30:00+10:00", "channelType": "controlledLoad"}
+        ]"#)?)?;
+        let start = date.and_hms_opt(0, 0, 0).unwrap();
+        assert_eq!((amber.usage.get(&start), amber.feedin.get(&start)), (Some(&0.2433), Some(&-0.055)));
+        assert!(amber.has_feedin());
+        Ok(())
+    }
+}
@@ -0,0 +1,305 @@
+//! Reading the first worksheet of an Excel workbook (`.xlsx`) as CSV.
+//!
+//! An xlsx file is a zip archive of XML parts (see [`crate::compress`]):
+//! - `xl/worksheets/sheet1.xml`: the rows of `c` (cell) elements, with the cell reference (`r`, e.g. `B2`),
+//!   type (`t`: `s` shared string, `inlineStr`, `str`, `b`, `e`, or a number) and style (`s`) attributes
+//! - `xl/sharedStrings.xml`: the `si` strings indexed by `s` cells
+//! - `xl/styles.xml`: the `cellXfs` styles indexed by `s`, whose `numFmtId` says whether a number is a date or time
+//!
+//! Cells with a date or time format are written like the CSV files: `YYYYMMDD` for dates, `HH:MM:SS` for times.
+//! Other numbers are written in their shortest form (Excel stores 0.073 as `7.2999999999999995E-2`). Missing cells are blank.
+
+use crate::compress::{zip_file, zip_names};
+use anyhow::{anyhow, Context, Result};
+use chrono::{Duration, NaiveDate};
+use csv::Writer;
+use log::{debug, info};
+use std::collections::HashSet;
+
+const WORKBOOK: &str = "xl/workbook.xml";
+const SHEET: &str = "xl/worksheets/sheet1.xml";
+const SHARED_STRINGS: &str = "xl/sharedStrings.xml";
+const STYLES: &str = "xl/styles.xml";
+
+// XML nodes, element names have their namespace prefix removed
+#[derive(Debug, PartialEq)]
+enum Node<'a> {
+    Start(&'a str, &'a str), // name, attributes
+    End(&'a str),
+    Text(&'a str),
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+// Split an XML document into nodes, ignoring declarations and comments. Text is not unescaped.
+fn nodes(xml: &str) -> Result<Vec<Node<'_>>> {
+    let mut nodes = Vec::new();
+    let mut rest = xml;
+    while let Some(lt) = rest.find('<') {
+        if lt > 0 {
+            nodes.push(Node::Text(&rest[.. lt]));
+        }
+        rest = &rest[lt ..];
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = &after[after.find("-->").context("xlsx: unterminated comment")? + 3 ..];
+            continue;
+        }
+        let gt = rest.find('>').context("xlsx: unterminated tag")?;
+        let tag = &rest[1 .. gt];
+        rest = &rest[gt + 1 ..];
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+        if let Some(name) = tag.strip_prefix('/') {
+            nodes.push(Node::End(local_name(name.trim())));
+        } else {
+            let body = tag.strip_suffix('/').unwrap_or(tag);
+            let (name, attrs) = body.split_once(char::is_whitespace).unwrap_or((body, ""));
+            nodes.push(Node::Start(local_name(name), attrs));
+            if body.len() < tag.len() {
+                nodes.push(Node::End(local_name(name)));
+            }
+        }
+    }
+    Ok(nodes)
+}
+
+// The value of an attribute, e.g. r in `r="A1" s="2"`
+fn attr<'a>(attrs: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = attrs;
+    while let Some(eq) = rest.find('=') {
+        let key = rest[.. eq].trim();
+        let value = rest[eq + 1 ..].trim_start();
+        let quote = value.chars().next()?;
+        let end = value[1 ..].find(quote)? + 1;
+        if local_name(key) == name {
+            return Some(&value[1 .. end]);
+        }
+        rest = &value[end + 1 ..];
+    }
+    None
+}
+
+// Replace the XML entity and character references
+fn unescape(text: &str) -> Result<String> {
+    let mut s = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        s.push_str(&rest[.. amp]);
+        let semi = rest[amp ..].find(';').with_context(|| format!("xlsx: unterminated reference in '{}'", text))? + amp;
+        s.push(match &rest[amp + 1 .. semi] {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            r => r.strip_prefix("#x").map(|h| u32::from_str_radix(h, 16))
+                .or_else(|| r.strip_prefix('#').map(|d| d.parse::<u32>()))
+                .and_then(|c| c.ok().and_then(char::from_u32))
+                .with_context(|| format!("xlsx: invalid reference &{}; in '{}'", r, text))?,
+        });
+        rest = &rest[semi + 1 ..];
+    }
+    s.push_str(rest);
+    Ok(s)
+}
+
+// The zero based column of a cell reference, e.g. 1 for B2
+fn column(r: &str) -> Result<usize> {
+    let letters = r.chars().take_while(|c| c.is_ascii_uppercase()).collect::<Vec<char>>();
+    if letters.is_empty() {
+        return Err(anyhow!("xlsx: invalid cell reference '{}'", r));
+    }
+    Ok(letters.iter().fold(0, |n, c| n * 26 + (*c as usize - 'A' as usize + 1)) - 1)
+}
+
+// The shared strings, each the concatenation of its text runs (without phonetic runs)
+fn shared_strings(xml: &str) -> Result<Vec<String>> {
+    let mut strings = Vec::new();
+    let mut stack = Vec::new();
+    for node in nodes(xml)? {
+        match node {
+            Node::Start(name, _) => {
+                if name == "si" {
+                    strings.push(String::new());
+                }
+                stack.push(name);
+            },
+            Node::End(_) => { stack.pop(); },
+            Node::Text(text) => if stack.last() == Some(&"t") && !stack.contains(&"rPh") {
+                if let Some(s) = strings.last_mut() {
+                    s.push_str(&unescape(text)?);
+                }
+            },
+        }
+    }
+    Ok(strings)
+}
+
+// Whether a number format code is a date or time, ignoring quoted text, escapes and [colour] sections
+fn is_date_format(code: &str) -> bool {
+    let mut plain = String::new();
+    let (mut quoted, mut bracket, mut escaped) = (false, false, false);
+    for c in code.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            '[' if !quoted => bracket = true,
+            ']' if !quoted => bracket = false,
+            c if !quoted && !bracket => plain.push(c.to_ascii_lowercase()),
+            _ => {},
+        }
+    }
+    plain.contains(['y', 'd', 'h', 's'])
+}
+
+// The indexes of the cell styles (cellXfs) with a date or time number format
+fn date_styles(xml: &str) -> Result<HashSet<usize>> {
+    let mut custom = HashSet::new();
+    let mut styles = HashSet::new();
+    let (mut in_xfs, mut xf) = (false, 0);
+    for node in nodes(xml)? {
+        match node {
+            Node::Start("numFmt", attrs) => {
+                if let (Some(id), Some(code)) = (attr(attrs, "numFmtId"), attr(attrs, "formatCode")) {
+                    if is_date_format(&unescape(code)?) {
+                        custom.insert(id.parse::<usize>()?);
+                    }
+                }
+            },
+            Node::Start("cellXfs", _) => in_xfs = true,
+            Node::End("cellXfs") => in_xfs = false,
+            Node::Start("xf", attrs) if in_xfs => {
+                let id = attr(attrs, "numFmtId").unwrap_or("0").parse::<usize>()?;
+                if matches!(id, 14..=22 | 45..=47) || custom.contains(&id) {
+                    styles.insert(xf);
+                }
+                xf += 1;
+            },
+            _ => {},
+        }
+    }
+    Ok(styles)
+}
+
+/// An Excel date and time serial number (days since 1899-12-30) like the CSV files:
+/// `YYYYMMDD`, `HH:MM:SS`, or both for a date with a time.
+pub fn serial_date(serial: f64) -> Result<String> {
+    let seconds = (serial * 24.0 * 60.0 * 60.0).round() as i64;
+    let epoch = NaiveDate::from_ymd_opt(1899, 12, 30).context("serial_date: epoch")?.and_hms_opt(0, 0, 0).context("serial_date: epoch")?;
+    let t = epoch.checked_add_signed(Duration::seconds(seconds)).with_context(|| format!("serial_date: {} is out of range", serial))?;
+    Ok(match (seconds / (24 * 60 * 60), seconds % (24 * 60 * 60)) {
+        (_, 0) => t.format("%Y%m%d").to_string(),
+        (0, _) => t.format("%H:%M:%S").to_string(),
+        _ => t.format("%Y%m%d %H:%M:%S").to_string(),
+    })
+}
+
+// One cell being read
+#[derive(Default)]
+struct Cell<'a> {
+    column: usize,
+    kind: &'a str, // the t attribute
+    style: usize,
+    value: String, // from v, or t for an inline string
+}
+
+// The rows of a worksheet as text, with missing cells blank
+fn sheet_rows(xml: &str, strings: &[String], dates: &HashSet<usize>) -> Result<Vec<Vec<String>>> {
+    let mut rows = Vec::new();
+    let mut row: Vec<String> = Vec::new();
+    let mut cell = Cell::default();
+    let mut stack = Vec::new();
+    for node in nodes(xml)? {
+        match node {
+            Node::Start(name, attrs) => {
+                match name {
+                    "row" => row = Vec::new(),
+                    "c" => cell = Cell {
+                        column: attr(attrs, "r").map(column).transpose()?.unwrap_or(row.len()),
+                        kind: attr(attrs, "t").unwrap_or("n"),
+                        style: attr(attrs, "s").map(str::parse).transpose()?.unwrap_or(0),
+                        value: String::new(),
+                    },
+                    _ => {},
+                }
+                stack.push(name);
+            },
+            Node::End(name) => {
+                stack.pop();
+                match name {
+                    "c" => {
+                        let text = match cell.kind {
+                            "s" => strings.get(cell.value.trim().parse::<usize>()?).cloned()
+                                .with_context(|| format!("xlsx: shared string {} doesn't exist", cell.value))?,
+                            "n" if cell.value.is_empty() => String::new(),
+                            "n" if dates.contains(&cell.style) => serial_date(cell.value.trim().parse()?)?,
+                            "n" => cell.value.trim().parse::<f64>()?.to_string(), // shortest, e.g. 0.073 for 7.2999999999999995E-2
+                            _ => cell.value.clone(),
+                        };
+                        if row.len() <= cell.column {
+                            row.resize(cell.column + 1, String::new());
+                        }
+                        row[cell.column] = text;
+                    },
+                    "row" => rows.push(std::mem::take(&mut row)),
+                    _ => {},
+                }
+            },
+            Node::Text(text) => if matches!(stack.last(), Some(&"v") | Some(&"t")) && stack.contains(&"c") {
+                cell.value.push_str(&unescape(text)?);
+            },
+        }
+    }
+    Ok(rows)
+}
+
+/// Whether zip data is an xlsx workbook.
+pub fn is_xlsx(zip: &[u8]) -> bool {
+    zip_names(zip).is_ok_and(|names| names.iter().any(|n| n == WORKBOOK))
+}
+
+/// The first worksheet of an xlsx workbook as CSV, with rows padded to the same number of columns
+/// as the first row (the header) where they are shorter.
+pub fn xlsx_csv(zip: &[u8]) -> Result<Vec<u8>> {
+    let part = |name: &str| -> Result<Option<String>> {
+        zip_file(zip, name)?.map(|x| String::from_utf8(x).map_err(|e| anyhow!("xlsx_csv: {}: {}", name, e))).transpose()
+    };
+    let sheet = part(SHEET)?.ok_or_else(|| anyhow!("xlsx_csv: no first worksheet {}", SHEET))?;
+    let strings = part(SHARED_STRINGS)?.map(|x| shared_strings(&x)).transpose()?.unwrap_or_default();
+    let dates = part(STYLES)?.map(|x| date_styles(&x)).transpose()?.unwrap_or_default();
+    debug!("xlsx_csv: {} shared strings, date styles {:?}", strings.len(), dates);
+    let rows = sheet_rows(&sheet, &strings, &dates)?;
+    info!("xlsx_csv: {} rows", rows.len());
+
+    let width = rows.first().map_or(0, |r| r.len());
+    let mut writer = Writer::from_writer(Vec::new());
+    for mut row in rows {
+        if row.len() < width {
+            row.resize(width, String::new());
+        }
+        writer.write_record(&row)?;
+    }
+    writer.into_inner().map_err(|e| anyhow!("xlsx_csv: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xlsx_csv() -> Result<()> {
+        let zip = std::fs::read("data/test/energy/consumption.xlsx")?;
+        assert!(is_xlsx(&zip));
+        let csv = String::from_utf8(xlsx_csv(&zip)?)?;
+        // a date cell, a number cell with a shared string for the date, a row with a missing cell
+        assert_eq!(csv, "Date/Time,00:00:00,08:00:00,16:00:00\n20230806,0.073,0.028,0.063\n20230807,0.073,0.028,0.063\n20230808,0.073,,0.063\n");
+        assert_eq!(serial_date(45144.5)?, "20230806 12:00:00");
+        assert_eq!(unescape("a &amp; &#x41;&#66;")?, "a & AB");
+        assert!(is_date_format("yyyy-mm-dd") && !is_date_format("0.000") && !is_date_format("\"days\" 0"));
+        Ok(())
+    }
+}